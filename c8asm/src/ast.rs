@@ -1,19 +1,54 @@
+use std::fmt;
+
 #[derive(Debug)]
 pub struct Line {
     pub label: Option<String>,
     pub instr: Option<Instr>,
+    pub location: Location,
 }
 
 impl Line {
     pub fn size(&self) -> usize {
         self.instr.as_ref().map(|i| i.size()).unwrap_or_default()
     }
+
+    /// Returns the address following the line, when it starts at `addr`.
+    pub fn next_address(&self, addr: usize) -> usize {
+        match self.instr {
+            Some(Instr::Org(org)) => org as usize,
+            _ => addr + self.size(),
+        }
+    }
+}
+
+/// Where a line comes from, for error messages.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Location {
+    /// Name of the included file the line is from, None for the file being assembled.
+    pub file: Option<String>,
+    /// Number of the line in its file, starting at 1.
+    pub line: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{} line {}", file, self.line),
+            None => write!(f, "line {}", self.line),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum Instr {
     Opcode(Opcode),
     Data(Vec<u8>),
+    /// Big-endian 16-bit words, eg. the addresses of a jump table.
+    Words(Vec<Addr>),
+    /// Replaced by the lines of the file when parsing.
+    Include(String),
+    /// Places the following lines from an address, the gap before it is filled with zeros.
+    Org(u16),
 }
 
 impl Instr {
@@ -21,6 +56,8 @@ impl Instr {
         match self {
             Instr::Opcode(_) => 2,
             Instr::Data(d) => d.len(),
+            Instr::Words(w) => 2 * w.len(),
+            Instr::Include(_) | Instr::Org(_) => 0,
         }
     }
 }
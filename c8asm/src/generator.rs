@@ -1,28 +1,223 @@
-use crate::ast::{Addr, Instr, Line, Opcode, VReg};
+use crate::ast::{Addr, Instr, Line, Location, Opcode, VReg};
 use std::collections::HashMap;
 use std::error::Error;
+use std::fmt;
 use std::io::Write;
 
 const ORIGIN: usize = 0x200;
 
+/// The machine a program is assembled for, which determines the memory available to it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Platform {
+    #[default]
+    Chip8,
+    XoChip,
+}
+
+impl Platform {
+    /// Number of bytes available to a program, from its origin to the end of memory.
+    pub fn capacity(self) -> usize {
+        match self {
+            Platform::Chip8 => 0x1000 - ORIGIN,
+            Platform::XoChip => 0x10000 - ORIGIN,
+        }
+    }
+}
+
+/// A program too large for the memory of its platform.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Overflow {
+    pub size: usize,
+    pub capacity: usize,
+    /// First source line going beyond the end of memory.
+    pub location: Location,
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "program is {} bytes, {} more than the {} available, from {}",
+            self.size,
+            self.size - self.capacity,
+            self.capacity,
+            self.location
+        )
+    }
+}
+
+impl Error for Overflow {}
+
+/// Returns the size of the program, or an error if it doesn't fit in the memory of the platform.
+/// `lines` are expected to be all the lines of the source, in order.
+pub fn check_size(lines: &[Line], platform: Platform) -> Result<usize, Overflow> {
+    let capacity = platform.capacity();
+    let mut addr = ORIGIN;
+    let mut overflow_location = None;
+
+    for l in lines {
+        addr = l.next_address(addr);
+        // an org before the origin is reported when generating the image
+        if addr.saturating_sub(ORIGIN) > capacity && overflow_location.is_none() {
+            overflow_location = Some(l.location.clone());
+        }
+    }
+    let size = addr.saturating_sub(ORIGIN);
+
+    match overflow_location {
+        Some(location) => Err(Overflow {
+            size,
+            capacity,
+            location,
+        }),
+        None => Ok(size),
+    }
+}
+
 pub fn generate<W: Write>(lines: &[Line], w: &mut W) -> Result<(), Box<dyn Error>> {
     let labels = labels(lines)?;
     opcodes(lines, &labels, w)
 }
 
+/// Fills an image with `fill` bytes up to exactly `size` bytes, eg. to program a memory chip
+/// or to concatenate images at fixed offsets.
+pub fn pad_image(image: &mut Vec<u8>, size: usize, fill: u8) -> Result<(), String> {
+    if image.len() > size {
+        return Err(format!(
+            "program is {} bytes, more than the {} to pad it to",
+            image.len(),
+            size
+        ));
+    }
+    image.resize(size, fill);
+    Ok(())
+}
+
+/// Returns the labels of the program along with their addresses, in address order.
+pub fn symbols(lines: &[Line]) -> Result<Vec<(u16, String)>, String> {
+    let mut symbols: Vec<(u16, String)> = labels(lines)?
+        .into_iter()
+        .map(|(label, addr)| (addr as u16, label))
+        .collect();
+    symbols.sort();
+    Ok(symbols)
+}
+
+/// Writes a symbol map, with one `address label` pair per line, eg. `0x0204 draw_prompt`.
+pub fn write_symbols<W: Write>(symbols: &[(u16, String)], w: &mut W) -> std::io::Result<()> {
+    for (addr, label) in symbols {
+        writeln!(w, "{:#06x} {}", addr, label)?;
+    }
+    Ok(())
+}
+
+/// A label, where it is defined, and the lines referencing it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CrossReference {
+    pub label: String,
+    pub address: u16,
+    pub definition: Location,
+    /// Location and address of each line referencing the label, in source order.
+    pub references: Vec<(Location, u16)>,
+}
+
+/// Returns the cross-references of all the labels of the program, in address order.
+/// Labels without references are kept, they may be dead code.
+pub fn cross_references(lines: &[Line]) -> Result<Vec<CrossReference>, String> {
+    let labels = labels(lines)?;
+    let mut xrefs: HashMap<&str, CrossReference> = HashMap::new();
+    for l in lines {
+        if let Some(label) = &l.label {
+            xrefs.insert(
+                label,
+                CrossReference {
+                    label: label.clone(),
+                    address: labels[label] as u16,
+                    definition: l.location.clone(),
+                    references: vec![],
+                },
+            );
+        }
+    }
+
+    let mut addr = ORIGIN;
+    for l in lines {
+        for label in label_refs(l.instr.as_ref()) {
+            // undefined labels are reported when generating the image
+            if let Some(xref) = xrefs.get_mut(label) {
+                xref.references.push((l.location.clone(), addr as u16));
+            }
+        }
+        addr = l.next_address(addr);
+    }
+
+    let mut xrefs: Vec<CrossReference> = xrefs.into_values().collect();
+    xrefs.sort_by(|a, b| (a.address, &a.label).cmp(&(b.address, &b.label)));
+    Ok(xrefs)
+}
+
+/// Writes a cross-reference report: each label with its address and definition,
+/// followed by one indented line per reference, eg. `    0x0212 line 14`.
+pub fn write_cross_references<W: Write>(
+    xrefs: &[CrossReference],
+    w: &mut W,
+) -> std::io::Result<()> {
+    for xref in xrefs {
+        writeln!(
+            w,
+            "{:#06x} {} ({})",
+            xref.address, xref.label, xref.definition
+        )?;
+        if xref.references.is_empty() {
+            writeln!(w, "    unreferenced")?;
+        }
+        for (location, addr) in &xref.references {
+            writeln!(w, "    {:#06x} {}", addr, location)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the labels referenced by an instruction.
+fn label_refs(instr: Option<&Instr>) -> Vec<&str> {
+    let addrs: Vec<&Addr> = match instr {
+        Some(Instr::Opcode(
+            Opcode::Jump(a) | Opcode::Call(a) | Opcode::LoadI(a) | Opcode::JumpV0(a),
+        )) => vec![a],
+        Some(Instr::Words(words)) => words.iter().collect(),
+        _ => vec![],
+    };
+    addrs
+        .into_iter()
+        .filter_map(|a| match a {
+            Addr::LabelRef(label) => Some(label.as_str()),
+            Addr::Imm(_) => None,
+        })
+        .collect()
+}
+
 fn labels(lines: &[Line]) -> Result<HashMap<String, usize>, String> {
     let mut addr = ORIGIN;
     let mut labels = HashMap::new();
 
     for l in lines {
+        if let Some(Instr::Org(org)) = l.instr {
+            // segments can't overlap
+            if (org as usize) < addr {
+                return Err(format!(
+                    "{}: org {:#x} is before the current address {:#x}",
+                    l.location, org, addr
+                ));
+            }
+        }
         if let Some(label) = &l.label {
             // forbid duplicate labels
             if labels.contains_key(label) {
-                return Err(format!("duplicate label: '{}'", label));
+                return Err(format!("{}: duplicate label: '{}'", l.location, label));
             }
             labels.insert(label.to_owned(), addr);
         }
-        addr += l.size();
+        addr = l.next_address(addr);
     }
 
     Ok(labels)
@@ -33,12 +228,24 @@ fn opcodes<W: Write>(
     labels: &HashMap<String, usize>,
     w: &mut W,
 ) -> Result<(), Box<dyn Error>> {
+    let mut addr = ORIGIN;
     for line in lines {
         match &line.instr {
+            Some(Instr::Org(org)) => w.write_all(&vec![0; *org as usize - addr])?,
             Some(Instr::Data(d)) => w.write_all(d)?,
-            Some(Instr::Opcode(o)) => opcode(o, labels, w)?,
+            Some(Instr::Words(words)) => {
+                for a in words {
+                    let word =
+                        resolve(a, labels).map_err(|e| format!("{}: {}", line.location, e))?;
+                    w.write_all(&word.to_be_bytes())?;
+                }
+            }
+            Some(Instr::Opcode(o)) => {
+                opcode(o, labels, w).map_err(|e| format!("{}: {}", line.location, e))?
+            }
             _ => {}
         }
+        addr = line.next_address(addr);
     }
 
     Ok(())
@@ -90,11 +297,19 @@ fn opcode<W: Write>(
 }
 
 fn addr(c: u16, addr: &Addr, labels: &HashMap<String, usize>) -> Result<u16, String> {
-    let a = match addr {
-        Addr::Imm(a) => *a as usize,
-        Addr::LabelRef(s) => *labels.get(s).ok_or(format!("unknown label: '{}'", s))?,
-    };
-    Ok(c | (a & 0xFFF) as u16)
+    let a = resolve(addr, labels)?;
+    Ok(c | (a & 0xFFF))
+}
+
+/// Returns the value of an address, the one of its label for a label reference.
+fn resolve(addr: &Addr, labels: &HashMap<String, usize>) -> Result<u16, String> {
+    match addr {
+        Addr::Imm(a) => Ok(*a),
+        Addr::LabelRef(s) => labels
+            .get(s)
+            .map(|&a| a as u16)
+            .ok_or(format!("unknown label: '{}'", s)),
+    }
 }
 
 fn reg_imm(c: u16, r: VReg, b: u8) -> u16 {
@@ -116,6 +331,128 @@ fn reg(c: u16, r: VReg) -> u16 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parser::parse_source;
+    use std::path::Path;
+
+    #[test]
+    fn oversized_programs_are_reported() {
+        let line = |line, size| Line {
+            label: None,
+            instr: Some(Instr::Data(vec![0; size])),
+            location: Location { file: None, line },
+        };
+        let lines = [line(1, 3000), line(2, 584), line(3, 2), line(4, 10)];
+
+        assert_eq!(check_size(&lines[..2], Platform::Chip8), Ok(3584));
+        assert_eq!(
+            check_size(&lines, Platform::Chip8),
+            Err(Overflow {
+                size: 3596,
+                capacity: 3584,
+                location: Location {
+                    file: None,
+                    line: 3
+                }
+            })
+        );
+        assert_eq!(check_size(&lines, Platform::XoChip), Ok(3596));
+    }
+
+    #[test]
+    fn symbols_are_listed_by_address() {
+        let line = |label: Option<&str>, size| Line {
+            label: label.map(String::from),
+            instr: Some(Instr::Data(vec![0; size])),
+            location: Location::default(),
+        };
+        let lines = [
+            line(Some("start"), 2),
+            line(None, 4),
+            line(Some("b"), 0),
+            line(Some("a"), 1),
+        ];
+
+        let symbols = symbols(&lines).unwrap();
+        assert_eq!(
+            symbols,
+            [
+                (0x200, "start".to_string()),
+                (0x206, "a".to_string()),
+                (0x206, "b".to_string())
+            ]
+        );
+
+        let mut map = vec![];
+        write_symbols(&symbols[..1], &mut map).unwrap();
+        assert_eq!(map, b"0x0200 start\n");
+    }
+
+    #[test]
+    fn org_starts_a_segment() {
+        let source = "  jp next\n  org 0x206\nnext:\n  1, 2\n  org 0x20a\n";
+        let lines = parse_source(source, Path::new("")).unwrap();
+
+        let mut image = vec![];
+        generate(&lines, &mut image).unwrap();
+        assert_eq!(image, [0x12, 0x06, 0, 0, 0, 0, 1, 2, 0, 0]);
+        assert_eq!(check_size(&lines, Platform::Chip8), Ok(10));
+
+        let lines = parse_source("  cls\n  cls\n  org 0x202\n", Path::new("")).unwrap();
+        let err = generate(&lines, &mut vec![]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 3: org 0x202 is before the current address 0x204"
+        );
+    }
+
+    #[test]
+    fn words_reference_labels() {
+        let source = "  dw first, 0x1234, last\nfirst:\n  cls\nlast:\n  ret\n";
+        let lines = parse_source(source, Path::new("")).unwrap();
+
+        let mut image = vec![];
+        generate(&lines, &mut image).unwrap();
+        assert_eq!(
+            image,
+            [0x02, 0x06, 0x12, 0x34, 0x02, 0x08, 0x00, 0xE0, 0x00, 0xEE]
+        );
+
+        let lines = parse_source("  cls\n  dw missing\n", Path::new("")).unwrap();
+        let err = generate(&lines, &mut vec![]).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: unknown label: 'missing'");
+    }
+
+    #[test]
+    fn cross_references_list_definitions_and_references() {
+        let source = "start:\n  call draw\n  jp start\ndraw:\n  ld i, sprite\n  ret\n\
+            unused:\n  ret\nsprite:\n  dw draw\n";
+        let lines = parse_source(source, Path::new("")).unwrap();
+        let xrefs = cross_references(&lines).unwrap();
+
+        let labels: Vec<_> = xrefs.iter().map(|x| x.label.as_str()).collect();
+        assert_eq!(labels, ["start", "draw", "unused", "sprite"]);
+        let line = |line| Location { file: None, line };
+        assert_eq!(xrefs[1].address, 0x204);
+        assert_eq!(xrefs[1].definition, line(4));
+        assert_eq!(xrefs[1].references, [(line(2), 0x200), (line(10), 0x20a)]);
+        assert!(xrefs[2].references.is_empty());
+
+        let mut report = vec![];
+        write_cross_references(&xrefs[2..3], &mut report).unwrap();
+        assert_eq!(report, b"0x0208 unused (line 7)\n    unreferenced\n");
+    }
+
+    #[test]
+    fn images_are_padded_to_size() {
+        let mut image = vec![0x00, 0xE0];
+        pad_image(&mut image, 4, 0xFF).unwrap();
+        assert_eq!(image, [0x00, 0xE0, 0xFF, 0xFF]);
+
+        assert_eq!(
+            pad_image(&mut image, 3, 0).unwrap_err(),
+            "program is 4 bytes, more than the 3 to pad it to"
+        );
+    }
 
     #[test]
     fn test_addr() {
@@ -0,0 +1,31 @@
+use crate::generator::{check_size, generate, symbols, Platform};
+use crate::parser::parse_file;
+use std::error::Error;
+use std::path::Path;
+
+pub mod ast;
+pub mod generator;
+pub mod parser;
+pub mod stdlib;
+
+/// Assembles a source file into a ROM image, which must fit in the memory of a CHIP-8.
+/// Errors are prefixed with the path of the file, eg. `game.c8asm: line 12: ...`.
+pub fn assemble_file<P: AsRef<Path>>(p: P) -> Result<Vec<u8>, Box<dyn Error>> {
+    let path = p.as_ref();
+    assemble(path).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+/// Returns the labels of a source file along with their addresses, in address order.
+pub fn symbols_file<P: AsRef<Path>>(p: P) -> Result<Vec<(u16, String)>, Box<dyn Error>> {
+    let path = p.as_ref();
+    let symbols = parse_file(path).and_then(|lines| Ok(symbols(&lines)?));
+    symbols.map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+fn assemble(path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    let lines = parse_file(path)?;
+    check_size(&lines, Platform::Chip8)?;
+    let mut image = vec![];
+    generate(&lines, &mut image)?;
+    Ok(image)
+}
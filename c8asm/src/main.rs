@@ -1,23 +1,90 @@
-use crate::generator::generate;
-use crate::parser::parse_file;
+use c8asm::generator::{
+    check_size, cross_references, generate, pad_image, symbols, write_cross_references,
+    write_symbols, Platform,
+};
+use c8asm::parser::{parse_file, parse_files};
 use std::env::args;
 use std::error::Error;
+use std::fs;
 use std::fs::File;
 
-mod ast;
-mod generator;
-mod parser;
+const USAGE: &str = "usage: c8asm [--platform chip8|xochip] [--allow-overflow] \
+    [--symbols symbol_file] [--xref xref_file] [--pad-to size [--fill byte]] input_file... output_file";
+
+/// Parses a decimal or hexadecimal (0x...) number.
+fn parse_number(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let mut args = args();
-    let input_file = args.nth(1).ok_or("missing input filename")?;
-    let output_file = args.next().ok_or("missing output filename")?;
-    let r = parse_file(&input_file)?;
+    let mut platform = Platform::Chip8;
+    let mut allow_overflow = false;
+    let mut symbol_file = None;
+    let mut xref_file = None;
+    let mut pad_to = None;
+    let mut fill = 0;
+    let mut files = vec![];
+
+    let mut args = args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--platform" => {
+                platform = match args.next().as_deref() {
+                    Some("chip8") => Platform::Chip8,
+                    Some("xochip") => Platform::XoChip,
+                    _ => return Err(USAGE.into()),
+                }
+            }
+            "--allow-overflow" => allow_overflow = true,
+            "--symbols" => symbol_file = Some(args.next().ok_or(USAGE)?),
+            "--xref" => xref_file = Some(args.next().ok_or(USAGE)?),
+            "--pad-to" => pad_to = Some(args.next().and_then(|s| parse_number(&s)).ok_or(USAGE)?),
+            "--fill" => {
+                fill = args
+                    .next()
+                    .and_then(|s| parse_number(&s))
+                    .and_then(|n| u8::try_from(n).ok())
+                    .ok_or(USAGE)?
+            }
+            _ => files.push(arg),
+        }
+    }
+    let [input_files @ .., output_file] = &files[..] else {
+        return Err(USAGE.into());
+    };
+
+    // several files are linked in one image, in order
+    let r = match input_files {
+        [] => return Err(USAGE.into()),
+        [input_file] => parse_file(input_file)?,
+        _ => parse_files(input_files)?,
+    };
 
     // println!("{:?}", &r);
 
-    let mut output = File::create(&output_file)?;
-    generate(&r, &mut output)?;
+    match check_size(&r, platform) {
+        Err(overflow) if allow_overflow => eprintln!("warning: {}", overflow),
+        Err(overflow) => return Err(overflow.into()),
+        Ok(_) => {}
+    }
+
+    let mut image = vec![];
+    generate(&r, &mut image)?;
+    if let Some(size) = pad_to {
+        pad_image(&mut image, size, fill)?;
+    }
+    fs::write(output_file, image)?;
+
+    if let Some(symbol_file) = symbol_file {
+        write_symbols(&symbols(&r)?, &mut File::create(symbol_file)?)?;
+    }
+
+    if let Some(xref_file) = xref_file {
+        write_cross_references(&cross_references(&r)?, &mut File::create(xref_file)?)?;
+    }
 
     Ok(())
 }
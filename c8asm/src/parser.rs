@@ -1,6 +1,7 @@
-use crate::ast::{Addr, Instr, Line, Opcode, VReg};
+use crate::ast::{Addr, Instr, Line, Location, Opcode, VReg};
+use crate::stdlib;
 use nom::branch::alt;
-use nom::bytes::complete::{tag, tag_no_case, take_while, take_while_m_n};
+use nom::bytes::complete::{is_not, tag, tag_no_case, take_while, take_while_m_n};
 use nom::character::complete::{digit1, hex_digit1, line_ending, not_line_ending, space0, space1};
 use nom::combinator::{all_consuming, map, map_res, opt, peek, recognize, verify};
 use nom::error::ErrorKind;
@@ -13,6 +14,9 @@ use std::fs;
 use std::path::Path;
 use std::str::FromStr;
 
+/// Includes can be nested up to this depth, which stops files including themselves.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
 fn bin_digit1(i: &str) -> IResult<&str, &str> {
     i.split_at_position1_complete(|c| c != '0' && c != '1', ErrorKind::Digit)
 }
@@ -77,6 +81,30 @@ fn data(i: &str) -> IResult<&str, Instr> {
     map(separated_list1(arg_sep, u8), Instr::Data)(i)
 }
 
+fn words(i: &str) -> IResult<&str, Instr> {
+    map(
+        preceded(
+            pair(tag_no_case("dw"), space1),
+            separated_list1(arg_sep, addr),
+        ),
+        Instr::Words,
+    )(i)
+}
+
+fn include(i: &str) -> IResult<&str, Instr> {
+    map(
+        preceded(
+            pair(tag_no_case("include"), space1),
+            delimited(tag("\""), is_not("\"\r\n"), tag("\"")),
+        ),
+        |name: &str| Instr::Include(name.to_owned()),
+    )(i)
+}
+
+fn org(i: &str) -> IResult<&str, Instr> {
+    map(preceded(pair(tag_no_case("org"), space1), u16), Instr::Org)(i)
+}
+
 fn imm_addr(i: &str) -> IResult<&str, Addr> {
     map(u16, Addr::Imm)(i)
 }
@@ -301,7 +329,7 @@ fn opcode(i: &str) -> IResult<&str, Instr> {
 }
 
 fn instr(i: &str) -> IResult<&str, Instr> {
-    terminated(alt((data, opcode)), space0)(i)
+    terminated(alt((include, org, words, data, opcode)), space0)(i)
 }
 
 fn maybe_label(i: &str) -> IResult<&str, Option<String>> {
@@ -319,7 +347,11 @@ fn maybe_comment(i: &str) -> IResult<&str, &str> {
 fn line(i: &str) -> IResult<&str, Line> {
     map(
         terminated(pair(maybe_label, maybe_instr), maybe_comment),
-        |(label, instr)| Line { label, instr },
+        |(label, instr)| Line {
+            label,
+            instr,
+            location: Location::default(),
+        },
     )(i)
 }
 
@@ -330,13 +362,86 @@ fn lines(i: &str) -> IResult<&str, Vec<Line>> {
 fn parse_lines(i: &str) -> Result<Vec<Line>, String> {
     all_consuming(lines)(i)
         .finish()
-        .map(|(_, l)| l)
-        .map_err(|e| e.to_string())
+        .map(|(_, mut lines)| {
+            for (i, l) in lines.iter_mut().enumerate() {
+                l.location.line = i + 1;
+            }
+            lines
+        })
+        .map_err(|e| {
+            // the error points at the remaining input, from the part of the line not understood
+            let offset = i.len() - e.input.len();
+            let line = i[..offset].matches('\n').count() + 1;
+            let near = e.input.lines().next().unwrap_or_default().trim();
+            format!("line {}: syntax error near \"{}\"", line, near)
+        })
+}
+
+/// Parses a source, replacing its `include "file"` lines by the lines of the files,
+/// which are either in the standard library (`std/...`) or relative to `dir`.
+pub fn parse_source(s: &str, dir: &Path) -> Result<Vec<Line>, Box<dyn Error>> {
+    let lines = parse_lines(s)?;
+    Ok(include_files(lines, dir, 0)?)
 }
 
 pub fn parse_file<P: AsRef<Path>>(p: P) -> Result<Vec<Line>, Box<dyn Error>> {
+    let p = p.as_ref();
     let s = fs::read_to_string(p)?;
-    parse_lines(&s).map_err(|e| e.into())
+    parse_source(&s, p.parent().unwrap_or(Path::new("")))
+}
+
+/// Parses several files to assemble them into one image, one after the other,
+/// with the labels of each file visible from the others.
+/// The lines are located by the path of their file.
+pub fn parse_files<P: AsRef<Path>>(paths: &[P]) -> Result<Vec<Line>, Box<dyn Error>> {
+    let mut lines = vec![];
+    for p in paths {
+        let p = p.as_ref();
+        let mut file_lines = parse_file(p).map_err(|e| format!("{}: {}", p.display(), e))?;
+        for l in &mut file_lines {
+            l.location
+                .file
+                .get_or_insert_with(|| p.display().to_string());
+        }
+        lines.append(&mut file_lines);
+    }
+    Ok(lines)
+}
+
+fn include_files(lines: Vec<Line>, dir: &Path, depth: usize) -> Result<Vec<Line>, String> {
+    let mut result = Vec::with_capacity(lines.len());
+
+    for mut line in lines {
+        let Some(Instr::Include(name)) = line.instr.take() else {
+            result.push(line);
+            continue;
+        };
+        if depth == MAX_INCLUDE_DEPTH {
+            return Err(format!("{}: includes nested too deeply", line.location));
+        }
+
+        let (source, included_dir) = match stdlib::source(&name) {
+            Some(source) => (source.to_owned(), dir.to_owned()),
+            None => {
+                let path = dir.join(&name);
+                let source = fs::read_to_string(&path).map_err(|e| {
+                    format!("{}: cannot include \"{}\": {}", line.location, name, e)
+                })?;
+                (source, path.parent().unwrap_or(dir).to_owned())
+            }
+        };
+
+        let mut included = parse_lines(&source).map_err(|e| format!("{} {}", name, e))?;
+        for l in &mut included {
+            l.location.file = Some(name.clone());
+        }
+
+        // a label on the include line points at the first included line
+        result.push(line);
+        result.append(&mut include_files(included, &included_dir, depth + 1)?);
+    }
+
+    Ok(result)
 }
 
 #[cfg(test)]
@@ -352,6 +457,51 @@ mod tests {
         assert_eq!(comment(s), Ok(("\n", " a comment")));
     }
 
+    #[test]
+    fn syntax_errors_have_a_location() {
+        let s = "start:\n  cls\n  jp start\n  ld v0 12\n";
+        assert_eq!(
+            parse_lines(s).unwrap_err(),
+            "line 4: syntax error near \"ld v0 12\""
+        );
+    }
+
+    #[test]
+    fn included_lines_keep_their_location() {
+        let s = "  jp start\n  include \"std/delay.c8asm\"\nstart:\n  cls\n";
+        let lines = parse_source(s, Path::new("")).unwrap();
+
+        assert!(lines.len() > 4);
+        assert_eq!(
+            lines[1].location,
+            Location {
+                file: None,
+                line: 2
+            }
+        );
+        assert!(lines[1].instr.is_none());
+        let delay = lines
+            .iter()
+            .find(|l| l.label.as_deref() == Some("std_delay"));
+        assert_eq!(
+            delay.unwrap().location.file.as_deref(),
+            Some("std/delay.c8asm")
+        );
+        let start = lines.iter().find(|l| l.label.as_deref() == Some("start"));
+        assert_eq!(
+            start.unwrap().location,
+            Location {
+                file: None,
+                line: 3
+            }
+        );
+
+        let err = parse_source("  include \"missing.c8asm\"\n", Path::new("")).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("line 1: cannot include \"missing.c8asm\""));
+    }
+
     #[test]
     fn test_u16s() {
         let s = "0b0011";
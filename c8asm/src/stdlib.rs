@@ -0,0 +1,97 @@
+//! The standard library, a set of routines shipped with the assembler,
+//! which programs include by name, eg. `include "std/bcd.c8asm"`.
+//!
+//! Routines are called with `call`, their labels all start with `std_`,
+//! and the header of each file lists the registers they use.
+
+/// Names and sources of the files of the library.
+pub const FILES: &[(&str, &str)] = &[
+    ("std/bcd.c8asm", include_str!("../std/bcd.c8asm")),
+    ("std/delay.c8asm", include_str!("../std/delay.c8asm")),
+    ("std/keypad.c8asm", include_str!("../std/keypad.c8asm")),
+    ("std/random.c8asm", include_str!("../std/random.c8asm")),
+];
+
+/// Returns the source of a file of the library.
+pub fn source(name: &str) -> Option<&'static str> {
+    FILES.iter().find(|(n, _)| *n == name).map(|(_, s)| *s)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generator::generate;
+    use crate::parser::parse_source;
+    use chip8_system::keyboard::{Key, KeypadState};
+    use chip8_system::machine::Machine;
+    use chip8_system::system::SystemOptions;
+    use std::path::Path;
+
+    /// Runs a program with all the library included after it, and returns its machine.
+    fn machine(program: &str) -> Machine {
+        let mut source = format!("{}\nhalt:\n  jp halt\n", program);
+        for (name, _) in super::FILES {
+            source.push_str(&format!("  include \"{}\"\n", name));
+        }
+        let lines = parse_source(&source, Path::new("")).unwrap();
+        let mut image = vec![];
+        generate(&lines, &mut image).unwrap();
+
+        let opts = SystemOptions::builder()
+            .instructions_per_frame(1000)
+            .build()
+            .unwrap();
+        let mut machine = Machine::new(opts);
+        machine.load_program(&image);
+        machine
+    }
+
+    fn registers(machine: &mut Machine) -> [u8; 16] {
+        machine.system().cpu_state().v
+    }
+
+    #[test]
+    fn numbers_are_printed_in_decimal() {
+        let mut machine = machine("  ld v0, 207\n  ld vA, 1\n  ld vB, 2\n  call std_print_number");
+        let screen = machine.step_frame(KeypadState::default()).unwrap();
+        assert!(screen.pixels().any());
+
+        let v = registers(&mut machine);
+        assert_eq!(v[0..3], [2, 0, 7]);
+        assert_eq!(v[0xA], 16);
+    }
+
+    #[test]
+    fn delays_last_a_number_of_frames() {
+        let mut machine = machine("  ld v0, 3\n  call std_delay\n  ld v5, 1");
+        machine.step_frame(KeypadState::default()).unwrap();
+        assert_eq!(registers(&mut machine)[5], 0);
+
+        for _ in 0..4 {
+            machine.step_frame(KeypadState::default()).unwrap();
+        }
+        assert_eq!(registers(&mut machine)[5], 1);
+    }
+
+    #[test]
+    fn keys_are_polled() {
+        let mut machine = machine("loop:\n  call std_poll_key\n  ld v5, v0\n  jp loop");
+        machine.step_frame(KeypadState::default()).unwrap();
+        assert_eq!(registers(&mut machine)[5], 255);
+
+        let mut keys = KeypadState::default();
+        keys.set(Key::Key7, true);
+        machine.step_frame(keys).unwrap();
+        assert_eq!(registers(&mut machine)[5], 7);
+    }
+
+    #[test]
+    fn random_numbers_depend_on_their_seed() {
+        let mut machine = machine(
+            "  ld v0, 1\n  call std_seed_random\n  call std_random\n  ld v5, v0\n  call std_random\n  ld v6, v0",
+        );
+        machine.step_frame(KeypadState::default()).unwrap();
+
+        let v = registers(&mut machine);
+        assert_eq!(v[5..7], [0xB8, 0x5C]);
+    }
+}
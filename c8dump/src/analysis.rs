@@ -0,0 +1,535 @@
+use chip8_system::cpu::VReg;
+use chip8_system::dialect::Variant;
+use chip8_system::memory::RESERVED_SIZE;
+use chip8_system::opcode::Instr;
+use chip8_system::system::{Quirks, HIRES_CLEAR_DISPLAY, HIRES_STARTUP, HIRES_START_ADDRESS};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Address where programs are loaded.
+pub const START_ADDRESS: u16 = RESERVED_SIZE as u16;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ReferenceKind {
+    Jump,
+    Call,
+    /// The base address of a `jp v0` jump.
+    ComputedJump,
+    LoadI,
+}
+
+impl fmt::Display for ReferenceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReferenceKind::Jump => write!(f, "jump"),
+            ReferenceKind::Call => write!(f, "call"),
+            ReferenceKind::ComputedJump => write!(f, "computed jump"),
+            ReferenceKind::LoadI => write!(f, "ld i"),
+        }
+    }
+}
+
+/// An address used by an instruction.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Reference {
+    /// Address of the instruction.
+    pub from: u16,
+    pub kind: ReferenceKind,
+}
+
+/// Data which is probably a sprite, as I points to it when drawing.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SpriteCandidate {
+    pub address: u16,
+    /// Number of bytes drawn, the largest if drawn several times.
+    pub size: u8,
+    /// Address of the first draw instruction found.
+    pub drawn_from: u16,
+}
+
+/// Something unusual found in the code, which may be a bug or a misinterpretation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Warning {
+    pub address: u16,
+    pub message: String,
+}
+
+/// A clue about the behavior a program expects from an instruction affected by a quirk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuirkHint {
+    pub quirk: Quirks,
+    /// Address of the instruction.
+    pub address: u16,
+    /// Whether the program seems to expect the quirk to be enabled.
+    pub expected: bool,
+    pub reason: &'static str,
+}
+
+/// The last `ld [i], vx` or `ld vx, [i]` executed since I was set.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum RegsAccess {
+    Save,
+    Load,
+}
+
+/// What is known about the state of the system along an execution path.
+#[derive(Copy, Clone, Default)]
+struct PathState {
+    i: Option<u16>,
+    regs_access: Option<RegsAccess>,
+}
+
+impl PathState {
+    fn set_i(self, i: Option<u16>) -> Self {
+        Self {
+            i,
+            regs_access: None,
+        }
+    }
+}
+
+/// What can be found about a ROM without running it.
+/// Only the code reachable from the start address is analyzed.
+#[derive(Debug, Default)]
+pub struct Analysis {
+    /// Number of reachable instructions of each kind.
+    pub histogram: BTreeMap<String, usize>,
+    /// The subroutines by entry point, the main program being the first,
+    /// along with the subroutines they call.
+    pub call_graph: BTreeMap<u16, BTreeSet<u16>>,
+    /// Addresses referenced by the code.
+    pub references: BTreeMap<u16, BTreeSet<Reference>>,
+    pub sprites: Vec<SpriteCandidate>,
+    pub warnings: Vec<Warning>,
+    /// Addresses of the reachable instructions.
+    pub code: BTreeSet<u16>,
+    pub quirk_hints: Vec<QuirkHint>,
+    // registers written by some instruction, and the shifts reading another register
+    written: [bool; 16],
+    shifts: Vec<(u16, VReg)>,
+}
+
+impl Analysis {
+    /// Returns the quirks for which more hints suggest they are expected than not.
+    pub fn suggested_quirks(&self) -> Quirks {
+        let mut quirks = Quirks::empty();
+        for quirk in [Quirks::SHIFT_READS_VX, Quirks::LOAD_STORE_IGNORES_I] {
+            let hints = self.quirk_hints.iter().filter(|h| h.quirk == quirk);
+            let (expected, unexpected): (Vec<_>, Vec<_>) = hints.partition(|h| h.expected);
+            if expected.len() > unexpected.len() {
+                quirks |= quirk;
+            }
+        }
+        quirks
+    }
+
+    fn hint(&mut self, quirk: Quirks, address: u16, expected: bool, reason: &'static str) {
+        let hint = QuirkHint {
+            quirk,
+            address,
+            expected,
+            reason,
+        };
+        if !self.quirk_hints.contains(&hint) {
+            self.quirk_hints.push(hint);
+        }
+    }
+
+    fn warn(&mut self, address: u16, message: impl Into<String>) {
+        let warning = Warning {
+            address,
+            message: message.into(),
+        };
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+
+    fn reference(&mut self, address: u16, from: u16, kind: ReferenceKind) {
+        self.references
+            .entry(address)
+            .or_default()
+            .insert(Reference { from, kind });
+    }
+
+    fn sprite(&mut self, address: u16, size: u8, drawn_from: u16) {
+        match self.sprites.iter_mut().find(|s| s.address == address) {
+            Some(s) => s.size = s.size.max(size),
+            None => self.sprites.push(SpriteCandidate {
+                address,
+                size,
+                drawn_from,
+            }),
+        }
+    }
+}
+
+/// The ROM image, as loaded at the start address.
+struct Rom<'a> {
+    bytes: &'a [u8],
+    /// Whether this is a two-page hires CHIP-8 program, which starts with the patched interpreter.
+    hires: bool,
+}
+
+impl Rom<'_> {
+    /// Returns the address of the first instruction of the program itself.
+    fn entry(&self) -> u16 {
+        match self.hires {
+            true => HIRES_START_ADDRESS,
+            false => START_ADDRESS,
+        }
+    }
+
+    fn end(&self) -> u32 {
+        START_ADDRESS as u32 + self.bytes.len() as u32
+    }
+
+    fn contains(&self, address: u16) -> bool {
+        address >= START_ADDRESS && (address as u32) < self.end()
+    }
+
+    fn read_u16(&self, address: u16) -> Option<u16> {
+        let i = address.checked_sub(START_ADDRESS)? as usize;
+        let b = self.bytes.get(i..i + 2)?;
+        Some(u16::from_be_bytes([b[0], b[1]]))
+    }
+}
+
+/// Analyzes a ROM image, decoding its instructions as `variant` does.
+pub fn analyze(rom: &[u8], variant: Variant) -> Analysis {
+    let rom = Rom {
+        bytes: rom,
+        hires: variant == Variant::Chip8 && rom.starts_with(&HIRES_STARTUP),
+    };
+    let mut analysis = Analysis::default();
+
+    let mut routines = vec![rom.entry()];
+    let mut explored = BTreeSet::new();
+    while let Some(entry) = routines.pop() {
+        if explored.insert(entry) {
+            let calls = explore(&rom, variant, entry, &mut analysis);
+            routines.extend(calls.iter().rev());
+            analysis.call_graph.insert(entry, calls);
+        }
+    }
+
+    let code = analysis.code.clone();
+    for sprite in analysis.sprites.clone() {
+        let end = sprite.address.saturating_add(sprite.size as u16);
+        if code
+            .range(sprite.address.saturating_sub(1)..end)
+            .next()
+            .is_some()
+        {
+            analysis.warn(
+                sprite.address,
+                format!("sprite drawn at {:#05x} overlaps code", sprite.drawn_from),
+            );
+        }
+    }
+    analysis.sprites.sort_by_key(|s| s.address);
+    analysis.warnings.sort_by_key(|w| w.address);
+
+    // a shift reading a register which is never written can only mean to shift VX
+    for (address, y) in std::mem::take(&mut analysis.shifts) {
+        match analysis.written[y as usize] {
+            true => analysis.hint(Quirks::SHIFT_READS_VX, address, false, "shifts VY into VX"),
+            false => analysis.hint(
+                Quirks::SHIFT_READS_VX,
+                address,
+                true,
+                "shifts a VY which is never written",
+            ),
+        }
+    }
+    analysis.quirk_hints.sort_by_key(|h| h.address);
+
+    analysis
+}
+
+/// Follows every path of the routine starting at `entry`, returns the routines it calls.
+fn explore(rom: &Rom, variant: Variant, entry: u16, analysis: &mut Analysis) -> BTreeSet<u16> {
+    let mut calls = BTreeSet::new();
+    let mut visited = BTreeSet::new();
+
+    // the address of the next instruction, along with what is known at that point
+    let mut paths = vec![(entry, PathState::default())];
+    while let Some((pc, state)) = paths.pop() {
+        if !visited.insert(pc) {
+            continue;
+        }
+
+        if !rom.contains(pc) {
+            analysis.warn(pc, "execution goes outside of the ROM");
+            continue;
+        }
+        let Some(opcode) = rom.read_u16(pc) else {
+            analysis.warn(pc, "execution runs past the end of the ROM");
+            continue;
+        };
+        let decoded = match opcode {
+            HIRES_CLEAR_DISPLAY if rom.hires => Some(Instr::ClearDisplay),
+            _ => variant.decode(opcode),
+        };
+        let Some(instr) = decoded else {
+            analysis.warn(pc, format!("invalid opcode {:04X}", opcode));
+            continue;
+        };
+
+        if analysis.code.insert(pc) {
+            *analysis.histogram.entry(mnemonic(&instr)).or_default() += 1;
+            record_registers(&instr, pc, analysis);
+        }
+
+        let next = pc.wrapping_add(2);
+        match instr {
+            Instr::Return => {
+                if entry == rom.entry() {
+                    analysis.warn(pc, "returns from the main program");
+                }
+            }
+            Instr::Jump(target) => {
+                analysis.reference(target, pc, ReferenceKind::Jump);
+                check_target(rom, pc, target, analysis);
+                paths.push((target, state));
+            }
+            Instr::Call(target) => {
+                analysis.reference(target, pc, ReferenceKind::Call);
+                check_target(rom, pc, target, analysis);
+                calls.insert(target);
+                // the subroutine may change I
+                paths.push((next, PathState::default()));
+            }
+            Instr::JumpV0(base) => {
+                analysis.reference(base, pc, ReferenceKind::ComputedJump);
+                analysis.warn(pc, "computed jump, its targets are not analyzed");
+            }
+            Instr::SkipEqImm(..)
+            | Instr::SkipNotEqImm(..)
+            | Instr::SkipEqReg(..)
+            | Instr::SkipNotEqReg(..)
+            | Instr::SkipKeyPressed(_)
+            | Instr::SkipKeyNotPressed(_) => {
+                paths.push((next.wrapping_add(2), state));
+                paths.push((next, state));
+            }
+            Instr::LoadI(address) => {
+                analysis.reference(address, pc, ReferenceKind::LoadI);
+                paths.push((next, state.set_i(Some(address))));
+            }
+            Instr::Draw(_, _, n) => {
+                if let Some(address) = state.i {
+                    // 16x16 sprites of SUPER-CHIP
+                    let size = if n == 0 { 32 } else { n };
+                    analysis.sprite(address, size, pc);
+                }
+                paths.push((next, state));
+            }
+            Instr::SaveRegs(_) | Instr::LoadRegs(_) => {
+                let access = match instr {
+                    Instr::SaveRegs(_) => RegsAccess::Save,
+                    _ => RegsAccess::Load,
+                };
+                hint_regs_access(state.regs_access, access, pc, analysis);
+                let state = PathState {
+                    i: None,
+                    regs_access: Some(access),
+                };
+                paths.push((next, state));
+            }
+            Instr::AddI(_) | Instr::LoadSprite(_) => {
+                paths.push((next, state.set_i(None)));
+            }
+            _ => paths.push((next, state)),
+        }
+    }
+
+    calls
+}
+
+/// Uses of I after a previous save or load, without setting I in between,
+/// tell whether the program expects I to be left unchanged.
+fn hint_regs_access(
+    previous: Option<RegsAccess>,
+    access: RegsAccess,
+    pc: u16,
+    analysis: &mut Analysis,
+) {
+    let quirk = Quirks::LOAD_STORE_IGNORES_I;
+    match (previous, access) {
+        (Some(RegsAccess::Save), RegsAccess::Load) | (Some(RegsAccess::Load), RegsAccess::Save) => {
+            analysis.hint(
+                quirk,
+                pc,
+                true,
+                "reads back the registers just saved, or saves the ones just read",
+            );
+        }
+        (Some(_), _) => {
+            analysis.hint(quirk, pc, false, "accesses consecutive blocks of memory");
+        }
+        (None, _) => {}
+    }
+}
+
+fn record_registers(instr: &Instr, pc: u16, analysis: &mut Analysis) {
+    match *instr {
+        Instr::LoadImm(x, _)
+        | Instr::AddImm(x, _)
+        | Instr::LoadReg(x, _)
+        | Instr::OrReg(x, _)
+        | Instr::AndReg(x, _)
+        | Instr::XorReg(x, _)
+        | Instr::AddReg(x, _)
+        | Instr::SubReg(x, _)
+        | Instr::SubN(x, _)
+        | Instr::Random(x, _)
+        | Instr::LoadDelayTimer(x)
+        | Instr::WaitKeyPress(x) => analysis.written[x as usize] = true,
+        Instr::ShiftRight(x, y) | Instr::ShiftLeft(x, y) => {
+            analysis.written[x as usize] = true;
+            if x != y {
+                analysis.shifts.push((pc, y));
+            }
+        }
+        Instr::LoadRegs(x) => {
+            for r in 0..=x as usize {
+                analysis.written[r] = true;
+            }
+        }
+        _ => {}
+    }
+}
+
+fn check_target(rom: &Rom, pc: u16, target: u16, analysis: &mut Analysis) {
+    if target == pc {
+        // commonly used to stop the program
+        analysis.warn(pc, "infinite loop");
+    } else if !rom.contains(target) {
+        analysis.warn(pc, format!("target {:#05x} is outside of the ROM", target));
+    } else if target % 2 != 0 {
+        analysis.warn(pc, format!("target {:#05x} is not aligned", target));
+    }
+}
+
+/// The name of the kind of an instruction, eg. `Draw` for `drw v0, v1, 5`.
+fn mnemonic(instr: &Instr) -> String {
+    let s = format!("{:?}", instr);
+    match s.find('(') {
+        Some(n) => s[..n].to_string(),
+        None => s,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyze_chip8(rom: &[u8]) -> Analysis {
+        analyze(rom, Variant::Chip8)
+    }
+
+    #[test]
+    fn calls_and_sprites_are_found() {
+        let rom = [
+            0x22, 0x06, // 200: call 206
+            0x12, 0x04, // 202: jp 204
+            0x12, 0x04, // 204: jp 204
+            0xA2, 0x0C, // 206: ld i, 20c
+            0xD0, 0x15, // 208: drw v0, v1, 5
+            0x00, 0xEE, // 20a: ret
+            0xF0, 0x90, 0x90, 0x90, 0xF0, // 20c: sprite
+        ];
+        let a = analyze_chip8(&rom);
+
+        assert_eq!(a.call_graph[&0x200], BTreeSet::from([0x206]));
+        assert!(a.call_graph[&0x206].is_empty());
+        assert_eq!(a.histogram["Jump"], 2);
+        assert_eq!(
+            a.sprites,
+            vec![SpriteCandidate {
+                address: 0x20C,
+                size: 5,
+                drawn_from: 0x208
+            }]
+        );
+        assert_eq!(
+            a.references[&0x206],
+            BTreeSet::from([Reference {
+                from: 0x200,
+                kind: ReferenceKind::Call
+            }])
+        );
+        assert_eq!(a.warnings.len(), 1);
+        assert_eq!(a.warnings[0].message, "infinite loop");
+        assert!(!a.code.contains(&0x20C));
+    }
+
+    #[test]
+    fn quirks_are_suggested() {
+        let rom = [
+            0x60, 0x05, // 200: ld v0, 5
+            0x80, 0x26, // 202: shr v0, v2
+            0xF1, 0x55, // 204: ld [i], v1
+            0xF1, 0x65, // 206: ld v1, [i]
+            0x12, 0x08, // 208: jp 208
+        ];
+        let a = analyze_chip8(&rom);
+
+        assert_eq!(a.quirk_hints.len(), 2);
+        assert!(a.quirk_hints.iter().all(|h| h.expected));
+        assert_eq!(
+            a.suggested_quirks(),
+            Quirks::SHIFT_READS_VX | Quirks::LOAD_STORE_IGNORES_I
+        );
+
+        let rom = [
+            0x60, 0x05, // 200: ld v0, 5
+            0x61, 0x05, // 202: ld v1, 5
+            0x80, 0x16, // 204: shr v0, v1
+            0xF1, 0x55, // 206: ld [i], v1
+            0xF1, 0x55, // 208: ld [i], v1
+            0x12, 0x0A, // 20a: jp 20a
+        ];
+        let a = analyze_chip8(&rom);
+
+        assert_eq!(a.quirk_hints.len(), 2);
+        assert_eq!(a.suggested_quirks(), Quirks::empty());
+    }
+
+    #[test]
+    fn skips_follow_both_paths() {
+        let rom = [
+            0x30, 0x01, // 200: se v0, 1
+            0x12, 0x06, // 202: jp 206
+            0xFF, 0xFF, // 204: invalid
+            0x00, 0xEE, // 206: ret
+        ];
+        let a = analyze_chip8(&rom);
+
+        assert_eq!(a.code.len(), 3);
+        let addresses: Vec<_> = a.warnings.iter().map(|w| w.address).collect();
+        assert_eq!(addresses, vec![0x204, 0x206]);
+    }
+
+    #[test]
+    fn hires_programs_start_after_the_interpreter() {
+        let mut rom = vec![0x12, 0x60]; // 200: jp 260
+        rom.resize(0xC0, 0xFF); // the patched interpreter
+        rom.extend([
+            0x02, 0x30, // 2c0: cls
+            0x12, 0xC2, // 2c2: jp 2c2
+        ]);
+        let a = analyze_chip8(&rom);
+
+        assert_eq!(a.code, BTreeSet::from([0x2C0, 0x2C2]));
+        assert_eq!(a.histogram["ClearDisplay"], 1);
+        assert!(a.call_graph.contains_key(&0x2C0));
+        assert_eq!(a.warnings.len(), 1);
+        assert_eq!(a.warnings[0].message, "infinite loop");
+
+        // other variants have no such interpreter
+        let a = analyze(&rom, Variant::SChip);
+        assert!(a.code.contains(&0x200));
+    }
+}
@@ -0,0 +1,208 @@
+//! Listing of a ROM as source for the assembler.
+
+use crate::analysis::{Analysis, ReferenceKind, START_ADDRESS};
+use crate::project::{Project, RegionKind};
+use chip8_system::dialect::Variant;
+use chip8_system::opcode::Instr;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Maximum number of bytes on a line of data.
+const DATA_PER_LINE: usize = 8;
+
+/// A line of the listing, before the labels are resolved.
+struct Item {
+    address: u16,
+    kind: RegionKind,
+    bytes: Vec<u8>,
+    instr: Option<Instr>,
+}
+
+/// Disassembles a ROM as `c8asm` source.
+///
+/// The regions marked in the project are disassembled as such, the rest is code
+/// when reachable according to the analysis, a sprite when drawn, and data otherwise.
+/// Referenced addresses are labelled, with the names from the project when given.
+pub fn disassemble(rom: &[u8], variant: Variant, analysis: &Analysis, project: &Project) -> String {
+    let kind_at =
+        |address: u16| {
+            project.kind_at(address).unwrap_or_else(|| {
+                if analysis.code.contains(&address) {
+                    RegionKind::Code
+                } else if analysis.sprites.iter().any(|s| {
+                    (s.address..s.address.saturating_add(s.size as u16)).contains(&address)
+                }) {
+                    RegionKind::Sprite
+                } else {
+                    RegionKind::Data
+                }
+            })
+        };
+    // lines must start at the labels
+    let breaks_at = |address: u16| {
+        project.annotation(address).is_some() || analysis.references.contains_key(&address)
+    };
+
+    let mut items: Vec<Item> = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = START_ADDRESS + offset as u16;
+        let kind = kind_at(address);
+        let opcode = rom
+            .get(offset..offset + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]));
+        let instr = match kind {
+            RegionKind::Code => opcode.and_then(|o| variant.decode(o)),
+            _ => None,
+        };
+
+        match instr {
+            Some(instr) => items.push(Item {
+                address,
+                kind,
+                bytes: rom[offset..offset + 2].to_vec(),
+                instr: Some(instr),
+            }),
+            None => {
+                let byte = rom[offset];
+                let kind = match kind {
+                    RegionKind::Code => RegionKind::Data,
+                    kind => kind,
+                };
+                match items.last_mut() {
+                    Some(last)
+                        if last.instr.is_none()
+                            && last.kind == kind
+                            && kind == RegionKind::Data
+                            && last.bytes.len() < DATA_PER_LINE
+                            && !breaks_at(address) =>
+                    {
+                        last.bytes.push(byte)
+                    }
+                    _ => items.push(Item {
+                        address,
+                        kind,
+                        bytes: vec![byte],
+                        instr: None,
+                    }),
+                }
+            }
+        }
+        offset += items.last().map_or(1, |item| match item.instr {
+            Some(_) => 2,
+            None => 1,
+        });
+    }
+
+    let labels = labels(&items, analysis, project);
+    let mut out = String::new();
+    for item in &items {
+        if let Some(comment) = project
+            .annotation(item.address)
+            .and_then(|a| a.comment.as_deref())
+        {
+            for line in comment.lines() {
+                let _ = writeln!(out, "# {}", line);
+            }
+        }
+        if let Some(label) = labels.get(&item.address) {
+            let _ = writeln!(out, "{}:", label);
+        }
+        let (text, note) = match (&item.instr, item.kind) {
+            (Some(instr), _) => (operands(instr, &labels), format!("{:#05x}", item.address)),
+            (None, RegionKind::Sprite) => (
+                format!("{:#04x}", item.bytes[0]),
+                format!("{:#05x} {}", item.address, bits(item.bytes[0])),
+            ),
+            (None, _) => {
+                let bytes: Vec<_> = item.bytes.iter().map(|b| format!("{:#04x}", b)).collect();
+                (bytes.join(", "), format!("{:#05x}", item.address))
+            }
+        };
+        let _ = writeln!(out, "    {:<40} # {}", text, note);
+    }
+    out
+}
+
+/// The labels of the referenced addresses which start a line.
+fn labels(items: &[Item], analysis: &Analysis, project: &Project) -> BTreeMap<u16, String> {
+    items
+        .iter()
+        .filter_map(|item| {
+            let address = item.address;
+            let name = match project.name(address) {
+                Some(name) => name.to_owned(),
+                None => {
+                    let refs = analysis.references.get(&address)?;
+                    let prefix = match refs.iter().map(|r| r.kind).min()? {
+                        ReferenceKind::Call => "sub",
+                        ReferenceKind::Jump | ReferenceKind::ComputedJump => "loc",
+                        ReferenceKind::LoadI => "data",
+                    };
+                    format!("{}_{:03x}", prefix, address)
+                }
+            };
+            Some((address, name))
+        })
+        .collect()
+}
+
+/// Formats an instruction, with the labels of the addresses it uses.
+fn operands(instr: &Instr, labels: &BTreeMap<u16, String>) -> String {
+    let (mnemonic, address) = match *instr {
+        Instr::Jump(a) => ("jp", a),
+        Instr::Call(a) => ("call", a),
+        Instr::LoadI(a) => ("ld i,", a),
+        Instr::JumpV0(a) => ("jp v0,", a),
+        _ => return instr.to_string(),
+    };
+    match labels.get(&address) {
+        Some(label) => format!("{} {}", mnemonic, label),
+        None => instr.to_string(),
+    }
+}
+
+/// Draws the pixels of a byte of a sprite, eg. `..####..`.
+pub fn bits(byte: u8) -> String {
+    (0..8)
+        .map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::analyze;
+
+    #[test]
+    fn annotated_listing() {
+        let rom = [
+            0xA2, 0x08, // ld i, sprite
+            0xD0, 0x11, // drw v0, v1, 1
+            0x12, 0x04, // loop: jp loop
+            0x01, 0x02, // data
+            0x3C, // sprite
+        ];
+        let analysis = analyze(&rom, Variant::Chip8);
+        let mut project = Project::default();
+        project.set_name(0x204, "forever").unwrap();
+        project.set_comment(0x200, "draws a line");
+        project.mark(0x206, 0x208, RegionKind::Data);
+
+        let listing = disassemble(&rom, Variant::Chip8, &analysis, &project);
+        let lines: Vec<_> = listing.lines().map(|l| l.trim_end()).collect();
+        assert_eq!(
+            lines,
+            [
+                "# draws a line",
+                "    ld i, data_208                           # 0x200",
+                "    drw v0, v1, 1                            # 0x202",
+                "forever:",
+                "    jp forever                               # 0x204",
+                "    0x01, 0x02                               # 0x206",
+                "data_208:",
+                "    0x3c                                     # 0x208 ..####..",
+            ]
+        );
+    }
+}
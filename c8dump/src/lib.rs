@@ -0,0 +1,5 @@
+//! Static analysis of CHIP-8 ROM images.
+
+pub mod analysis;
+pub mod disassembly;
+pub mod project;
@@ -0,0 +1,240 @@
+use c8dump::analysis::{analyze, Analysis, START_ADDRESS};
+use c8dump::disassembly::{bits, disassemble};
+use c8dump::project::{Project, RegionKind};
+use chip8_system::dialect::Variant;
+use chip8_system::system::Quirks;
+use std::env::args;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+const USAGE: &str = "usage: c8dump [--disassemble | --interactive] input_file";
+
+const HELP: &str = "\
+code|data|sprite START END  marks the addresses from START up to END
+name ADDRESS [NAME]         names an address, or removes its name
+comment ADDRESS [TEXT]      comments an address, or removes its comment
+list                        prints the disassembly
+quit                        exits, the project being saved after each change";
+
+enum Mode {
+    Report,
+    Disassemble,
+    Interactive,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut mode = Mode::Report;
+    let mut input_file = None;
+    for arg in args().skip(1) {
+        match arg.as_str() {
+            "--disassemble" => mode = Mode::Disassemble,
+            "--interactive" => mode = Mode::Interactive,
+            _ if input_file.is_none() => input_file = Some(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+    let input_file = input_file.ok_or(USAGE)?;
+    let rom = fs::read(&input_file)?;
+    let analysis = analyze(&rom, Variant::default());
+
+    match mode {
+        Mode::Report => {}
+        Mode::Disassemble => {
+            let project = Project::load(Path::new(&input_file))?;
+            print!(
+                "{}",
+                disassemble(&rom, Variant::default(), &analysis, &project)
+            );
+            return Ok(());
+        }
+        Mode::Interactive => return interact(Path::new(&input_file), &rom, &analysis),
+    }
+
+    print_histogram(&analysis);
+    print_call_graph(&analysis);
+    print_references(&analysis);
+    print_sprites(&analysis, &rom);
+    print_warnings(&analysis);
+    print_quirks(&analysis);
+
+    Ok(())
+}
+
+fn print_histogram(a: &Analysis) {
+    println!("Instructions ({} reachable):", a.code.len());
+    let mut counts: Vec<_> = a.histogram.iter().collect();
+    counts.sort_by(|(_, x), (_, y)| y.cmp(x));
+    for (name, count) in counts {
+        println!("  {:<20} {:>5}", name, count);
+    }
+    println!();
+}
+
+fn print_call_graph(a: &Analysis) {
+    println!("Call graph:");
+    for (routine, calls) in &a.call_graph {
+        let calls: Vec<_> = calls.iter().map(|c| format!("{:#05x}", c)).collect();
+        println!("  {:#05x} -> {}", routine, calls.join(", "));
+    }
+    println!();
+}
+
+fn print_references(a: &Analysis) {
+    println!("Referenced addresses:");
+    for (address, refs) in &a.references {
+        let refs: Vec<_> = refs
+            .iter()
+            .map(|r| format!("{} from {:#05x}", r.kind, r.from))
+            .collect();
+        println!("  {:#05x}: {}", address, refs.join(", "));
+    }
+    println!();
+}
+
+fn print_sprites(a: &Analysis, rom: &[u8]) {
+    println!("Sprite candidates:");
+    for sprite in &a.sprites {
+        println!(
+            "  {:#05x}: {} bytes, drawn at {:#05x}",
+            sprite.address, sprite.size, sprite.drawn_from
+        );
+        let Some(start) = sprite.address.checked_sub(START_ADDRESS) else {
+            // eg. the font
+            continue;
+        };
+        let data: Vec<u8> = rom
+            .iter()
+            .skip(start as usize)
+            .take(sprite.size as usize)
+            .copied()
+            .collect();
+        // 16x16 sprites are made of 2 bytes per row
+        let width = if sprite.size == 32 { 2 } else { 1 };
+        for bytes in data.chunks(width) {
+            let row: String = bytes.iter().map(|&b| bits(b)).collect();
+            println!("    {}", row);
+        }
+    }
+    println!();
+}
+
+fn print_warnings(a: &Analysis) {
+    println!("Suspicious code:");
+    for w in &a.warnings {
+        println!("  {:#05x}: {}", w.address, w.message);
+    }
+    println!();
+}
+
+fn print_quirks(a: &Analysis) {
+    println!("Quirk hints:");
+    for h in &a.quirk_hints {
+        let sign = if h.expected { '+' } else { '-' };
+        println!(
+            "  {:#05x}: {} {}: {}",
+            h.address,
+            sign,
+            quirk_flag(h.quirk),
+            h.reason
+        );
+    }
+
+    let flags: Vec<_> = a.suggested_quirks().iter().map(quirk_flag).collect();
+    match flags.is_empty() {
+        true => println!("No quirk suggested"),
+        false => println!("Suggested flags: {}", flags.join(" ")),
+    }
+}
+
+/// The option of the emulator enabling a quirk.
+fn quirk_flag(quirk: Quirks) -> &'static str {
+    if quirk == Quirks::LOAD_STORE_IGNORES_I {
+        "--load-store-ignores-i"
+    } else if quirk == Quirks::LOAD_STORE_INCREMENTS_I_BY_X {
+        "--load-store-increments-i-by-x"
+    } else if quirk == Quirks::JUMP_USES_VX {
+        "--jump-uses-vx"
+    } else if quirk == Quirks::SHIFT_READS_VX {
+        "--shift-reads-vx"
+    } else {
+        "--draw-wraps-pixels"
+    }
+}
+
+/// Reads commands annotating the ROM from the standard input.
+fn interact(path: &Path, rom: &[u8], analysis: &Analysis) -> Result<(), Box<dyn Error>> {
+    let mut project = Project::load(path)?;
+    println!("{}", HELP);
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+        let Some(line) = lines.next().transpose()? else {
+            return Ok(());
+        };
+        let line = line.trim();
+        let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args = args.trim();
+
+        let result = match command {
+            "" => continue,
+            "quit" => return Ok(()),
+            "help" => {
+                println!("{}", HELP);
+                continue;
+            }
+            "list" => {
+                print!(
+                    "{}",
+                    disassemble(rom, Variant::default(), analysis, &project)
+                );
+                continue;
+            }
+            "code" | "data" | "sprite" => {
+                let kind = match command {
+                    "code" => RegionKind::Code,
+                    "data" => RegionKind::Data,
+                    _ => RegionKind::Sprite,
+                };
+                match args.split_whitespace().collect::<Vec<_>>()[..] {
+                    [start, end] => parse_address(start).and_then(|start| {
+                        let end = parse_address(end)?;
+                        if start >= end {
+                            return Err("the end must be after the start".to_owned());
+                        }
+                        project.mark(start, end, kind);
+                        Ok(())
+                    }),
+                    _ => Err(format!("usage: {} START END", command)),
+                }
+            }
+            "name" => {
+                let (address, name) = args.split_once(' ').unwrap_or((args, ""));
+                parse_address(address).and_then(|a| project.set_name(a, name.trim()))
+            }
+            "comment" => {
+                let (address, text) = args.split_once(' ').unwrap_or((args, ""));
+                parse_address(address).map(|a| project.set_comment(a, text))
+            }
+            _ => Err(format!("unknown command: {}, try help", command)),
+        };
+
+        match result {
+            Ok(()) => project.save(path)?,
+            Err(e) => println!("error: {}", e),
+        }
+    }
+}
+
+/// Parses an address, in hexadecimal when prefixed by `0x`.
+fn parse_address(s: &str) -> Result<u16, String> {
+    let address = match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    address.map_err(|_| format!("invalid address: {}", s))
+}
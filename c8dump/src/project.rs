@@ -0,0 +1,257 @@
+//! Annotations of a ROM made while reverse engineering it, which are kept in a TOML file
+//! next to it: `game.c8dump.toml` for `game.ch8`.
+//!
+//! ```toml
+//! [[region]]
+//! start = 0x300
+//! end = 0x320
+//! kind = "sprite"
+//!
+//! [[annotation]]
+//! address = 0x200
+//! name = "main"
+//! comment = "initializes the score"
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How the bytes of a region are to be disassembled.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegionKind {
+    Code,
+    Data,
+    Sprite,
+}
+
+/// Addresses from `start` up to, but not including, `end`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Region {
+    pub start: u16,
+    pub end: u16,
+    pub kind: RegionKind,
+}
+
+/// A name or a comment given to an address.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Annotation {
+    pub address: u16,
+    pub name: Option<String>,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Project {
+    /// Regions marked by hand, sorted and not overlapping.
+    #[serde(default, rename = "region")]
+    regions: Vec<Region>,
+    /// Sorted by address.
+    #[serde(default, rename = "annotation")]
+    annotations: Vec<Annotation>,
+}
+
+impl Project {
+    pub fn path_for(rom: &Path) -> PathBuf {
+        rom.with_extension("c8dump.toml")
+    }
+
+    /// Loads the project of a ROM, which is empty if there is no project file yet.
+    pub fn load(rom: &Path) -> Result<Self, Box<dyn Error>> {
+        let path = Self::path_for(rom);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let mut project: Self = toml::from_str(&fs::read_to_string(&path)?)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        // the file may have been edited by hand
+        let regions = std::mem::take(&mut project.regions);
+        for r in regions {
+            project.mark(r.start, r.end, r.kind);
+        }
+        project.annotations.sort_by_key(|a| a.address);
+        project.annotations.dedup_by_key(|a| a.address);
+        for name in project.annotations.iter().filter_map(|a| a.name.as_deref()) {
+            check_name(name).map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+
+        Ok(project)
+    }
+
+    pub fn save(&self, rom: &Path) -> Result<(), Box<dyn Error>> {
+        fs::write(Self::path_for(rom), toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// Marks the addresses from `start` up to `end` as `kind`,
+    /// replacing what was marked before.
+    pub fn mark(&mut self, start: u16, end: u16, kind: RegionKind) {
+        if start >= end {
+            return;
+        }
+        let mut regions = Vec::with_capacity(self.regions.len() + 2);
+        for r in self.regions.drain(..) {
+            // keep the parts outside of the new region
+            if r.start < start {
+                regions.push(Region {
+                    end: r.end.min(start),
+                    ..r
+                });
+            }
+            if r.end > end {
+                regions.push(Region {
+                    start: r.start.max(end),
+                    ..r
+                });
+            }
+        }
+        regions.push(Region { start, end, kind });
+        regions.sort_by_key(|r| r.start);
+        self.regions = regions;
+    }
+
+    /// Returns the kind of the address, if it has been marked.
+    pub fn kind_at(&self, address: u16) -> Option<RegionKind> {
+        self.regions
+            .iter()
+            .find(|r| (r.start..r.end).contains(&address))
+            .map(|r| r.kind)
+    }
+
+    pub fn annotation(&self, address: u16) -> Option<&Annotation> {
+        self.annotations
+            .binary_search_by_key(&address, |a| a.address)
+            .ok()
+            .map(|i| &self.annotations[i])
+    }
+
+    pub fn name(&self, address: u16) -> Option<&str> {
+        self.annotation(address).and_then(|a| a.name.as_deref())
+    }
+
+    /// Names an address, which is used as a label in the disassembly.
+    /// An empty name removes it.
+    pub fn set_name(&mut self, address: u16, name: &str) -> Result<(), String> {
+        if !name.is_empty() {
+            check_name(name)?;
+            if let Some(other) = self
+                .annotations
+                .iter()
+                .find(|a| a.address != address && a.name.as_deref() == Some(name))
+            {
+                return Err(format!("{} already names {:#05x}", name, other.address));
+            }
+        }
+        self.annotate(address, |a| {
+            a.name = Some(name.to_owned()).filter(|n| !n.is_empty())
+        });
+        Ok(())
+    }
+
+    /// Sets the comment of an address. An empty comment removes it.
+    pub fn set_comment(&mut self, address: u16, comment: &str) {
+        let comment = comment.trim();
+        self.annotate(address, |a| {
+            a.comment = Some(comment.to_owned()).filter(|c| !c.is_empty())
+        });
+    }
+
+    fn annotate(&mut self, address: u16, f: impl FnOnce(&mut Annotation)) {
+        let i = match self
+            .annotations
+            .binary_search_by_key(&address, |a| a.address)
+        {
+            Ok(i) => i,
+            Err(i) => {
+                let a = Annotation {
+                    address,
+                    ..Default::default()
+                };
+                self.annotations.insert(i, a);
+                i
+            }
+        };
+        f(&mut self.annotations[i]);
+        if self.annotations[i].name.is_none() && self.annotations[i].comment.is_none() {
+            self.annotations.remove(i);
+        }
+    }
+}
+
+/// Names must be valid labels for the assembler.
+fn check_name(name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+    let valid = chars.next().map_or(false, char::is_alphabetic)
+        && chars.all(|c| c.is_alphanumeric() || c == '_');
+    match valid {
+        true => Ok(()),
+        false => Err(format!("invalid name: {}", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marking_splits_regions() {
+        let mut p = Project::default();
+        p.mark(0x200, 0x300, RegionKind::Code);
+        p.mark(0x280, 0x290, RegionKind::Sprite);
+        assert_eq!(
+            p.regions(),
+            [
+                Region {
+                    start: 0x200,
+                    end: 0x280,
+                    kind: RegionKind::Code
+                },
+                Region {
+                    start: 0x280,
+                    end: 0x290,
+                    kind: RegionKind::Sprite
+                },
+                Region {
+                    start: 0x290,
+                    end: 0x300,
+                    kind: RegionKind::Code
+                },
+            ]
+        );
+        assert_eq!(p.kind_at(0x28F), Some(RegionKind::Sprite));
+        assert_eq!(p.kind_at(0x300), None);
+    }
+
+    #[test]
+    fn annotations_round_trip() {
+        let mut p = Project::default();
+        p.mark(0x300, 0x310, RegionKind::Data);
+        p.set_name(0x204, "loop").unwrap();
+        p.set_name(0x200, "main").unwrap();
+        p.set_comment(0x200, "starts here");
+        assert!(p.set_name(0x206, "loop").is_err());
+        assert!(p.set_name(0x206, "2x").is_err());
+
+        let text = toml::to_string(&p).unwrap();
+        let q: Project = toml::from_str(&text).unwrap();
+        assert_eq!(p, q);
+        assert_eq!(q.name(0x200), Some("main"));
+        assert_eq!(
+            q.annotation(0x200).unwrap().comment.as_deref(),
+            Some("starts here")
+        );
+
+        p.set_name(0x204, "").unwrap();
+        assert_eq!(p.annotation(0x204), None);
+    }
+}
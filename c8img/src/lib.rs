@@ -0,0 +1,146 @@
+//! Conversion of monochrome images into CHIP-8 sprites.
+
+/// Width of a sprite, in pixels.
+pub const SLICE_WIDTH: usize = 8;
+
+/// Largest height of a sprite, as drawn by DXYN.
+pub const SLICE_MAX_HEIGHT: usize = 15;
+
+/// A monochrome image.
+pub struct Bitmap {
+    width: usize,
+    height: usize,
+    pixels: Vec<bool>,
+}
+
+/// A sprite cut from a bitmap, along with its position in the bitmap.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Slice {
+    pub x: usize,
+    pub y: usize,
+    /// One byte per row.
+    pub rows: Vec<u8>,
+}
+
+impl Bitmap {
+    /// Creates a bitmap from 8-bit grayscale pixels, row by row.
+    /// Pixels lighter than `threshold` are on, or darker ones if `invert` is set.
+    pub fn from_luma(
+        width: usize,
+        height: usize,
+        luma: &[u8],
+        threshold: u8,
+        invert: bool,
+    ) -> Self {
+        let pixels = luma.iter().map(|&l| (l >= threshold) != invert).collect();
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn get(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.pixels[y * self.width + x]
+    }
+
+    /// Cuts the bitmap into sprites of 8 pixels wide and up to 15 pixels high,
+    /// column by column. Blank sprites are left out, as they don't need to be drawn.
+    pub fn slices(&self) -> Vec<Slice> {
+        let mut slices = vec![];
+        for x in (0..self.width).step_by(SLICE_WIDTH) {
+            for y in (0..self.height).step_by(SLICE_MAX_HEIGHT) {
+                let rows: Vec<u8> = (y..self.height.min(y + SLICE_MAX_HEIGHT))
+                    .map(|py| {
+                        (0..SLICE_WIDTH).fold(0, |b, bit| (b << 1) | self.get(x + bit, py) as u8)
+                    })
+                    .collect();
+                if rows.iter().any(|&r| r != 0) {
+                    slices.push(Slice { x, y, rows });
+                }
+            }
+        }
+        slices
+    }
+
+    /// Returns the assembly source of a subroutine named `draw_<label>` drawing the image
+    /// at the top left corner of the screen, followed by the data of its sprites.
+    pub fn to_asm(&self, label: &str) -> String {
+        let slices = self.slices();
+        let name =
+            |s: &Slice| format!("{}_{}_{}", label, s.x / SLICE_WIDTH, s.y / SLICE_MAX_HEIGHT);
+
+        let mut s = format!(
+            "# {}: {}x{} pixels, {} sprites\n",
+            label,
+            self.width,
+            self.height,
+            slices.len()
+        );
+        s += &format!(
+            "# call draw_{} to draw it at (0, 0), v0 and v1 are modified\n",
+            label
+        );
+        s += &format!("draw_{}:\n", label);
+        for slice in &slices {
+            s += &format!("        ld v0, {}\n", slice.x);
+            s += &format!("        ld v1, {}\n", slice.y);
+            s += &format!("        ld i, {}\n", name(slice));
+            s += &format!("        drw v0, v1, {}\n", slice.rows.len());
+        }
+        s += "        ret\n";
+
+        for slice in &slices {
+            s += &format!("\n{}:\n", name(slice));
+            for row in &slice.rows {
+                s += &format!("        0b{:08b}\n", row);
+            }
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bitmap_is_sliced_into_sprites() {
+        // 10x17 image, with a pixel in the top left and bottom right corners
+        let mut luma = vec![0; 10 * 17];
+        luma[0] = 255;
+        luma[10 * 17 - 1] = 255;
+        let bitmap = Bitmap::from_luma(10, 17, &luma, 128, false);
+
+        let slices = bitmap.slices();
+        assert_eq!(slices.len(), 2);
+        assert_eq!(slices[0].rows.len(), 15);
+        assert_eq!(slices[0].rows[0], 0b1000_0000);
+        assert_eq!(
+            slices[1],
+            Slice {
+                x: 8,
+                y: 15,
+                rows: vec![0, 0b0100_0000]
+            }
+        );
+
+        let asm = bitmap.to_asm("logo");
+        assert!(asm.contains("draw_logo:\n        ld v0, 0\n        ld v1, 0\n        ld i, logo_0_0\n        drw v0, v1, 15\n"));
+        assert!(asm.contains("logo_1_1:\n        0b00000000\n        0b01000000\n"));
+    }
+
+    #[test]
+    fn threshold_can_be_inverted() {
+        let bitmap = Bitmap::from_luma(2, 1, &[10, 200], 128, true);
+        assert_eq!(bitmap.slices()[0].rows, vec![0b1000_0000]);
+    }
+}
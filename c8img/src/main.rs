@@ -0,0 +1,59 @@
+use c8img::Bitmap;
+use clap::Parser;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+/// Size of the CHIP-8 screen, larger images can't be drawn at once.
+const SCREEN_SIZE: (usize, usize) = (64, 32);
+
+#[derive(Parser)]
+struct Options {
+    /// Pixels at least this light are on (0 to 255)
+    #[clap(long, short, default_value_t = 128)]
+    threshold: u8,
+
+    /// Turn dark pixels on instead of light ones
+    #[clap(long, short)]
+    invert: bool,
+
+    /// Name of the image in the generated labels
+    #[clap(long, short, default_value = "image")]
+    label: String,
+
+    /// Write the assembly source to this file instead of the standard output
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+
+    /// Set input filename of the image to convert (PNG or BMP)
+    filename: PathBuf,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = Options::parse();
+
+    let image = image::open(&options.filename)?.to_luma8();
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    if width > SCREEN_SIZE.0 || height > SCREEN_SIZE.1 {
+        eprintln!(
+            "Warning: the image is larger than the screen ({}x{})",
+            SCREEN_SIZE.0, SCREEN_SIZE.1
+        );
+    }
+
+    let bitmap = Bitmap::from_luma(
+        width,
+        height,
+        image.as_raw(),
+        options.threshold,
+        options.invert,
+    );
+    let asm = bitmap.to_asm(&options.label);
+
+    match options.output {
+        Some(path) => fs::write(path, asm)?,
+        None => print!("{}", asm),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use crate::sprite::{Sprite, MAX_HEIGHT};
+use druid::widget::{Button, Checkbox, CrossAxisAlignment, Flex, Label, Stepper, TextBox};
+use druid::*;
+
+mod sprite;
+
+/// Size of a pixel of the grid, in logical pixels.
+const PIXEL_SIZE: f64 = 24.0;
+
+/// The pixels of the sprite, which are toggled by clicking them.
+/// Dragging the mouse paints all the pixels it goes over like the first one.
+#[derive(Default)]
+struct PixelGrid {
+    // value painted while the mouse button is held down
+    painting: Option<bool>,
+}
+
+impl PixelGrid {
+    fn pixel_at(pos: Point, data: &Sprite) -> Option<(usize, usize)> {
+        let (x, y) = ((pos.x / PIXEL_SIZE), (pos.y / PIXEL_SIZE));
+        (x >= 0.0 && y >= 0.0 && (x as usize) < data.width() && (y as usize) < data.rows())
+            .then_some((x as usize, y as usize))
+    }
+}
+
+impl Widget<Sprite> for PixelGrid {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut Sprite, _env: &Env) {
+        match event {
+            Event::MouseDown(e) => {
+                if let Some((x, y)) = Self::pixel_at(e.pos, data) {
+                    let on = !data.get(x, y);
+                    data.set(x, y, on);
+                    self.painting = Some(on);
+                    ctx.set_active(true);
+                }
+            }
+            Event::MouseMove(e) if ctx.is_active() => {
+                if let (Some((x, y)), Some(on)) = (Self::pixel_at(e.pos, data), self.painting) {
+                    data.set(x, y, on);
+                }
+            }
+            Event::MouseUp(_) => {
+                self.painting = None;
+                ctx.set_active(false);
+            }
+            _ => {}
+        }
+    }
+
+    fn lifecycle(&mut self, _: &mut LifeCycleCtx, _: &LifeCycle, _: &Sprite, _: &Env) {}
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &Sprite, data: &Sprite, _env: &Env) {
+        if !old_data.same(data) {
+            ctx.request_paint();
+        }
+    }
+
+    fn layout(&mut self, _: &mut LayoutCtx, bc: &BoxConstraints, _: &Sprite, _: &Env) -> Size {
+        let side = PIXEL_SIZE * sprite::LARGE_SIZE as f64;
+        bc.constrain(Size::new(side, side))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &Sprite, _env: &Env) {
+        for y in 0..data.rows() {
+            for x in 0..data.width() {
+                let r = Rect::from_origin_size(
+                    (x as f64 * PIXEL_SIZE, y as f64 * PIXEL_SIZE),
+                    (PIXEL_SIZE, PIXEL_SIZE),
+                )
+                .inset(-1.0);
+                let color = if data.get(x, y) {
+                    Color::WHITE
+                } else {
+                    Color::grey8(0x30)
+                };
+                ctx.fill(r, &color);
+            }
+        }
+    }
+}
+
+fn ui() -> impl Widget<Sprite> {
+    let settings = Flex::row()
+        .with_child(Label::new("Label"))
+        .with_spacer(4.0)
+        .with_child(TextBox::new().lens(Sprite::label))
+        .with_spacer(12.0)
+        .with_child(Checkbox::new("16x16 (SUPER-CHIP)").lens(Sprite::large))
+        .with_spacer(12.0)
+        .with_child(Label::new(|s: &Sprite, _: &_| {
+            format!("Height: {}", s.rows())
+        }))
+        .with_child(
+            Stepper::new()
+                .with_range(1.0, MAX_HEIGHT as f64)
+                .with_step(1.0)
+                .lens(Sprite::height)
+                .disabled_if(|s: &Sprite, _| s.large),
+        );
+
+    let actions = Flex::row()
+        .with_child(Button::new("Clear").on_click(|_, s: &mut Sprite, _| s.clear()))
+        .with_spacer(4.0)
+        .with_child(Button::new("Invert").on_click(|_, s: &mut Sprite, _| s.invert()))
+        .with_spacer(4.0)
+        .with_child(
+            Button::new("Copy assembly").on_click(|_, s: &mut Sprite, _| {
+                Application::global().clipboard().put_string(s.to_asm());
+            }),
+        );
+
+    let assembly = Label::new(|s: &Sprite, _: &_| s.to_asm())
+        .with_font(FontDescriptor::new(FontFamily::MONOSPACE));
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(settings)
+        .with_spacer(8.0)
+        .with_child(PixelGrid::default())
+        .with_spacer(8.0)
+        .with_child(actions)
+        .with_spacer(8.0)
+        .with_child(assembly)
+        .padding(10.0)
+        .scroll()
+        .vertical()
+}
+
+fn main() {
+    let window = WindowDesc::new(ui())
+        .title("CHIP-8 Sprite Editor")
+        .window_size((560.0, 760.0));
+
+    AppLauncher::with_window(window)
+        .launch(Sprite::default())
+        .expect("Unable to start the sprite editor");
+}
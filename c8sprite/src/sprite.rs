@@ -0,0 +1,119 @@
+use druid::{Data, Lens};
+use std::sync::Arc;
+
+/// Largest height of a regular sprite, as drawn by DXYN.
+pub const MAX_HEIGHT: usize = 15;
+
+/// Size of the SUPER-CHIP sprites, drawn by DXY0.
+pub const LARGE_SIZE: usize = 16;
+
+/// A sprite being edited, 8 pixels wide and up to 15 pixels high,
+/// or 16x16 for SUPER-CHIP.
+#[derive(Clone, Data, Lens)]
+pub struct Sprite {
+    /// Label of the sprite in the exported assembly.
+    pub label: String,
+    pub large: bool,
+    /// Height of a regular sprite, as a float for the stepper.
+    pub height: f64,
+    // pixels of a 16x16 sprite, regular sprites use the top left corner
+    pixels: Arc<Vec<bool>>,
+}
+
+impl Default for Sprite {
+    fn default() -> Self {
+        Self {
+            label: "sprite".to_string(),
+            large: false,
+            height: 8.0,
+            pixels: Arc::new(vec![false; LARGE_SIZE * LARGE_SIZE]),
+        }
+    }
+}
+
+impl Sprite {
+    pub fn width(&self) -> usize {
+        if self.large {
+            LARGE_SIZE
+        } else {
+            8
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        if self.large {
+            LARGE_SIZE
+        } else {
+            (self.height as usize).clamp(1, MAX_HEIGHT)
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.pixels[y * LARGE_SIZE + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, on: bool) {
+        Arc::make_mut(&mut self.pixels)[y * LARGE_SIZE + x] = on;
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels = Arc::new(vec![false; LARGE_SIZE * LARGE_SIZE]);
+    }
+
+    /// Inverts the visible pixels.
+    pub fn invert(&mut self) {
+        for y in 0..self.rows() {
+            for x in 0..self.width() {
+                self.set(x, y, !self.get(x, y));
+            }
+        }
+    }
+
+    /// Returns the sprite data, one byte per row, or two for 16x16 sprites.
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        for y in 0..self.rows() {
+            for byte in 0..self.width() / 8 {
+                let b = (0..8).fold(0, |b, bit| (b << 1) | self.get(byte * 8 + bit, y) as u8);
+                bytes.push(b);
+            }
+        }
+        bytes
+    }
+
+    /// Returns the sprite as data lines for the assembler, one line per row.
+    pub fn to_asm(&self) -> String {
+        let mut s = format!("{}:\n", self.label);
+        for row in self.bytes().chunks(self.width() / 8) {
+            let row: Vec<_> = row.iter().map(|b| format!("0b{:08b}", b)).collect();
+            s += &format!("        {}\n", row.join(", "));
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sprite_is_exported_as_data() {
+        let mut sprite = Sprite {
+            height: 2.0,
+            ..Default::default()
+        };
+        sprite.set(0, 0, true);
+        sprite.set(7, 1, true);
+        assert_eq!(sprite.bytes(), vec![0x80, 0x01]);
+        assert_eq!(
+            sprite.to_asm(),
+            "sprite:\n        0b10000000\n        0b00000001\n"
+        );
+
+        sprite.large = true;
+        sprite.set(15, 15, true);
+        let asm = sprite.to_asm();
+        assert_eq!(asm.lines().count(), 17);
+        assert!(asm.ends_with("        0b00000000, 0b00000001\n"));
+    }
+}
@@ -0,0 +1,51 @@
+/// Size in bytes of an XO-CHIP audio pattern.
+pub const PATTERN_SIZE: usize = 16;
+
+/// The XO-CHIP pattern voice: 128 1-bit samples played in a loop
+/// while the sound timer is running, at a rate set by the pitch register.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AudioPattern {
+    pub pattern: [u8; PATTERN_SIZE],
+    pub pitch: u8,
+}
+
+impl Default for AudioPattern {
+    fn default() -> Self {
+        Self {
+            pattern: [0; PATTERN_SIZE],
+            // 4000 Hz
+            pitch: 64,
+        }
+    }
+}
+
+impl AudioPattern {
+    /// Number of samples played per second.
+    pub fn sample_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    /// Returns the sample at position `i`, modulo the pattern length.
+    pub fn sample(&self, i: usize) -> bool {
+        let i = i % (PATTERN_SIZE * 8);
+        self.pattern[i / 8] & (0x80 >> (i % 8)) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pattern_samples_are_read_msb_first() {
+        let mut p = AudioPattern::default();
+        p.pattern[0] = 0b1000_0001;
+
+        assert!(p.sample(0));
+        assert!(!p.sample(1));
+        assert!(p.sample(7));
+        assert!(p.sample(128));
+        assert_eq!(p.sample_rate(), 4000.0);
+    }
+}
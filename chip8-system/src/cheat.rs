@@ -0,0 +1,173 @@
+use crate::memory::Memory;
+#[cfg(feature = "serde")]
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum CheatError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[cfg(feature = "serde")]
+    #[error("{0}")]
+    Parse(#[from] toml::de::Error),
+}
+
+/// A value written in memory, eg. to keep the number of lives of a game at its maximum.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+pub struct Cheat {
+    pub name: String,
+    pub address: u16,
+    pub value: u8,
+    /// Writes the value again at each frame, instead of once when the cheat is enabled.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub freeze: bool,
+    #[cfg_attr(feature = "serde", serde(default = "enabled_by_default"))]
+    pub enabled: bool,
+}
+
+#[cfg(feature = "serde")]
+fn enabled_by_default() -> bool {
+    true
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct CheatFile {
+    #[serde(default)]
+    cheat: Vec<Cheat>,
+}
+
+/// The cheats of a program, which can be toggled while it is running.
+///
+/// They are read from TOML files, with one `[[cheat]]` table per cheat:
+///
+/// ```toml
+/// [[cheat]]
+/// name = "Infinite lives"
+/// address = 0x3a2
+/// value = 3
+/// freeze = true
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+    #[cfg(feature = "serde")]
+    pub fn from_toml(s: &str) -> Result<Self, CheatError> {
+        let file: CheatFile = toml::from_str(s)?;
+        Ok(Self { cheats: file.cheat })
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn load(path: &Path) -> Result<Self, CheatError> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    /// Location of the cheat file of a program, next to it: `game.ch8` has `game.cheats.toml`.
+    pub fn path_for(program: &Path) -> PathBuf {
+        program.with_extension("cheats.toml")
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    /// Enables or disables a cheat, the value of a cheat is written as soon as it is enabled.
+    pub(crate) fn set_enabled(&mut self, index: usize, enabled: bool, memory: &mut Memory) {
+        if let Some(cheat) = self.cheats.get_mut(index) {
+            cheat.enabled = enabled;
+            if enabled {
+                poke(cheat, memory);
+            }
+        }
+    }
+
+    /// Disables all the cheats if any of them is enabled, enables them all otherwise.
+    pub(crate) fn toggle_all(&mut self, memory: &mut Memory) {
+        let enabled = !self.cheats.iter().any(|c| c.enabled);
+        for i in 0..self.cheats.len() {
+            self.set_enabled(i, enabled, memory);
+        }
+    }
+
+    /// Writes the values of all the enabled cheats.
+    pub(crate) fn apply(&self, memory: &mut Memory) {
+        for cheat in self.cheats.iter().filter(|c| c.enabled) {
+            poke(cheat, memory);
+        }
+    }
+
+    /// Writes the values of the enabled cheats which are frozen, once per frame.
+    pub(crate) fn apply_frozen(&self, memory: &mut Memory) {
+        for cheat in self.cheats.iter().filter(|c| c.enabled && c.freeze) {
+            poke(cheat, memory);
+        }
+    }
+}
+
+/// Addresses beyond the end of memory are ignored.
+fn poke(cheat: &Cheat, memory: &mut Memory) {
+    if let Some(byte) = memory.as_bytes_mut().get_mut(cheat.address as usize) {
+        *byte = cheat.value;
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    const CHEATS: &str = r#"
+        [[cheat]]
+        name = "Infinite lives"
+        address = 0x300
+        value = 3
+        freeze = true
+
+        [[cheat]]
+        name = "Level 5"
+        address = 0x301
+        value = 5
+        enabled = false
+    "#;
+
+    #[test]
+    fn cheats_are_parsed() {
+        let cheats = Cheats::from_toml(CHEATS).unwrap();
+
+        assert_eq!(cheats.cheats().len(), 2);
+        assert_eq!(cheats.cheats()[0].address, 0x300);
+        assert!(cheats.cheats()[0].freeze && cheats.cheats()[0].enabled);
+        assert!(!cheats.cheats()[1].freeze && !cheats.cheats()[1].enabled);
+
+        let err = Cheats::from_toml("[[cheat]]\nname = \"x\"\naddress = 0\nvalue = 256\n");
+        assert!(matches!(err, Err(CheatError::Parse(_))));
+    }
+
+    #[test]
+    fn cheats_are_written_when_enabled() {
+        let mut cheats = Cheats::from_toml(CHEATS).unwrap();
+        let mut memory = Memory::new();
+
+        cheats.apply(&mut memory);
+        assert_eq!(memory.as_bytes()[0x300..=0x301], [3, 0]);
+
+        cheats.set_enabled(1, true, &mut memory);
+        assert_eq!(memory.as_bytes()[0x301], 5);
+
+        memory.as_bytes_mut()[0x300..=0x301].copy_from_slice(&[0, 0]);
+        cheats.apply_frozen(&mut memory);
+        assert_eq!(memory.as_bytes()[0x300..=0x301], [3, 0]);
+
+        cheats.toggle_all(&mut memory);
+        assert!(cheats.cheats().iter().all(|c| !c.enabled));
+    }
+}
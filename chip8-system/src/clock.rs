@@ -0,0 +1,166 @@
+//! Sources of time for the system and its timers.
+//!
+//! Systems run on the real time of a `SystemClock`, tests can run them on a `ManualClock`
+//! instead, whose time only moves forward when they advance it.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+pub trait Clock: Send + Sync {
+    /// Returns the time elapsed since the origin of the clock.
+    fn now(&self) -> Duration;
+
+    /// Blocks until the time of the clock reaches `deadline`.
+    fn sleep_until(&self, deadline: Duration);
+}
+
+/// The clock used by default.
+pub fn default_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock::new())
+}
+
+/// Real time, with accurate sleeps.
+pub struct SystemClock {
+    origin: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+
+    fn sleep_until(&self, deadline: Duration) {
+        if let Some(d) = deadline.checked_sub(self.now()) {
+            spin_sleep::sleep(d);
+        }
+    }
+}
+
+/// A clock which only moves forward when advanced, for tests.
+/// Clones share the same time.
+#[derive(Clone, Default)]
+pub struct ManualClock {
+    state: Arc<(Mutex<ManualState>, Condvar)>,
+}
+
+#[derive(Default)]
+struct ManualState {
+    now: Duration,
+    // deadlines of the threads sleeping
+    sleeping: Vec<Duration>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Moves the time forward, waking up the threads sleeping until then.
+    pub fn advance(&self, duration: Duration) {
+        let (state, cond) = &*self.state;
+        state.lock().unwrap().now += duration;
+        cond.notify_all();
+    }
+
+    /// Blocks until `n` threads are sleeping until a time yet to come,
+    /// eg. until a loop is done with the time it has been woken up for.
+    pub fn wait_for_sleepers(&self, n: usize) {
+        let (state, cond) = &*self.state;
+        let _state = cond
+            .wait_while(state.lock().unwrap(), |s| {
+                s.sleeping.iter().filter(|&&d| d > s.now).count() < n
+            })
+            .unwrap();
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.state.0.lock().unwrap().now
+    }
+
+    fn sleep_until(&self, deadline: Duration) {
+        let (state, cond) = &*self.state;
+        let mut s = state.lock().unwrap();
+        s.sleeping.push(deadline);
+        cond.notify_all();
+        s = cond.wait_while(s, |s| s.now < deadline).unwrap();
+        if let Some(i) = s.sleeping.iter().position(|&d| d == deadline) {
+            s.sleeping.swap_remove(i);
+        }
+    }
+}
+
+/// Runs a loop at a fixed rate on a clock, like `spin_sleep::LoopHelper`.
+pub(crate) struct Pacer {
+    clock: Arc<dyn Clock>,
+    period: Duration,
+    last_start: Duration,
+}
+
+impl Pacer {
+    pub fn new(clock: Arc<dyn Clock>, rate: f64) -> Self {
+        let last_start = clock.now();
+        Self {
+            clock,
+            period: Duration::from_secs_f64(1.0 / rate),
+            last_start,
+        }
+    }
+
+    /// Starts an iteration, returns the time elapsed since the start of the previous one.
+    pub fn loop_start(&mut self) -> Duration {
+        let now = self.clock.now();
+        let elapsed = now.saturating_sub(self.last_start);
+        self.last_start = now;
+        elapsed
+    }
+
+    /// Sleeps until the next iteration is due.
+    pub fn loop_sleep(&self) {
+        self.clock.sleep_until(self.last_start + self.period);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn manual_clock_wakes_up_sleepers() {
+        let clock = ManualClock::new();
+        let c = clock.clone();
+        let sleeper = thread::spawn(move || {
+            let mut pacer = Pacer::new(Arc::new(c), 10.0);
+            let mut elapsed = vec![];
+            for _ in 0..3 {
+                pacer.loop_sleep();
+                elapsed.push(pacer.loop_start());
+            }
+            elapsed
+        });
+
+        for _ in 0..3 {
+            clock.wait_for_sleepers(1);
+            clock.advance(Duration::from_millis(100));
+        }
+        let elapsed = sleeper.join().unwrap();
+        assert_eq!(elapsed, [Duration::from_millis(100); 3]);
+        assert_eq!(clock.now(), Duration::from_millis(300));
+    }
+}
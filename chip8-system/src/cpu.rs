@@ -0,0 +1,82 @@
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::{Index, IndexMut};
+use num_derive::FromPrimitive;
+
+#[derive(Copy, Clone, Debug, FromPrimitive, PartialEq)]
+pub enum VReg {
+    V0 = 0x0,
+    V1 = 0x1,
+    V2 = 0x2,
+    V3 = 0x3,
+    V4 = 0x4,
+    V5 = 0x5,
+    V6 = 0x6,
+    V7 = 0x7,
+    V8 = 0x8,
+    V9 = 0x9,
+    VA = 0xa,
+    VB = 0xb,
+    VC = 0xc,
+    VD = 0xd,
+    VE = 0xe,
+    VF = 0xf,
+}
+
+impl fmt::Display for VReg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{:x}", *self as u8)
+    }
+}
+
+pub(crate) type VRegBank = [u8; 16];
+
+impl Index<VReg> for VRegBank {
+    type Output = u8;
+
+    fn index(&self, index: VReg) -> &Self::Output {
+        let idx = index as usize;
+        self.index(idx)
+    }
+}
+
+impl IndexMut<VReg> for VRegBank {
+    fn index_mut(&mut self, index: VReg) -> &mut Self::Output {
+        let idx = index as usize;
+        self.index_mut(idx)
+    }
+}
+
+pub(crate) const STACK_SIZE: usize = 16;
+
+/// The CPU registers.
+#[derive(Clone)]
+pub(crate) struct Cpu {
+    pub pc: u16,
+    pub v: VRegBank,
+    pub i: u16,
+    pub stack: Vec<u16>,
+}
+
+impl Cpu {
+    pub fn new(pc: u16) -> Self {
+        Self {
+            pc,
+            v: Default::default(),
+            i: 0,
+            stack: Vec::with_capacity(STACK_SIZE),
+        }
+    }
+}
+
+/// A snapshot of the state of the CPU, along with the timers.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuState {
+    pub pc: u16,
+    pub i: u16,
+    pub v: [u8; 16],
+    pub stack: Vec<u16>,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
@@ -0,0 +1,130 @@
+//! Settings of known programs, so that they run as intended without choosing
+//! their variant, quirks and speed by hand.
+
+use crate::dialect::Variant;
+use crate::movie::program_hash;
+use crate::system::{Quirks, Speed};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use thiserror::Error;
+
+const DATABASE: &str = include_str!("../rom-database.toml");
+
+#[derive(Debug, Error)]
+pub enum DatabaseError {
+    #[error("{0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("{0}: invalid hash {1:?}")]
+    InvalidHash(String, String),
+    #[error("{0}: unknown quirk {1:?}")]
+    UnknownQuirk(String, String),
+    #[error("{0}: quirks {1:?} are already defined by the {2:?} variant")]
+    ConflictingQuirks(String, Quirks, Variant),
+}
+
+/// The settings a program is meant to run with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomProfile {
+    pub name: String,
+    pub variant: Variant,
+    pub quirks: Quirks,
+    pub speed: Option<Speed>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawProfile {
+    name: String,
+    hash: String,
+    #[serde(default)]
+    variant: Variant,
+    #[serde(default)]
+    quirks: Vec<String>,
+    cpu_frequency: Option<f64>,
+    instructions_per_frame: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct RawDatabase {
+    rom: Vec<RawProfile>,
+}
+
+/// Parses a database, indexed by the hash of the programs.
+fn parse(s: &str) -> Result<HashMap<u64, RomProfile>, DatabaseError> {
+    let raw: RawDatabase = toml::from_str(s)?;
+    raw.rom
+        .into_iter()
+        .map(|r| {
+            let hash = u64::from_str_radix(&r.hash, 16)
+                .map_err(|_| DatabaseError::InvalidHash(r.name.clone(), r.hash.clone()))?;
+            let quirks = r
+                .quirks
+                .iter()
+                .map(|q| {
+                    Quirks::from_name(&q.replace('-', "_").to_uppercase())
+                        .ok_or_else(|| DatabaseError::UnknownQuirk(r.name.clone(), q.clone()))
+                })
+                .collect::<Result<Quirks, _>>()?;
+            let conflicts = quirks & r.variant.quirks();
+            if !conflicts.is_empty() {
+                return Err(DatabaseError::ConflictingQuirks(
+                    r.name, conflicts, r.variant,
+                ));
+            }
+            let speed = match (r.instructions_per_frame, r.cpu_frequency) {
+                (Some(n), _) => Some(Speed::InstructionsPerFrame(n)),
+                (None, f) => f.map(Speed::Frequency),
+            };
+            let profile = RomProfile {
+                name: r.name,
+                variant: r.variant,
+                quirks,
+                speed,
+            };
+            Ok((hash, profile))
+        })
+        .collect()
+}
+
+/// Returns the settings of `program` if it is a known program.
+pub fn lookup(program: &[u8]) -> Option<&'static RomProfile> {
+    static PROFILES: OnceLock<HashMap<u64, RomProfile>> = OnceLock::new();
+    PROFILES
+        .get_or_init(|| parse(DATABASE).expect("invalid ROM database"))
+        .get(&program_hash(program))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn database_is_valid() {
+        assert!(!parse(DATABASE).unwrap().is_empty());
+    }
+
+    #[test]
+    fn known_programs_are_found() {
+        let profile = lookup(include_bytes!("../../examples/octojam2title.c8")).unwrap();
+        assert_eq!(profile.variant, Variant::XoChip);
+        assert_eq!(profile.speed, Some(Speed::InstructionsPerFrame(1000)));
+
+        let profile = lookup(include_bytes!("../../examples/octojam7title.c8")).unwrap();
+        assert_eq!(profile.name, "Octojam 7 title");
+
+        assert_eq!(lookup(&[0x12, 0x00]), None);
+    }
+
+    #[test]
+    fn invalid_entries_are_rejected() {
+        let db = "[[rom]]\nname = \"a\"\nhash = \"0\"\nquirks = [\"unknown\"]\n";
+        assert!(matches!(parse(db), Err(DatabaseError::UnknownQuirk(..))));
+
+        let db = "[[rom]]\nname = \"a\"\nhash = \"0\"\nvariant = \"s-chip\"\nquirks = [\"shift-reads-vx\"]\n";
+        assert!(matches!(
+            parse(db),
+            Err(DatabaseError::ConflictingQuirks(..))
+        ));
+    }
+}
@@ -0,0 +1,519 @@
+use crate::clock::Clock;
+use crate::cpu::CpuState;
+use crate::port::LatestValue;
+use bitvec::prelude::*;
+use crossbeam_channel::{Receiver, Sender};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Number of program counter values kept in the execution history.
+pub(crate) const PC_HISTORY_SIZE: usize = 32;
+
+/// Commands sent by the `SystemController` to a running system.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DebugCommand {
+    Pause,
+    Resume,
+    /// Run until the next display update, then pause.
+    StepFrame,
+    /// Run the next instruction then pause, reporting it as a `DebugEvent::Stepped`.
+    /// Ignored while running.
+    Step,
+    /// Run the next instruction then pause, running a whole subroutine if it is a call.
+    StepOver,
+    /// Run until the current subroutine returns, then pause.
+    /// At the top level, where there is nothing to return from, this runs a single instruction.
+    StepOut,
+    /// Restore the most recent rewind snapshot.
+    Rewind,
+    /// Pause if running, resume if paused.
+    TogglePause,
+    /// Keep a snapshot of the current state in the quick save slot.
+    SaveState,
+    /// Restore the snapshot of the quick save slot, if any.
+    LoadState,
+    /// Run the CPU faster than its configured speed while enabled.
+    Turbo(bool),
+    /// Enable or disable one of the cheats, by index.
+    SetCheat {
+        index: usize,
+        enabled: bool,
+    },
+    /// Disable all the cheats if any is enabled, enable them all otherwise.
+    ToggleCheats,
+    /// Emit the current call stack as a `DebugEvent::CallStack`.
+    CallStack,
+    /// Set or clear a breakpoint: the system pauses before executing the instruction at `address`.
+    SetBreakpoint {
+        address: u16,
+        enabled: bool,
+    },
+    /// Change a register while paused, ignored while running.
+    SetRegister {
+        register: Register,
+        value: u16,
+    },
+    /// Keep a copy of the registers and memory, for a later `DiffState`.
+    MarkState,
+    /// Emit the changes since the last `MarkState` as a `DebugEvent::StateDiff`,
+    /// ignored if no state has been marked.
+    DiffState,
+}
+
+/// A register of the CPU, or a timer, which can be changed by a debugger.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Register {
+    /// One of V0 to VF, by index.
+    V(u8),
+    I,
+    Pc,
+    DelayTimer,
+    SoundTimer,
+}
+
+impl Register {
+    /// All the registers, in the order of `StateDiff::registers`.
+    pub fn all() -> impl Iterator<Item = Register> {
+        (0..16).map(Register::V).chain([
+            Register::I,
+            Register::Pc,
+            Register::DelayTimer,
+            Register::SoundTimer,
+        ])
+    }
+
+    /// Returns the value of the register in a state of the CPU.
+    pub fn read(self, state: &CpuState) -> u16 {
+        match self {
+            Register::V(x) => state.v[x as usize] as u16,
+            Register::I => state.i,
+            Register::Pc => state.pc,
+            Register::DelayTimer => state.delay_timer as u16,
+            Register::SoundTimer => state.sound_timer as u16,
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I => write!(f, "I"),
+            Register::Pc => write!(f, "PC"),
+            Register::DelayTimer => write!(f, "DT"),
+            Register::SoundTimer => write!(f, "ST"),
+        }
+    }
+}
+
+/// Events emitted by a system when its execution state changes.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DebugEvent {
+    /// The system has been paused, `pc` is the address of the next instruction.
+    Paused {
+        pc: u16,
+    },
+    Resumed,
+    /// The system has been paused by the breakpoint at `address`, the next instruction.
+    BreakpointHit {
+        address: u16,
+    },
+    /// A single instruction has been run by `DebugCommand::Step`.
+    Stepped {
+        /// The address of the instruction.
+        address: u16,
+        /// Its code, the first word of it for long XO-CHIP instructions.
+        opcode: u16,
+        /// Its disassembly.
+        instruction: String,
+    },
+    CallStack(CallStack),
+    StateDiff(StateDiff),
+}
+
+/// Execution state of the system, as driven by debug commands.
+pub(crate) struct Debugger {
+    pub command_sender: Sender<DebugCommand>,
+    pub commands: Receiver<DebugCommand>,
+    event_sender: Sender<DebugEvent>,
+    event_receiver: Receiver<DebugEvent>,
+    pub paused: bool,
+    // number of frames sent by the display when the frame step started
+    pub frame_step: Option<u64>,
+    // pause as soon as the call stack is at most this deep
+    pub step_depth: Option<usize>,
+    // pause after the next instruction, for `DebugCommand::Step`
+    pub single_step: bool,
+    pub breakpoints: BTreeSet<u16>,
+    // registers and memory kept by `DebugCommand::MarkState`
+    pub mark: Option<(CpuState, Vec<u8>)>,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        let (command_sender, commands) = crossbeam_channel::unbounded();
+        let (event_sender, event_receiver) = crossbeam_channel::bounded(128);
+        Self {
+            command_sender,
+            commands,
+            event_sender,
+            event_receiver,
+            paused: false,
+            frame_step: None,
+            step_depth: None,
+            single_step: false,
+            breakpoints: BTreeSet::new(),
+            mark: None,
+        }
+    }
+}
+
+impl Debugger {
+    pub fn emit(&self, event: DebugEvent) {
+        let _ = self.event_sender.try_send(event);
+    }
+
+    pub fn output(&self) -> Receiver<DebugEvent> {
+        self.event_receiver.clone()
+    }
+}
+
+/// Interval between two memory views of a running system.
+pub(crate) const MEMORY_VIEW_PERIOD: Duration = Duration::from_micros(16_667);
+
+/// A copy of the memory taken between two instructions, for memory viewers.
+/// The bytes are shared, so views are cheap to clone and to keep.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryView {
+    /// Number of the view, starting at 1.
+    pub sequence: u64,
+    pub bytes: Arc<[u8]>,
+    /// The addresses written by the program since the previous view.
+    pub written: BitVec,
+    pub pc: u16,
+    pub i: u16,
+    /// Return addresses of the call stack, the innermost last.
+    pub stack: Vec<u16>,
+    /// Addresses holding the stack, when it is in memory.
+    pub stack_region: Option<Range<u16>>,
+}
+
+/// Publishes memory views, if enabled by `SystemOptionsBuilder::memory_view`.
+pub(crate) struct MemoryViewer {
+    pub enabled: bool,
+    views: LatestValue<MemoryView>,
+    sequence: u64,
+    written: BitVec,
+    clock: Arc<dyn Clock>,
+    last_view: Option<Duration>,
+}
+
+impl MemoryViewer {
+    pub fn new(enabled: bool, memory_size: usize, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            enabled,
+            views: LatestValue::new(),
+            sequence: 0,
+            written: bitvec![0; memory_size],
+            clock,
+            last_view: None,
+        }
+    }
+
+    /// Records a write to memory, to be reported by the next view.
+    pub fn written(&mut self, addr: u16, len: usize) {
+        if self.enabled {
+            let start = (addr as usize).min(self.written.len());
+            let end = (start + len).min(self.written.len());
+            self.written[start..end].fill(true);
+        }
+    }
+
+    /// Returns true if a running system is due to publish a view.
+    pub fn is_due(&self) -> bool {
+        self.enabled
+            && self
+                .last_view
+                .map_or(true, |t| self.clock.now() - t >= MEMORY_VIEW_PERIOD)
+    }
+
+    pub fn publish(&mut self, mut view: MemoryView) {
+        self.sequence += 1;
+        view.sequence = self.sequence;
+        let len = self.written.len();
+        view.written = std::mem::replace(&mut self.written, bitvec![0; len]);
+        self.views.send(view);
+        self.last_view = Some(self.clock.now());
+    }
+
+    pub fn output(&self) -> Receiver<MemoryView> {
+        self.views.receiver()
+    }
+}
+
+/// Ring buffer of the addresses of the last executed instructions.
+pub(crate) struct PcHistory {
+    pcs: VecDeque<u16>,
+}
+
+impl PcHistory {
+    pub fn new() -> Self {
+        Self {
+            pcs: VecDeque::with_capacity(PC_HISTORY_SIZE),
+        }
+    }
+
+    pub fn push(&mut self, pc: u16) {
+        if self.pcs.len() == PC_HISTORY_SIZE {
+            self.pcs.pop_front();
+        }
+        self.pcs.push_back(pc);
+    }
+
+    /// Returns the recorded addresses, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        self.pcs.iter().copied()
+    }
+}
+
+/// An instruction executed before the current one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub pc: u16,
+    pub opcode: u16,
+    /// Decoded instruction, or `None` if the opcode is not valid.
+    pub disassembly: Option<String>,
+}
+
+/// The last instructions executed by the system, oldest first.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Backtrace {
+    pub frames: Vec<Frame>,
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (n, frame) in self.frames.iter().rev().enumerate() {
+            write!(f, "{:>4}: {:#06x}  {:04X}", n, frame.pc, frame.opcode)?;
+            match &frame.disassembly {
+                Some(instr) => writeln!(f, "  {}", instr)?,
+                None => writeln!(f, "  ???")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A subroutine call in progress.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StackFrame {
+    /// Address of the instruction following the call.
+    pub return_address: u16,
+    /// The return address as `label+offset`, if symbols are loaded.
+    pub symbol: Option<String>,
+}
+
+/// The subroutine calls in progress, innermost first.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CallStack {
+    pub frames: Vec<StackFrame>,
+}
+
+impl fmt::Display for CallStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (n, frame) in self.frames.iter().enumerate() {
+            write!(f, "{:>4}: {:#06x}", n, frame.return_address)?;
+            match &frame.symbol {
+                Some(symbol) => writeln!(f, "  {}", symbol)?,
+                None => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A register whose value differs between two states.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RegisterChange {
+    pub register: Register,
+    pub before: u16,
+    pub after: u16,
+}
+
+/// Consecutive bytes of memory which all differ between two states.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemoryChange {
+    pub address: u16,
+    pub before: Vec<u8>,
+    pub after: Vec<u8>,
+}
+
+impl MemoryChange {
+    /// Addresses of the bytes changed, the end may be past the last address of a 64 KB memory.
+    pub fn range(&self) -> Range<usize> {
+        self.address as usize..self.address as usize + self.after.len()
+    }
+}
+
+/// What changed between two states of the system, eg. what a subroutine modified.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StateDiff {
+    pub registers: Vec<RegisterChange>,
+    pub memory: Vec<MemoryChange>,
+}
+
+impl StateDiff {
+    /// Compares the registers and memory of two states. Only the memory
+    /// both states have is compared, the call stack is left out.
+    pub fn between(
+        before: &CpuState,
+        before_memory: &[u8],
+        after: &CpuState,
+        after_memory: &[u8],
+    ) -> Self {
+        let registers = Register::all()
+            .filter_map(|register| {
+                let (old, new) = (register.read(before), register.read(after));
+                (old != new).then_some(RegisterChange {
+                    register,
+                    before: old,
+                    after: new,
+                })
+            })
+            .collect();
+
+        let mut memory: Vec<MemoryChange> = vec![];
+        for (addr, (&old, &new)) in before_memory.iter().zip(after_memory).enumerate() {
+            if old == new {
+                continue;
+            }
+            match memory.last_mut() {
+                Some(change) if change.range().end == addr => {
+                    change.before.push(old);
+                    change.after.push(new);
+                }
+                _ => memory.push(MemoryChange {
+                    address: addr as u16,
+                    before: vec![old],
+                    after: vec![new],
+                }),
+            }
+        }
+
+        Self { registers, memory }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.registers.is_empty() && self.memory.is_empty()
+    }
+}
+
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        for change in &self.registers {
+            writeln!(
+                f,
+                "{:>4}: {:04X} -> {:04X}",
+                change.register, change.before, change.after
+            )?;
+        }
+        for change in &self.memory {
+            let range = change.range();
+            writeln!(
+                f,
+                "{:#06x}..{:#06x}: {} -> {}",
+                range.start,
+                range.end,
+                hex(&change.before),
+                hex(&change.after)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SymbolsError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("line {0}: expected an address and a label")]
+    Parse(usize),
+}
+
+/// The labels of a program, such as the ones of a c8asm source,
+/// to show addresses as `label+offset`.
+#[derive(Clone, Debug, Default)]
+pub struct Symbols {
+    labels: BTreeMap<u16, String>,
+}
+
+impl Symbols {
+    pub fn new(labels: impl IntoIterator<Item = (u16, String)>) -> Self {
+        Self {
+            labels: labels.into_iter().collect(),
+        }
+    }
+
+    /// Parses a symbol map with one `address label` pair per line, eg. `0x0204 draw_prompt`,
+    /// as written by `c8asm --symbols`.
+    pub fn from_text(s: &str) -> Result<Self, SymbolsError> {
+        let mut labels = BTreeMap::new();
+        for (i, line) in s.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let parsed = line.split_once(' ').and_then(|(addr, label)| {
+                let addr = u16::from_str_radix(addr.trim_start_matches("0x"), 16).ok()?;
+                Some((addr, label.trim().to_string()))
+            });
+            let (addr, label) = parsed.ok_or(SymbolsError::Parse(i + 1))?;
+            labels.insert(addr, label);
+        }
+        Ok(Self { labels })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, SymbolsError> {
+        Self::from_text(&fs::read_to_string(path)?)
+    }
+
+    /// Location of the symbol map of a program, next to it: `game.ch8` has `game.sym`.
+    pub fn path_for(program: &Path) -> PathBuf {
+        program.with_extension("sym")
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// Returns the closest label at or before the address, followed by the offset from it
+    /// if any, eg. `draw_prompt+0x4`.
+    pub fn resolve(&self, addr: u16) -> Option<String> {
+        let (&label_addr, label) = self.labels.range(..=addr).next_back()?;
+        Some(match addr - label_addr {
+            0 => label.clone(),
+            offset => format!("{}+{:#x}", label, offset),
+        })
+    }
+}
@@ -0,0 +1,134 @@
+use bitvec::prelude::*;
+use crossbeam_channel::{Receiver, Sender};
+
+/// Events reporting suspicious behavior of the running program.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Diagnostic {
+    /// The instruction at `pc` wrote `len` bytes at `address`,
+    /// overwriting memory which has already been executed as code.
+    SelfModifyingCode { pc: u16, address: u16, len: u16 },
+}
+
+/// Keeps track of the memory executed as code, to detect when it gets overwritten.
+pub(crate) struct CodeTracker {
+    executed: BitVec,
+    // while a frame is run ahead, the marks before it and the diagnostics held back
+    speculation: Option<(BitVec, Vec<Diagnostic>)>,
+    sender: Sender<Diagnostic>,
+    receiver: Receiver<Diagnostic>,
+}
+
+impl CodeTracker {
+    pub fn new(memory_size: usize) -> Self {
+        let (sender, receiver) = crossbeam_channel::bounded(128);
+        Self {
+            executed: bitvec![0; memory_size],
+            speculation: None,
+            sender,
+            receiver,
+        }
+    }
+
+    /// Marks the instruction at `pc` as executed.
+    pub fn executed(&mut self, pc: u16) {
+        let pc = pc as usize;
+        if let Some(bits) = self.executed.get_mut(pc..pc + 2) {
+            bits.fill(true);
+        }
+    }
+
+    /// Starts tracking a frame run ahead of time, which only counts once `settle` keeps it.
+    pub fn speculate(&mut self) {
+        self.speculation = Some((self.executed.clone(), vec![]));
+    }
+
+    /// Ends the frame run ahead of time: its marks and diagnostics are kept if `keep` is set,
+    /// forgotten otherwise.
+    pub fn settle(&mut self, keep: bool) {
+        if let Some((executed, held)) = self.speculation.take() {
+            if keep {
+                for diagnostic in held {
+                    let _ = self.sender.try_send(diagnostic);
+                }
+            } else {
+                self.executed = executed;
+            }
+        }
+    }
+
+    /// Checks a memory write performed by the instruction at `pc`.
+    pub fn written(&mut self, pc: u16, address: u16, len: usize) {
+        let start = address as usize;
+        let overwrites_code = self
+            .executed
+            .get(start..start + len)
+            .map(|bits| bits.any())
+            .unwrap_or(false);
+
+        if overwrites_code {
+            let diagnostic = Diagnostic::SelfModifyingCode {
+                pc,
+                address,
+                len: len as u16,
+            };
+            match &mut self.speculation {
+                Some((_, held)) => held.push(diagnostic),
+                None => {
+                    let _ = self.sender.try_send(diagnostic);
+                }
+            }
+        }
+    }
+
+    pub fn output(&self) -> Receiver<Diagnostic> {
+        self.receiver.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overwriting_executed_code_is_reported() {
+        let mut t = CodeTracker::new(0x1000);
+        let r = t.output();
+
+        t.executed(0x200);
+        t.executed(0x202);
+
+        t.written(0x202, 0x300, 3);
+        assert!(r.try_recv().is_err());
+
+        t.written(0x202, 0x1FF, 2);
+        assert_eq!(
+            r.try_recv(),
+            Ok(Diagnostic::SelfModifyingCode {
+                pc: 0x202,
+                address: 0x1FF,
+                len: 2
+            })
+        );
+    }
+
+    #[test]
+    fn frames_run_ahead_count_once_kept() {
+        let mut t = CodeTracker::new(0x1000);
+        let r = t.output();
+
+        t.speculate();
+        t.executed(0x300);
+        t.written(0x300, 0x300, 1);
+        assert!(r.try_recv().is_err());
+        t.settle(false);
+        t.written(0x200, 0x300, 1);
+        assert!(r.try_recv().is_err());
+
+        t.speculate();
+        t.executed(0x300);
+        t.written(0x300, 0x300, 1);
+        t.settle(true);
+        assert!(r.try_recv().is_ok());
+    }
+}
@@ -0,0 +1,238 @@
+use crate::cpu::VReg;
+use crate::opcode::{
+    parse_megachip_opcode, parse_opcode, parse_schip_opcode, parse_xo_chip_opcode, Instr,
+};
+use crate::system::{Quirks, System, SystemError, HORIZONTAL_SCROLL};
+use num_traits::FromPrimitive;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// The CHIP-8 instruction set variants supported by the system.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum Variant {
+    /// The original COSMAC VIP interpreter.
+    #[default]
+    Chip8,
+    /// The HP48 interpreter.
+    Chip48,
+    /// SUPER-CHIP 1.1.
+    SChip,
+    /// Octo's XO-CHIP extension.
+    XoChip,
+    /// The MegaChip extension of SUPER-CHIP, with a 256x192 screen of colored sprites.
+    /// Only partly supported: it runs with the whole 64 KiB of memory but no extended memory,
+    /// and blending, screen alpha and digitized sound are ignored.
+    MegaChip,
+}
+
+impl Variant {
+    pub const ALL: [Variant; 5] = [
+        Variant::Chip8,
+        Variant::Chip48,
+        Variant::SChip,
+        Variant::XoChip,
+        Variant::MegaChip,
+    ];
+
+    /// The name of the variant, as serialized, eg. `xo-chip`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Variant::Chip8 => "chip8",
+            Variant::Chip48 => "chip48",
+            Variant::SChip => "s-chip",
+            Variant::XoChip => "xo-chip",
+            Variant::MegaChip => "mega-chip",
+        }
+    }
+
+    /// The quirks whose behavior is the one of this variant, eg. to run its programs
+    /// with the original instruction set.
+    pub fn quirks(self) -> Quirks {
+        self.dialect().defined_quirks()
+    }
+
+    /// Decodes an opcode with the instruction set of this variant.
+    pub fn decode(self, opcode: u16) -> Option<Instr> {
+        self.dialect().decode(opcode)
+    }
+
+    pub(crate) fn dialect(self) -> &'static dyn Dialect {
+        match self {
+            Variant::Chip8 => &Chip8,
+            Variant::Chip48 => &Chip48,
+            Variant::SChip => &SChip,
+            Variant::XoChip => &XoChip,
+            Variant::MegaChip => &MegaChip,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("unknown variant: {0}")]
+pub struct UnknownVariant(pub String);
+
+/// Parses the name of a variant, with or without its hyphen and in any case, eg. `schip`.
+impl FromStr for Variant {
+    type Err = UnknownVariant;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let squashed = |name: &str| name.replace('-', "").to_lowercase();
+        Variant::ALL
+            .into_iter()
+            .find(|v| squashed(v.name()) == squashed(s))
+            .ok_or_else(|| UnknownVariant(s.to_string()))
+    }
+}
+
+/// A dialect defines how opcodes are decoded, and how the instructions
+/// whose behavior differs between variants are executed.
+pub(crate) trait Dialect: Sync {
+    fn decode(&self, opcode: u16) -> Option<Instr> {
+        parse_opcode(opcode)
+    }
+
+    /// Quirks whose behavior is already defined by this dialect.
+    fn defined_quirks(&self) -> Quirks {
+        Quirks::empty()
+    }
+
+    /// Executes the given instruction if its behavior is specific to this dialect.
+    /// Returns `None` if the instruction should be executed with the common behavior.
+    fn execute(&self, _system: &mut System, _instr: &Instr) -> Option<Result<(), SystemError>> {
+        None
+    }
+}
+
+/// The original interpreter, where quirks can be enabled individually.
+struct Chip8;
+
+impl Dialect for Chip8 {}
+
+struct Chip48;
+
+impl Dialect for Chip48 {
+    fn defined_quirks(&self) -> Quirks {
+        Quirks::SHIFT_READS_VX | Quirks::LOAD_STORE_INCREMENTS_I_BY_X | Quirks::JUMP_USES_VX
+    }
+
+    fn execute(&self, system: &mut System, instr: &Instr) -> Option<Result<(), SystemError>> {
+        match *instr {
+            Instr::ShiftRight(x, _) => system.shift_right(x, x),
+            Instr::ShiftLeft(x, _) => system.shift_left(x, x),
+            Instr::JumpV0(nnn) => system.jump_offset(nnn, jump_register(nnn)),
+            // I is left pointing at the last register stored or loaded
+            Instr::SaveRegs(x) => return Some(system.save_regs(x, x as u16)),
+            Instr::LoadRegs(x) => return Some(system.load_regs(x, x as u16)),
+            _ => return None,
+        }
+        Some(Ok(()))
+    }
+}
+
+struct SChip;
+
+impl Dialect for SChip {
+    fn decode(&self, opcode: u16) -> Option<Instr> {
+        parse_schip_opcode(opcode)
+    }
+
+    fn defined_quirks(&self) -> Quirks {
+        Quirks::SHIFT_READS_VX | Quirks::LOAD_STORE_IGNORES_I | Quirks::JUMP_USES_VX
+    }
+
+    fn execute(&self, system: &mut System, instr: &Instr) -> Option<Result<(), SystemError>> {
+        match *instr {
+            Instr::ShiftRight(x, _) => system.shift_right(x, x),
+            Instr::ShiftLeft(x, _) => system.shift_left(x, x),
+            Instr::JumpV0(nnn) => system.jump_offset(nnn, jump_register(nnn)),
+            Instr::SaveRegs(x) => return Some(system.save_regs(x, 0)),
+            Instr::LoadRegs(x) => return Some(system.load_regs(x, 0)),
+            // a sprite of 0 rows is a 16x16 sprite
+            Instr::Draw(x, y, 0) => return Some(system.draw_large(x, y, false)),
+            // scrolls are counted in high resolution pixels, which are halves in low resolution,
+            // odd distances are rounded up to the next whole pixel
+            Instr::ScrollDown(n) if system.is_low_res() => system.scroll_down((n as usize + 1) / 2),
+            Instr::ScrollRight if system.is_low_res() => system.scroll_right(HORIZONTAL_SCROLL / 2),
+            Instr::ScrollLeft if system.is_low_res() => system.scroll_left(HORIZONTAL_SCROLL / 2),
+            _ => return None,
+        }
+        Some(Ok(()))
+    }
+}
+
+struct XoChip;
+
+impl Dialect for XoChip {
+    fn decode(&self, opcode: u16) -> Option<Instr> {
+        parse_xo_chip_opcode(opcode)
+    }
+
+    fn defined_quirks(&self) -> Quirks {
+        Quirks::DRAW_WRAPS_PIXELS
+    }
+
+    fn execute(&self, system: &mut System, instr: &Instr) -> Option<Result<(), SystemError>> {
+        match *instr {
+            Instr::Draw(x, y, n) => Some(system.draw(x, y, n, true)),
+            _ => None,
+        }
+    }
+}
+
+/// SUPER-CHIP, until the program switches the mega mode on with `0011`.
+struct MegaChip;
+
+impl Dialect for MegaChip {
+    fn decode(&self, opcode: u16) -> Option<Instr> {
+        parse_megachip_opcode(opcode)
+    }
+
+    fn defined_quirks(&self) -> Quirks {
+        SChip.defined_quirks()
+    }
+
+    fn execute(&self, system: &mut System, instr: &Instr) -> Option<Result<(), SystemError>> {
+        if !system.is_mega() {
+            return SChip.execute(system, instr);
+        }
+        let scroll = HORIZONTAL_SCROLL as isize;
+        match *instr {
+            // the frame drawn so far is shown, the next one starts blank
+            Instr::ClearDisplay => system.display.flip_mega(),
+            Instr::Draw(x, y, _) => return Some(system.draw_mega(x, y)),
+            Instr::ScrollDown(n) => system.scroll_mega(0, n as isize),
+            Instr::ScrollUp(n) => system.scroll_mega(0, -(n as isize)),
+            Instr::ScrollRight => system.scroll_mega(scroll, 0),
+            Instr::ScrollLeft => system.scroll_mega(-scroll, 0),
+            // the resolution is fixed in mega mode
+            Instr::LowRes | Instr::HighRes => {}
+            _ => return SChip.execute(system, instr),
+        }
+        Some(Ok(()))
+    }
+}
+
+/// BNNN becomes BXNN: the offset register is given by the highest nibble of the address.
+pub(crate) fn jump_register(nnn: u16) -> VReg {
+    VReg::from_u16(nnn >> 8).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_are_parsed_from_variant_names() {
+        assert_eq!("schip".parse::<Variant>().unwrap(), Variant::SChip);
+        assert_eq!("XO-CHIP".parse::<Variant>().unwrap(), Variant::XoChip);
+        assert!("chip9".parse::<Variant>().is_err());
+
+        assert_eq!(Variant::Chip8.quirks(), Quirks::empty());
+        assert_eq!(Variant::XoChip.quirks(), Quirks::DRAW_WRAPS_PIXELS);
+    }
+}
@@ -1,10 +1,32 @@
-use crate::port::OutputPort;
+#[cfg(feature = "std")]
+use crate::clock::{default_clock, Clock};
+#[cfg(feature = "std")]
+use crate::pacing::{FramePacing, PacingRecorder};
+#[cfg(feature = "std")]
+use crate::port::{LatestValue, OutputPort};
+use alloc::vec;
+use alloc::vec::Vec;
 use bitvec::prelude::*;
-use crossbeam_channel::{Receiver, Sender};
+use core::time::Duration;
+#[cfg(feature = "std")]
+use crossbeam_channel::Receiver;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::sync::Arc;
 
 pub const DISPLAY_WIDTH: usize = 64;
 pub const DISPLAY_HEIGHT: usize = 32;
 pub const DISPLAY_BUFFER_SIZE: usize = DISPLAY_WIDTH * DISPLAY_HEIGHT;
+/// Height of the screen of hires CHIP-8 programs, which draw on two pages of 32 rows,
+/// and of SUPER-CHIP programs in high resolution mode.
+pub const HIRES_DISPLAY_HEIGHT: usize = 64;
+/// Width of the screen of SUPER-CHIP programs in high resolution mode.
+pub const EXTENDED_DISPLAY_WIDTH: usize = 128;
+/// Width of the screen of MegaChip programs in mega mode.
+pub const MEGA_DISPLAY_WIDTH: usize = 256;
+/// Height of the screen of MegaChip programs in mega mode.
+pub const MEGA_DISPLAY_HEIGHT: usize = 192;
 
 pub type PixelBuffer = BitVec;
 
@@ -12,102 +34,625 @@ pub fn pixel_buffer() -> PixelBuffer {
     bitvec![0; DISPLAY_BUFFER_SIZE]
 }
 
-pub enum DisplayMessage {
+/// Returns the width and height of a screen from its number of pixels: the usual 64x32 screen,
+/// the 64x64 one of hires CHIP-8 programs, the 128x64 one of SUPER-CHIP programs in high resolution,
+/// or the 256x192 one of MegaChip programs.
+pub fn screen_size(pixels: usize) -> (usize, usize) {
+    let width = match pixels {
+        n if n == EXTENDED_DISPLAY_WIDTH * HIRES_DISPLAY_HEIGHT => EXTENDED_DISPLAY_WIDTH,
+        n if n == MEGA_DISPLAY_WIDTH * MEGA_DISPLAY_HEIGHT => MEGA_DISPLAY_WIDTH,
+        _ => DISPLAY_WIDTH,
+    };
+    (width, pixels / width)
+}
+
+/// An update of the screen, sent by the display on its output port.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DisplayMessage {
+    /// Number of the update, starting at 1 and incremented for each message sent:
+    /// a gap between two received messages means that frames have been dropped.
+    pub sequence: u64,
+    /// Emulation time of the update since the system was started, which excludes pauses.
+    pub time: Duration,
+    pub content: DisplayContent,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DisplayContent {
     Clear,
     Update(PixelBuffer),
+    /// A colored frame of a MegaChip program.
+    Indexed(IndexedFrame),
 }
 
-pub struct DisplayBuffer {
+impl DisplayMessage {
+    /// A blank screen sent outside of any running system, eg. between two runs.
+    /// Its sequence number is 0.
+    pub fn clear() -> Self {
+        Self {
+            sequence: 0,
+            time: Duration::ZERO,
+            content: DisplayContent::Clear,
+        }
+    }
+
+    /// Returns the pixels of the screen after the update.
+    pub fn into_pixels(self) -> PixelBuffer {
+        match self.content {
+            DisplayContent::Clear => pixel_buffer(),
+            DisplayContent::Update(pixels) => pixels,
+            DisplayContent::Indexed(frame) => frame.lit(),
+        }
+    }
+}
+
+/// The pixels of the screen, along with the sprite drawing primitives.
+/// The screen is usually `DISPLAY_WIDTH` by `DISPLAY_HEIGHT` pixels, see `screen_size`.
+#[derive(Clone)]
+pub struct Framebuffer {
     pixels: PixelBuffer,
-    sender: Sender<DisplayMessage>,
-    receiver: Receiver<DisplayMessage>,
+    width: usize,
+    height: usize,
 }
 
-impl Default for DisplayBuffer {
+impl Default for Framebuffer {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl DisplayBuffer {
-    pub(crate) fn new() -> Self {
-        let (s, r) = crossbeam_channel::unbounded();
+impl Framebuffer {
+    pub fn new() -> Self {
+        Self::with_height(DISPLAY_HEIGHT)
+    }
 
+    /// Creates a blank framebuffer with the given number of rows, eg. `HIRES_DISPLAY_HEIGHT`.
+    pub fn with_height(height: usize) -> Self {
+        Self::with_size(DISPLAY_WIDTH, height)
+    }
+
+    /// Creates a blank framebuffer, eg. `EXTENDED_DISPLAY_WIDTH` by `HIRES_DISPLAY_HEIGHT`.
+    pub fn with_size(width: usize, height: usize) -> Self {
         Self {
-            pixels: pixel_buffer(),
-            sender: s,
-            receiver: r,
+            pixels: bitvec![0; width * height],
+            width,
+            height,
         }
     }
 
-    pub(crate) fn clear(&mut self) {
-        self.pixels = pixel_buffer();
-        let _ = self.sender.try_send(DisplayMessage::Clear);
+    pub fn pixels(&self) -> &PixelBuffer {
+        &self.pixels
     }
 
-    pub(crate) fn draw_sprite_clipped(&mut self, (x, y): (u8, u8), sprite: &[u8]) -> bool {
-        let mut collision = false;
-        let x = x as usize % DISPLAY_WIDTH;
-        let y = y as usize % DISPLAY_HEIGHT;
-        for (row, &data) in sprite.iter().enumerate() {
-            let py = y + row;
+    pub fn width(&self) -> usize {
+        self.width
+    }
 
-            // if we go beyond the screen limits, just stop
-            if py >= DISPLAY_HEIGHT {
-                break;
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels = bitvec![0; self.width * self.height];
+    }
+
+    /// Creates a framebuffer from bytes of 8 pixels, the leftmost being the most significant bit,
+    /// row after row, as in the display refresh buffer of the COSMAC VIP.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut framebuffer = Self::new();
+        for (i, &byte) in bytes.iter().take(DISPLAY_BUFFER_SIZE / 8).enumerate() {
+            for bit in 0..8 {
+                framebuffer
+                    .pixels
+                    .set(i * 8 + bit, bit_at(byte, 7 - bit as u8));
             }
+        }
+        framebuffer
+    }
+
+    /// Returns the pixels as bytes, see `from_bytes`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.pixels
+            .chunks(8)
+            .map(|pixels| {
+                pixels
+                    .iter()
+                    .fold(0, |byte, pixel| (byte << 1) | *pixel as u8)
+            })
+            .collect()
+    }
+
+    /// Draws a sprite, pixels going beyond the edges of the screen are not drawn.
+    /// Returns true if a pixel has been erased.
+    pub fn draw_sprite_clipped(&mut self, pos: (u8, u8), sprite: &[u8]) -> bool {
+        self.draw_rows(pos, sprite.iter().map(|&data| (data as u16) << 8), false)
+    }
 
-            for bit in 0..8u8 {
-                let px = x + bit as usize;
-                if px >= DISPLAY_WIDTH {
+    /// Draws a sprite, pixels going beyond the edges of the screen
+    /// wrap around to the opposite side.
+    /// Returns true if a pixel has been erased.
+    pub fn draw_sprite_wrapped(&mut self, pos: (u8, u8), sprite: &[u8]) -> bool {
+        self.draw_rows(pos, sprite.iter().map(|&data| (data as u16) << 8), true)
+    }
+
+    /// Draws a 16x16 SUPER-CHIP sprite, made of 16 rows of 2 bytes,
+    /// clipped or wrapped at the edges of the screen.
+    /// Returns true if a pixel has been erased.
+    pub fn draw_large_sprite(&mut self, pos: (u8, u8), sprite: &[u8], wrap: bool) -> bool {
+        let rows = sprite
+            .chunks(2)
+            .map(|row| u16::from_be_bytes([row[0], row.get(1).copied().unwrap_or_default()]));
+        self.draw_rows(pos, rows, wrap)
+    }
+
+    /// Draws rows of up to 16 pixels, the leftmost being the most significant bit.
+    fn draw_rows(&mut self, (x, y): (u8, u8), rows: impl Iterator<Item = u16>, wrap: bool) -> bool {
+        let mut collision = false;
+        let x = x as usize % self.width;
+        let y = y as usize % self.height;
+        for (row, data) in rows.enumerate() {
+            let mut py = y + row;
+            if py >= self.height {
+                // if we go beyond the screen limits, just stop
+                if !wrap {
                     break;
                 }
-                collision |= self.update_pixel(px, py, bit, data);
+                py %= self.height;
+            }
+
+            for bit in 0..16 {
+                let mut px = x + bit;
+                if px >= self.width {
+                    if !wrap {
+                        break;
+                    }
+                    px %= self.width;
+                }
+                if data & (0x8000 >> bit) != 0 {
+                    collision |= self.flip_pixel(px, py);
+                }
             }
         }
-        let _ = self
-            .sender
-            .try_send(DisplayMessage::Update(self.pixels.clone()));
 
         collision
     }
 
-    pub(crate) fn draw_sprite_wrapped(&mut self, (x, y): (u8, u8), sprite: &[u8]) -> bool {
-        let mut collision = false;
-        for (row, &data) in sprite.iter().enumerate() {
-            let py = (y as usize + row) % DISPLAY_HEIGHT;
-            for bit in 0..8u8 {
-                let px = (x as usize + bit as usize) % DISPLAY_WIDTH;
-                collision |= self.update_pixel(px, py, bit, data);
+    /// Flips a pixel, returns true if it has been switched from 1 to 0,
+    /// which sets the collision flag.
+    fn flip_pixel(&mut self, x: usize, y: usize) -> bool {
+        let i = self.width * y + x;
+        match self.pixels.get_mut(i) {
+            Some(mut pixel) => {
+                let prev = *pixel;
+                *pixel = !prev;
+                prev
             }
+            None => false,
+        }
+    }
+
+    /// Scrolls the screen down by `n` rows, blank rows appearing at the top.
+    pub fn scroll_down(&mut self, n: usize) {
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let pixel = y >= n && self.pixels[(y - n) * self.width + x];
+                self.pixels.set(y * self.width + x, pixel);
+            }
+        }
+    }
+
+    /// Scrolls the screen up by `n` rows, blank rows appearing at the bottom.
+    pub fn scroll_up(&mut self, n: usize) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let pixel = y + n < self.height && self.pixels[(y + n) * self.width + x];
+                self.pixels.set(y * self.width + x, pixel);
+            }
+        }
+    }
+
+    /// Scrolls the screen right by `n` pixels, blank columns appearing on the left.
+    pub fn scroll_right(&mut self, n: usize) {
+        for y in 0..self.height {
+            let row = y * self.width;
+            for x in (0..self.width).rev() {
+                let pixel = x >= n && self.pixels[row + x - n];
+                self.pixels.set(row + x, pixel);
+            }
+        }
+    }
+
+    /// Scrolls the screen left by `n` pixels, blank columns appearing on the right.
+    pub fn scroll_left(&mut self, n: usize) {
+        for y in 0..self.height {
+            let row = y * self.width;
+            for x in 0..self.width {
+                let pixel = x + n < self.width && self.pixels[row + x + n];
+                self.pixels.set(row + x, pixel);
+            }
+        }
+    }
+}
+
+/// A screen of colored pixels, each one an index in a palette of 256 ARGB colors,
+/// as drawn by MegaChip programs. Index 0 is transparent, showing the background.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IndexedFrame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+    pub palette: Vec<u32>,
+}
+
+impl IndexedFrame {
+    /// Creates a blank frame, whose palette maps every index but 0 to opaque white.
+    pub fn new(width: usize, height: usize) -> Self {
+        let mut palette = vec![0xFFFF_FFFF; 256];
+        palette[0] = 0;
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height],
+            palette,
+        }
+    }
+
+    /// Returns the ARGB color of a pixel.
+    pub fn color(&self, i: usize) -> u32 {
+        self.palette[self.pixels[i] as usize]
+    }
+
+    /// Returns the pixels which are not transparent, for monochrome frontends.
+    pub fn lit(&self) -> PixelBuffer {
+        self.pixels.iter().map(|&p| p != 0).collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.fill(0);
+    }
+
+    /// Sets the colors from index 1 on, given as 4 bytes each, alpha, red, green and blue.
+    pub fn load_palette(&mut self, colors: &[u8]) {
+        for (entry, argb) in self.palette[1..].iter_mut().zip(colors.chunks_exact(4)) {
+            *entry = u32::from_be_bytes([argb[0], argb[1], argb[2], argb[3]]);
+        }
+    }
+
+    /// Draws a sprite of `width` by `height` palette indices, row after row. Transparent pixels
+    /// are skipped, and pixels going beyond the edges of the screen are not drawn.
+    /// Returns true if a pixel of the `collision` index has been drawn over.
+    pub fn draw_sprite(
+        &mut self,
+        (x, y): (usize, usize),
+        (width, height): (usize, usize),
+        sprite: &[u8],
+        collision: u8,
+    ) -> bool {
+        let mut collided = false;
+        for (row, data) in sprite.chunks(width).take(height).enumerate() {
+            let py = y + row;
+            if py >= self.height {
+                break;
+            }
+            for (column, &index) in data.iter().enumerate() {
+                let px = x + column;
+                if px >= self.width {
+                    break;
+                }
+                if index != 0 {
+                    let pixel = &mut self.pixels[py * self.width + px];
+                    collided |= *pixel != 0 && *pixel == collision;
+                    *pixel = index;
+                }
+            }
+        }
+        collided
+    }
+
+    /// Moves the pixels by `dx` columns and `dy` rows, the uncovered ones becoming transparent.
+    pub fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.width as isize, self.height as isize);
+        let previous = core::mem::replace(&mut self.pixels, vec![0; self.width * self.height]);
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = (x - dx, y - dy);
+                if (0..width).contains(&sx) && (0..height).contains(&sy) {
+                    self.pixels[(y * width + x) as usize] = previous[(sy * width + sx) as usize];
+                }
+            }
+        }
+    }
+}
+
+/// The screen of a MegaChip program in mega mode: sprites are drawn on a back frame,
+/// which is shown when the program clears the screen.
+#[cfg(feature = "std")]
+#[derive(Clone)]
+pub(crate) struct MegaScreen {
+    pub back: IndexedFrame,
+    pub front: IndexedFrame,
+    /// Width and height of the sprites, set by `03NN` and `04NN`.
+    pub sprite_size: (usize, usize),
+    /// Palette index which sets VF when drawn over, set by `09NN`.
+    pub collision: u8,
+}
+
+#[cfg(feature = "std")]
+impl MegaScreen {
+    fn new() -> Self {
+        let frame = IndexedFrame::new(MEGA_DISPLAY_WIDTH, MEGA_DISPLAY_HEIGHT);
+        Self {
+            back: frame.clone(),
+            front: frame,
+            sprite_size: (1, 1),
+            collision: 0,
+        }
+    }
+}
+
+/// The display of the system, a framebuffer which sends its updates on its output port.
+#[cfg(feature = "std")]
+pub struct DisplayBuffer {
+    framebuffer: Framebuffer,
+    // frames are full snapshots of the screen, so only the latest one matters
+    frames: LatestValue<DisplayMessage>,
+    // when deferred, updates are only sent when the buffer is flushed
+    deferred: bool,
+    dirty: bool,
+    // number of frames sent, for metrics and sequence numbers
+    frames_sent: u64,
+    // emulation time, advanced by the system
+    time: Duration,
+    // sprites erased by drawing them again, with the number of frames sent before
+    erased: HashMap<(u8, u8), u64>,
+    // number of sprites erased then redrawn at the same place, with a frame sent in between
+    flickers: u64,
+    // time between the updates sent, on the clock of the system
+    pacing: PacingRecorder,
+    clock: Arc<dyn Clock>,
+    // the colored screen of a MegaChip program, which replaces the framebuffer while set
+    mega: Option<MegaScreen>,
+}
+
+#[cfg(feature = "std")]
+impl Default for DisplayBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl DisplayBuffer {
+    pub(crate) fn new() -> Self {
+        Self {
+            framebuffer: Framebuffer::new(),
+            frames: LatestValue::new(),
+            deferred: false,
+            dirty: false,
+            frames_sent: 0,
+            time: Duration::ZERO,
+            erased: HashMap::new(),
+            flickers: 0,
+            pacing: PacingRecorder::default(),
+            clock: default_clock(),
+            mega: None,
         }
-        let _ = self
-            .sender
-            .try_send(DisplayMessage::Update(self.pixels.clone()));
+    }
+
+    /// Creates a display buffer which only sends updates when flushed.
+    pub(crate) fn new_deferred() -> Self {
+        Self {
+            deferred: true,
+            ..Self::new()
+        }
+    }
 
+    pub(crate) fn clear(&mut self) {
+        self.framebuffer.clear();
+        self.erased.clear();
+        if self.deferred {
+            self.dirty = true;
+        } else if self.framebuffer.pixels().len() != DISPLAY_BUFFER_SIZE {
+            // a Clear message stands for a screen of the usual size
+            self.send_update();
+        } else {
+            self.send(DisplayContent::Clear);
+        }
+    }
+
+    /// Changes the size of the screen, which is cleared.
+    pub(crate) fn set_size(&mut self, width: usize, height: usize) {
+        self.framebuffer = Framebuffer::with_size(width, height);
+        self.erased.clear();
+        self.updated();
+    }
+
+    /// Sends the current state of a deferred display, if it has been modified.
+    pub(crate) fn flush(&mut self) {
+        if self.dirty {
+            self.dirty = false;
+            self.send_update();
+        }
+    }
+
+    pub(crate) fn draw_sprite_clipped(&mut self, pos: (u8, u8), sprite: &[u8]) -> bool {
+        let before = self.framebuffer.pixels().count_ones();
+        let collision = self.framebuffer.draw_sprite_clipped(pos, sprite);
+        self.track_flicker(pos, before);
+        self.updated();
         collision
     }
 
-    fn update_pixel(&mut self, x: usize, y: usize, bit: u8, data: u8) -> bool {
-        let i = DISPLAY_WIDTH * y + x;
-        if let Some(mut pixel) = self.pixels.get_mut(i) {
-            let prev = *pixel;
-            let sprite = bit_at(data, 7u8 - bit);
-            let new = prev ^ sprite;
-            *pixel = new;
+    pub(crate) fn draw_sprite_wrapped(&mut self, pos: (u8, u8), sprite: &[u8]) -> bool {
+        let before = self.framebuffer.pixels().count_ones();
+        let collision = self.framebuffer.draw_sprite_wrapped(pos, sprite);
+        self.track_flicker(pos, before);
+        self.updated();
+        collision
+    }
 
-            // collision flag set to true if a pixel has been switched
-            // from 1 to 0 during the draw operation
-            prev && !new
+    pub(crate) fn draw_large_sprite(&mut self, pos: (u8, u8), sprite: &[u8], wrap: bool) -> bool {
+        let before = self.framebuffer.pixels().count_ones();
+        let collision = self.framebuffer.draw_large_sprite(pos, sprite, wrap);
+        self.track_flicker(pos, before);
+        self.updated();
+        collision
+    }
+
+    pub(crate) fn scroll_down(&mut self, n: usize) {
+        self.framebuffer.scroll_down(n);
+        self.scrolled();
+    }
+
+    pub(crate) fn scroll_up(&mut self, n: usize) {
+        self.framebuffer.scroll_up(n);
+        self.scrolled();
+    }
+
+    pub(crate) fn scroll_right(&mut self, n: usize) {
+        self.framebuffer.scroll_right(n);
+        self.scrolled();
+    }
+
+    pub(crate) fn scroll_left(&mut self, n: usize) {
+        self.framebuffer.scroll_left(n);
+        self.scrolled();
+    }
+
+    fn scrolled(&mut self) {
+        // the sprites have moved, they are not erased where they were
+        self.erased.clear();
+        self.updated();
+    }
+
+    /// Counts the sprites erased then drawn again at the same place, when the screen has been
+    /// sent without them in between. A draw which only turns pixels off is taken as an erase,
+    /// and one which turns more pixels on than off as a redraw.
+    fn track_flicker(&mut self, pos: (u8, u8), before: usize) {
+        let after = self.framebuffer.pixels().count_ones();
+        if after < before {
+            self.erased.insert(pos, self.frames_sent);
+        } else if after > before {
+            // with a deferred display, the erase may not have been sent yet
+            if matches!(self.erased.remove(&pos), Some(sent) if self.frames_sent > sent) {
+                self.flickers += 1;
+            }
+        }
+    }
+
+    fn updated(&mut self) {
+        if self.deferred {
+            self.dirty = true;
         } else {
-            false
+            self.send_update();
         }
     }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    /// Replaces the whole screen, eg. when restoring a snapshot.
+    pub(crate) fn restore(&mut self, framebuffer: Framebuffer) {
+        self.framebuffer = framebuffer;
+        self.erased.clear();
+        self.updated();
+    }
+
+    /// Switches the MegaChip mode on or off, the screen is cleared either way.
+    pub(crate) fn set_mega(&mut self, enabled: bool) {
+        if enabled {
+            self.mega = Some(MegaScreen::new());
+            self.erased.clear();
+            self.updated();
+        } else {
+            self.mega = None;
+            self.set_size(DISPLAY_WIDTH, DISPLAY_HEIGHT);
+        }
+    }
+
+    pub(crate) fn mega(&self) -> Option<&MegaScreen> {
+        self.mega.as_ref()
+    }
+
+    pub(crate) fn mega_mut(&mut self) -> Option<&mut MegaScreen> {
+        self.mega.as_mut()
+    }
+
+    /// Shows the frame drawn by a MegaChip program, and starts the next one from a blank frame.
+    pub(crate) fn flip_mega(&mut self) {
+        if let Some(mega) = &mut self.mega {
+            mega.front = mega.back.clone();
+            mega.back.clear();
+            self.updated();
+        }
+    }
+
+    /// Replaces the MegaChip screen, eg. when restoring a snapshot.
+    pub(crate) fn restore_mega(&mut self, mega: Option<MegaScreen>) {
+        self.mega = mega;
+    }
+
+    /// Advances the emulation time stamped on the following updates.
+    pub(crate) fn advance_time(&mut self, elapsed: Duration) {
+        self.time += elapsed;
+    }
+
+    /// Returns the number of frames sent, and the number of frames
+    /// which have been replaced by a newer one before being received.
+    pub(crate) fn frame_counters(&self) -> (u64, u64) {
+        (self.frames_sent, self.frames.dropped())
+    }
+
+    /// Returns the number of sprites erased then drawn again at the same place,
+    /// with the screen shown without them in between.
+    pub(crate) fn flickers(&self) -> u64 {
+        self.flickers
+    }
+
+    /// Measures the pacing with another clock than the real time, eg. a `ManualClock` in tests.
+    pub(crate) fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Forgets the time of the last update, eg. when resuming after a pause.
+    pub(crate) fn restart_pacing(&mut self) {
+        self.pacing.restart();
+    }
+
+    /// Emits the pacing of the updates sent since the last report.
+    pub(crate) fn report_pacing(&mut self) {
+        self.pacing.report();
+    }
+
+    pub(crate) fn pacing_output(&self) -> Receiver<FramePacing> {
+        self.pacing.output()
+    }
+
+    fn send_update(&mut self) {
+        let content = match &self.mega {
+            Some(mega) => DisplayContent::Indexed(mega.front.clone()),
+            None => DisplayContent::Update(self.framebuffer.pixels().clone()),
+        };
+        self.send(content);
+    }
+
+    fn send(&mut self, content: DisplayContent) {
+        self.frames_sent += 1;
+        self.pacing.record(self.clock.now());
+        self.frames.send(DisplayMessage {
+            sequence: self.frames_sent,
+            time: self.time,
+            content,
+        });
+    }
 }
 
+#[cfg(feature = "std")]
 impl OutputPort<DisplayMessage> for DisplayBuffer {
     fn output(&self) -> Receiver<DisplayMessage> {
-        self.receiver.clone()
+        self.frames.receiver()
     }
 }
 
@@ -140,6 +685,8 @@ fn debug_pixels() -> PixelBuffer {
 }
 
 pub const FONT_SPRITES_ADDRESS: u16 = 0;
+/// Address of the 8x10 digits of SUPER-CHIP, after the 4x5 ones.
+pub const LARGE_FONT_SPRITES_ADDRESS: u16 = 0x50;
 
 #[rustfmt::skip]
 pub fn font_sprites() -> &'static [u8] {
@@ -243,6 +790,29 @@ pub fn font_sprites() -> &'static [u8] {
     ]
 }
 
+/// The 8x10 digits of SUPER-CHIP 1.1, 10 bytes each, followed by A to F in the same style.
+#[rustfmt::skip]
+pub fn large_font_sprites() -> &'static [u8] {
+    &[
+        0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+        0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+        0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+        0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+        0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+        0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+        0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+        0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+        0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+        0x3C, 0x7E, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, // A
+        0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, // B
+        0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, // C
+        0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xFF, 0xFF, // E
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xC0, 0xC0, // F
+    ]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -255,4 +825,98 @@ mod tests {
         assert!(!bit_at(a, 2));
         assert!(bit_at(a, 3));
     }
+
+    #[test]
+    fn framebuffer_bytes_round_trip() {
+        let mut fb = Framebuffer::new();
+        fb.draw_sprite_clipped((9, 1), &[0b1100_0001]);
+        let bytes = fb.to_bytes();
+        assert_eq!(bytes.len(), 256);
+        assert_eq!(bytes[9], 0b0110_0000);
+        assert_eq!(bytes[10], 0b1000_0000);
+        assert_eq!(Framebuffer::from_bytes(&bytes).pixels(), fb.pixels());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn flickers_are_counted_when_shown() {
+        let sprite = [0xF0, 0x90, 0xF0];
+        let mut display = DisplayBuffer::new();
+        display.draw_sprite_clipped((8, 4), &sprite);
+        for _ in 0..3 {
+            display.draw_sprite_clipped((8, 4), &sprite);
+            display.draw_sprite_clipped((8, 4), &sprite);
+        }
+        // moving a sprite isn't flickering
+        display.draw_sprite_clipped((8, 4), &sprite);
+        display.draw_sprite_clipped((9, 4), &sprite);
+        assert_eq!(display.flickers(), 3);
+
+        // the erased screen is never sent when redrawn within the same frame
+        let mut display = DisplayBuffer::new_deferred();
+        display.draw_sprite_clipped((8, 4), &sprite);
+        display.draw_sprite_clipped((8, 4), &sprite);
+        display.draw_sprite_clipped((8, 4), &sprite);
+        assert_eq!(display.flickers(), 0);
+        display.draw_sprite_clipped((8, 4), &sprite);
+        display.flush();
+        display.draw_sprite_clipped((8, 4), &sprite);
+        assert_eq!(display.flickers(), 1);
+    }
+
+    #[test]
+    fn hires_framebuffer_draws_on_both_pages() {
+        let mut fb = Framebuffer::with_height(HIRES_DISPLAY_HEIGHT);
+        fb.draw_sprite_clipped((0, 62), &[0x80, 0x80, 0x80]);
+        assert!(fb.pixels()[62 * DISPLAY_WIDTH]);
+        assert!(fb.pixels()[63 * DISPLAY_WIDTH]);
+        assert_eq!(fb.pixels().count_ones(), 2);
+
+        fb.draw_sprite_wrapped((0, 63), &[0x80, 0x80]);
+        assert!(!fb.pixels()[63 * DISPLAY_WIDTH]);
+        assert!(fb.pixels()[0]);
+
+        fb.clear();
+        assert_eq!(fb.pixels().len(), DISPLAY_WIDTH * HIRES_DISPLAY_HEIGHT);
+    }
+
+    #[test]
+    fn large_sprites_are_drawn_and_scrolled() {
+        let mut fb = Framebuffer::with_size(EXTENDED_DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT);
+        assert_eq!(screen_size(fb.pixels().len()), (128, 64));
+        let sprite = [0x80, 0x01].repeat(16);
+        assert!(!fb.draw_large_sprite((120, 0), &sprite, false));
+        assert!(fb.pixels()[120]);
+        assert!(!fb.pixels()[127]);
+        assert_eq!(fb.pixels().count_ones(), 16);
+
+        fb.scroll_down(4);
+        assert!(!fb.pixels()[120]);
+        assert!(fb.pixels()[4 * EXTENDED_DISPLAY_WIDTH + 120]);
+        fb.scroll_right(4);
+        assert!(fb.pixels()[4 * EXTENDED_DISPLAY_WIDTH + 124]);
+        fb.scroll_left(8);
+        assert!(fb.pixels()[4 * EXTENDED_DISPLAY_WIDTH + 116]);
+        assert_eq!(fb.pixels().count_ones(), 12);
+    }
+
+    #[test]
+    fn indexed_frames_draw_opaque_pixels() {
+        let mut frame = IndexedFrame::new(MEGA_DISPLAY_WIDTH, MEGA_DISPLAY_HEIGHT);
+        frame.load_palette(&[0xFF, 0x12, 0x34, 0x56, 0x80, 0xFF, 0x00, 0x00]);
+        assert_eq!(frame.palette[1..3], [0xFF12_3456, 0x80FF_0000]);
+
+        // a 2x2 sprite whose top right pixel is transparent
+        assert!(!frame.draw_sprite((254, 0), (2, 2), &[1, 0, 2, 2], 2));
+        assert_eq!(frame.color(254), 0xFF12_3456);
+        assert_eq!(frame.pixels[255], 0);
+        assert!(frame.draw_sprite((255, 1), (2, 2), &[1, 1, 1, 1], 2));
+        assert_eq!(frame.lit().count_ones(), 4);
+
+        // the rows below 191 are scrolled out
+        frame.scroll(0, 190);
+        assert_eq!(frame.pixels[191 * MEGA_DISPLAY_WIDTH + 254], 2);
+        assert_eq!(frame.lit().count_ones(), 2);
+        assert_eq!(screen_size(frame.pixels.len()), (256, 192));
+    }
 }
@@ -0,0 +1,51 @@
+//! All the events of a system on a single output port, for frontends which would rather
+//! consume one channel than connect each port of the system on its own.
+
+use crate::debug::DebugEvent;
+use crate::display::DisplayMessage;
+use crate::metrics::SystemMetrics;
+use crate::timer::TimerMessage;
+
+/// An event of a system, as sent on its `OutputPort<SystemEvent>`.
+///
+/// The events are taken from the same channels as the separate ports,
+/// so a frontend uses either this port or the separate ones.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SystemEvent {
+    Display(DisplayMessage),
+    /// The sound timer has started or stopped, the beeper should follow it.
+    Timer(TimerMessage),
+    Metrics(SystemMetrics),
+    Debug(DebugEvent),
+    /// The program has exited on its own with the SUPER-CHIP `00FD` instruction,
+    /// followed by `Halted` without an error.
+    Exited,
+    /// The system has stopped running, with the error which stopped it, if any.
+    Halted {
+        error: Option<String>,
+    },
+}
+
+impl From<DisplayMessage> for SystemEvent {
+    fn from(m: DisplayMessage) -> Self {
+        Self::Display(m)
+    }
+}
+
+impl From<TimerMessage> for SystemEvent {
+    fn from(m: TimerMessage) -> Self {
+        Self::Timer(m)
+    }
+}
+
+impl From<SystemMetrics> for SystemEvent {
+    fn from(m: SystemMetrics) -> Self {
+        Self::Metrics(m)
+    }
+}
+
+impl From<DebugEvent> for SystemEvent {
+    fn from(e: DebugEvent) -> Self {
+        Self::Debug(e)
+    }
+}
@@ -1,17 +1,32 @@
-use crate::port::InputPort;
-use crossbeam_channel::{select, Receiver, Sender};
+use crate::clock::{default_clock, Clock};
+use crate::port::{InputPort, LatestValue, OutputPort};
+use crossbeam_channel::{after, never, select, Receiver, Sender};
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
-use std::sync::{Arc, Mutex, RwLock};
-use std::thread;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The keys held down, and the keys pressed since the last poll, of a latched keyboard,
+/// along with the frames left before the keys recently pressed can be released.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct KeyboardState {
+    pub keys: u16,
+    pub presses: u16,
+    pub press_frames: [u8; 16],
+    pub pending_releases: u16,
+}
 
 #[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum KeyState {
     Up,
     Down,
 }
 
 #[derive(Copy, Clone, Debug, FromPrimitive, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Key {
     Key0 = 0x0,
     Key1 = 0x1,
@@ -37,6 +52,46 @@ impl Key {
     }
 }
 
+/// The keys of the keypad currently held down.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KeypadState(u16);
+
+impl KeypadState {
+    /// Creates a state from a bitmask, where bit N is set when key N is down.
+    pub fn from_bits(bits: u16) -> Self {
+        Self(bits)
+    }
+
+    pub fn bits(&self) -> u16 {
+        self.0
+    }
+
+    pub fn is_down(&self, key: Key) -> bool {
+        self.0 & key_mask(key) != 0
+    }
+
+    pub fn set(&mut self, key: Key, down: bool) {
+        match down {
+            true => self.0 |= key_mask(key),
+            false => self.0 &= !key_mask(key),
+        }
+    }
+}
+
+/// How a key held down is reported to programs waiting for a key press.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum KeyRepeat {
+    /// A key must be released before it is reported again.
+    #[default]
+    Off,
+    /// The last key pressed is reported again after `delay` while held down,
+    /// then every `interval`, as for menus navigated by holding a key.
+    After { delay: Duration, interval: Duration },
+}
+
+#[derive(PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct KeyboardMessage {
     state: KeyState,
     key: Key,
@@ -72,12 +127,51 @@ impl KeyboardController {
     }
 }
 
+/// The input messages as they are received, as bitmasks of the keys held down
+/// and of the keys pressed since they were last taken.
+#[derive(Default)]
+struct Inbox {
+    keys: Cell<u16>,
+    presses: Cell<u16>,
+}
+
+impl Inbox {
+    fn receive(&self, KeyboardMessage { state, key }: KeyboardMessage) {
+        let mask = key_mask(key);
+        match state {
+            KeyState::Down => {
+                self.keys.set(self.keys.get() | mask);
+                self.presses.set(self.presses.get() | mask);
+            }
+            KeyState::Up => self.keys.set(self.keys.get() & !mask),
+        }
+    }
+}
+
+/// The keyboard state is kept as a bitmask of the keys currently down.
+/// Pending input messages are folded into an inbox of bitmasks by the CPU thread itself
+/// whenever the state is queried, so no dedicated thread or lock is needed. The channel
+/// is unbounded so that no message is lost while the keys aren't queried.
 pub struct Keyboard {
-    key_states: Arc<RwLock<[KeyState; 16]>>,
-    wait_for_key: Arc<Mutex<bool>>,
-    stop_sender: Sender<()>,
+    key_states: AtomicU16,
+    inbox: Inbox,
+    // keys pressed since the last poll, only used when latched
+    presses: AtomicU16,
+    // when latched, messages are only processed by `latch`
+    latched: bool,
+    // published each time the keys held down change
+    keypad_states: LatestValue<KeypadState>,
+    repeat: KeyRepeat,
+    // the last key pressed while it is held down, with the time of the clock it is next repeated
+    repeating: Cell<Option<(Key, Duration)>>,
+    clock: Arc<dyn Clock>,
+    // minimum number of frames a key is down, see `set_min_press_frames`
+    min_press_frames: u8,
+    // frames left before each key can be released, and the keys released before then
+    press_frames: Cell<[u8; 16]>,
+    pending_releases: Cell<u16>,
     sender: Sender<KeyboardMessage>,
-    wait_receiver: Receiver<Key>,
+    receiver: Receiver<KeyboardMessage>,
     stop_waiter_receiver: Receiver<()>,
     stop_waiter_sender: Sender<()>,
 }
@@ -91,142 +185,294 @@ impl Default for Keyboard {
 impl Keyboard {
     pub(crate) fn new() -> Self {
         // channel for input keyboard messages
-        let (sender, receiver) = crossbeam_channel::bounded(128);
+        let (sender, receiver) = crossbeam_channel::unbounded();
 
-        // list of current key states (up or down)
-        let key_states = Arc::new(RwLock::new([KeyState::Up; 16]));
-        let key_states_clone = Arc::clone(&key_states);
+        // channel to interrupt the wait_for_key_press function
+        let (stop_waiter_sender, stop_waiter_receiver) = crossbeam_channel::bounded(1);
 
-        // are we currently in wait_for_key_press
-        let wait_for_key = Arc::new(Mutex::new(false));
-        let wait_for_key_clone = Arc::clone(&wait_for_key);
+        Self {
+            key_states: AtomicU16::new(0),
+            inbox: Inbox::default(),
+            presses: AtomicU16::new(0),
+            latched: false,
+            keypad_states: LatestValue::new(),
+            repeat: KeyRepeat::Off,
+            repeating: Cell::new(None),
+            clock: default_clock(),
+            min_press_frames: 0,
+            press_frames: Cell::new([0; 16]),
+            pending_releases: Cell::new(0),
+            sender,
+            receiver,
+            stop_waiter_receiver,
+            stop_waiter_sender,
+        }
+    }
 
-        // channel for stopping the keyboard controller thread
-        let (stop_sender, stop_receiver) = crossbeam_channel::bounded(0);
+    /// Creates a keyboard whose state only changes when `latch` is called,
+    /// so that the same frame can be replayed with the same input.
+    pub(crate) fn new_latched() -> Self {
+        Self {
+            latched: true,
+            ..Self::new()
+        }
+    }
 
-        // channel to send pressed key to the blocked wait_for_key_press function
-        let (wait_sender, wait_receiver) = crossbeam_channel::bounded(1);
+    /// Makes the state of the keyboard only change when `latch` is called, or not.
+    pub(crate) fn set_latched(&mut self, latched: bool) {
+        self.latched = latched;
+    }
 
-        // channel to interrupt the wait_for_key_press function
-        let (stop_waiter_sender, stop_waiter_receiver) = crossbeam_channel::bounded(1);
+    pub(crate) fn set_repeat(&mut self, repeat: KeyRepeat) {
+        self.repeat = repeat;
+    }
 
-        thread::spawn(move || {
-            loop {
-                select! {
-                    recv(stop_receiver) -> _ => {
-                        break;
+    /// Times the repeat of the keys held down on the clock of the system.
+    pub(crate) fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Keeps a key down for at least `frames` frames after it is pressed, so that a quick tap
+    /// isn't missed by a program polling the keys less often.
+    pub(crate) fn set_min_press_frames(&mut self, frames: u8) {
+        self.min_press_frames = frames;
+    }
+
+    pub(crate) fn is_key_down(&self, key: Key) -> bool {
+        if !self.latched {
+            self.apply_inbox();
+        }
+        self.key_states.load(Ordering::Relaxed) & key_mask(key) != 0
+    }
+
+    pub(crate) fn wait_for_key_press(&self) -> Option<Key> {
+        // keys pressed before we started waiting don't count
+        self.apply_inbox();
+
+        // wait for either interruption, a key press, or the repeat of a held key
+        loop {
+            let repeat = match self.repeating.get() {
+                Some((_, at)) => after(at.saturating_sub(self.clock.now())),
+                None => never(),
+            };
+            select! {
+                recv(self.stop_waiter_receiver) -> _ => {
+                    return None;
+                }
+                recv(repeat) -> _ => {
+                    if let Some(key) = self.take_repeat() {
+                        return Some(key);
                     }
-                    recv(receiver) -> msg => {
-                        if let Ok(KeyboardMessage { state, key }) = msg {
-                            if let Ok(mut key_states) = key_states_clone.write() {
-                                // update key status
-                                let idx = key as usize;
-                                key_states[idx] = state;
-
-                                // if a key has been pressed and we are waiting for a key press
-                                if state == KeyState::Down {
-                                    let mut waiting = wait_for_key_clone.lock().unwrap();
-                                    if *waiting {
-                                        // notify our condition variable
-                                        //let (ref key_lock, ref cv) = &*wake_cond_clone;
-                                        //*key_lock.lock() = Some(key);
-                                        //cv.notify_one();
-
-                                        _ = wait_sender.try_send(key);
-
-                                        // stop waiting
-                                        *waiting = false;
-                                    }
-                                }
-                            }
-                        } else {
-                            break;
-                        }
+                }
+                recv(self.receiver) -> msg => {
+                    self.inbox.receive(msg.ok()?);
+                    if let Some(key) = lowest_key(self.apply_inbox()) {
+                        return Some(key);
                     }
                 }
             }
-        });
+        }
+    }
 
-        Self {
-            key_states,
-            wait_for_key,
-            //wake_cond,
-            stop_sender,
-            sender,
-            wait_receiver,
-            stop_waiter_receiver,
-            stop_waiter_sender,
+    /// Non blocking version of `wait_for_key_press`,
+    /// returns the first key pressed since the last call, if any.
+    pub(crate) fn poll_key_press(&self) -> Option<Key> {
+        if self.latched {
+            // the lowest key pressed since the last poll
+            return lowest_key(self.presses.swap(0, Ordering::Relaxed));
         }
+
+        lowest_key(self.apply_inbox()).or_else(|| self.take_repeat())
     }
 
-    pub(crate) fn is_key_down(&self, key: Key) -> bool {
-        self.key_states
-            .read()
-            .map(|ks| ks[key as usize] == KeyState::Down)
-            .unwrap_or(false)
+    pub(crate) fn has_pending_input(&self) -> bool {
+        !self.receiver.is_empty()
     }
 
-    pub(crate) fn wait_for_key_press(&self) -> Option<Key> {
-        {
-            // register wait
-            *self.wait_for_key.lock().unwrap() = true;
+    /// Applies the pending messages to a latched keyboard.
+    pub(crate) fn latch(&self) {
+        let presses = self.apply_inbox();
+        self.presses.fetch_or(presses, Ordering::Relaxed);
+        if let Some(key) = self.take_repeat() {
+            self.presses.fetch_or(key_mask(key), Ordering::Relaxed);
         }
+    }
 
-        // wait for either interruption, or a key press
-        select! {
-            recv(self.stop_waiter_receiver) -> _ => {
-                None
-            }
-            recv(self.wait_receiver) -> key => {
-                key.ok()
-            }
+    pub(crate) fn state(&self) -> KeyboardState {
+        KeyboardState {
+            keys: self.key_states.load(Ordering::Relaxed),
+            presses: self.presses.load(Ordering::Relaxed),
+            press_frames: self.press_frames.get(),
+            pending_releases: self.pending_releases.get(),
         }
     }
 
+    pub(crate) fn restore_state(&self, state: KeyboardState) {
+        self.press_frames.set(state.press_frames);
+        self.pending_releases.set(state.pending_releases);
+        let previous = self.key_states.swap(state.keys, Ordering::Relaxed);
+        self.presses.store(state.presses, Ordering::Relaxed);
+        self.publish(previous);
+    }
+
     pub(crate) fn controller(&self) -> KeyboardController {
         KeyboardController {
             stop_waiter_sender: self.stop_waiter_sender.clone(),
         }
     }
+
+    /// Counts an emulated frame for the keys recently pressed,
+    /// and releases the ones released before their minimum number of frames.
+    pub(crate) fn end_frame(&self) {
+        let mut press_frames = self.press_frames.get();
+        let mut elapsed = 0;
+        for (k, frames) in press_frames.iter_mut().enumerate() {
+            if *frames > 0 {
+                *frames -= 1;
+                if *frames == 0 {
+                    elapsed |= 1 << k;
+                }
+            }
+        }
+        self.press_frames.set(press_frames);
+
+        let released = elapsed & self.pending_releases.get();
+        if released != 0 {
+            self.pending_releases
+                .set(self.pending_releases.get() & !released);
+            let previous = self.key_states.fetch_and(!released, Ordering::Relaxed);
+            self.publish(previous);
+        }
+    }
+
+    /// Applies the messages received since the last call to the keys held down,
+    /// returns the keys pressed in the meantime.
+    fn apply_inbox(&self) -> u16 {
+        for msg in self.receiver.try_iter() {
+            self.inbox.receive(msg);
+        }
+        let presses = self.inbox.presses.take();
+        let keys = self.inbox.keys.get();
+
+        let mut press_frames = self.press_frames.get();
+        for (k, frames) in press_frames.iter_mut().enumerate() {
+            if presses & (1 << k) != 0 {
+                *frames = self.min_press_frames;
+            }
+        }
+        self.press_frames.set(press_frames);
+
+        // keys released before they have been down long enough are released by `end_frame`
+        let held = held_keys(&press_frames);
+        self.pending_releases.set(held & !keys);
+        let previous = self.key_states.swap(keys | held, Ordering::Relaxed);
+        self.publish(previous);
+
+        if let KeyRepeat::After { delay, .. } = self.repeat {
+            if let Some(key) = lowest_key(presses) {
+                self.repeating.set(Some((key, self.clock.now() + delay)));
+            }
+            if matches!(self.repeating.get(), Some((k, _)) if keys & key_mask(k) == 0) {
+                self.repeating.set(None);
+            }
+        }
+
+        presses
+    }
+
+    /// Returns the key held down if its repeat is due, and schedules the next one.
+    fn take_repeat(&self) -> Option<Key> {
+        let KeyRepeat::After { interval, .. } = self.repeat else {
+            return None;
+        };
+        let (key, at) = self.repeating.get()?;
+        let now = self.clock.now();
+        if now < at {
+            return None;
+        }
+        self.repeating.set(Some((key, now + interval)));
+        Some(key)
+    }
+
+    fn publish(&self, previous: u16) {
+        let keys = self.key_states.load(Ordering::Relaxed);
+        if keys != previous {
+            self.keypad_states.send(KeypadState(keys));
+        }
+    }
+}
+
+fn key_mask(key: Key) -> u16 {
+    1 << key as u16
 }
 
-impl Drop for Keyboard {
-    fn drop(&mut self) {
-        _ = self.stop_sender.try_send(());
+/// Returns the lowest key of a bitmask of keys, if any.
+fn lowest_key(keys: u16) -> Option<Key> {
+    match keys {
+        0 => None,
+        _ => Key::from(keys.trailing_zeros() as u8),
     }
 }
 
+/// Returns the bitmask of the keys which have not been down for their minimum number of frames.
+fn held_keys(press_frames: &[u8; 16]) -> u16 {
+    press_frames
+        .iter()
+        .enumerate()
+        .filter(|(_, &frames)| frames > 0)
+        .fold(0, |keys, (k, _)| keys | 1 << k)
+}
+
 impl InputPort<KeyboardMessage> for Keyboard {
     fn input(&self) -> Sender<KeyboardMessage> {
         self.sender.clone()
     }
 }
 
+impl OutputPort<KeypadState> for Keyboard {
+    fn output(&self) -> Receiver<KeypadState> {
+        self.keypad_states.receiver()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::ManualClock;
+    use crate::port::ControlPin;
+    use std::thread;
     use std::time::Duration;
 
+    /// Keeps pressing a key from another thread until the returned pin is raised,
+    /// so that it is pressed again once `wait_for_key_press` is waiting.
+    fn keep_pressing(kb: &Keyboard, key: Key) -> ControlPin {
+        let sender = kb.input();
+        let done = ControlPin::default();
+        let pin = done.clone();
+        thread::spawn(move || {
+            while !pin.is_raised() {
+                sender.send(KeyboardMessage::down(key)).unwrap();
+                thread::yield_now();
+            }
+        });
+        done
+    }
+
     #[test]
     fn test_is_key_down_works() {
         let kb = Keyboard::new();
-        let sender = kb.input();
 
-        sender
+        kb.sender
             .send(KeyboardMessage::new(KeyState::Down, Key::Key0))
             .unwrap();
 
-        thread::sleep(Duration::from_millis(100));
-
         assert!(kb.is_key_down(Key::Key0));
         assert!(!kb.is_key_down(Key::Key1));
 
-        sender
+        kb.sender
             .send(KeyboardMessage::new(KeyState::Up, Key::Key0))
             .unwrap();
 
-        thread::sleep(Duration::from_millis(100));
-
         assert!(!kb.is_key_down(Key::Key0));
         assert!(!kb.is_key_down(Key::Key1));
     }
@@ -234,16 +480,159 @@ mod tests {
     #[test]
     fn test_wait_for_key_press_works() {
         let kb = Keyboard::new();
-        let sender = kb.input();
-
-        thread::spawn(move || {
-            thread::sleep(Duration::from_millis(500));
-            sender
-                .send(KeyboardMessage::new(KeyState::Down, Key::Key0))
-                .unwrap();
-        });
 
+        let done = keep_pressing(&kb, Key::Key0);
         let k = kb.wait_for_key_press();
+        done.raise();
         assert_eq!(k.unwrap(), Key::Key0);
     }
+
+    #[test]
+    fn test_wait_for_key_press_ignores_earlier_presses() {
+        let kb = Keyboard::new();
+
+        kb.sender.send(KeyboardMessage::down(Key::Key1)).unwrap();
+
+        let done = keep_pressing(&kb, Key::Key2);
+        let k = kb.wait_for_key_press();
+        done.raise();
+        assert_eq!(k.unwrap(), Key::Key2);
+        assert!(kb.is_key_down(Key::Key1));
+        assert!(kb.is_key_down(Key::Key2));
+    }
+
+    #[test]
+    fn held_key_is_repeated() {
+        let clock = ManualClock::new();
+        let mut kb = Keyboard::new();
+        kb.set_clock(Arc::new(clock.clone()));
+        kb.set_repeat(KeyRepeat::After {
+            delay: Duration::from_millis(100),
+            interval: Duration::from_millis(20),
+        });
+
+        kb.sender.send(KeyboardMessage::down(Key::Key3)).unwrap();
+        assert_eq!(kb.poll_key_press(), Some(Key::Key3));
+        assert_eq!(kb.poll_key_press(), None);
+
+        clock.advance(Duration::from_millis(99));
+        assert_eq!(kb.poll_key_press(), None);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(kb.wait_for_key_press(), Some(Key::Key3));
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(kb.wait_for_key_press(), Some(Key::Key3));
+
+        kb.sender.send(KeyboardMessage::up(Key::Key3)).unwrap();
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(kb.poll_key_press(), None);
+    }
+
+    #[test]
+    fn brief_presses_are_held_for_the_minimum_number_of_frames() {
+        let mut kb = Keyboard::new();
+        kb.set_min_press_frames(2);
+
+        kb.sender.send(KeyboardMessage::down(Key::Key4)).unwrap();
+        kb.sender.send(KeyboardMessage::up(Key::Key4)).unwrap();
+        assert!(kb.is_key_down(Key::Key4));
+        kb.end_frame();
+        assert!(kb.is_key_down(Key::Key4));
+        kb.end_frame();
+        assert!(!kb.is_key_down(Key::Key4));
+
+        // keys held down longer are released right away
+        kb.sender.send(KeyboardMessage::down(Key::Key5)).unwrap();
+        assert!(kb.is_key_down(Key::Key5));
+        kb.end_frame();
+        kb.end_frame();
+        kb.sender.send(KeyboardMessage::up(Key::Key5)).unwrap();
+        assert!(!kb.is_key_down(Key::Key5));
+    }
+
+    #[test]
+    fn latched_keyboard_only_changes_when_latched() {
+        let kb = Keyboard::new_latched();
+
+        kb.sender.send(KeyboardMessage::down(Key::Key5)).unwrap();
+        kb.sender.send(KeyboardMessage::up(Key::Key5)).unwrap();
+        kb.sender.send(KeyboardMessage::down(Key::Key2)).unwrap();
+        assert!(kb.has_pending_input());
+        assert!(!kb.is_key_down(Key::Key2));
+        assert_eq!(kb.poll_key_press(), None);
+
+        kb.latch();
+        let state = kb.state();
+        assert!(!kb.has_pending_input());
+        assert!(kb.is_key_down(Key::Key2));
+        assert!(!kb.is_key_down(Key::Key5));
+        assert_eq!(kb.poll_key_press(), Some(Key::Key2));
+        assert_eq!(kb.poll_key_press(), None);
+
+        // presses can be replayed
+        kb.restore_state(state);
+        assert_eq!(kb.poll_key_press(), Some(Key::Key2));
+    }
+
+    #[test]
+    fn restored_state_keeps_brief_presses_down() {
+        let mut kb = Keyboard::new_latched();
+        kb.set_min_press_frames(2);
+
+        kb.sender.send(KeyboardMessage::down(Key::Key6)).unwrap();
+        kb.sender.send(KeyboardMessage::up(Key::Key6)).unwrap();
+        kb.latch();
+        let state = kb.state();
+        kb.end_frame();
+        kb.end_frame();
+        assert!(!kb.is_key_down(Key::Key6));
+
+        // eg. rolled back to replay the frame with run-ahead
+        kb.restore_state(state);
+        assert!(kb.is_key_down(Key::Key6));
+        kb.end_frame();
+        assert!(kb.is_key_down(Key::Key6));
+        kb.end_frame();
+        assert!(!kb.is_key_down(Key::Key6));
+    }
+
+    #[test]
+    fn keypad_state_is_published_on_change() {
+        let kb = Keyboard::new();
+        let states: Receiver<KeypadState> = kb.output();
+
+        kb.sender.send(KeyboardMessage::down(Key::KeyA)).unwrap();
+        assert!(kb.is_key_down(Key::KeyA));
+        kb.sender.send(KeyboardMessage::down(Key::KeyA)).unwrap();
+        assert!(kb.is_key_down(Key::KeyA));
+
+        let state = states.try_recv().unwrap();
+        assert!(state.is_down(Key::KeyA));
+        assert!(!state.is_down(Key::Key0));
+        assert!(states.try_recv().is_err());
+    }
+
+    #[test]
+    fn messages_are_received_while_the_keys_are_not_queried() {
+        let kb = Keyboard::new();
+        let sender = kb.input();
+
+        // more messages than a frontend sends in a while, the last one leaves a key down
+        for _ in 0..200 {
+            sender.send(KeyboardMessage::down(Key::Key1)).unwrap();
+            sender.send(KeyboardMessage::up(Key::Key1)).unwrap();
+        }
+        sender.send(KeyboardMessage::down(Key::Key2)).unwrap();
+        assert!(kb.is_key_down(Key::Key2));
+        assert!(!kb.is_key_down(Key::Key1));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn keyboard_message_can_be_serialized() {
+        let s = toml::to_string(&KeyboardMessage::down(Key::KeyB)).unwrap();
+        assert_eq!(s, "state = \"Down\"\nkey = \"KeyB\"\n");
+
+        let msg: KeyboardMessage = toml::from_str(&s).unwrap();
+        assert!(msg.state == KeyState::Down && msg.key == Key::KeyB);
+    }
 }
@@ -1,8 +1,37 @@
-use crate::keyboard::Key;
+use crate::keyboard::{Key, KeyboardMessage};
 use serde::Deserialize;
 use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum KeyboardMapError {
+    #[error("{0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("key {name:?} is bound to {value}, keypad keys go from 0x0 to 0xf")]
+    InvalidKey { name: String, value: i64 },
+    #[error("keypad key {0:#x} is not bound to any key")]
+    MissingKey(u8),
+    #[error("keys {0:?} and {1:?} are the same physical key")]
+    DuplicateKey(String, String),
+}
+
+#[derive(Debug, Error)]
+#[error("invalid keyboard profile {profile}: {source}")]
+pub struct ProfileError {
+    pub profile: String,
+    pub source: KeyboardMapError,
+}
+
+/// Alternative spellings of the names of physical keys, along with their canonical names.
+const KEY_ALIASES: [(&str, &str); 1] = [(" ", "Space")];
 
 #[derive(Deserialize)]
+struct RawKeyboardMap {
+    keys: HashMap<String, i64>,
+}
+
+/// Maps the names of physical keys to keypad keys,
+/// several physical keys can be bound to the same keypad key.
 pub struct KeyboardMap {
     keys: HashMap<String, u8>,
 }
@@ -35,46 +64,183 @@ impl Default for KeyboardMap {
 }
 
 impl KeyboardMap {
-    pub fn from_toml(s: &str) -> Result<Self, toml::de::Error> {
-        toml::from_str(s)
+    /// Parses a map, which must bind each keypad key at least once.
+    pub fn from_toml(s: &str) -> Result<Self, KeyboardMapError> {
+        let raw: RawKeyboardMap = toml::from_str(s)?;
+
+        let mut keys = HashMap::with_capacity(raw.keys.len());
+        for (name, value) in raw.keys {
+            let v = match u8::try_from(value) {
+                Ok(v) if v <= 0xf => v,
+                _ => return Err(KeyboardMapError::InvalidKey { name, value }),
+            };
+            let canonical = KEY_ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == name)
+                .map_or(name.as_str(), |(_, canonical)| canonical)
+                .to_string();
+            if keys.insert(canonical.clone(), v).is_some() {
+                return Err(KeyboardMapError::DuplicateKey(name, canonical));
+            }
+        }
+
+        if let Some(missing) = (0..=0xf).find(|v| !keys.values().any(|k| k == v)) {
+            return Err(KeyboardMapError::MissingKey(missing));
+        }
+
+        Ok(Self { keys })
     }
 
     pub fn key(&self, s: &str) -> Option<Key> {
         self.keys.get(s).and_then(|&v| Key::from(v))
     }
+
+    /// Returns the names of the physical keys bound to a keypad key, in alphabetical order.
+    pub fn names(&self, key: Key) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .keys
+            .iter()
+            .filter(|(_, &v)| v == key as u8)
+            .map(|(name, _)| name.as_str())
+            .collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+/// Translates physical key events into keypad messages.
+/// A keypad key bound to several physical keys is held down
+/// until all of them are released.
+#[derive(Default)]
+pub struct HeldKeys {
+    held: HashMap<String, Key>,
+}
+
+impl HeldKeys {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns the message to send when the physical key `name` is pressed, if any.
+    pub fn press(&mut self, map: &KeyboardMap, name: &str) -> Option<KeyboardMessage> {
+        let key = map.key(name)?;
+        let already_down = self.is_held(key);
+        self.held.insert(name.to_string(), key);
+        (!already_down).then(|| KeyboardMessage::down(key))
+    }
+
+    /// Returns the message to send when the physical key `name` is released, if any.
+    /// The key is released as it was pressed, even if the map has changed since.
+    pub fn release(&mut self, name: &str) -> Option<KeyboardMessage> {
+        let key = self.held.remove(name)?;
+        (!self.is_held(key)).then(|| KeyboardMessage::up(key))
+    }
+
+    fn is_held(&self, key: Key) -> bool {
+        self.held.values().any(|&k| k == key)
+    }
 }
 
-pub fn load_profiles() -> HashMap<String, KeyboardMap> {
-    let mut profiles = HashMap::new();
-    profiles.insert(
-        "default".to_string(),
-        KeyboardMap::from_toml(include_str!("../keyboard-profiles/default.toml"))
-            .expect("Unable to load profile"),
-    );
-    profiles.insert(
-        "qwerty".to_string(),
-        KeyboardMap::from_toml(include_str!("../keyboard-profiles/qwerty.toml"))
-            .expect("Unable to load profile"),
-    );
-    profiles.insert(
-        "azerty".to_string(),
-        KeyboardMap::from_toml(include_str!("../keyboard-profiles/azerty.toml"))
-            .expect("Unable to load profile"),
-    );
-
-    profiles
+const PROFILES: [(&str, &str); 3] = [
+    ("default", include_str!("../keyboard-profiles/default.toml")),
+    ("qwerty", include_str!("../keyboard-profiles/qwerty.toml")),
+    ("azerty", include_str!("../keyboard-profiles/azerty.toml")),
+];
+
+pub fn load_profiles() -> Result<HashMap<String, KeyboardMap>, ProfileError> {
+    PROFILES
+        .iter()
+        .map(|&(profile, s)| match KeyboardMap::from_toml(s) {
+            Ok(map) => Ok((profile.to_string(), map)),
+            Err(source) => Err(ProfileError {
+                profile: profile.to_string(),
+                source,
+            }),
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// A map binding the hexadecimal digits, followed by `extra` bindings.
+    fn map_toml(extra: &str) -> String {
+        let mut s = "[keys]\n".to_string();
+        for v in 0..=0xf {
+            s += &format!("{:x} = {}\n", v, v);
+        }
+        s + extra
+    }
+
     #[test]
     fn test_from_toml() {
-        let b = "[keys]\n0 = 0x0\n1 = 0x1\n";
-        let m = KeyboardMap::from_toml(b).unwrap();
+        let m = KeyboardMap::from_toml(&map_toml("")).unwrap();
 
         assert!(matches!(m.key("0"), Some(Key::Key0)));
         assert!(matches!(m.key("1"), Some(Key::Key1)));
     }
+
+    #[test]
+    fn keys_are_listed_by_keypad_key() {
+        let m = KeyboardMap::from_toml(&map_toml("Space = 0x5\nArrowUp = 0x5\n")).unwrap();
+
+        assert_eq!(m.names(Key::Key5), ["5", "ArrowUp", "Space"]);
+        assert_eq!(m.names(Key::KeyA), ["a"]);
+    }
+
+    #[test]
+    fn invalid_maps_are_rejected() {
+        let err = KeyboardMap::from_toml(&map_toml("x = 16\n")).unwrap_err();
+        assert!(matches!(
+            err,
+            KeyboardMapError::InvalidKey { value: 16, .. }
+        ));
+
+        let err = KeyboardMap::from_toml("[keys]\n0 = 0\n").unwrap_err();
+        assert!(matches!(err, KeyboardMapError::MissingKey(1)));
+
+        let err = KeyboardMap::from_toml(&map_toml("\" \" = 1\nSpace = 2\n")).unwrap_err();
+        assert!(matches!(err, KeyboardMapError::DuplicateKey(..)));
+
+        let err = KeyboardMap::from_toml(&map_toml("a = 1\n")).unwrap_err();
+        assert!(matches!(err, KeyboardMapError::Parse(_)));
+        assert!(err.to_string().contains("duplicate key"));
+    }
+
+    #[test]
+    fn aliases_are_canonical() {
+        let m = KeyboardMap::from_toml(&map_toml("\" \" = 5\n")).unwrap();
+        assert!(matches!(m.key("Space"), Some(Key::Key5)));
+    }
+
+    #[test]
+    fn profiles_are_valid() {
+        assert!(load_profiles().is_ok());
+    }
+
+    #[test]
+    fn profiles_bind_named_keys() {
+        let profiles = load_profiles().unwrap();
+        let m = &profiles["default"];
+
+        assert!(matches!(m.key("ArrowUp"), Some(Key::Key2)));
+        assert!(matches!(m.key("Numpad4"), Some(Key::Key4)));
+    }
+
+    #[test]
+    fn shared_key_is_released_with_the_last_binding() {
+        let m = KeyboardMap::from_toml(&map_toml("w = 0x5\n")).unwrap();
+        let mut held = HeldKeys::new();
+
+        let msg = held.press(&m, "w").unwrap();
+        assert!(msg == KeyboardMessage::down(Key::Key5));
+        assert!(held.press(&m, "5").is_none());
+        assert!(held.release("w").is_none());
+        let msg = held.release("5").unwrap();
+        assert!(msg == KeyboardMessage::up(Key::Key5));
+
+        assert!(held.press(&m, "x").is_none());
+        assert!(held.release("x").is_none());
+    }
 }
@@ -1,8 +1,60 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+//! The CHIP-8 system.
+//!
+//! Without the default `std` feature, only the pure machine is available:
+//! memory, instruction decoding, CPU registers and the display framebuffer.
+//! The system itself, with its ports, timers and threads, requires `std`.
+//! Reading the TOML files (cheats, movies, ROM database, keyboard maps) also requires `serde`.
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod audio;
+#[cfg(feature = "std")]
+pub mod cheat;
+#[cfg(feature = "std")]
+pub mod clock;
+pub mod cpu;
+#[cfg(all(feature = "std", feature = "serde"))]
+pub mod database;
+#[cfg(feature = "std")]
+pub mod debug;
+#[cfg(feature = "std")]
+pub mod diagnostic;
+#[cfg(feature = "std")]
+pub mod dialect;
 pub mod display;
+#[cfg(feature = "std")]
+pub mod event;
+#[cfg(feature = "std")]
 pub mod keyboard;
+#[cfg(all(feature = "std", feature = "serde"))]
 pub mod keyboard_map;
+#[cfg(feature = "std")]
+pub mod machine;
 pub mod memory;
+#[cfg(feature = "std")]
+pub mod metrics;
+#[cfg(feature = "std")]
+pub mod movie;
+#[cfg(feature = "net")]
+pub mod net;
 pub mod opcode;
+#[cfg(feature = "std")]
+pub mod pacing;
+#[cfg(feature = "std")]
 pub mod port;
+#[cfg(feature = "std")]
+pub mod profiling;
+#[cfg(feature = "std")]
+pub mod random;
+#[cfg(feature = "std")]
+pub mod routine;
+#[cfg(feature = "std")]
+pub mod snapshot;
+#[cfg(feature = "std")]
 pub mod system;
+#[cfg(feature = "std")]
 pub mod timer;
+pub mod timing;
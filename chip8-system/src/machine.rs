@@ -0,0 +1,109 @@
+//! A synchronous facade over the system, for frontends which drive the emulator
+//! with one call per frame, such as libretro cores, wasm builds or game engines.
+//!
+//! ```
+//! # use chip8_system::keyboard::{Key, KeypadState};
+//! # use chip8_system::machine::Machine;
+//! # use chip8_system::system::SystemOptions;
+//! let mut machine = Machine::new(SystemOptions::new());
+//! // ld f, v0; drw v0, v0, 5; ld v1, k; jp 0x200
+//! machine.load_program(&[0xF0, 0x29, 0xD0, 0x05, 0xF1, 0x0A, 0x12, 0x00]);
+//!
+//! let mut keys = KeypadState::default();
+//! keys.set(Key::Key5, true);
+//! let screen = machine.step_frame(keys).unwrap();
+//! let lit = screen.pixels().count_ones();
+//! let beeping = machine.sound_active();
+//! # let _ = (lit, beeping);
+//! ```
+
+use crate::display::Framebuffer;
+use crate::keyboard::{KeyboardState, KeypadState};
+use crate::system::{ExecutionMode, System, SystemError, SystemOptions};
+use crate::timer::ObservableTimer;
+
+/// A system running on the calling thread, one frame at a time,
+/// without any channel to connect or thread to manage: the keys and the timers
+/// only change in `step_frame`.
+pub struct Machine {
+    system: System,
+    keys: KeypadState,
+}
+
+impl Machine {
+    /// Creates a machine with the given options, always in the single threaded execution mode.
+    pub fn new(mut options: SystemOptions) -> Self {
+        options.execution_mode = ExecutionMode::SingleThreaded;
+        let mut system = System::new_with_options(options);
+        // the keys only change when given to step_frame
+        system.keyboard.set_latched(true);
+        Self {
+            system,
+            keys: Default::default(),
+        }
+    }
+
+    pub fn load_program(&mut self, program: &[u8]) {
+        self.system.load_image_bytes(program);
+    }
+
+    /// Runs one 60 Hz frame with the given keys held down, and returns the screen at its end.
+    pub fn step_frame(&mut self, keys: KeypadState) -> Result<&Framebuffer, SystemError> {
+        let presses = keys.bits() & !self.keys.bits();
+        self.system.keyboard.restore_state(KeyboardState {
+            keys: keys.bits(),
+            presses,
+            ..Default::default()
+        });
+        self.keys = keys;
+
+        self.system.run_frame()?;
+        Ok(self.system.display.framebuffer())
+    }
+
+    /// Returns the screen at the end of the last frame.
+    pub fn framebuffer(&self) -> &Framebuffer {
+        self.system.display.framebuffer()
+    }
+
+    /// Returns true while the buzzer is playing.
+    pub fn sound_active(&self) -> bool {
+        self.system.sound_timer.value() > 0
+    }
+
+    /// Gives access to the underlying system, eg. to take snapshots.
+    pub fn system(&mut self) -> &mut System {
+        &mut self.system
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard::Key;
+
+    #[test]
+    fn frames_are_driven_by_the_caller() {
+        let opts = SystemOptions::builder()
+            .instructions_per_frame(10)
+            .build()
+            .unwrap();
+        let mut machine = Machine::new(opts);
+
+        // ld v1, k; ld f, v1; drw v0, v0, 5; ld st, v1; add v3, 1; jp 0x208
+        let image = [
+            0xF1, 0x0A, 0xF1, 0x29, 0xD0, 0x05, 0xF1, 0x18, 0x73, 0x01, 0x12, 0x08,
+        ];
+        machine.load_program(&image);
+
+        let screen = machine.step_frame(KeypadState::default()).unwrap();
+        assert!(screen.pixels().not_any());
+        assert!(!machine.sound_active());
+
+        let mut keys = KeypadState::default();
+        keys.set(Key::Key8, true);
+        let screen = machine.step_frame(keys).unwrap();
+        assert!(screen.pixels()[0]);
+        assert!(machine.sound_active());
+    }
+}
@@ -1,10 +1,91 @@
-use std::convert::TryInto;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
 
 pub const MEMORY_SIZE: usize = 4096;
+pub const MAX_MEMORY_SIZE: usize = 65536;
 pub const RESERVED_SIZE: usize = 512;
 
+/// Size of the upper memory used by the COSMAC VIP interpreter.
+pub const VIP_RESERVED_SIZE: usize = 0x160;
+/// Number of return addresses held by the COSMAC VIP stack.
+pub const VIP_STACK_SIZE: usize = 12;
+// offsets from the end of memory: the stack grows down from 0xECF in 4 KB,
+// the display refresh buffer fills the last 256 bytes
+const VIP_STACK_TOP: usize = 0x130;
+const VIP_DISPLAY_BUFFER: usize = 0x100;
+
+/// Where the interpreter keeps its own data.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum MemoryLayout {
+    /// The stack and the display are kept outside of memory,
+    /// which is all available to programs.
+    #[default]
+    Modern,
+    /// As in the original interpreter, the stack, the interpreter variables and
+    /// the display refresh buffer are in the last 352 bytes of memory
+    /// (0xEA0 to 0xFFF in 4 KB), which programs can read and write.
+    /// The stack holds 12 return addresses.
+    CosmacVip,
+}
+
+impl MemoryLayout {
+    /// Returns the address following the memory available to programs.
+    pub fn program_end(self, memory_size: usize) -> usize {
+        match self {
+            MemoryLayout::Modern => memory_size,
+            MemoryLayout::CosmacVip => memory_size - VIP_RESERVED_SIZE,
+        }
+    }
+
+    /// Returns the maximum depth of the stack.
+    pub(crate) fn stack_size(self, default: usize) -> usize {
+        match self {
+            MemoryLayout::Modern => default,
+            MemoryLayout::CosmacVip => VIP_STACK_SIZE,
+        }
+    }
+
+    /// Returns the address of the `n`th return address of the stack, when in memory.
+    pub(crate) fn stack_slot(self, memory_size: usize, n: usize) -> Option<u16> {
+        match self {
+            MemoryLayout::Modern => None,
+            MemoryLayout::CosmacVip => Some((memory_size - VIP_STACK_TOP - 2 * (n + 1)) as u16),
+        }
+    }
+
+    /// Returns the addresses holding the stack, when in memory.
+    pub fn stack_region(self, memory_size: usize) -> Option<Range<u16>> {
+        match self {
+            MemoryLayout::Modern => None,
+            MemoryLayout::CosmacVip => {
+                let top = (memory_size - VIP_STACK_TOP) as u16;
+                Some(top - 2 * VIP_STACK_SIZE as u16..top)
+            }
+        }
+    }
+
+    /// Returns the address of the display refresh buffer, when in memory.
+    pub(crate) fn display_buffer(self, memory_size: usize) -> Option<u16> {
+        match self {
+            MemoryLayout::Modern => None,
+            MemoryLayout::CosmacVip => Some((memory_size - VIP_DISPLAY_BUFFER) as u16),
+        }
+    }
+}
+
+/// A write which goes past the end of memory.
+#[derive(Debug, PartialEq)]
+pub(crate) struct WriteOverflow;
+
+#[derive(Clone)]
 pub(crate) struct Memory {
-    bytes: [u8; MEMORY_SIZE],
+    bytes: Vec<u8>,
 }
 
 impl Default for Memory {
@@ -15,11 +96,23 @@ impl Default for Memory {
 
 impl Memory {
     pub fn new() -> Self {
+        Self::with_size(MEMORY_SIZE)
+    }
+
+    pub fn with_size(size: usize) -> Self {
         Self {
-            bytes: [0; MEMORY_SIZE],
+            bytes: vec![0; size],
         }
     }
 
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
     pub fn as_bytes_mut(&mut self) -> &mut [u8] {
         &mut self.bytes
     }
@@ -35,9 +128,14 @@ impl Memory {
         self.bytes.get(addr..addr + (n as usize))
     }
 
-    pub fn write_slice(&mut self, addr: u16, data: &[u8]) {
+    pub fn write_slice(&mut self, addr: u16, data: &[u8]) -> Result<(), WriteOverflow> {
         let addr = addr as usize;
-        self.bytes[addr..addr + data.len()].copy_from_slice(data);
+        let bytes = self
+            .bytes
+            .get_mut(addr..addr + data.len())
+            .ok_or(WriteOverflow)?;
+        bytes.copy_from_slice(data);
+        Ok(())
     }
 }
 
@@ -45,12 +143,33 @@ impl Memory {
 mod tests {
     use super::*;
 
+    #[test]
+    fn vip_layout_is_in_upper_memory() {
+        let layout = MemoryLayout::CosmacVip;
+        assert_eq!(layout.program_end(MEMORY_SIZE), 0xEA0);
+        assert_eq!(layout.stack_slot(MEMORY_SIZE, 0), Some(0xECE));
+        assert_eq!(
+            layout.stack_slot(MEMORY_SIZE, VIP_STACK_SIZE - 1),
+            Some(0xEA0)
+        );
+        assert_eq!(layout.stack_region(MEMORY_SIZE), Some(0xEA0..0xED0));
+        assert_eq!(layout.display_buffer(MEMORY_SIZE), Some(0xF00));
+        assert_eq!(MemoryLayout::Modern.stack_slot(MEMORY_SIZE, 0), None);
+    }
+
     #[test]
     fn test_read_u16_big_endian_works() {
         let mut m = Memory::new();
-        m.write_slice(0x200, &[0xAB]);
-        m.write_slice(0x201, &[0xCD]);
+        m.write_slice(0x200, &[0xAB]).unwrap();
+        m.write_slice(0x201, &[0xCD]).unwrap();
         let v = m.read_u16(0x200);
         assert_eq!(v, Some(0xABCD));
     }
+
+    #[test]
+    fn writes_past_the_end_fail() {
+        let mut m = Memory::new();
+        assert_eq!(m.write_slice(0xFFF, &[1, 2]), Err(WriteOverflow));
+        assert_eq!(m.as_bytes()[0xFFF], 0);
+    }
 }
@@ -0,0 +1,75 @@
+use crate::port::LatestValue;
+use std::time::Duration;
+
+/// Rate of flickers from which a program is considered to flicker, see `SystemMetrics::is_flickering`.
+const FLICKER_THRESHOLD: f64 = 10.0;
+
+/// Performance information about a running system, emitted once per second.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SystemMetrics {
+    /// Number of instructions actually executed per second.
+    pub instructions_per_second: f64,
+    /// Number of display updates emitted during the period.
+    pub frames: u64,
+    /// Number of display updates replaced by a newer one before being received.
+    pub dropped_frames: u64,
+    /// Number of times the delay timer has been decremented during the period.
+    pub delay_timer_ticks: u64,
+    /// Number of times the sound timer has been decremented during the period.
+    pub sound_timer_ticks: u64,
+    /// Number of timer messages which could not be sent during the period.
+    pub dropped_timer_messages: u64,
+    /// Number of sprites erased then drawn again at the same place per second,
+    /// with the screen shown without them in between.
+    pub flickers_per_second: f64,
+}
+
+impl SystemMetrics {
+    /// Returns true if the program flickers enough for a frontend to suggest,
+    /// or enable, a rendering which blends the last frames.
+    pub fn is_flickering(&self) -> bool {
+        self.flickers_per_second >= FLICKER_THRESHOLD
+    }
+}
+
+/// Cumulated counters, sampled by the system at the end of each period.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Counters {
+    pub instructions: u64,
+    pub frames: u64,
+    pub dropped_frames: u64,
+    pub delay_timer_ticks: u64,
+    pub sound_timer_ticks: u64,
+    pub dropped_timer_messages: u64,
+    pub flickers: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct MetricsCollector {
+    last: Counters,
+    output: LatestValue<SystemMetrics>,
+}
+
+impl MetricsCollector {
+    /// Emits the metrics for the period which just ended.
+    pub fn report(&mut self, counters: Counters, period: Duration) {
+        let last = self.last;
+        self.last = counters;
+
+        self.output.send(SystemMetrics {
+            instructions_per_second: (counters.instructions - last.instructions) as f64
+                / period.as_secs_f64(),
+            frames: counters.frames - last.frames,
+            dropped_frames: counters.dropped_frames - last.dropped_frames,
+            delay_timer_ticks: counters.delay_timer_ticks - last.delay_timer_ticks,
+            sound_timer_ticks: counters.sound_timer_ticks - last.sound_timer_ticks,
+            dropped_timer_messages: counters.dropped_timer_messages - last.dropped_timer_messages,
+            flickers_per_second: (counters.flickers - last.flickers) as f64 / period.as_secs_f64(),
+        });
+    }
+
+    pub fn output(&self) -> crossbeam_channel::Receiver<SystemMetrics> {
+        self.output.receiver()
+    }
+}
@@ -0,0 +1,234 @@
+use crate::dialect::Variant;
+use crate::keyboard::KeyboardState;
+use crate::memory::MemoryLayout;
+use crate::system::{ExecutionMode, OptionsError, Quirks, Speed, SystemOptions};
+#[cfg(feature = "serde")]
+use std::fs;
+use std::io;
+#[cfg(feature = "serde")]
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum MovieError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[cfg(feature = "serde")]
+    #[error("{0}")]
+    Parse(#[from] toml::de::Error),
+    #[cfg(feature = "serde")]
+    #[error("{0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("{0}")]
+    Options(#[from] OptionsError),
+    #[error("the movie was recorded with another program")]
+    ProgramMismatch,
+    #[error("movies require the single threaded execution mode, without run-ahead")]
+    RequiresSingleThreaded,
+}
+
+/// The input of a run, frame by frame, along with everything needed to replay it exactly:
+/// the program, the options of the system and the seed of its random numbers.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Movie {
+    /// Hash of the program the movie was recorded with, see `program_hash`.
+    #[cfg_attr(feature = "serde", serde(with = "hex_u64"))]
+    pub program_hash: u64,
+    #[cfg_attr(feature = "serde", serde(with = "hex_u64"))]
+    pub seed: u64,
+    pub variant: Variant,
+    pub quirks: u8,
+    pub speed: Speed,
+    pub memory_size: usize,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub memory_layout: MemoryLayout,
+    /// Number of frames recorded.
+    pub frames: u64,
+    /// Changes of the keypad, as the frame where they happen, the keys held down during that
+    /// frame and the keys pressed before it. Frames without changes are not listed.
+    pub input: Vec<(u64, u16, u16)>,
+}
+
+impl Movie {
+    pub(crate) fn new(program: &[u8], options: &SystemOptions, seed: u64) -> Self {
+        Self {
+            program_hash: program_hash(program),
+            seed,
+            variant: options.variant,
+            quirks: options.quirks.bits(),
+            speed: options.speed,
+            memory_size: options.memory_size,
+            memory_layout: options.memory_layout,
+            frames: 0,
+            input: vec![],
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn from_toml(s: &str) -> Result<Self, MovieError> {
+        Ok(toml::from_str(s)?)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_toml(&self) -> Result<String, MovieError> {
+        Ok(toml::to_string(self)?)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn load(path: &Path) -> Result<Self, MovieError> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn save(&self, path: &Path) -> Result<(), MovieError> {
+        fs::write(path, self.to_toml()?)?;
+        Ok(())
+    }
+
+    /// Returns the options to replay the movie with.
+    pub fn options(&self) -> Result<SystemOptions, MovieError> {
+        let mut builder = SystemOptions::builder();
+        match self.speed {
+            Speed::Frequency(f) => builder.cpu_frequency_hz(f),
+            Speed::InstructionsPerFrame(n) => builder.instructions_per_frame(n),
+        };
+        Ok(builder
+            .variant(self.variant)
+            .quirk(Quirks::from_bits_retain(self.quirks))
+            .memory_size(self.memory_size)
+            .memory_layout(self.memory_layout)
+            .seed(self.seed)
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .build()?)
+    }
+
+    /// Appends the input of the next frame.
+    pub(crate) fn record(&mut self, state: KeyboardState) {
+        let keys = self.input.last().map_or(0, |&(_, keys, _)| keys);
+        if state.keys != keys || state.presses != 0 {
+            self.input.push((self.frames, state.keys, state.presses));
+        }
+        self.frames += 1;
+    }
+}
+
+/// Replays the input of a movie, one frame at a time.
+pub(crate) struct MoviePlayer {
+    pub movie: Movie,
+    frame: u64,
+    next: usize,
+    keys: u16,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> Self {
+        Self {
+            movie,
+            frame: 0,
+            next: 0,
+            keys: 0,
+        }
+    }
+
+    /// Returns the input of the next frame, or None once all the frames have been replayed.
+    pub fn next_frame(&mut self) -> Option<KeyboardState> {
+        if self.frame >= self.movie.frames {
+            return None;
+        }
+
+        let mut presses = 0;
+        if let Some(&(frame, keys, p)) = self.movie.input.get(self.next) {
+            if frame == self.frame {
+                self.keys = keys;
+                presses = p;
+                self.next += 1;
+            }
+        }
+        self.frame += 1;
+
+        // the keys held down are replayed as they were recorded, without press frames
+        Some(KeyboardState {
+            keys: self.keys,
+            presses,
+            ..Default::default()
+        })
+    }
+}
+
+/// Movie being recorded or replayed by a system.
+pub(crate) enum MovieState {
+    Recording(Movie),
+    Playing(MoviePlayer),
+}
+
+/// Hash identifying a program (64 bit FNV-1a).
+pub fn program_hash(program: &[u8]) -> u64 {
+    program.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// TOML integers are signed, so 64 bit values are stored as hex strings.
+#[cfg(feature = "serde")]
+mod hex_u64 {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &u64, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&format!("{:016x}", v))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<u64, D::Error> {
+        let s = String::deserialize(d)?;
+        u64::from_str_radix(&s, 16).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn movies_round_trip_through_toml() {
+        let options = SystemOptions::builder()
+            .instructions_per_frame(15)
+            .quirk(Quirks::SHIFT_READS_VX)
+            .build()
+            .unwrap();
+        let mut movie = Movie::new(&[0x12, 0x00], &options, u64::MAX);
+        movie.record(KeyboardState::default());
+        movie.record(KeyboardState {
+            keys: 0x20,
+            presses: 0x20,
+            ..Default::default()
+        });
+
+        let s = movie.to_toml().unwrap();
+        assert!(s.contains("seed = \"ffffffffffffffff\""));
+        assert_eq!(Movie::from_toml(&s).unwrap(), movie);
+        assert_eq!(movie.input, [(1, 0x20, 0x20)]);
+    }
+
+    #[test]
+    fn recorded_input_is_replayed() {
+        let mut movie = Movie::new(&[], &SystemOptions::new(), 0);
+        let frames = [(0, 0), (0x1, 0x1), (0x1, 0), (0x1, 0), (0, 0)];
+        for (keys, presses) in frames {
+            movie.record(KeyboardState {
+                keys,
+                presses,
+                ..Default::default()
+            });
+        }
+        assert_eq!(movie.input.len(), 3);
+
+        let mut player = MoviePlayer::new(movie);
+        for (keys, presses) in frames {
+            let state = player.next_frame().unwrap();
+            assert_eq!((state.keys, state.presses), (keys, presses));
+        }
+        assert_eq!(player.next_frame(), None);
+    }
+}
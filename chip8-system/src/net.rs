@@ -0,0 +1,222 @@
+//! Port adapters carrying messages over TCP, as one JSON document per line,
+//! or over WebSocket, as one JSON document per text message.
+//!
+//! The adapters are plain channel ends, so they can be used with `connect`
+//! like any other port, eg. to view the display of a system from another machine:
+//!
+//! ```no_run
+//! # use chip8_system::display::DisplayMessage;
+//! # use chip8_system::keyboard::KeyboardMessage;
+//! # use chip8_system::net::{tcp_input, tcp_output};
+//! # use chip8_system::port::connect;
+//! # use chip8_system::system::System;
+//! # use std::net::TcpListener;
+//! # fn main() -> std::io::Result<()> {
+//! let system = System::new();
+//! let (stream, _) = TcpListener::bind("0.0.0.0:7000")?.accept()?;
+//! connect(&system.display, &tcp_input::<DisplayMessage>(stream.try_clone()?));
+//! connect(&tcp_output::<KeyboardMessage>(stream), &system.keyboard);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! WebSocket connections carry both directions, their ports are returned together
+//! once the handshake is done, eg. for a frontend running in a web browser.
+
+use crossbeam_channel::{Receiver, Sender, TryRecvError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+use tungstenite::{Message, WebSocket};
+
+/// Number of messages buffered on each side of a connection.
+const CHANNEL_SIZE: usize = 128;
+
+/// Time a WebSocket waits for a message before sending the pending ones.
+const WEBSOCKET_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Returns an input port whose messages are written to the stream.
+/// The port is disconnected when the stream is closed.
+pub fn tcp_input<T>(stream: TcpStream) -> Sender<T>
+where
+    T: Serialize + Send + 'static,
+{
+    let (sender, receiver) = crossbeam_channel::bounded::<T>(CHANNEL_SIZE);
+
+    thread::spawn(move || {
+        let mut writer = BufWriter::new(stream);
+        for msg in receiver {
+            let sent = serde_json::to_writer(&mut writer, &msg)
+                .map_err(std::io::Error::from)
+                .and_then(|_| writer.write_all(b"\n"))
+                .and_then(|_| writer.flush());
+            if sent.is_err() {
+                break;
+            }
+        }
+    });
+
+    sender
+}
+
+/// Returns an output port emitting the messages read from the stream.
+/// Lines which cannot be decoded are skipped.
+pub fn tcp_output<T>(stream: TcpStream) -> Receiver<T>
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    let (sender, receiver) = crossbeam_channel::bounded(CHANNEL_SIZE);
+
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else { break };
+            if let Ok(msg) = serde_json::from_str(&line) {
+                if sender.send(msg).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    receiver
+}
+
+/// Accepts a WebSocket connection on the stream, and returns its ports:
+/// an input port whose messages are sent to the peer, and an output port emitting
+/// the messages received from it. Both are disconnected when the connection is closed.
+pub fn websocket_accept<I, O>(stream: TcpStream) -> io::Result<(Sender<I>, Receiver<O>)>
+where
+    I: Serialize + Send + 'static,
+    O: DeserializeOwned + Send + 'static,
+{
+    let socket = tungstenite::accept(stream).map_err(handshake_error)?;
+    websocket_ports(socket)
+}
+
+/// Opens a WebSocket connection to `url` (eg. `ws://192.168.1.10:7000`) on the stream,
+/// and returns its ports, as [`websocket_accept`] does.
+pub fn websocket_connect<I, O>(stream: TcpStream, url: &str) -> io::Result<(Sender<I>, Receiver<O>)>
+where
+    I: Serialize + Send + 'static,
+    O: DeserializeOwned + Send + 'static,
+{
+    let (socket, _) = tungstenite::client(url, stream).map_err(handshake_error)?;
+    websocket_ports(socket)
+}
+
+fn handshake_error(e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("WebSocket handshake failed: {}", e),
+    )
+}
+
+/// Reads and writes the socket on a single thread, so that pings are answered between
+/// the messages sent. Reads time out for the messages to send not to wait for the next one
+/// to be received.
+fn websocket_ports<I, O>(mut socket: WebSocket<TcpStream>) -> io::Result<(Sender<I>, Receiver<O>)>
+where
+    I: Serialize + Send + 'static,
+    O: DeserializeOwned + Send + 'static,
+{
+    socket
+        .get_ref()
+        .set_read_timeout(Some(WEBSOCKET_POLL_INTERVAL))?;
+    let (input, to_send) = crossbeam_channel::bounded::<I>(CHANNEL_SIZE);
+    let (received, output) = crossbeam_channel::bounded(CHANNEL_SIZE);
+
+    thread::spawn(move || loop {
+        loop {
+            match to_send.try_recv() {
+                Ok(msg) => {
+                    let Ok(text) = serde_json::to_string(&msg) else {
+                        continue;
+                    };
+                    if socket.send(Message::Text(text)).is_err() {
+                        return;
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                // the input port is dropped, the connection is over
+                Err(TryRecvError::Disconnected) => {
+                    let _ = socket.close(None);
+                    let _ = socket.flush();
+                    return;
+                }
+            }
+        }
+
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                if let Ok(msg) = serde_json::from_str(&text) {
+                    let _ = received.send(msg);
+                }
+            }
+            // pings are answered by the socket, binary messages are skipped
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(_) => return,
+        }
+    });
+
+    Ok((input, output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debug::DebugEvent;
+    use std::net::TcpListener;
+
+    #[test]
+    fn messages_go_through_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        let input = tcp_input::<DebugEvent>(client);
+        let output = tcp_output::<DebugEvent>(server);
+
+        input.send(DebugEvent::Paused { pc: 0x234 }).unwrap();
+        input.send(DebugEvent::Resumed).unwrap();
+
+        let timeout = Duration::from_secs(1);
+        assert_eq!(
+            output.recv_timeout(timeout),
+            Ok(DebugEvent::Paused { pc: 0x234 })
+        );
+        assert_eq!(output.recv_timeout(timeout), Ok(DebugEvent::Resumed));
+    }
+
+    #[test]
+    fn messages_go_through_websockets() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            websocket_accept::<DebugEvent, DebugEvent>(stream).unwrap()
+        });
+        let stream = TcpStream::connect(address).unwrap();
+        let url = format!("ws://{}", address);
+        let (client_input, client_output) =
+            websocket_connect::<DebugEvent, DebugEvent>(stream, &url).unwrap();
+        let (server_input, server_output) = server.join().unwrap();
+
+        client_input.send(DebugEvent::Paused { pc: 0x234 }).unwrap();
+        server_input.send(DebugEvent::Resumed).unwrap();
+
+        let timeout = Duration::from_secs(1);
+        assert_eq!(
+            server_output.recv_timeout(timeout),
+            Ok(DebugEvent::Paused { pc: 0x234 })
+        );
+        assert_eq!(client_output.recv_timeout(timeout), Ok(DebugEvent::Resumed));
+    }
+}
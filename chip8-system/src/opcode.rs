@@ -1,8 +1,10 @@
-use crate::system::VReg;
+use crate::cpu::VReg;
+use core::fmt;
 use num_traits::FromPrimitive;
 
+/// A decoded instruction, displayed with the syntax of the assembler.
 #[derive(Debug, PartialEq)]
-pub(crate) enum Instr {
+pub enum Instr {
     ClearDisplay,
     Return,
     Jump(u16),
@@ -37,6 +39,144 @@ pub(crate) enum Instr {
     LoadBCD(VReg),
     SaveRegs(VReg),
     LoadRegs(VReg),
+    // SUPER-CHIP
+    ScrollDown(u8),
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    LowRes,
+    HighRes,
+    LoadLargeSprite(VReg),
+    SaveFlags(VReg),
+    LoadFlags(VReg),
+    // MegaChip
+    MegaOff,
+    MegaOn,
+    /// Loads a 24 bit address in I, made of this high byte and of the next word.
+    LoadLongI(u8),
+    LoadPalette(u8),
+    SpriteWidth(u8),
+    SpriteHeight(u8),
+    ScreenAlpha(u8),
+    PlaySound(u8),
+    StopSound,
+    BlendMode(u8),
+    CollisionColor(u8),
+    ScrollUp(u8),
+    // XO-CHIP
+    LoadAudioPattern,
+    SetPitch(VReg),
+    /// Calls a machine routine registered with `System::register_routine`.
+    MachineCall(u16),
+}
+
+impl Instr {
+    /// Returns true if the instruction has an effect outside of the CPU registers,
+    /// or depends on something other than the CPU registers and memory.
+    pub fn has_side_effects(&self) -> bool {
+        matches!(
+            self,
+            Instr::ClearDisplay
+                | Instr::Random(..)
+                | Instr::Draw(..)
+                | Instr::SkipKeyPressed(_)
+                | Instr::SkipKeyNotPressed(_)
+                | Instr::WaitKeyPress(_)
+                | Instr::SetDelayTimer(_)
+                | Instr::SetSoundTimer(_)
+                | Instr::LoadBCD(_)
+                | Instr::SaveRegs(_)
+                | Instr::ScrollDown(_)
+                | Instr::ScrollRight
+                | Instr::ScrollLeft
+                | Instr::Exit
+                | Instr::LowRes
+                | Instr::HighRes
+                | Instr::SaveFlags(_)
+                | Instr::LoadFlags(_)
+                | Instr::MegaOff
+                | Instr::MegaOn
+                | Instr::LoadPalette(_)
+                | Instr::SpriteWidth(_)
+                | Instr::SpriteHeight(_)
+                | Instr::ScreenAlpha(_)
+                | Instr::PlaySound(_)
+                | Instr::StopSound
+                | Instr::BlendMode(_)
+                | Instr::CollisionColor(_)
+                | Instr::ScrollUp(_)
+                | Instr::LoadAudioPattern
+                | Instr::SetPitch(_)
+                | Instr::MachineCall(_)
+        )
+    }
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instr::ClearDisplay => write!(f, "cls"),
+            Instr::Return => write!(f, "ret"),
+            Instr::Jump(nnn) => write!(f, "jp {:#05x}", nnn),
+            Instr::Call(nnn) => write!(f, "call {:#05x}", nnn),
+            Instr::SkipEqImm(x, kk) => write!(f, "se {}, {:#04x}", x, kk),
+            Instr::SkipNotEqImm(x, kk) => write!(f, "sne {}, {:#04x}", x, kk),
+            Instr::SkipEqReg(x, y) => write!(f, "se {}, {}", x, y),
+            Instr::LoadImm(x, kk) => write!(f, "ld {}, {:#04x}", x, kk),
+            Instr::AddImm(x, kk) => write!(f, "add {}, {:#04x}", x, kk),
+            Instr::LoadReg(x, y) => write!(f, "ld {}, {}", x, y),
+            Instr::OrReg(x, y) => write!(f, "or {}, {}", x, y),
+            Instr::AndReg(x, y) => write!(f, "and {}, {}", x, y),
+            Instr::XorReg(x, y) => write!(f, "xor {}, {}", x, y),
+            Instr::AddReg(x, y) => write!(f, "add {}, {}", x, y),
+            Instr::SubReg(x, y) => write!(f, "sub {}, {}", x, y),
+            Instr::ShiftRight(x, y) => write!(f, "shr {}, {}", x, y),
+            Instr::SubN(x, y) => write!(f, "subn {}, {}", x, y),
+            Instr::ShiftLeft(x, y) => write!(f, "shl {}, {}", x, y),
+            Instr::SkipNotEqReg(x, y) => write!(f, "sne {}, {}", x, y),
+            Instr::LoadI(nnn) => write!(f, "ld i, {:#05x}", nnn),
+            Instr::JumpV0(nnn) => write!(f, "jp v0, {:#05x}", nnn),
+            Instr::Random(x, kk) => write!(f, "rnd {}, {:#04x}", x, kk),
+            Instr::Draw(x, y, n) => write!(f, "drw {}, {}, {}", x, y, n),
+            Instr::SkipKeyPressed(x) => write!(f, "skp {}", x),
+            Instr::SkipKeyNotPressed(x) => write!(f, "skpn {}", x),
+            Instr::LoadDelayTimer(x) => write!(f, "ld {}, dt", x),
+            Instr::WaitKeyPress(x) => write!(f, "ld {}, k", x),
+            Instr::SetDelayTimer(x) => write!(f, "ld dt, {}", x),
+            Instr::SetSoundTimer(x) => write!(f, "ld st, {}", x),
+            Instr::AddI(x) => write!(f, "add i, {}", x),
+            Instr::LoadSprite(x) => write!(f, "ld f, {}", x),
+            Instr::LoadBCD(x) => write!(f, "ld b, {}", x),
+            Instr::SaveRegs(x) => write!(f, "ld [i], {}", x),
+            Instr::LoadRegs(x) => write!(f, "ld {}, [i]", x),
+            // not supported by the assembler, named as in Octo
+            Instr::ScrollDown(n) => write!(f, "scroll-down {}", n),
+            Instr::ScrollRight => write!(f, "scroll-right"),
+            Instr::ScrollLeft => write!(f, "scroll-left"),
+            Instr::Exit => write!(f, "exit"),
+            Instr::LowRes => write!(f, "lores"),
+            Instr::HighRes => write!(f, "hires"),
+            Instr::LoadLargeSprite(x) => write!(f, "ld hf, {}", x),
+            Instr::SaveFlags(x) => write!(f, "ld r, {}", x),
+            Instr::LoadFlags(x) => write!(f, "ld {}, r", x),
+            // named as in the MegaChip documentation
+            Instr::MegaOff => write!(f, "megaoff"),
+            Instr::MegaOn => write!(f, "megaon"),
+            Instr::LoadLongI(hi) => write!(f, "ldhi i, {:#04x}", hi),
+            Instr::LoadPalette(n) => write!(f, "ldpal {}", n),
+            Instr::SpriteWidth(n) => write!(f, "sprw {}", n),
+            Instr::SpriteHeight(n) => write!(f, "sprh {}", n),
+            Instr::ScreenAlpha(n) => write!(f, "alpha {}", n),
+            Instr::PlaySound(n) => write!(f, "digisnd {}", n),
+            Instr::StopSound => write!(f, "stopsnd"),
+            Instr::BlendMode(n) => write!(f, "bmode {}", n),
+            Instr::CollisionColor(n) => write!(f, "ccol {}", n),
+            Instr::ScrollUp(n) => write!(f, "scru {}", n),
+            Instr::LoadAudioPattern => write!(f, "audio"),
+            Instr::SetPitch(x) => write!(f, "pitch {}", x),
+            Instr::MachineCall(nnn) => write!(f, "sys {:#05x}", nnn),
+        }
+    }
 }
 
 fn nnn(opcode: u16) -> u16 {
@@ -84,6 +224,50 @@ pub(crate) fn parse_opcode(opcode: u16) -> Option<Instr> {
     }
 }
 
+/// Decodes the SUPER-CHIP 1.1 extensions, along with the original instructions.
+pub(crate) fn parse_schip_opcode(opcode: u16) -> Option<Instr> {
+    match opcode {
+        o if o & 0xFFF0 == 0x00C0 => Some(Instr::ScrollDown(n(o))),
+        0x00FB => Some(Instr::ScrollRight),
+        0x00FC => Some(Instr::ScrollLeft),
+        0x00FD => Some(Instr::Exit),
+        0x00FE => Some(Instr::LowRes),
+        0x00FF => Some(Instr::HighRes),
+        o if o & 0xF0FF == 0xF030 => Some(Instr::LoadLargeSprite(x(o))),
+        o if o & 0xF0FF == 0xF075 => Some(Instr::SaveFlags(x(o))),
+        o if o & 0xF0FF == 0xF085 => Some(Instr::LoadFlags(x(o))),
+        o => parse_opcode(o),
+    }
+}
+
+/// Decodes the MegaChip extensions, along with the SUPER-CHIP instructions.
+pub(crate) fn parse_megachip_opcode(opcode: u16) -> Option<Instr> {
+    match opcode {
+        0x0010 => Some(Instr::MegaOff),
+        0x0011 => Some(Instr::MegaOn),
+        o if o & 0xFFF0 == 0x00B0 => Some(Instr::ScrollUp(n(o))),
+        o if o & 0xFF00 == 0x0100 => Some(Instr::LoadLongI(kk(o))),
+        o if o & 0xFF00 == 0x0200 => Some(Instr::LoadPalette(kk(o))),
+        o if o & 0xFF00 == 0x0300 => Some(Instr::SpriteWidth(kk(o))),
+        o if o & 0xFF00 == 0x0400 => Some(Instr::SpriteHeight(kk(o))),
+        o if o & 0xFF00 == 0x0500 => Some(Instr::ScreenAlpha(kk(o))),
+        o if o & 0xFFF0 == 0x0600 => Some(Instr::PlaySound(n(o))),
+        0x0700 => Some(Instr::StopSound),
+        o if o & 0xFFF0 == 0x0800 => Some(Instr::BlendMode(n(o))),
+        o if o & 0xFF00 == 0x0900 => Some(Instr::CollisionColor(kk(o))),
+        o => parse_schip_opcode(o),
+    }
+}
+
+/// Decodes the XO-CHIP extensions, along with the original instructions.
+pub(crate) fn parse_xo_chip_opcode(opcode: u16) -> Option<Instr> {
+    match opcode {
+        0xF002 => Some(Instr::LoadAudioPattern),
+        o if o & 0xF0FF == 0xF03A => Some(Instr::SetPitch(x(o))),
+        o => parse_opcode(o),
+    }
+}
+
 fn parse_opcode_8(opcode: u16) -> Option<Instr> {
     let lsn = opcode & 0xF;
     let (x, y) = (x(opcode), y(opcode));
@@ -131,8 +315,8 @@ fn parse_opcode_f(opcode: u16) -> Option<Instr> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cpu::VReg::*;
     use crate::opcode::Instr::*;
-    use crate::system::VReg::*;
 
     #[test]
     fn test_parse_opcode() {
@@ -178,6 +362,58 @@ mod tests {
         }
     }
 
+    #[test]
+    fn instructions_are_displayed_as_assembly() {
+        assert_eq!(Jump(0x2A4).to_string(), "jp 0x2a4");
+        assert_eq!(LoadImm(VA, 0x05).to_string(), "ld va, 0x05");
+        assert_eq!(Draw(V1, V2, 5).to_string(), "drw v1, v2, 5");
+        assert_eq!(SaveRegs(V3).to_string(), "ld [i], v3");
+        assert_eq!(SkipKeyNotPressed(V0).to_string(), "skpn v0");
+    }
+
+    #[test]
+    fn test_parse_schip_opcode() {
+        assert_eq!(parse_schip_opcode(0x00C4), Some(ScrollDown(4)));
+        assert_eq!(parse_schip_opcode(0x00FB), Some(ScrollRight));
+        assert_eq!(parse_schip_opcode(0x00FC), Some(ScrollLeft));
+        assert_eq!(parse_schip_opcode(0x00FD), Some(Exit));
+        assert_eq!(parse_schip_opcode(0x00FE), Some(LowRes));
+        assert_eq!(parse_schip_opcode(0x00FF), Some(HighRes));
+        assert_eq!(parse_schip_opcode(0xF530), Some(LoadLargeSprite(V5)));
+        assert_eq!(parse_schip_opcode(0xF775), Some(SaveFlags(V7)));
+        assert_eq!(parse_schip_opcode(0xF785), Some(LoadFlags(V7)));
+        assert_eq!(parse_schip_opcode(0x00E0), Some(ClearDisplay));
+        assert_eq!(parse_opcode(0x00FF), None);
+        assert_eq!(HighRes.to_string(), "hires");
+        assert_eq!(SaveFlags(V3).to_string(), "ld r, v3");
+    }
+
+    #[test]
+    fn test_parse_megachip_opcode() {
+        assert_eq!(parse_megachip_opcode(0x0010), Some(MegaOff));
+        assert_eq!(parse_megachip_opcode(0x0011), Some(MegaOn));
+        assert_eq!(parse_megachip_opcode(0x0102), Some(LoadLongI(0x02)));
+        assert_eq!(parse_megachip_opcode(0x0210), Some(LoadPalette(0x10)));
+        assert_eq!(parse_megachip_opcode(0x0300), Some(SpriteWidth(0)));
+        assert_eq!(parse_megachip_opcode(0x0408), Some(SpriteHeight(8)));
+        assert_eq!(parse_megachip_opcode(0x0602), Some(PlaySound(2)));
+        assert_eq!(parse_megachip_opcode(0x0700), Some(StopSound));
+        assert_eq!(parse_megachip_opcode(0x0801), Some(BlendMode(1)));
+        assert_eq!(parse_megachip_opcode(0x0905), Some(CollisionColor(5)));
+        assert_eq!(parse_megachip_opcode(0x00B3), Some(ScrollUp(3)));
+        assert_eq!(parse_megachip_opcode(0x00FD), Some(Exit));
+        assert_eq!(parse_schip_opcode(0x0011), None);
+        assert_eq!(SpriteWidth(16).to_string(), "sprw 16");
+    }
+
+    #[test]
+    fn test_parse_xo_chip_opcode() {
+        assert_eq!(parse_xo_chip_opcode(0xF002), Some(LoadAudioPattern));
+        assert_eq!(parse_xo_chip_opcode(0xF43A), Some(SetPitch(V4)));
+        assert_eq!(parse_xo_chip_opcode(0xF233), Some(LoadBCD(V2)));
+        assert_eq!(parse_opcode(0xF002), None);
+    }
+
     #[test]
     fn test_parse_bad_opcode() {
         let test_cases = [0x0000, 0x5561, 0x8458, 0x9127, 0xE501, 0xF501];
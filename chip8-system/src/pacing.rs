@@ -0,0 +1,149 @@
+use crate::port::LatestValue;
+use std::time::Duration;
+
+/// Duration of a frame at 60 Hz, which display updates are expected to line up with.
+pub const FRAME_PERIOD: Duration = Duration::from_micros(16_667);
+
+/// Upper bounds of the buckets of `FramePacing::histogram`,
+/// the last bucket holding the intervals which deviate more.
+pub const JITTER_BUCKETS: [Duration; 5] = [
+    Duration::from_millis(1),
+    Duration::from_millis(2),
+    Duration::from_millis(4),
+    Duration::from_millis(8),
+    Duration::from_millis(16),
+];
+
+/// Timing of the display updates over a period, to tell stutter from a frontend.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FramePacing {
+    /// Number of intervals between two updates measured during the period.
+    pub intervals: u64,
+    /// Mean time between two updates.
+    pub mean_interval: Duration,
+    /// Longest time between two updates.
+    pub max_interval: Duration,
+    /// Standard deviation of the time between two updates.
+    pub jitter: Duration,
+    /// Number of intervals by their distance to a whole number of frames,
+    /// bucketed by `JITTER_BUCKETS`.
+    pub histogram: [u64; JITTER_BUCKETS.len() + 1],
+}
+
+impl FramePacing {
+    /// Computes the pacing of updates separated by the given intervals.
+    pub fn from_intervals(intervals: &[Duration]) -> Self {
+        if intervals.is_empty() {
+            return Self::default();
+        }
+        let count = intervals.len() as f64;
+        let mean = intervals.iter().map(Duration::as_secs_f64).sum::<f64>() / count;
+        let variance = intervals
+            .iter()
+            .map(|i| (i.as_secs_f64() - mean).powi(2))
+            .sum::<f64>()
+            / count;
+
+        let mut histogram = [0; JITTER_BUCKETS.len() + 1];
+        for &interval in intervals {
+            let deviation = frame_deviation(interval);
+            let bucket = JITTER_BUCKETS
+                .iter()
+                .position(|&bound| deviation < bound)
+                .unwrap_or(JITTER_BUCKETS.len());
+            histogram[bucket] += 1;
+        }
+
+        Self {
+            intervals: intervals.len() as u64,
+            mean_interval: Duration::from_secs_f64(mean),
+            max_interval: intervals.iter().copied().max().unwrap_or_default(),
+            jitter: Duration::from_secs_f64(variance.sqrt()),
+            histogram,
+        }
+    }
+
+    /// Returns the number of intervals which are off by more than the last bucket bound,
+    /// ie. updates shown about a frame late or early.
+    pub fn stutters(&self) -> u64 {
+        self.histogram[JITTER_BUCKETS.len()]
+    }
+}
+
+/// Returns the distance of an interval to the nearest whole number of frames.
+fn frame_deviation(interval: Duration) -> Duration {
+    let frames = (interval.as_secs_f64() / FRAME_PERIOD.as_secs_f64()).round();
+    let nearest = FRAME_PERIOD.mul_f64(frames);
+    if interval > nearest {
+        interval - nearest
+    } else {
+        nearest - interval
+    }
+}
+
+/// Measures the time between display updates, and emits their pacing once per period.
+#[derive(Default)]
+pub(crate) struct PacingRecorder {
+    // time of the clock of the system at the last update
+    last: Option<Duration>,
+    intervals: Vec<Duration>,
+    output: LatestValue<FramePacing>,
+}
+
+impl PacingRecorder {
+    pub fn record(&mut self, now: Duration) {
+        if let Some(last) = self.last.replace(now) {
+            self.intervals.push(now - last);
+        }
+    }
+
+    /// Forgets the last update, so that a pause is not measured as an interval.
+    pub fn restart(&mut self) {
+        self.last = None;
+    }
+
+    /// Emits the pacing of the period which just ended.
+    pub fn report(&mut self) {
+        self.output
+            .send(FramePacing::from_intervals(&self.intervals));
+        self.intervals.clear();
+    }
+
+    pub fn output(&self) -> crossbeam_channel::Receiver<FramePacing> {
+        self.output.receiver()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals_are_bucketed_by_distance_to_whole_frames() {
+        let ms = Duration::from_millis;
+        let pacing = FramePacing::from_intervals(&[ms(17), ms(33), ms(50), ms(25), ms(20)]);
+        assert_eq!(pacing.intervals, 5);
+        assert_eq!(pacing.max_interval, ms(50));
+        assert!((pacing.mean_interval.as_secs_f64() - 0.029).abs() < 1e-9);
+        // 17, 33 and 50 ms are within a millisecond of 1, 2 and 3 frames
+        assert_eq!(pacing.histogram, [3, 0, 1, 0, 1, 0]);
+        assert_eq!(pacing.stutters(), 0);
+        assert_eq!(FramePacing::from_intervals(&[]), FramePacing::default());
+    }
+
+    #[test]
+    fn pauses_are_not_measured() {
+        let mut recorder = PacingRecorder::default();
+        let pacing = recorder.output();
+        let start = Duration::from_secs(1);
+        recorder.record(start);
+        recorder.record(start + FRAME_PERIOD);
+        recorder.restart();
+        recorder.record(start + Duration::from_secs(5));
+        recorder.report();
+        let report = pacing.try_recv().unwrap();
+        assert_eq!(report.intervals, 1);
+        assert_eq!(report.max_interval, FRAME_PERIOD);
+    }
+}
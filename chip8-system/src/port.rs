@@ -1,7 +1,7 @@
-use crossbeam_channel::{Receiver, Sender, TrySendError};
-use std::sync::atomic::{AtomicBool, Ordering};
+use crossbeam_channel::{select, Receiver, Select, Sender, TrySendError};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::thread;
+use std::thread::{self, JoinHandle};
 
 pub trait InputPort<TInput> {
     fn input(&self) -> Sender<TInput>;
@@ -11,9 +11,25 @@ pub trait OutputPort<TOutput> {
     fn output(&self) -> Receiver<TOutput>;
 }
 
+/// A channel sender can be used directly as an input port.
+impl<T> InputPort<T> for Sender<T> {
+    fn input(&self) -> Sender<T> {
+        self.clone()
+    }
+}
+
+/// A channel receiver can be used directly as an output port.
+impl<T> OutputPort<T> for Receiver<T> {
+    fn output(&self) -> Receiver<T> {
+        self.clone()
+    }
+}
+
 pub struct PortAdapter<TFrom, TInto> {
     input_receiver: Receiver<TFrom>,
     output_sender: Sender<TInto>,
+    // only the most recent message matters, see `connect_latest`
+    latest: bool,
 }
 
 impl<TFrom, TInto> PortAdapter<TFrom, TInto>
@@ -22,19 +38,55 @@ where
     TInto: From<TFrom> + Send + 'static,
 {
     fn start(self) {
-        thread::spawn(move || {
-            self.run();
+        thread::spawn(move || match self.latest {
+            true => self.run_latest(),
+            false => self.run(),
         });
     }
 
     fn run(&self) {
+        // blocking send, so that a slow consumer applies backpressure
+        // to the producer instead of losing the newest messages
         while let Ok(msg) = self.input_receiver.recv() {
-            let to = msg.into();
-            if let Err(TrySendError::Disconnected(_)) = self.output_sender.try_send(to) {
+            if self.output_sender.send(msg.into()).is_err() {
                 break;
             }
         }
     }
+
+    /// Forwards the messages without blocking the producer: while the consumer is busy,
+    /// the message waiting to be sent is replaced by each newer one.
+    fn run_latest(&self) {
+        let mut pending = None;
+        loop {
+            let msg = match pending.take() {
+                Some(msg) => msg,
+                None => match self.input_receiver.recv() {
+                    Ok(msg) => msg.into(),
+                    Err(_) => break,
+                },
+            };
+
+            let mut sel = Select::new();
+            let send = sel.send(&self.output_sender);
+            sel.recv(&self.input_receiver);
+            let oper = sel.select();
+            if oper.index() == send {
+                if oper.send(&self.output_sender, msg).is_err() {
+                    break;
+                }
+            } else {
+                match oper.recv(&self.input_receiver) {
+                    Ok(newer) => pending = Some(newer.into()),
+                    // the producer is gone, its last message is still delivered
+                    Err(_) => {
+                        let _ = self.output_sender.send(msg);
+                        break;
+                    }
+                }
+            }
+        }
+    }
 }
 
 pub fn connect<F, T, TFrom, TInto>(from: &F, to: &T)
@@ -47,10 +99,125 @@ where
     PortAdapter {
         input_receiver: from.output(),
         output_sender: to.input(),
+        latest: false,
     }
     .start();
 }
 
+/// Connects ports where only the most recent message matters, eg. states published
+/// periodically: messages the consumer isn't ready for are replaced by newer ones,
+/// instead of holding back the producer as `connect` does.
+pub fn connect_latest<F, T, TFrom, TInto>(from: &F, to: &T)
+where
+    F: OutputPort<TFrom>,
+    T: InputPort<TInto>,
+    TFrom: Send + 'static,
+    TInto: From<TFrom> + Send + 'static,
+{
+    PortAdapter {
+        input_receiver: from.output(),
+        output_sender: to.input(),
+        latest: true,
+    }
+    .start();
+}
+
+/// A connection started by `forward`, which can be joined once the messages
+/// already sent to it have been delivered.
+pub(crate) struct Forwarder {
+    finish: Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl Forwarder {
+    /// Delivers the messages waiting in the input, then stops forwarding.
+    pub fn join(self) {
+        drop(self.finish);
+        let _ = self.thread.join();
+    }
+}
+
+/// Connects ports as `connect` does, with a blocking send, and returns the forwarder
+/// so that its owner knows when all the messages have gone through.
+pub(crate) fn forward<F, T, TFrom, TInto>(from: &F, to: &T) -> Forwarder
+where
+    F: OutputPort<TFrom>,
+    T: InputPort<TInto>,
+    TFrom: Send + 'static,
+    TInto: From<TFrom> + Send + 'static,
+{
+    let input = from.output();
+    let output = to.input();
+    let (finish, finished) = crossbeam_channel::bounded::<()>(0);
+    let thread = thread::spawn(move || loop {
+        select! {
+            recv(input) -> msg => match msg {
+                Ok(msg) if output.send(msg.into()).is_ok() => {}
+                _ => break,
+            },
+            recv(finished) -> _ => {
+                for msg in input.try_iter() {
+                    if output.send(msg.into()).is_err() {
+                        break;
+                    }
+                }
+                break;
+            }
+        }
+    });
+    Forwarder { finish, thread }
+}
+
+/// A single slot channel where sending a new value replaces the pending one
+/// if it has not been received yet, so that the receiving end always
+/// gets the most recent value.
+pub(crate) struct LatestValue<T> {
+    sender: Sender<T>,
+    receiver: Receiver<T>,
+    // number of values replaced before being received
+    dropped: AtomicU64,
+}
+
+impl<T> Default for LatestValue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> LatestValue<T> {
+    pub fn new() -> Self {
+        let (sender, receiver) = crossbeam_channel::bounded(1);
+        Self {
+            sender,
+            receiver,
+            dropped: Default::default(),
+        }
+    }
+
+    pub fn send(&self, mut msg: T) {
+        loop {
+            match self.sender.try_send(msg) {
+                Err(TrySendError::Full(m)) => {
+                    // drop the stale value and try again
+                    if self.receiver.try_recv().is_ok() {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                    msg = m;
+                }
+                _ => break,
+            }
+        }
+    }
+
+    pub fn receiver(&self) -> Receiver<T> {
+        self.receiver.clone()
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
 #[derive(Clone)]
 pub struct ControlPin(Arc<AtomicBool>);
 
@@ -69,3 +236,54 @@ impl Default for ControlPin {
         Self(Arc::new(Default::default()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latest_value_overwrites_pending_value() {
+        let l = LatestValue::new();
+        let r = l.receiver();
+        l.send(1);
+        l.send(2);
+        l.send(3);
+        assert_eq!(r.try_recv(), Ok(3));
+        assert!(r.try_recv().is_err());
+        assert_eq!(l.dropped(), 2);
+    }
+
+    #[test]
+    fn latest_connection_delivers_most_recent_message() {
+        let (input, from) = crossbeam_channel::bounded::<u32>(1);
+        let (to, output) = crossbeam_channel::bounded::<u32>(1);
+        connect_latest(&from, &to);
+        drop(to);
+
+        input.send(1).unwrap();
+        assert_eq!(output.recv(), Ok(1));
+        // the consumer is busy, the producer isn't held back
+        for n in 2..100 {
+            input.send(n).unwrap();
+        }
+        drop(input);
+        let received: Vec<u32> = output.iter().collect();
+        assert_eq!(received.last(), Some(&99));
+        assert!(received.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn joined_forwarders_have_delivered_all_messages() {
+        let (input, from) = crossbeam_channel::unbounded::<u32>();
+        let (to, output) = crossbeam_channel::unbounded::<u32>();
+        let forwarder = forward(&from, &to);
+        for n in 0..100 {
+            input.send(n).unwrap();
+        }
+        forwarder.join();
+        input.send(100).unwrap();
+
+        let received: Vec<u32> = output.try_iter().collect();
+        assert_eq!(received, (0..100).collect::<Vec<_>>());
+    }
+}
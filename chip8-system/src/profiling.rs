@@ -0,0 +1,147 @@
+//! Statistics on the instructions executed by a program, by kind of instruction.
+
+use crate::opcode::Instr;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem::{discriminant, Discriminant};
+use std::time::Duration;
+
+/// The instructions of one kind executed by a program, eg. all the `LoadImm`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProfileEntry {
+    /// The kind of instruction, as named by `Instr`.
+    pub instruction: String,
+    /// Number of instructions executed.
+    pub count: u64,
+    /// Time spent by the host executing them.
+    pub time: Duration,
+}
+
+/// Statistics collected while a system runs with `SystemOptionsBuilder::profile_instructions`,
+/// the kinds of instructions taking the most time first.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProfileReport {
+    pub entries: Vec<ProfileEntry>,
+}
+
+impl ProfileReport {
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the total time spent executing instructions.
+    pub fn time(&self) -> Duration {
+        self.entries.iter().map(|e| e.time).sum()
+    }
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.time().as_secs_f64();
+        writeln!(
+            f,
+            "{:<20} {:>12} {:>12} {:>7}",
+            "instruction", "count", "time (ms)", "time"
+        )?;
+        for e in &self.entries {
+            let share = match total {
+                t if t > 0.0 => e.time.as_secs_f64() / t * 100.0,
+                _ => 0.0,
+            };
+            writeln!(
+                f,
+                "{:<20} {:>12} {:>12.3} {:>6.1}%",
+                e.instruction,
+                e.count,
+                e.time.as_secs_f64() * 1000.0,
+                share
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts the instructions executed, and the time they took, by kind.
+#[derive(Default)]
+pub(crate) struct Profiler {
+    entries: HashMap<Discriminant<Instr>, ProfileEntry>,
+}
+
+impl Profiler {
+    /// Returns the kind of `instr`, to be passed to `record` once it has been executed.
+    pub fn kind(&mut self, instr: &Instr) -> Discriminant<Instr> {
+        let kind = discriminant(instr);
+        self.entries.entry(kind).or_insert_with(|| ProfileEntry {
+            instruction: kind_name(instr),
+            count: 0,
+            time: Duration::ZERO,
+        });
+        kind
+    }
+
+    pub fn record(&mut self, kind: Discriminant<Instr>, time: Duration) {
+        if let Some(entry) = self.entries.get_mut(&kind) {
+            entry.count += 1;
+            entry.time += time;
+        }
+    }
+
+    pub fn report(&self) -> ProfileReport {
+        let mut entries: Vec<ProfileEntry> = self.entries.values().cloned().collect();
+        entries.sort_by(|a, b| {
+            b.time
+                .cmp(&a.time)
+                .then_with(|| a.instruction.cmp(&b.instruction))
+        });
+        ProfileReport { entries }
+    }
+}
+
+/// Returns the name of the variant of `instr`, without its operands.
+fn kind_name(instr: &Instr) -> String {
+    let debug = format!("{:?}", instr);
+    debug
+        .split(['(', ' '])
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::VReg;
+
+    #[test]
+    fn instructions_are_counted_by_kind() {
+        let mut profiler = Profiler::default();
+        for (instr, ms) in [
+            (Instr::LoadImm(VReg::V0, 1), 1),
+            (Instr::Jump(0x200), 1),
+            (Instr::LoadImm(VReg::V1, 2), 2),
+        ] {
+            let kind = profiler.kind(&instr);
+            profiler.record(kind, Duration::from_millis(ms));
+        }
+
+        let report = profiler.report();
+        assert_eq!(
+            report.entries,
+            [
+                ProfileEntry {
+                    instruction: "LoadImm".to_string(),
+                    count: 2,
+                    time: Duration::from_millis(3),
+                },
+                ProfileEntry {
+                    instruction: "Jump".to_string(),
+                    count: 1,
+                    time: Duration::from_millis(1),
+                },
+            ]
+        );
+        assert_eq!(report.time(), Duration::from_millis(4));
+    }
+}
@@ -0,0 +1,88 @@
+//! Sources of the random numbers of `CXKK`.
+//!
+//! Systems draw them from a `SeededRandom` by default, tests can script them
+//! with a `ScriptedRandom` instead.
+
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+pub trait RandomSource: Send + Sync {
+    /// Returns the next random byte.
+    fn next_byte(&mut self) -> u8;
+
+    /// Returns a copy of the source in its current state, which returns the same bytes,
+    /// so that snapshots replay identically.
+    fn box_clone(&self) -> Box<dyn RandomSource>;
+}
+
+impl Clone for Box<dyn RandomSource> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// Pseudo random bytes, the same for a given seed.
+#[derive(Clone)]
+pub struct SeededRandom(SmallRng);
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> Self {
+        Self(SmallRng::seed_from_u64(seed))
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_byte(&mut self) -> u8 {
+        self.0.gen()
+    }
+
+    fn box_clone(&self) -> Box<dyn RandomSource> {
+        Box::new(self.clone())
+    }
+}
+
+/// Returns the given bytes in order, starting over after the last one.
+#[derive(Clone)]
+pub struct ScriptedRandom {
+    bytes: Vec<u8>,
+    next: usize,
+}
+
+impl ScriptedRandom {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self { bytes, next: 0 }
+    }
+}
+
+impl RandomSource for ScriptedRandom {
+    fn next_byte(&mut self) -> u8 {
+        let Some(&b) = self.bytes.get(self.next) else {
+            return 0;
+        };
+        self.next = (self.next + 1) % self.bytes.len();
+        b
+    }
+
+    fn box_clone(&self) -> Box<dyn RandomSource> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_are_cloned_in_their_current_state() {
+        let mut scripted = ScriptedRandom::new(vec![1, 2, 3]);
+        assert_eq!(scripted.next_byte(), 1);
+        let mut copy = scripted.box_clone();
+        assert_eq!([scripted.next_byte(), scripted.next_byte()], [2, 3]);
+        assert_eq!([copy.next_byte(), copy.next_byte()], [2, 3]);
+        assert_eq!(scripted.next_byte(), 1);
+
+        let mut seeded = SeededRandom::new(7);
+        let mut copy = seeded.box_clone();
+        assert_eq!(seeded.next_byte(), copy.next_byte());
+    }
+}
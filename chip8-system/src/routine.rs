@@ -0,0 +1,38 @@
+//! Machine routines called by programs with `0NNN`, which ran native code on the original
+//! hardware. Library users register them to emulate specific routines or to offer host calls.
+
+use std::collections::HashMap;
+
+/// The machine state a routine can read and change.
+pub struct RoutineContext<'a> {
+    pub v: &'a mut [u8; 16],
+    pub i: &'a mut u16,
+    pub memory: &'a mut [u8],
+}
+
+type Routine = Box<dyn FnMut(RoutineContext) + Send>;
+
+/// The routines registered with a system, by address.
+#[derive(Default)]
+pub(crate) struct Routines(HashMap<u16, Routine>);
+
+impl Routines {
+    pub fn register(&mut self, address: u16, routine: impl FnMut(RoutineContext) + Send + 'static) {
+        self.0.insert(address, Box::new(routine));
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        self.0.contains_key(&address)
+    }
+
+    /// Calls the routine at `address`, returns false if there is none.
+    pub fn call(&mut self, address: u16, context: RoutineContext) -> bool {
+        match self.0.get_mut(&address) {
+            Some(routine) => {
+                routine(context);
+                true
+            }
+            None => false,
+        }
+    }
+}
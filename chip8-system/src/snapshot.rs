@@ -0,0 +1,91 @@
+use crate::cpu::Cpu;
+use crate::display::{Framebuffer, MegaScreen};
+use crate::memory::Memory;
+use crate::random::RandomSource;
+use crate::system::RplFlags;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Time between two snapshots kept for rewinding.
+pub(crate) const REWIND_INTERVAL: Duration = Duration::from_secs(1);
+/// Number of snapshots kept for rewinding.
+pub(crate) const REWIND_DEPTH: usize = 30;
+
+/// A complete copy of the state of the machine, which can be restored later.
+#[derive(Clone)]
+pub struct Snapshot {
+    pub(crate) cpu: Cpu,
+    pub(crate) memory: Memory,
+    pub(crate) framebuffer: Framebuffer,
+    pub(crate) delay_timer: u8,
+    pub(crate) sound_timer: u8,
+    // needed to replay frames identically
+    pub(crate) rng: Box<dyn RandomSource>,
+    pub(crate) frame_budget: f64,
+    pub(crate) tick_countdown: u32,
+    pub(crate) rpl_flags: RplFlags,
+    pub(crate) mega: Option<MegaScreen>,
+}
+
+/// Ring buffer of the most recent periodic snapshots.
+pub(crate) struct RewindBuffer {
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl RewindBuffer {
+    pub fn new() -> Self {
+        Self {
+            snapshots: VecDeque::with_capacity(REWIND_DEPTH),
+        }
+    }
+
+    pub fn push(&mut self, snapshot: Snapshot) {
+        if self.snapshots.len() == REWIND_DEPTH {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(snapshot);
+    }
+
+    /// Removes and returns the most recent snapshot.
+    pub fn pop(&mut self) -> Option<Snapshot> {
+        self.snapshots.pop_back()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::SeededRandom;
+
+    fn snapshot(pc: u16) -> Snapshot {
+        Snapshot {
+            cpu: Cpu::new(pc),
+            memory: Memory::new(),
+            framebuffer: Framebuffer::new(),
+            delay_timer: 0,
+            sound_timer: 0,
+            rng: Box::new(SeededRandom::new(0)),
+            frame_budget: 0.0,
+            tick_countdown: 0,
+            rpl_flags: Default::default(),
+            mega: None,
+        }
+    }
+
+    #[test]
+    fn rewind_buffer_keeps_latest_snapshots() {
+        let mut buffer = RewindBuffer::new();
+        for pc in 0..(REWIND_DEPTH + 2) as u16 {
+            buffer.push(snapshot(pc));
+        }
+
+        let mut pcs = Vec::new();
+        while let Some(s) = buffer.pop() {
+            pcs.push(s.cpu.pc);
+        }
+
+        assert_eq!(pcs.len(), REWIND_DEPTH);
+        assert_eq!(pcs[0], REWIND_DEPTH as u16 + 1);
+        assert_eq!(pcs[REWIND_DEPTH - 1], 2);
+    }
+}
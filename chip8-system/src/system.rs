@@ -1,20 +1,48 @@
-use crate::display::{font_sprites, DisplayBuffer, FONT_SPRITES_ADDRESS};
-use crate::keyboard::{Key, Keyboard, KeyboardController};
-use crate::memory::{Memory, RESERVED_SIZE};
-use crate::opcode::{parse_opcode, Instr};
-use crate::port::ControlPin;
+use crate::audio::{AudioPattern, PATTERN_SIZE};
+use crate::cheat::Cheats;
+use crate::clock::{default_clock, Clock, Pacer};
+use crate::cpu::{Cpu, CpuState, VReg, VRegBank, STACK_SIZE};
+#[cfg(feature = "serde")]
+use crate::database::RomProfile;
+use crate::debug::{
+    Backtrace, CallStack, DebugCommand, DebugEvent, Debugger, Frame, MemoryView, MemoryViewer,
+    PcHistory, Register, StackFrame, StateDiff, Symbols,
+};
+use crate::diagnostic::{CodeTracker, Diagnostic};
+use crate::dialect::{jump_register, Variant};
+use crate::display::{
+    font_sprites, large_font_sprites, DisplayBuffer, Framebuffer, MegaScreen, DISPLAY_BUFFER_SIZE,
+    DISPLAY_HEIGHT, DISPLAY_WIDTH, EXTENDED_DISPLAY_WIDTH, FONT_SPRITES_ADDRESS,
+    HIRES_DISPLAY_HEIGHT, LARGE_FONT_SPRITES_ADDRESS,
+};
+use crate::event::SystemEvent;
+use crate::keyboard::{Key, KeyRepeat, Keyboard, KeyboardController, KeyboardState};
+use crate::memory::{
+    Memory, MemoryLayout, WriteOverflow, MAX_MEMORY_SIZE, MEMORY_SIZE, RESERVED_SIZE,
+};
+use crate::metrics::{Counters, MetricsCollector, SystemMetrics};
+use crate::movie::{program_hash, Movie, MovieError, MoviePlayer, MovieState};
+use crate::opcode::Instr;
+use crate::pacing::FramePacing;
+use crate::port::{forward, ControlPin, Forwarder, LatestValue, OutputPort};
+use crate::profiling::{ProfileReport, Profiler};
+use crate::random::{RandomSource, SeededRandom};
+use crate::routine::{RoutineContext, Routines};
+use crate::snapshot::{RewindBuffer, Snapshot, REWIND_INTERVAL};
 use crate::timer::{CountDownTimer, ObservableTimer};
+use crate::timing::{vip_cycles, Timing, VIP_CYCLES_PER_SECOND};
 use bitflags::bitflags;
-use num_derive::FromPrimitive;
+use bitvec::vec::BitVec;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
 use rand::prelude::SmallRng;
 use rand::{Rng, SeedableRng};
-use spin_sleep::LoopHelper;
 use std::fmt::Debug;
-use std::fs::File;
-use std::io::Read;
-use std::ops::{Index, IndexMut};
+use std::fs;
 use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use std::{io, thread};
 use thiserror::Error;
 
@@ -26,81 +54,187 @@ pub enum SystemError {
     UnknownInstruction(u16),
     #[error("memory read overflow")]
     MemoryReadOverflow,
+    #[error("memory write overflow")]
+    MemoryWriteOverflow,
+    #[error("write to the reserved address {0:#05x}")]
+    MemoryWriteViolation(u16),
     #[error("stack underflow")]
     StackUnderflow,
     #[error("stack overflow")]
     StackOverflow,
-    #[error("jump at current address")]
-    SelfJump,
+    /// Returned by `run_frame` when the program spins in a loop which can't do anything else,
+    /// `run` returns `Ok` instead.
+    #[error("program spinning in an endless loop")]
+    Spinning,
     #[error("interrupted")]
     Interrupted,
+    #[error("address {0:#08x} is beyond the 64 KiB of memory, MegaChip extended memory is not supported")]
+    AddressOutOfRange(u32),
+    /// Returned by `run_frame` when a SUPER-CHIP program exits, `run` returns `Ok` instead.
+    #[error("program exited")]
+    Exited,
+}
+
+impl From<WriteOverflow> for SystemError {
+    fn from(_: WriteOverflow) -> Self {
+        SystemError::MemoryWriteOverflow
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum OptionsError {
+    #[error("CPU frequency must be greater than 0 and less than 5000 Hz, got {0}")]
+    CpuFrequencyOutOfRange(f64),
+    #[error("instructions per frame must be between 1 and 1000, got {0}")]
+    InstructionsPerFrameOutOfRange(u32),
+    #[error("quirks {0:?} are already defined by the {1:?} variant")]
+    ConflictingQuirks(Quirks, Variant),
+    #[error("memory size must be between 4096 and 65536 bytes, got {0}")]
+    MemorySizeOutOfRange(usize),
+    #[error("run-ahead requires the single threaded execution mode")]
+    RunAheadRequiresSingleThreaded,
+    #[error("timers driven by instructions require the single threaded execution mode")]
+    InstructionTimersRequireSingleThreaded,
+    #[error("timers must tick every 1 instruction or more")]
+    InstructionsPerTickOutOfRange,
 }
 
 bitflags! {
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
     pub struct Quirks: u8 {
         const LOAD_STORE_IGNORES_I = 0x1;
         const SHIFT_READS_VX = 0x2;
         const DRAW_WRAPS_PIXELS = 0x4;
+        /// Load and store instructions increment I by X instead of X + 1, as on the CHIP-48.
+        const LOAD_STORE_INCREMENTS_I_BY_X = 0x8;
+        /// BNNN is BXNN, and jumps to XNN + VX.
+        const JUMP_USES_VX = 0x10;
     }
 }
 
-#[derive(Copy, Clone, Debug, FromPrimitive, PartialEq)]
-pub(crate) enum VReg {
-    V0 = 0x0,
-    V1 = 0x1,
-    V2 = 0x2,
-    V3 = 0x3,
-    V4 = 0x4,
-    V5 = 0x5,
-    V6 = 0x6,
-    V7 = 0x7,
-    V8 = 0x8,
-    V9 = 0x9,
-    VA = 0xa,
-    VB = 0xb,
-    VC = 0xc,
-    VD = 0xd,
-    VE = 0xe,
-    VF = 0xf,
-}
+/// Maximum rate at which the run loop wakes up to execute a batch of instructions.
+const MAX_SLICE_RATE_HZ: f64 = 1000.0;
 
-type VRegBank = [u8; 16];
+/// Maximum number of time slices the run loop is allowed to catch up on.
+const MAX_CATCH_UP_SLICES: f64 = 4.0;
 
-impl Index<VReg> for VRegBank {
-    type Output = u8;
+/// Rate of the virtual clock in single threaded mode, which matches the timers.
+const FRAME_RATE_HZ: f64 = 60.0;
 
-    fn index(&self, index: VReg) -> &Self::Output {
-        let idx = index as usize;
-        self.index(idx)
-    }
-}
+/// Longest loop without side effects, in instructions, after which the system halts by default.
+const DEFAULT_SPIN_LOOP_LENGTH: u32 = 4;
 
-impl IndexMut<VReg> for VRegBank {
-    fn index_mut(&mut self, index: VReg) -> &mut Self::Output {
-        let idx = index as usize;
-        self.index_mut(idx)
-    }
+/// Maximum time the CPU sleeps while idle, so that it can still be stopped.
+const IDLE_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Speed multiplier of the CPU while turbo is enabled.
+const TURBO_FACTOR: f64 = 4.0;
+
+/// Period at which metrics are emitted.
+const METRICS_PERIOD: Duration = Duration::from_secs(1);
+
+/// First instruction of hires CHIP-8 programs, a jump to the end of the patched interpreter.
+pub const HIRES_STARTUP: [u8; 2] = [0x12, 0x60];
+
+/// Address where hires CHIP-8 programs actually start.
+pub const HIRES_START_ADDRESS: u16 = 0x2C0;
+
+/// Clears the screen in hires CHIP-8 programs, as a call to the patched interpreter.
+pub const HIRES_CLEAR_DISPLAY: u16 = 0x0230;
+
+/// Size of a 16x16 SUPER-CHIP sprite, two bytes per row.
+const LARGE_SPRITE_SIZE: u8 = 32;
+
+/// Number of pixels scrolled horizontally by the SUPER-CHIP scroll instructions.
+pub(crate) const HORIZONTAL_SCROLL: usize = 4;
+
+/// The flag registers of the HP48 calculator, where SUPER-CHIP programs save V0 to VX
+/// with `FX75`, eg. to keep high scores.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RplFlags(pub [u8; 16]);
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ExecutionMode {
+    /// The CPU runs in real time, timers tick on their own threads.
+    #[default]
+    Threaded,
+    /// The CPU, timers and display all run on the calling thread,
+    /// driven by a virtual clock advancing one frame at a time.
+    SingleThreaded,
 }
 
-const STACK_SIZE: usize = 16;
+/// Why `System::run_for` or `System::run_until` has returned.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RunStop {
+    /// The instructions have all been executed, or the condition has been met.
+    Done,
+    /// The system has been paused, eg. by a breakpoint.
+    Paused,
+    /// The program has exited.
+    Exited,
+    /// The program is spinning in an endless loop.
+    Spinning,
+}
 
-struct Cpu {
-    pc: u16,
-    v: VRegBank,
-    i: u16,
-    stack: Vec<u16>,
+/// How fast the CPU executes instructions.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Speed {
+    /// A number of instructions per second.
+    Frequency(f64),
+    /// A fixed number of instructions per 60 Hz frame, as in Octo.
+    InstructionsPerFrame(u32),
 }
 
+#[derive(Clone)]
 pub struct SystemOptions {
-    cpu_frequency_hz: f64,
-    quirks: Quirks,
+    pub(crate) speed: Speed,
+    pub(crate) timing: Timing,
+    pub(crate) quirks: Quirks,
+    pub(crate) variant: Variant,
+    pub(crate) execution_mode: ExecutionMode,
+    pub(crate) memory_size: usize,
+    pub(crate) memory_layout: MemoryLayout,
+    debug_state_interval: u32,
+    memory_view: bool,
+    run_ahead: bool,
+    key_repeat: KeyRepeat,
+    min_press_frames: u8,
+    seed: Option<u64>,
+    spin_loop_length: u32,
+    random: Option<Box<dyn RandomSource>>,
+    instructions_per_tick: Option<u32>,
+    protect_reserved: bool,
+    profile_instructions: bool,
+    clock: Arc<dyn Clock>,
 }
 
 impl Default for SystemOptions {
     fn default() -> Self {
         Self {
-            cpu_frequency_hz: 500.0,
+            speed: Speed::Frequency(500.0),
+            timing: Default::default(),
             quirks: Quirks::empty(),
+            variant: Default::default(),
+            execution_mode: Default::default(),
+            memory_size: MEMORY_SIZE,
+            memory_layout: Default::default(),
+            debug_state_interval: 0,
+            memory_view: false,
+            run_ahead: false,
+            key_repeat: KeyRepeat::Off,
+            min_press_frames: 0,
+            seed: None,
+            spin_loop_length: DEFAULT_SPIN_LOOP_LENGTH,
+            random: None,
+            instructions_per_tick: None,
+            protect_reserved: false,
+            profile_instructions: false,
+            clock: default_clock(),
         }
     }
 }
@@ -110,27 +244,370 @@ impl SystemOptions {
         Default::default()
     }
 
+    pub fn builder() -> SystemOptionsBuilder {
+        Default::default()
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// Returns the size of the largest program which fits in the memory of the system.
+    pub fn max_program_size(&self) -> usize {
+        self.memory_size - RESERVED_SIZE
+    }
+
+    /// Returns true if instructions are executed in one batch at the start of each frame.
+    fn batches_frames(&self) -> bool {
+        self.timing == Timing::FlatRate && matches!(self.speed, Speed::InstructionsPerFrame(_))
+    }
+
+    /// Returns the cycles executed per second, each instruction takes one cycle at a flat rate.
+    fn cycles_per_second(&self) -> f64 {
+        match (self.timing, self.speed) {
+            (Timing::VipCycles, _) => VIP_CYCLES_PER_SECOND,
+            (Timing::FlatRate, Speed::Frequency(f)) => f,
+            (Timing::FlatRate, Speed::InstructionsPerFrame(n)) => n as f64 * FRAME_RATE_HZ,
+        }
+    }
+
+    fn cycles_per_frame(&self) -> f64 {
+        self.cycles_per_second() / FRAME_RATE_HZ
+    }
+}
+
+/// Builder for `SystemOptions`, the options are validated when built.
+#[derive(Default)]
+pub struct SystemOptionsBuilder {
+    options: SystemOptions,
+}
+
+impl SystemOptionsBuilder {
     pub fn cpu_frequency_hz(&mut self, f: f64) -> &mut Self {
-        self.cpu_frequency_hz = if f < 5000.0 && f > 0.0 { f } else { 500.0 };
+        self.options.speed = Speed::Frequency(f);
+        self
+    }
+
+    /// Executes `n` instructions per 60 Hz frame instead of following a frequency,
+    /// instructions are then executed in one batch at the start of each frame.
+    pub fn instructions_per_frame(&mut self, n: u32) -> &mut Self {
+        self.options.speed = Speed::InstructionsPerFrame(n);
         self
     }
 
     pub fn quirk(&mut self, quirk: Quirks) -> &mut Self {
-        self.quirks |= quirk;
+        self.options.quirks |= quirk;
+        self
+    }
+
+    /// Counts the time taken by each instruction as on the COSMAC VIP instead of
+    /// following the speed, for programs depending on the timing of the original.
+    pub fn timing(&mut self, timing: Timing) -> &mut Self {
+        self.options.timing = timing;
+        self
+    }
+
+    /// Selects the instruction set variant.
+    /// Quirks only apply to instructions whose behavior is not defined by the variant.
+    pub fn variant(&mut self, variant: Variant) -> &mut Self {
+        self.options.variant = variant;
+        self
+    }
+
+    pub fn execution_mode(&mut self, mode: ExecutionMode) -> &mut Self {
+        self.options.execution_mode = mode;
+        self
+    }
+
+    pub fn memory_size(&mut self, size: usize) -> &mut Self {
+        self.options.memory_size = size;
+        self
+    }
+
+    /// Keeps the stack and the display in memory as the original interpreter did,
+    /// which leaves less memory to programs.
+    pub fn memory_layout(&mut self, layout: MemoryLayout) -> &mut Self {
+        self.options.memory_layout = layout;
+        self
+    }
+
+    /// Publishes the CPU state on the debug output port every `n` instructions,
+    /// 0 disables it.
+    pub fn debug_state_interval(&mut self, n: u32) -> &mut Self {
+        self.options.debug_state_interval = n;
+        self
+    }
+
+    /// Publishes a `MemoryView` about 60 times per second while running,
+    /// and each time the system is paused, for memory viewers.
+    pub fn memory_view(&mut self, enabled: bool) -> &mut Self {
+        self.options.memory_view = enabled;
         self
     }
+
+    /// Presents each frame one frame ahead of the emulated one, which removes
+    /// one frame of input latency. Requires the single threaded execution mode.
+    pub fn run_ahead(&mut self, enabled: bool) -> &mut Self {
+        self.options.run_ahead = enabled;
+        self
+    }
+
+    /// Sets how a key held down is reported to programs waiting for a key press.
+    pub fn key_repeat(&mut self, repeat: KeyRepeat) -> &mut Self {
+        self.options.key_repeat = repeat;
+        self
+    }
+
+    /// Keeps a key down for at least `frames` emulated frames after it is pressed,
+    /// so that quick taps are seen by programs polling the keys at low CPU frequencies.
+    pub fn min_press_frames(&mut self, frames: u8) -> &mut Self {
+        self.options.min_press_frames = frames;
+        self
+    }
+
+    /// Seeds the random number generator, so that random numbers are the same from one run
+    /// to the other. Without a seed, they are different for each system.
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.options.seed = Some(seed);
+        self
+    }
+
+    /// Halts the system once the program spins in a loop of at most `n` instructions
+    /// with no side effects, eg. a jump to itself at its end, which can't ever do anything
+    /// else. 0 lets such programs spin forever.
+    pub fn halt_on_spin(&mut self, n: u32) -> &mut Self {
+        self.options.spin_loop_length = n;
+        self
+    }
+
+    /// Draws random numbers from `source` instead of a generator seeded by `seed`,
+    /// eg. a `ScriptedRandom` in tests.
+    pub fn random_source(&mut self, source: Box<dyn RandomSource>) -> &mut Self {
+        self.options.random = Some(source);
+        self
+    }
+
+    /// Ticks the delay and sound timers every `n` executed instructions instead of
+    /// once per frame, so that runs only depend on the program and its input, whatever
+    /// the speed. Requires the single threaded execution mode.
+    pub fn timers_tick_every(&mut self, n: u32) -> &mut Self {
+        self.options.instructions_per_tick = Some(n);
+        self
+    }
+
+    /// Stops programs writing below 0x200, where the fonts are, with
+    /// `SystemError::MemoryWriteViolation` instead of letting them overwrite it.
+    pub fn protect_reserved(&mut self, enabled: bool) -> &mut Self {
+        self.options.protect_reserved = enabled;
+        self
+    }
+
+    /// Counts the instructions executed and the time they take, by kind of instruction,
+    /// see `System::profile_report`. This slows the system down.
+    pub fn profile_instructions(&mut self, enabled: bool) -> &mut Self {
+        self.options.profile_instructions = enabled;
+        self
+    }
+
+    /// Selects the variant, quirks and speed a known program is meant to run with,
+    /// see `database::lookup`. Options set afterwards take precedence.
+    #[cfg(feature = "serde")]
+    pub fn rom_profile(&mut self, profile: &RomProfile) -> &mut Self {
+        self.options.variant = profile.variant;
+        self.options.quirks |= profile.quirks;
+        if let Some(speed) = profile.speed {
+            self.options.speed = speed;
+        }
+        self
+    }
+
+    /// Runs the system and its timers on another clock than the real time,
+    /// eg. a `ManualClock` in tests.
+    pub fn clock(&mut self, clock: Arc<dyn Clock>) -> &mut Self {
+        self.options.clock = clock;
+        self
+    }
+
+    pub fn build(&self) -> Result<SystemOptions, OptionsError> {
+        let o = &self.options;
+
+        match o.speed {
+            Speed::Frequency(f) if !(f > 0.0 && f < 5000.0) => {
+                return Err(OptionsError::CpuFrequencyOutOfRange(f));
+            }
+            Speed::InstructionsPerFrame(n) if !(1..=1000).contains(&n) => {
+                return Err(OptionsError::InstructionsPerFrameOutOfRange(n));
+            }
+            _ => {}
+        }
+
+        let conflicts = o.quirks & o.variant.dialect().defined_quirks();
+        if !conflicts.is_empty() {
+            return Err(OptionsError::ConflictingQuirks(conflicts, o.variant));
+        }
+
+        if !(MEMORY_SIZE..=MAX_MEMORY_SIZE).contains(&o.memory_size) {
+            return Err(OptionsError::MemorySizeOutOfRange(o.memory_size));
+        }
+
+        if o.run_ahead && o.execution_mode != ExecutionMode::SingleThreaded {
+            return Err(OptionsError::RunAheadRequiresSingleThreaded);
+        }
+
+        if let Some(n) = o.instructions_per_tick {
+            if o.execution_mode != ExecutionMode::SingleThreaded {
+                return Err(OptionsError::InstructionTimersRequireSingleThreaded);
+            }
+            if n == 0 {
+                return Err(OptionsError::InstructionsPerTickOutOfRange);
+            }
+        }
+
+        let mut options = o.clone();
+        // MegaChip programs and their data fill the whole address space
+        if o.variant == Variant::MegaChip {
+            options.memory_size = MAX_MEMORY_SIZE;
+        }
+        Ok(options)
+    }
 }
 
 pub struct SystemController {
     stop_pin: ControlPin,
     kb_controller: KeyboardController,
+    debug_commands: Sender<DebugCommand>,
+    state: Arc<Mutex<CpuState>>,
+    profile: Arc<Mutex<ProfileReport>>,
 }
 
 impl SystemController {
+    /// Returns the state of the CPU as of the last batch of instructions, or the last pause.
+    /// This can be called from any thread while the system runs, eg. to show the registers.
+    pub fn state_snapshot(&self) -> CpuState {
+        self.state.lock().unwrap().clone()
+    }
+
+    /// Returns the instruction statistics as of the last second, or the last pause,
+    /// see `SystemOptionsBuilder::profile_instructions`.
+    pub fn profile_report(&self) -> ProfileReport {
+        self.profile.lock().unwrap().clone()
+    }
+
     pub fn stop(&self) {
         self.stop_pin.raise();
         self.kb_controller.stop();
     }
+
+    /// Pauses the CPU along with the timers, until `resume` is called.
+    pub fn pause(&self) {
+        let _ = self.debug_commands.send(DebugCommand::Pause);
+    }
+
+    /// Resumes a paused system where it stopped.
+    pub fn resume(&self) {
+        let _ = self.debug_commands.send(DebugCommand::Resume);
+    }
+
+    /// Runs until the next display update, then pauses.
+    pub fn step_frame(&self) {
+        let _ = self.debug_commands.send(DebugCommand::StepFrame);
+    }
+
+    /// Runs the next instruction of a paused system then pauses again,
+    /// reporting the instruction as a `DebugEvent::Stepped`.
+    pub fn step(&self) {
+        let _ = self.debug_commands.send(DebugCommand::Step);
+    }
+
+    /// Runs the next instruction then pauses, running a whole subroutine if it is a call.
+    pub fn step_over(&self) {
+        let _ = self.debug_commands.send(DebugCommand::StepOver);
+    }
+
+    /// Runs until the current subroutine returns, then pauses.
+    pub fn step_out(&self) {
+        let _ = self.debug_commands.send(DebugCommand::StepOut);
+    }
+
+    /// Sets or clears a breakpoint, the system pauses each time PC reaches `address`.
+    pub fn set_breakpoint(&self, address: u16, enabled: bool) {
+        let _ = self
+            .debug_commands
+            .send(DebugCommand::SetBreakpoint { address, enabled });
+    }
+
+    /// Changes a register, only while the system is paused.
+    pub fn set_register(&self, register: Register, value: u16) {
+        let _ = self
+            .debug_commands
+            .send(DebugCommand::SetRegister { register, value });
+    }
+
+    /// Goes back to the most recent rewind snapshot. Snapshots are taken every second,
+    /// so repeated calls go further back in time.
+    pub fn rewind(&self) {
+        let _ = self.debug_commands.send(DebugCommand::Rewind);
+    }
+
+    pub fn toggle_pause(&self) {
+        let _ = self.debug_commands.send(DebugCommand::TogglePause);
+    }
+
+    /// Keeps a snapshot of the current state, which replaces the previous one.
+    pub fn save_state(&self) {
+        let _ = self.debug_commands.send(DebugCommand::SaveState);
+    }
+
+    /// Goes back to the state kept by the last call to `save_state`.
+    pub fn load_state(&self) {
+        let _ = self.debug_commands.send(DebugCommand::LoadState);
+    }
+
+    /// Runs the CPU several times faster while enabled.
+    pub fn turbo(&self, enabled: bool) {
+        let _ = self.debug_commands.send(DebugCommand::Turbo(enabled));
+    }
+
+    pub fn set_cheat(&self, index: usize, enabled: bool) {
+        let _ = self
+            .debug_commands
+            .send(DebugCommand::SetCheat { index, enabled });
+    }
+
+    /// Disables all the cheats if any is enabled, enables them all otherwise.
+    pub fn toggle_cheats(&self) {
+        let _ = self.debug_commands.send(DebugCommand::ToggleCheats);
+    }
+
+    /// Asks the system to emit its call stack as a `DebugEvent::CallStack`.
+    pub fn call_stack(&self) {
+        let _ = self.debug_commands.send(DebugCommand::CallStack);
+    }
+
+    /// Keeps a copy of the registers and memory, to compare them later with `diff_state`.
+    pub fn mark_state(&self) {
+        let _ = self.debug_commands.send(DebugCommand::MarkState);
+    }
+
+    /// Asks the system to emit the changes since `mark_state` as a `DebugEvent::StateDiff`.
+    pub fn diff_state(&self) {
+        let _ = self.debug_commands.send(DebugCommand::DiffState);
+    }
+
+    /// Sends any debug command, eg. one received from a remote frontend.
+    pub fn send(&self, cmd: DebugCommand) {
+        let _ = self.debug_commands.send(cmd);
+    }
+}
+
+/// The state before the frame run ahead of time, restored if the input changes.
+struct RunAhead {
+    snapshot: Snapshot,
+    // the input the frame was run with
+    keyboard: KeyboardState,
+    // the metrics before the frame, which is left out of them if it is run again
+    instructions: u64,
+    timer_ticks: [u64; 2],
 }
 
 pub struct System {
@@ -142,6 +619,47 @@ pub struct System {
     stop: ControlPin,
     memory: Memory,
     options: SystemOptions,
+    seed: u64,
+    rng: Box<dyn RandomSource>,
+    // instructions left to execute in the current frame, in single threaded mode
+    frame_budget: f64,
+    // instructions left until the timers tick, with `timers_tick_every`
+    tick_countdown: u32,
+    idle: IdleDetector,
+    spin: SpinDetector,
+    // total number of instructions and frames executed, for metrics
+    instructions: u64,
+    frames: u64,
+    metrics: MetricsCollector,
+    code_tracker: CodeTracker,
+    debug_state: LatestValue<CpuState>,
+    // the state read by `SystemController::state_snapshot`
+    shared_state: Arc<Mutex<CpuState>>,
+    // only while profiling, with the report read by `SystemController::profile_report`
+    profiler: Option<Profiler>,
+    shared_profile: Arc<Mutex<ProfileReport>>,
+    memory_viewer: MemoryViewer,
+    debugger: Debugger,
+    history: PcHistory,
+    rewind: RewindBuffer,
+    run_ahead: Option<RunAhead>,
+    quick_save: Option<Snapshot>,
+    turbo: bool,
+    cheats: Cheats,
+    symbols: Symbols,
+    audio_pattern: AudioPattern,
+    audio: LatestValue<AudioPattern>,
+    rpl_flags: RplFlags,
+    rpl_output: LatestValue<RplFlags>,
+    // the program has stopped on its own, with 00FD
+    exited: bool,
+    // the program has been halted in an endless loop
+    spinning: bool,
+    movie: Option<MovieState>,
+    routines: Routines,
+    events: (Sender<SystemEvent>, Receiver<SystemEvent>),
+    // the other ports are connected to the events port once it is used
+    events_forwarders: Mutex<Option<Vec<Forwarder>>>,
 }
 
 impl Default for System {
@@ -156,24 +674,91 @@ impl System {
     }
 
     pub fn new_with_options(options: SystemOptions) -> Self {
-        let mut memory = Memory::new();
-        memory.write_slice(FONT_SPRITES_ADDRESS, font_sprites());
+        let mut memory = Memory::with_size(options.memory_size);
+        memory
+            .write_slice(FONT_SPRITES_ADDRESS, font_sprites())
+            .and_then(|_| memory.write_slice(LARGE_FONT_SPRITES_ADDRESS, large_font_sprites()))
+            .expect("the fonts fit in the reserved memory");
+
+        let (delay_timer, sound_timer, mut display) = match options.execution_mode {
+            ExecutionMode::Threaded => (
+                CountDownTimer::with_clock(options.clock.clone()),
+                CountDownTimer::with_clock(options.clock.clone()),
+                DisplayBuffer::new(),
+            ),
+            ExecutionMode::SingleThreaded => (
+                CountDownTimer::new_manual(),
+                CountDownTimer::new_manual(),
+                DisplayBuffer::new_deferred(),
+            ),
+        };
+
+        let mut keyboard = match options.run_ahead {
+            true => Keyboard::new_latched(),
+            false => Keyboard::new(),
+        };
+        keyboard.set_repeat(options.key_repeat);
+        keyboard.set_clock(options.clock.clone());
+        keyboard.set_min_press_frames(options.min_press_frames);
+        display.set_clock(options.clock.clone());
+
+        let seed = options
+            .seed
+            .unwrap_or_else(|| SmallRng::from_entropy().gen());
+        let rng = options
+            .random
+            .clone()
+            .unwrap_or_else(|| Box::new(SeededRandom::new(seed)));
+        let profiler = options.profile_instructions.then(Profiler::default);
+        let tick_countdown = options.instructions_per_tick.unwrap_or(0);
 
         Self {
             // user programs start at 0x200
-            cpu: Cpu {
-                pc: RESERVED_SIZE as u16,
-                v: Default::default(),
-                i: 0,
-                stack: Vec::with_capacity(STACK_SIZE),
-            },
-            delay_timer: Default::default(),
-            sound_timer: Default::default(),
-            keyboard: Default::default(),
-            display: Default::default(),
+            cpu: Cpu::new(RESERVED_SIZE as u16),
+            delay_timer,
+            sound_timer,
+            keyboard,
+            display,
             memory,
+            code_tracker: CodeTracker::new(options.memory_size),
+            memory_viewer: MemoryViewer::new(
+                options.memory_view,
+                options.memory_size,
+                options.clock.clone(),
+            ),
             options,
             stop: Default::default(),
+            seed,
+            rng,
+            frame_budget: 0.0,
+            tick_countdown,
+            idle: Default::default(),
+            spin: Default::default(),
+            instructions: 0,
+            frames: 0,
+            metrics: Default::default(),
+            debug_state: LatestValue::new(),
+            shared_state: Default::default(),
+            profiler,
+            shared_profile: Default::default(),
+            debugger: Default::default(),
+            history: PcHistory::new(),
+            rewind: RewindBuffer::new(),
+            run_ahead: None,
+            quick_save: None,
+            turbo: false,
+            cheats: Default::default(),
+            symbols: Default::default(),
+            audio_pattern: Default::default(),
+            audio: LatestValue::new(),
+            rpl_flags: Default::default(),
+            rpl_output: LatestValue::new(),
+            exited: false,
+            spinning: false,
+            movie: None,
+            routines: Default::default(),
+            events: crossbeam_channel::bounded(16),
+            events_forwarders: Mutex::new(None),
         }
     }
 
@@ -181,19 +766,146 @@ impl System {
         SystemController {
             stop_pin: self.stop.clone(),
             kb_controller: self.keyboard.controller(),
+            debug_commands: self.debugger.command_sender.clone(),
+            state: Arc::clone(&self.shared_state),
+            profile: Arc::clone(&self.shared_profile),
         }
     }
 
     pub fn load_image<P: AsRef<Path>>(&mut self, p: P) -> io::Result<()> {
-        let mut r = File::open(p)?;
-        let ram = &mut self.memory.as_bytes_mut()[RESERVED_SIZE..];
-        let _ = r.read(ram)?;
+        let bytes = fs::read(p)?;
+        let space = self.memory.size() - RESERVED_SIZE;
+        if bytes.len() > space {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "program size is {} bytes, at most {} fit in memory",
+                    bytes.len(),
+                    space
+                ),
+            ));
+        }
+        self.load_image_bytes(&bytes);
         Ok(())
     }
 
     pub fn load_image_bytes(&mut self, bytes: &[u8]) {
         let ram = &mut self.memory.as_bytes_mut()[RESERVED_SIZE..RESERVED_SIZE + bytes.len()];
         ram.copy_from_slice(bytes);
+
+        // hires CHIP-8 programs start by jumping over the patched interpreter,
+        // they run from its end on a 64x64 screen
+        if self.options.variant == Variant::Chip8 && bytes.starts_with(&HIRES_STARTUP) {
+            self.display.set_size(DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT);
+            self.cpu.pc = HIRES_START_ADDRESS;
+        }
+        self.share_state();
+    }
+
+    /// Returns true if the running program is a hires CHIP-8 program, see `load_image_bytes`.
+    fn is_hires(&self) -> bool {
+        let framebuffer = self.display.framebuffer();
+        (framebuffer.width(), framebuffer.height()) == (DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT)
+    }
+
+    /// Sets the flag registers, eg. to the ones saved by a previous run of the program.
+    pub fn set_rpl_flags(&mut self, flags: RplFlags) {
+        self.rpl_flags = flags;
+    }
+
+    /// Registers a routine called by `0NNN` when NNN is `address` and the opcode is not
+    /// an instruction of the variant. Routines are not part of the snapshots.
+    pub fn register_routine(
+        &mut self,
+        address: u16,
+        routine: impl FnMut(RoutineContext) + Send + 'static,
+    ) {
+        self.routines.register(address & 0xFFF, routine);
+    }
+
+    /// Sets the cheats of the program, the enabled ones are written immediately,
+    /// so this is called once the program is loaded.
+    pub fn set_cheats(&mut self, cheats: Cheats) {
+        cheats.apply(&mut self.memory);
+        self.cheats = cheats;
+    }
+
+    /// Sets the labels of the program, used to resolve the addresses of the call stack.
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// Starts recording the input of each frame in a movie, which can then be replayed exactly.
+    /// This is called once `program` is loaded, before running the system.
+    pub fn record_movie(&mut self, program: &[u8]) -> Result<(), MovieError> {
+        self.check_movie_support()?;
+        let movie = Movie::new(program, &self.options, self.seed);
+        self.keyboard.set_latched(true);
+        self.movie = Some(MovieState::Recording(movie));
+        Ok(())
+    }
+
+    /// Replays the input of a movie instead of the keyboard input, then pauses the system
+    /// once all its frames have been replayed. The system must have been created with
+    /// the options of the movie, and `program` loaded, before running it.
+    pub fn play_movie(&mut self, movie: Movie, program: &[u8]) -> Result<(), MovieError> {
+        self.check_movie_support()?;
+        if movie.program_hash != program_hash(program) {
+            return Err(MovieError::ProgramMismatch);
+        }
+        self.keyboard.set_latched(true);
+        self.movie = Some(MovieState::Playing(MoviePlayer::new(movie)));
+        Ok(())
+    }
+
+    /// Returns the movie being recorded or replayed, and stops it.
+    pub fn take_movie(&mut self) -> Option<Movie> {
+        self.keyboard.set_latched(self.options.run_ahead);
+        match self.movie.take()? {
+            MovieState::Recording(movie) => Some(movie),
+            MovieState::Playing(player) => Some(player.movie),
+        }
+    }
+
+    fn check_movie_support(&self) -> Result<(), MovieError> {
+        if self.options.execution_mode != ExecutionMode::SingleThreaded || self.options.run_ahead {
+            return Err(MovieError::RequiresSingleThreaded);
+        }
+        Ok(())
+    }
+
+    /// Returns the whole content of the memory, including the reserved area.
+    pub fn memory_bytes(&self) -> &[u8] {
+        self.memory.as_bytes()
+    }
+
+    /// Replaces the whole content of the memory,
+    /// `bytes` must have the exact size of the memory.
+    pub fn restore_memory_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let ram = self.memory.as_bytes_mut();
+        if bytes.len() != ram.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "memory dump size is {} bytes, expected {}",
+                    bytes.len(),
+                    ram.len()
+                ),
+            ));
+        }
+        ram.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Writes the whole content of the memory to a file.
+    pub fn dump_memory<P: AsRef<Path>>(&self, p: P) -> io::Result<()> {
+        fs::write(p, self.memory_bytes())
+    }
+
+    /// Restores the whole content of the memory from a file written by `dump_memory`.
+    pub fn restore_memory<P: AsRef<Path>>(&mut self, p: P) -> io::Result<()> {
+        let bytes = fs::read(p)?;
+        self.restore_memory_bytes(&bytes)
     }
 
     pub fn start(mut self) -> JoinHandle<()> {
@@ -203,68 +915,867 @@ impl System {
     }
 
     pub fn run(&mut self) -> Result<(), SystemError> {
-        let mut rng = SmallRng::from_entropy();
-        let mut loop_helper =
-            LoopHelper::builder().build_with_target_rate(self.options.cpu_frequency_hz);
+        let r = match self.options.execution_mode {
+            ExecutionMode::Threaded => self.run_slices(),
+            ExecutionMode::SingleThreaded => self.run_frames(),
+        };
+        // neither a SUPER-CHIP program exiting nor a program ending in a loop is an error
+        let r = match r {
+            Err(SystemError::Exited) => {
+                self.exited = true;
+                Ok(())
+            }
+            Err(SystemError::Spinning) => {
+                self.spinning = true;
+                Ok(())
+            }
+            r => r,
+        };
+        self.share_profile();
+        let error = r.as_ref().err().map(ToString::to_string);
+        self.send_last_events(error);
+        r
+    }
 
-        while !self.stop.is_raised() {
-            let _ = loop_helper.loop_start();
-            match self.execute_next_inst(&mut rng) {
-                Err(SystemError::Interrupted) => return Ok(()),
-                Err(e) => return Err(e),
-                _ => {}
+    /// Sends `Exited` and `Halted` on the events port, after the events of the other ports.
+    fn send_last_events(&self, error: Option<String>) {
+        let mut forwarders = self.events_forwarders.lock().unwrap();
+        let Some(connected) = forwarders.take() else {
+            // nobody is listening yet, the events are kept for a late receiver if there is room
+            if self.exited {
+                let _ = self.events.0.try_send(SystemEvent::Exited);
             }
-            loop_helper.loop_sleep();
+            let _ = self.events.0.try_send(SystemEvent::Halted { error });
+            return;
+        };
+        for forwarder in connected {
+            forwarder.join();
         }
+        if self.exited {
+            let _ = self.events.0.send(SystemEvent::Exited);
+        }
+        let _ = self.events.0.send(SystemEvent::Halted { error });
+        // the system may be run again
+        *forwarders = Some(self.forward_events());
+    }
 
-        Ok(())
+    fn forward_events(&self) -> Vec<Forwarder> {
+        let bus = &self.events.0;
+        let metrics: Receiver<SystemMetrics> = self.output();
+        let debug: Receiver<DebugEvent> = self.output();
+        vec![
+            forward(&self.display, bus),
+            forward(&self.sound_timer, bus),
+            forward(&metrics, bus),
+            forward(&debug, bus),
+        ]
     }
 
-    fn execute_next_inst(&mut self, rng: &mut impl Rng) -> Result<(), SystemError> {
-        // health check: PC must be even, otherwise we exit
-        /*if self.cpu.pc % 2 != 0 {
-            return Err(SystemError::OddPcAddress);
-        }*/
+    /// Returns true if `run` has returned because the program exited, rather than being stopped.
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
 
-        let instr = self
-            .memory
-            .read_u16(self.cpu.pc)
-            .ok_or(SystemError::MemoryReadOverflow)?;
-        let opcode = parse_opcode(instr).ok_or(SystemError::UnknownInstruction(instr))?;
+    /// Returns true if `run` has returned because the program was spinning in an endless loop,
+    /// see `SystemOptionsBuilder::halt_on_spin`.
+    pub fn is_spinning(&self) -> bool {
+        self.spinning
+    }
 
-        // println!("0x{:04x}: {:04X} {:?}", self.cpu.pc, instr, &opcode);
+    /// Executes `n` instructions right away, see `run_until`.
+    pub fn run_for(&mut self, n: u64) -> Result<RunStop, SystemError> {
+        let end = self.instructions + n;
+        self.run_until(|system| system.instructions >= end)
+    }
 
-        match opcode {
-            Instr::ClearDisplay => {
-                self.display.clear();
+    /// Executes instructions right away until `done` returns true, checking it before each one,
+    /// or until the system is paused, eg. by a breakpoint. A paused system is resumed first.
+    ///
+    /// Nothing is paced in real time: the timers are frozen meanwhile and only tick with
+    /// `SystemOptionsBuilder::timers_tick_every`, and the display is not updated.
+    pub fn run_until<F>(&mut self, done: F) -> Result<RunStop, SystemError>
+    where
+        F: FnMut(&System) -> bool,
+    {
+        self.resume();
+        // the timer threads of the threaded mode would count down in real time
+        self.delay_timer.freeze();
+        self.sound_timer.freeze();
+        let r = self.execute_until(done);
+        // a paused system keeps them frozen until it is resumed
+        if !self.debugger.paused {
+            self.delay_timer.thaw();
+            self.sound_timer.thaw();
+        }
+        r
+    }
+
+    fn execute_until<F>(&mut self, mut done: F) -> Result<RunStop, SystemError>
+    where
+        F: FnMut(&System) -> bool,
+    {
+        loop {
+            self.poll_debug_commands();
+            if self.debugger.paused {
+                return Ok(RunStop::Paused);
             }
-            Instr::Return => {
-                self.cpu.pc = self.cpu.stack.pop().ok_or(SystemError::StackUnderflow)?;
+            if done(self) {
+                return Ok(RunStop::Done);
             }
-            Instr::Jump(nnn) => {
-                if self.cpu.pc == nnn {
-                    return Err(SystemError::SelfJump);
+            match self.execute_next_inst() {
+                Ok(_) => {}
+                Err(SystemError::Exited) => {
+                    self.exited = true;
+                    return Ok(RunStop::Exited);
                 }
-                self.cpu.pc = nnn;
-                return Ok(());
-            }
-            Instr::Call(nnn) => {
-                if self.cpu.stack.len() >= 16 {
-                    return Err(SystemError::StackOverflow);
+                Err(SystemError::Spinning) => {
+                    self.spinning = true;
+                    return Ok(RunStop::Spinning);
                 }
-                self.cpu.stack.push(self.cpu.pc);
-                self.cpu.pc = nnn;
-                return Ok(());
+                Err(e) => return Err(e),
             }
-            Instr::SkipEqImm(x, kk) => {
-                if self.cpu.v[x] == kk {
-                    self.cpu.pc += 2;
-                }
+        }
+    }
+
+    /// Runs batches of instructions in real time.
+    fn run_slices(&mut self) -> Result<(), SystemError> {
+        // instructions are executed in batches, one batch per time slice,
+        // so that we don't have to sleep between each instruction
+        let frequency = self.options.cycles_per_second();
+        let slice_rate = match self.options.batches_frames() {
+            true => FRAME_RATE_HZ,
+            false => frequency.min(MAX_SLICE_RATE_HZ),
+        };
+        let max_due = MAX_CATCH_UP_SLICES * frequency / slice_rate;
+        let clock = self.options.clock.clone();
+        let mut pacer = Pacer::new(clock.clone(), slice_rate);
+
+        // number of instructions that should have been executed by now,
+        // the fractional part is carried over to the next slice
+        let mut due = 0.0;
+        // same for the emulated frames, counted by the keyboard
+        let mut frames_due = 0.0;
+
+        let mut last_report = clock.now();
+        let mut last_snapshot = clock.now();
+
+        while !self.stop.is_raised() {
+            // blocks while paused
+            self.handle_debug_commands();
+
+            let elapsed = pacer.loop_start();
+
+            // don't try to catch up after a long pause (eg. waiting for a key press)
+            let speed = match self.options.batches_frames() {
+                true => self.options.cycles_per_frame(),
+                false => elapsed.as_secs_f64() * frequency,
+            };
+            due = (due + speed * self.speed_factor()).min(max_due * self.speed_factor());
+            self.cheats.apply_frozen(&mut self.memory);
+            if !self.debugger.paused {
+                let elapsed =
+                    elapsed.min(Duration::from_secs_f64(MAX_CATCH_UP_SLICES / slice_rate));
+                self.display
+                    .advance_time(elapsed.mul_f64(self.speed_factor()));
+                frames_due += elapsed.as_secs_f64() * FRAME_RATE_HZ * self.speed_factor();
             }
-            Instr::SkipNotEqImm(x, kk) => {
-                if self.cpu.v[x] != kk {
-                    self.cpu.pc += 2;
-                }
+            while frames_due >= 1.0 {
+                frames_due -= 1.0;
+                self.keyboard.end_frame();
+            }
+
+            while due >= 1.0 && !self.debugger.paused {
+                match self.execute_next_inst() {
+                    Ok(cycles) => due -= cycles as f64,
+                    Err(SystemError::Interrupted) => return Ok(()),
+                    Err(e) => return Err(e),
+                }
+            }
+
+            self.share_state();
+            if self.memory_viewer.is_due() {
+                self.publish_memory_view();
+            }
+
+            let now = clock.now();
+            let period = now - last_report;
+            if period >= METRICS_PERIOD {
+                self.report_metrics(period);
+                last_report = now;
+            }
+
+            if now - last_snapshot >= REWIND_INTERVAL {
+                self.rewind.push(self.snapshot());
+                last_snapshot = now;
+            }
+
+            pacer.loop_sleep();
+        }
+
+        Ok(())
+    }
+
+    /// Runs single threaded frames in real time.
+    fn run_frames(&mut self) -> Result<(), SystemError> {
+        let mut pacer = Pacer::new(self.options.clock.clone(), FRAME_RATE_HZ);
+
+        while !self.stop.is_raised() {
+            // blocks while paused
+            self.handle_debug_commands();
+
+            let _ = pacer.loop_start();
+            if !self.debugger.paused {
+                self.run_frame()?;
+            }
+            pacer.loop_sleep();
+        }
+
+        Ok(())
+    }
+
+    /// Advances the virtual clock of a single threaded system by one 60 Hz frame:
+    /// executes the instructions due during the frame, ticks the timers
+    /// and sends the display update if the screen has changed.
+    ///
+    /// This never blocks, a program waiting for a key press is resumed
+    /// during the first frame where a key has been pressed.
+    pub fn run_frame(&mut self) -> Result<(), SystemError> {
+        self.display
+            .advance_time(Duration::from_secs_f64(self.speed_factor() / FRAME_RATE_HZ));
+        if !self.movie_frame() {
+            return Ok(());
+        }
+        if self.options.run_ahead {
+            return self.run_frame_ahead();
+        }
+
+        self.advance_frame()?;
+        self.display.flush();
+        self.check_frame_step();
+        self.end_frame();
+
+        Ok(())
+    }
+
+    /// Runs the next frame, then presents the display of the frame after it.
+    /// The state is rolled back to the end of the next frame afterwards,
+    /// unless no input has been received in the meantime: in that case,
+    /// the frame run ahead is kept as the next frame.
+    fn run_frame_ahead(&mut self) -> Result<(), SystemError> {
+        match self.run_ahead.take() {
+            Some(_) if !self.keyboard.has_pending_input() => self.code_tracker.settle(true),
+            Some(ahead) => {
+                self.discard_run_ahead(&ahead);
+                self.restore_snapshot(&ahead.snapshot);
+                self.keyboard.restore_state(ahead.keyboard);
+                self.keyboard.latch();
+                self.advance_frame()?;
+            }
+            None => {
+                self.keyboard.latch();
+                self.advance_frame()?;
+            }
+        }
+        self.end_frame();
+
+        self.run_ahead = Some(RunAhead {
+            snapshot: self.snapshot(),
+            keyboard: self.keyboard.state(),
+            instructions: self.instructions,
+            timer_ticks: [
+                self.delay_timer.counters().ticks.load(Ordering::Relaxed),
+                self.sound_timer.counters().ticks.load(Ordering::Relaxed),
+            ],
+        });
+        self.code_tracker.speculate();
+        self.advance_frame()?;
+        self.display.flush();
+        self.check_frame_step();
+
+        Ok(())
+    }
+
+    /// Leaves the frame run ahead of time out of the metrics and of the code tracker.
+    fn discard_run_ahead(&mut self, ahead: &RunAhead) {
+        self.code_tracker.settle(false);
+        self.instructions = ahead.instructions;
+        let [delay, sound] = ahead.timer_ticks;
+        self.delay_timer
+            .counters()
+            .ticks
+            .store(delay, Ordering::Relaxed);
+        self.sound_timer
+            .counters()
+            .ticks
+            .store(sound, Ordering::Relaxed);
+    }
+
+    /// Records the input of the frame in the movie being recorded,
+    /// or replaces it with the one of the movie being replayed.
+    /// Returns false if the movie has ended instead, the system is then paused.
+    fn movie_frame(&mut self) -> bool {
+        let ended = match &mut self.movie {
+            Some(MovieState::Recording(movie)) => {
+                self.keyboard.latch();
+                movie.record(self.keyboard.state());
+                false
+            }
+            Some(MovieState::Playing(player)) => match player.next_frame() {
+                Some(state) => {
+                    self.keyboard.restore_state(state);
+                    false
+                }
+                None => true,
+            },
+            None => false,
+        };
+
+        if ended {
+            self.take_movie();
+            self.pause();
+        }
+        !ended
+    }
+
+    /// Executes the instructions due during one frame, and ticks the timers.
+    fn advance_frame(&mut self) -> Result<(), SystemError> {
+        self.cheats.apply_frozen(&mut self.memory);
+        self.frame_budget += self.options.cycles_per_frame() * self.speed_factor();
+
+        while self.frame_budget >= 1.0 && !self.debugger.paused {
+            self.frame_budget -= self.execute_next_inst()? as f64;
+        }
+
+        if self.options.instructions_per_tick.is_none() {
+            self.delay_timer.tick();
+            self.sound_timer.tick();
+        }
+        self.keyboard.end_frame();
+
+        Ok(())
+    }
+
+    fn end_frame(&mut self) {
+        // metrics are based on the virtual clock
+        self.frames += 1;
+        self.share_state();
+        self.publish_memory_view();
+        if self.frames % FRAME_RATE_HZ as u64 == 0 {
+            self.report_metrics(METRICS_PERIOD);
+            self.rewind.push(self.snapshot());
+        }
+    }
+
+    /// Applies the pending debug commands. While the system is paused,
+    /// this blocks until it is resumed or stopped.
+    fn handle_debug_commands(&mut self) {
+        loop {
+            let cmd = if self.debugger.paused {
+                match self.debugger.commands.recv_timeout(IDLE_TIMEOUT) {
+                    Ok(cmd) => cmd,
+                    Err(RecvTimeoutError::Timeout) if !self.stop.is_raised() => continue,
+                    Err(_) => return,
+                }
+            } else {
+                match self.debugger.commands.try_recv() {
+                    Ok(cmd) => cmd,
+                    Err(_) => return,
+                }
+            };
+
+            self.handle_debug_command(cmd);
+        }
+    }
+
+    /// Applies the pending debug commands, without ever blocking.
+    fn poll_debug_commands(&mut self) {
+        while let Ok(cmd) = self.debugger.commands.try_recv() {
+            self.handle_debug_command(cmd);
+        }
+    }
+
+    fn handle_debug_command(&mut self, cmd: DebugCommand) {
+        match cmd {
+            DebugCommand::Pause => self.pause(),
+            DebugCommand::Resume => self.resume(),
+            DebugCommand::StepFrame => {
+                self.debugger.frame_step = Some(self.display.frame_counters().0);
+                self.resume();
+            }
+            DebugCommand::Step => {
+                if self.debugger.paused {
+                    self.debugger.single_step = true;
+                    self.resume();
+                }
+            }
+            DebugCommand::StepOver => {
+                self.debugger.step_depth = Some(self.cpu.stack.len());
+                self.resume();
+            }
+            DebugCommand::StepOut => {
+                self.debugger.step_depth = Some(self.cpu.stack.len().saturating_sub(1));
+                self.resume();
+            }
+            DebugCommand::Rewind => self.rewind(),
+            DebugCommand::TogglePause => match self.debugger.paused {
+                true => self.resume(),
+                false => self.pause(),
+            },
+            DebugCommand::SaveState => self.quick_save = Some(self.snapshot()),
+            DebugCommand::LoadState => self.load_state(),
+            // the speed and the cheats are not recorded, changing them would
+            // desynchronize the movie
+            DebugCommand::Turbo(_) | DebugCommand::SetCheat { .. } | DebugCommand::ToggleCheats
+                if self.movie.is_some() => {}
+            DebugCommand::Turbo(enabled) => self.turbo = enabled,
+            DebugCommand::SetCheat { index, enabled } => {
+                self.cheats.set_enabled(index, enabled, &mut self.memory)
+            }
+            DebugCommand::ToggleCheats => self.cheats.toggle_all(&mut self.memory),
+            DebugCommand::CallStack => self.debugger.emit(DebugEvent::CallStack(self.call_stack())),
+            DebugCommand::SetBreakpoint { address, enabled } => {
+                match enabled {
+                    true => self.debugger.breakpoints.insert(address),
+                    false => self.debugger.breakpoints.remove(&address),
+                };
+            }
+            DebugCommand::SetRegister { register, value } => self.set_register(register, value),
+            DebugCommand::MarkState => {
+                self.debugger.mark = Some((self.cpu_state(), self.memory.as_bytes().to_vec()))
+            }
+            DebugCommand::DiffState => {
+                if let Some((cpu, memory)) = &self.debugger.mark {
+                    let diff =
+                        StateDiff::between(cpu, memory, &self.cpu_state(), self.memory.as_bytes());
+                    self.debugger.emit(DebugEvent::StateDiff(diff));
+                }
+            }
+        }
+    }
+
+    fn speed_factor(&self) -> f64 {
+        match self.turbo {
+            true => TURBO_FACTOR,
+            false => 1.0,
+        }
+    }
+
+    fn pause(&mut self) {
+        // a step in progress ends with any pause
+        self.debugger.step_depth = None;
+        if !self.debugger.paused {
+            self.debugger.paused = true;
+            self.delay_timer.freeze();
+            self.sound_timer.freeze();
+            self.debugger.emit(DebugEvent::Paused { pc: self.cpu.pc });
+            self.publish_debug_state();
+            self.publish_memory_view();
+            self.share_profile();
+        }
+    }
+
+    fn resume(&mut self) {
+        if self.debugger.paused {
+            self.debugger.paused = false;
+            self.delay_timer.thaw();
+            self.sound_timer.thaw();
+            self.display.restart_pacing();
+            self.debugger.emit(DebugEvent::Resumed);
+        }
+    }
+
+    fn load_state(&mut self) {
+        if self.movie.is_some() {
+            return;
+        }
+        if let Some(snapshot) = self.quick_save.clone() {
+            self.restore_snapshot(&snapshot);
+            if let Some(ahead) = self.run_ahead.take() {
+                self.discard_run_ahead(&ahead);
+            }
+            self.publish_debug_state();
+            self.publish_memory_view();
+        }
+    }
+
+    fn rewind(&mut self) {
+        // restoring a previous state would desynchronize the movie
+        if self.movie.is_some() {
+            return;
+        }
+        if let Some(snapshot) = self.rewind.pop() {
+            self.restore_snapshot(&snapshot);
+            if let Some(ahead) = self.run_ahead.take() {
+                self.discard_run_ahead(&ahead);
+            }
+            self.publish_debug_state();
+            self.publish_memory_view();
+        }
+    }
+
+    /// Pauses the system if the next instruction has a breakpoint.
+    fn check_breakpoint(&mut self) {
+        let address = self.cpu.pc;
+        if self.debugger.breakpoints.contains(&address) && !self.debugger.paused {
+            self.pause();
+            self.debugger.emit(DebugEvent::BreakpointHit { address });
+        }
+    }
+
+    fn set_register(&mut self, register: Register, value: u16) {
+        // changing the state of a running system would race with the program,
+        // and would desynchronize a movie
+        if !self.debugger.paused || self.movie.is_some() {
+            return;
+        }
+        match register {
+            Register::V(x) => {
+                if let Some(v) = self.cpu.v.get_mut(x as usize) {
+                    *v = value as u8;
+                }
+            }
+            Register::I => self.cpu.i = value,
+            Register::Pc => self.cpu.pc = value,
+            Register::DelayTimer => self.delay_timer.update(value as u8),
+            Register::SoundTimer => self.sound_timer.update(value as u8),
+        }
+        self.publish_debug_state();
+        self.publish_memory_view();
+    }
+
+    /// Updates the state read by `SystemController::state_snapshot`.
+    fn share_state(&self) {
+        *self.shared_state.lock().unwrap() = self.cpu_state();
+    }
+
+    /// Updates the report read by `SystemController::profile_report`, if profiling.
+    fn share_profile(&self) {
+        if let Some(profiler) = &self.profiler {
+            *self.shared_profile.lock().unwrap() = profiler.report();
+        }
+    }
+
+    /// Publishes the state of the CPU, if enabled, and shares it with the controllers.
+    fn publish_debug_state(&mut self) {
+        self.share_state();
+        if self.options.debug_state_interval != 0 {
+            self.debug_state.send(self.cpu_state());
+        }
+    }
+
+    /// Publishes a view of the memory, if enabled.
+    fn publish_memory_view(&mut self) {
+        if !self.memory_viewer.enabled {
+            return;
+        }
+        let view = MemoryView {
+            sequence: 0,
+            bytes: self.memory.as_bytes().into(),
+            written: BitVec::new(),
+            pc: self.cpu.pc,
+            i: self.cpu.i,
+            stack: self.cpu.stack.clone(),
+            stack_region: self.options.memory_layout.stack_region(self.memory.size()),
+        };
+        self.memory_viewer.publish(view);
+    }
+
+    /// Pauses the system if a frame step is in progress and the display has been updated.
+    fn check_frame_step(&mut self) {
+        if let Some(frames) = self.debugger.frame_step {
+            if self.display.frame_counters().0 != frames {
+                self.debugger.frame_step = None;
+                self.pause();
+            }
+        }
+    }
+
+    /// Pauses the system if a step over or out is in progress and the call stack is back
+    /// to the depth where it ends.
+    fn check_step_depth(&mut self) {
+        if let Some(depth) = self.debugger.step_depth {
+            if self.cpu.stack.len() <= depth {
+                self.pause();
+            }
+        }
+    }
+
+    /// Pauses the system after a single step, reporting the instruction it ran.
+    fn end_single_step(&mut self, address: u16, opcode: u16, instruction: String) {
+        self.debugger.single_step = false;
+        self.debugger.emit(DebugEvent::Stepped {
+            address,
+            opcode,
+            instruction,
+        });
+        self.pause();
+    }
+
+    fn report_metrics(&mut self, period: Duration) {
+        let (frames, dropped_frames) = self.display.frame_counters();
+        let delay = self.delay_timer.counters();
+        let sound = self.sound_timer.counters();
+
+        self.metrics.report(
+            Counters {
+                instructions: self.instructions,
+                frames,
+                dropped_frames,
+                delay_timer_ticks: delay.ticks.load(Ordering::Relaxed),
+                sound_timer_ticks: sound.ticks.load(Ordering::Relaxed),
+                dropped_timer_messages: delay.dropped_messages.load(Ordering::Relaxed)
+                    + sound.dropped_messages.load(Ordering::Relaxed),
+                flickers: self.display.flickers(),
+            },
+            period,
+        );
+        self.display.report_pacing();
+        self.share_profile();
+    }
+
+    /// Executes the instruction at PC, returns the cycles it took.
+    fn execute_next_inst(&mut self) -> Result<u32, SystemError> {
+        // health check: PC must be even, otherwise we exit
+        /*if self.cpu.pc % 2 != 0 {
+            return Err(SystemError::OddPcAddress);
+        }*/
+
+        self.history.push(self.cpu.pc);
+
+        let instr = self
+            .memory
+            .read_u16(self.cpu.pc)
+            .ok_or(SystemError::MemoryReadOverflow)?;
+        let dialect = self.options.variant.dialect();
+        let opcode = match instr {
+            // the patched interpreter clears the screen with its own routine
+            HIRES_CLEAR_DISPLAY if self.is_hires() => Instr::ClearDisplay,
+            _ => match dialect.decode(instr) {
+                Some(opcode) => opcode,
+                None if instr & 0xF000 == 0 && self.routines.contains(instr) => {
+                    Instr::MachineCall(instr)
+                }
+                None => return Err(SystemError::UnknownInstruction(instr)),
+            },
+        };
+
+        // println!("0x{:04x}: {:04X} {:?}", self.cpu.pc, instr, &opcode);
+
+        if opcode.has_side_effects() {
+            self.idle.reset();
+        }
+        // the delay timer and the memory, which a debugger may poke, change the state of the loops polling them
+        if opcode.has_side_effects()
+            || matches!(opcode, Instr::LoadDelayTimer(_) | Instr::LoadRegs(_))
+        {
+            self.spin.reset();
+        }
+
+        self.code_tracker.executed(self.cpu.pc);
+
+        let cycles = match self.options.timing {
+            Timing::FlatRate => 1,
+            Timing::VipCycles => vip_cycles(&opcode, &self.cpu),
+        };
+
+        let stepped = self
+            .debugger
+            .single_step
+            .then(|| (self.cpu.pc, opcode.to_string()));
+
+        // during execution, PC points to the next instruction
+        self.cpu.pc += 2;
+        self.instructions += 1;
+
+        let profiled = self
+            .profiler
+            .as_mut()
+            .map(|profiler| (profiler.kind(&opcode), Instant::now()));
+
+        // the dialect gets a chance to handle the instruction first
+        let r = match dialect.execute(self, &opcode) {
+            Some(r) => r,
+            None => self.execute(opcode),
+        };
+
+        if let (Some(profiler), Some((kind, start))) = (&mut self.profiler, profiled) {
+            profiler.record(kind, start.elapsed());
+        }
+
+        self.check_frame_step();
+        self.check_step_depth();
+        if let Some((address, instruction)) = stepped {
+            self.end_single_step(address, instr, instruction);
+        }
+        self.check_breakpoint();
+
+        if let Some(n) = self.options.instructions_per_tick {
+            if self.tick_countdown <= 1 {
+                self.tick_countdown = n;
+                self.delay_timer.tick();
+                self.sound_timer.tick();
+            } else {
+                self.tick_countdown -= 1;
+            }
+        }
+
+        let interval = self.options.debug_state_interval as u64;
+        if interval != 0 && self.instructions % interval == 0 {
+            self.debug_state.send(self.cpu_state());
+        }
+
+        r.map(|_| cycles)
+    }
+
+    /// Executes `0NNN` with the routine registered at NNN.
+    fn call_routine(&mut self, address: u16) -> Result<(), SystemError> {
+        let context = RoutineContext {
+            v: &mut self.cpu.v,
+            i: &mut self.cpu.i,
+            memory: self.memory.as_bytes_mut(),
+        };
+        if !self.routines.call(address, context) {
+            return Err(SystemError::UnknownInstruction(address));
+        }
+        Ok(())
+    }
+
+    /// Returns a snapshot of the CPU registers and timers.
+    pub fn cpu_state(&self) -> CpuState {
+        CpuState {
+            pc: self.cpu.pc,
+            i: self.cpu.i,
+            v: self.cpu.v,
+            stack: self.cpu.stack.clone(),
+            delay_timer: self.delay_timer.value(),
+            sound_timer: self.sound_timer.value(),
+        }
+    }
+
+    /// Returns the instruction statistics collected so far, empty unless enabled by
+    /// `SystemOptionsBuilder::profile_instructions`.
+    pub fn profile_report(&self) -> ProfileReport {
+        self.profiler
+            .as_ref()
+            .map(Profiler::report)
+            .unwrap_or_default()
+    }
+
+    /// Returns a copy of the complete state of the machine.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            cpu: self.cpu.clone(),
+            memory: self.memory.clone(),
+            framebuffer: self.display.framebuffer().clone(),
+            delay_timer: self.delay_timer.value(),
+            sound_timer: self.sound_timer.value(),
+            rng: self.rng.clone(),
+            frame_budget: self.frame_budget,
+            tick_countdown: self.tick_countdown,
+            rpl_flags: self.rpl_flags,
+            mega: self.display.mega().cloned(),
+        }
+    }
+
+    /// Restores a state previously returned by `snapshot`.
+    pub fn restore_snapshot(&mut self, snapshot: &Snapshot) {
+        self.cpu = snapshot.cpu.clone();
+        self.memory = snapshot.memory.clone();
+        self.display.restore_mega(snapshot.mega.clone());
+        self.display.restore(snapshot.framebuffer.clone());
+        self.delay_timer.update(snapshot.delay_timer);
+        self.sound_timer.update(snapshot.sound_timer);
+        self.rng = snapshot.rng.clone();
+        self.frame_budget = snapshot.frame_budget;
+        self.tick_countdown = snapshot.tick_countdown;
+        self.rpl_flags = snapshot.rpl_flags;
+        // the recorded path doesn't lead to the restored state anymore,
+        // nor do the loops polled or spun in before
+        self.history = PcHistory::new();
+        self.idle.reset();
+        self.spin.reset();
+    }
+
+    /// Returns the last executed instructions, most useful after `run` returned an error
+    /// to see the path that led to it.
+    pub fn backtrace(&self) -> Backtrace {
+        let dialect = self.options.variant.dialect();
+        let frames = self
+            .history
+            .iter()
+            .map(|pc| {
+                let opcode = self.memory.read_u16(pc).unwrap_or_default();
+                Frame {
+                    pc,
+                    opcode,
+                    disassembly: dialect.decode(opcode).map(|i| i.to_string()),
+                }
+            })
+            .collect();
+        Backtrace { frames }
+    }
+
+    /// Returns the subroutine calls in progress, with their return addresses resolved
+    /// by the symbols of the program.
+    pub fn call_stack(&self) -> CallStack {
+        let frames = self
+            .cpu
+            .stack
+            .iter()
+            .rev()
+            .map(|&return_address| StackFrame {
+                return_address,
+                symbol: self.symbols.resolve(return_address),
+            })
+            .collect();
+        CallStack { frames }
+    }
+
+    fn execute(&mut self, opcode: Instr) -> Result<(), SystemError> {
+        match opcode {
+            Instr::ClearDisplay => {
+                self.display.clear();
+                self.write_display_buffer();
+            }
+            Instr::Return => {
+                self.cpu.pc = self.cpu.stack.pop().ok_or(SystemError::StackUnderflow)?;
+                // the program may have changed the return address in memory
+                let layout = self.options.memory_layout;
+                if let Some(slot) = layout.stack_slot(self.memory.size(), self.cpu.stack.len()) {
+                    self.cpu.pc = self
+                        .memory
+                        .read_u16(slot)
+                        .ok_or(SystemError::MemoryReadOverflow)?;
+                }
+            }
+            Instr::Jump(nnn) => {
+                let max = self.options.spin_loop_length as u64;
+                if nnn < self.cpu.pc && self.spin.jumped(&self.cpu, self.instructions, max) {
+                    return Err(SystemError::Spinning);
+                }
+                self.cpu.pc = nnn;
+            }
+            Instr::Call(nnn) => {
+                let layout = self.options.memory_layout;
+                if self.cpu.stack.len() >= layout.stack_size(STACK_SIZE) {
+                    return Err(SystemError::StackOverflow);
+                }
+                if let Some(slot) = layout.stack_slot(self.memory.size(), self.cpu.stack.len()) {
+                    self.memory.write_slice(slot, &self.cpu.pc.to_be_bytes())?;
+                    self.memory_viewer.written(slot, 2);
+                }
+                self.cpu.stack.push(self.cpu.pc);
+                self.cpu.pc = nnn;
+            }
+            Instr::SkipEqImm(x, kk) => {
+                if self.cpu.v[x] == kk {
+                    self.cpu.pc += 2;
+                }
+            }
+            Instr::SkipNotEqImm(x, kk) => {
+                if self.cpu.v[x] != kk {
+                    self.cpu.pc += 2;
+                }
             }
             Instr::SkipEqReg(x, y) => {
                 if self.cpu.v[x] == self.cpu.v[y] {
@@ -302,11 +1813,9 @@ impl System {
             }
             Instr::ShiftRight(x, y) => {
                 if self.options.quirks.contains(Quirks::SHIFT_READS_VX) {
-                    self.cpu.v[VReg::VF] = self.cpu.v[x] & 1;
-                    self.cpu.v[x] >>= 1;
+                    self.shift_right(x, x);
                 } else {
-                    self.cpu.v[VReg::VF] = self.cpu.v[y] & 1;
-                    self.cpu.v[x] = self.cpu.v[y] >> 1;
+                    self.shift_right(x, y);
                 }
             }
             Instr::SubN(x, y) => {
@@ -316,11 +1825,9 @@ impl System {
             }
             Instr::ShiftLeft(x, y) => {
                 if self.options.quirks.contains(Quirks::SHIFT_READS_VX) {
-                    self.cpu.v[VReg::VF] = ((self.cpu.v[x] & 0x80) != 0) as u8;
-                    self.cpu.v[x] <<= 1;
+                    self.shift_left(x, x);
                 } else {
-                    self.cpu.v[VReg::VF] = ((self.cpu.v[y] & 0x80) != 0) as u8;
-                    self.cpu.v[x] = self.cpu.v[y] << 1;
+                    self.shift_left(x, y);
                 }
             }
             Instr::SkipNotEqReg(x, y) => {
@@ -332,25 +1839,18 @@ impl System {
                 self.cpu.i = nnn;
             }
             Instr::JumpV0(nnn) => {
-                self.cpu.pc = nnn.wrapping_add(self.cpu.v[VReg::V0] as u16);
-                return Ok(());
+                if self.options.quirks.contains(Quirks::JUMP_USES_VX) {
+                    self.jump_offset(nnn, jump_register(nnn));
+                } else {
+                    self.jump_offset(nnn, VReg::V0);
+                }
             }
             Instr::Random(x, kk) => {
-                self.cpu.v[x] = kk & rng.gen::<u8>();
+                self.cpu.v[x] = kk & self.rng.next_byte();
             }
             Instr::Draw(x, y, n) => {
-                let bytes = self
-                    .memory
-                    .read_slice(self.cpu.i, n)
-                    .ok_or(SystemError::MemoryReadOverflow)?;
-
-                self.cpu.v[VReg::VF] = if self.options.quirks.contains(Quirks::DRAW_WRAPS_PIXELS) {
-                    self.display
-                        .draw_sprite_wrapped((self.cpu.v[x], self.cpu.v[y]), bytes)
-                } else {
-                    self.display
-                        .draw_sprite_clipped((self.cpu.v[x], self.cpu.v[y]), bytes)
-                } as u8;
+                let wrap = self.options.quirks.contains(Quirks::DRAW_WRAPS_PIXELS);
+                self.draw(x, y, n, wrap)?;
             }
             Instr::SkipKeyPressed(x) => {
                 if let Some(k) = Key::from(self.cpu.v[x]) {
@@ -367,14 +1867,28 @@ impl System {
                 }
             }
             Instr::LoadDelayTimer(x) => {
-                self.cpu.v[x] = self.delay_timer.value();
-            }
-            Instr::WaitKeyPress(x) => {
-                self.cpu.v[x] = self
-                    .keyboard
-                    .wait_for_key_press()
-                    .ok_or(SystemError::Interrupted)? as u8;
+                let value = self.delay_timer.value();
+                self.cpu.v[x] = value;
+                if value != 0 && self.idle.poll(&self.cpu) {
+                    self.wait_for_delay_timer(value);
+                }
             }
+            Instr::WaitKeyPress(x) => match self.options.execution_mode {
+                ExecutionMode::Threaded => {
+                    self.cpu.v[x] = self
+                        .keyboard
+                        .wait_for_key_press()
+                        .ok_or(SystemError::Interrupted)? as u8;
+                }
+                ExecutionMode::SingleThreaded => match self.keyboard.poll_key_press() {
+                    Some(k) => self.cpu.v[x] = k as u8,
+                    None => {
+                        // try again next frame
+                        self.cpu.pc -= 2;
+                        self.frame_budget = 0.0;
+                    }
+                },
+            },
             Instr::SetDelayTimer(x) => {
                 self.delay_timer.update(self.cpu.v[x]);
             }
@@ -395,42 +1909,441 @@ impl System {
                     .iter()
                     .map(|c| c.saturating_sub(b'0'))
                     .collect::<Vec<_>>();
-                self.memory.write_slice(self.cpu.i, &a);
+                self.write_memory(self.cpu.i, &a)?;
+            }
+            Instr::SaveRegs(x) => self.save_regs(x, self.load_store_increment(x))?,
+            Instr::LoadRegs(x) => self.load_regs(x, self.load_store_increment(x))?,
+            Instr::ScrollDown(n) => self.scroll_down(n as usize),
+            Instr::ScrollRight => self.scroll_right(HORIZONTAL_SCROLL),
+            Instr::ScrollLeft => self.scroll_left(HORIZONTAL_SCROLL),
+            Instr::Exit => return Err(SystemError::Exited),
+            Instr::LowRes => {
+                self.display.set_size(DISPLAY_WIDTH, DISPLAY_HEIGHT);
+                self.write_display_buffer();
+            }
+            Instr::HighRes => {
+                self.display
+                    .set_size(EXTENDED_DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT);
             }
-            Instr::SaveRegs(x) => {
-                self.memory
-                    .write_slice(self.cpu.i, &self.cpu.v[0..=x as usize]);
-                if !self.options.quirks.contains(Quirks::LOAD_STORE_IGNORES_I) {
-                    self.cpu.i += x as u16 + 1;
+            Instr::LoadLargeSprite(x) => {
+                self.cpu.i = LARGE_FONT_SPRITES_ADDRESS + (self.cpu.v[x] as u16 * 10);
+            }
+            Instr::SaveFlags(x) => {
+                self.rpl_flags.0[..=x as usize].copy_from_slice(&self.cpu.v[..=x as usize]);
+                self.rpl_output.send(self.rpl_flags);
+            }
+            Instr::LoadFlags(x) => {
+                self.cpu.v[..=x as usize].copy_from_slice(&self.rpl_flags.0[..=x as usize]);
+            }
+            Instr::MegaOff => self.display.set_mega(false),
+            Instr::MegaOn => self.display.set_mega(true),
+            Instr::LoadLongI(hi) => {
+                let lo = self
+                    .memory
+                    .read_u16(self.cpu.pc)
+                    .ok_or(SystemError::MemoryReadOverflow)?;
+                self.cpu.pc += 2;
+                let address = (hi as u32) << 16 | lo as u32;
+                if hi != 0 {
+                    return Err(SystemError::AddressOutOfRange(address));
                 }
+                self.cpu.i = lo;
             }
-            Instr::LoadRegs(x) => {
-                let s = self
+            Instr::LoadPalette(n) => {
+                let start = self.cpu.i as usize;
+                let colors = self
                     .memory
-                    .read_slice(self.cpu.i, x as u8 + 1)
+                    .as_bytes()
+                    .get(start..start + n as usize * 4)
                     .ok_or(SystemError::MemoryReadOverflow)?;
-                self.cpu.v[0..=x as usize].copy_from_slice(s);
-                if !self.options.quirks.contains(Quirks::LOAD_STORE_IGNORES_I) {
-                    self.cpu.i += x as u16 + 1;
+                if let Some(mega) = self.display.mega_mut() {
+                    mega.back.load_palette(colors);
+                }
+            }
+            Instr::SpriteWidth(n) => {
+                if let Some(mega) = self.display.mega_mut() {
+                    mega.sprite_size.0 = if n == 0 { 256 } else { n as usize };
                 }
             }
+            Instr::SpriteHeight(n) => {
+                if let Some(mega) = self.display.mega_mut() {
+                    mega.sprite_size.1 = if n == 0 { 256 } else { n as usize };
+                }
+            }
+            Instr::CollisionColor(n) => {
+                if let Some(mega) = self.display.mega_mut() {
+                    mega.collision = n;
+                }
+            }
+            // blending needs true colors, and digitized sound an audio backend which plays samples:
+            // sprites are drawn opaque, and programs run silently
+            Instr::ScreenAlpha(_)
+            | Instr::BlendMode(_)
+            | Instr::PlaySound(_)
+            | Instr::StopSound => {}
+            Instr::ScrollUp(n) => self.scroll_up(n as usize),
+            Instr::LoadAudioPattern => {
+                let pattern = self
+                    .memory
+                    .read_slice(self.cpu.i, PATTERN_SIZE as u8)
+                    .ok_or(SystemError::MemoryReadOverflow)?;
+                self.audio_pattern.pattern.copy_from_slice(pattern);
+                self.audio.send(self.audio_pattern);
+            }
+            Instr::SetPitch(x) => {
+                self.audio_pattern.pitch = self.cpu.v[x];
+                self.audio.send(self.audio_pattern);
+            }
+            Instr::MachineCall(nnn) => self.call_routine(nnn)?,
         }
 
-        self.cpu.pc += 2;
-
         Ok(())
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::thread::sleep;
-    use std::time::Duration;
+
+    /// Called when the program does nothing but poll the delay timer,
+    /// the CPU sleeps until the next tick instead of spinning.
+    fn wait_for_delay_timer(&mut self, value: u8) {
+        match self.options.execution_mode {
+            ExecutionMode::Threaded => self.delay_timer.wait_for_change(value, IDLE_TIMEOUT),
+            // timers ticked by instructions only change by running them
+            ExecutionMode::SingleThreaded if self.options.instructions_per_tick.is_some() => {}
+            // nothing will change until the timers tick at the end of the frame
+            ExecutionMode::SingleThreaded => self.frame_budget = 0.0,
+        }
+    }
+
+    /// Shifts `src` right into `x`, VF receives the shifted out bit.
+    pub(crate) fn shift_right(&mut self, x: VReg, src: VReg) {
+        let v = self.cpu.v[src];
+        self.cpu.v[VReg::VF] = v & 1;
+        self.cpu.v[x] = v >> 1;
+    }
+
+    /// Shifts `src` left into `x`, VF receives the shifted out bit.
+    pub(crate) fn shift_left(&mut self, x: VReg, src: VReg) {
+        let v = self.cpu.v[src];
+        self.cpu.v[VReg::VF] = ((v & 0x80) != 0) as u8;
+        self.cpu.v[x] = v << 1;
+    }
+
+    /// Jumps to `nnn` plus the value of the `offset` register.
+    pub(crate) fn jump_offset(&mut self, nnn: u16, offset: VReg) {
+        self.cpu.pc = nnn.wrapping_add(self.cpu.v[offset] as u16);
+    }
+
+    pub(crate) fn draw(&mut self, x: VReg, y: VReg, n: u8, wrap: bool) -> Result<(), SystemError> {
+        let bytes = self
+            .memory
+            .read_slice(self.cpu.i, n)
+            .ok_or(SystemError::MemoryReadOverflow)?;
+
+        self.cpu.v[VReg::VF] = if wrap {
+            self.display
+                .draw_sprite_wrapped((self.cpu.v[x], self.cpu.v[y]), bytes)
+        } else {
+            self.display
+                .draw_sprite_clipped((self.cpu.v[x], self.cpu.v[y]), bytes)
+        } as u8;
+        self.write_display_buffer();
+
+        Ok(())
+    }
+
+    pub(crate) fn scroll_down(&mut self, n: usize) {
+        self.display.scroll_down(n);
+        self.write_display_buffer();
+    }
+
+    pub(crate) fn scroll_up(&mut self, n: usize) {
+        self.display.scroll_up(n);
+        self.write_display_buffer();
+    }
+
+    pub(crate) fn scroll_right(&mut self, n: usize) {
+        self.display.scroll_right(n);
+        self.write_display_buffer();
+    }
+
+    pub(crate) fn scroll_left(&mut self, n: usize) {
+        self.display.scroll_left(n);
+        self.write_display_buffer();
+    }
+
+    /// Returns true if the screen is in the low resolution of SUPER-CHIP, the usual 64x32 one.
+    pub(crate) fn is_low_res(&self) -> bool {
+        self.display.framebuffer().width() == DISPLAY_WIDTH
+    }
+
+    /// Returns true if a MegaChip program has switched the mega mode on.
+    pub(crate) fn is_mega(&self) -> bool {
+        self.display.mega().is_some()
+    }
+
+    /// Draws the MegaChip sprite at I, made of palette indices, on the frame to show next.
+    pub(crate) fn draw_mega(&mut self, x: VReg, y: VReg) -> Result<(), SystemError> {
+        let pos = (self.cpu.v[x] as usize, self.cpu.v[y] as usize);
+        if let Some(mega) = self.display.mega_mut() {
+            let (width, height) = mega.sprite_size;
+            let start = self.cpu.i as usize;
+            let sprite = self
+                .memory
+                .as_bytes()
+                .get(start..start + width * height)
+                .ok_or(SystemError::MemoryReadOverflow)?;
+            let collision = mega.collision;
+            self.cpu.v[VReg::VF] =
+                mega.back
+                    .draw_sprite(pos, (width, height), sprite, collision) as u8;
+        }
+        Ok(())
+    }
+
+    /// Scrolls the frame a MegaChip program is drawing.
+    pub(crate) fn scroll_mega(&mut self, dx: isize, dy: isize) {
+        if let Some(mega) = self.display.mega_mut() {
+            mega.back.scroll(dx, dy);
+        }
+    }
+
+    /// Draws the 16x16 sprite at I, as `Dxy0` does on SUPER-CHIP.
+    pub(crate) fn draw_large(&mut self, x: VReg, y: VReg, wrap: bool) -> Result<(), SystemError> {
+        let bytes = self
+            .memory
+            .read_slice(self.cpu.i, LARGE_SPRITE_SIZE)
+            .ok_or(SystemError::MemoryReadOverflow)?;
+
+        self.cpu.v[VReg::VF] =
+            self.display
+                .draw_large_sprite((self.cpu.v[x], self.cpu.v[y]), bytes, wrap) as u8;
+        self.write_display_buffer();
+
+        Ok(())
+    }
+
+    /// Stores V0 to `x` in memory starting at I, then increments I by `i_increment`.
+    pub(crate) fn save_regs(&mut self, x: VReg, i_increment: u16) -> Result<(), SystemError> {
+        let v = self.cpu.v;
+        self.write_memory(self.cpu.i, &v[0..=x as usize])?;
+        self.cpu.i += i_increment;
+        Ok(())
+    }
+
+    /// Writes to memory on behalf of the current instruction.
+    fn write_memory(&mut self, addr: u16, data: &[u8]) -> Result<(), SystemError> {
+        if self.options.protect_reserved && (addr as usize) < RESERVED_SIZE {
+            return Err(SystemError::MemoryWriteViolation(addr));
+        }
+        self.memory.write_slice(addr, data)?;
+        // PC already points to the next instruction
+        self.code_tracker.written(self.cpu.pc - 2, addr, data.len());
+        self.memory_viewer.written(addr, data.len());
+
+        // writing to the display refresh buffer changes the screen
+        if let Some(buffer) = self.display_buffer() {
+            if addr as usize + data.len() > buffer as usize {
+                let bytes = &self.memory.as_bytes()[buffer as usize..];
+                self.display.restore(Framebuffer::from_bytes(bytes));
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies the screen to the display refresh buffer, when it is in memory.
+    fn write_display_buffer(&mut self) {
+        if let Some(buffer) = self.display_buffer() {
+            let bytes = self.display.framebuffer().to_bytes();
+            // the buffer fills the end of memory
+            let _ = self.memory.write_slice(buffer, &bytes);
+        }
+    }
+
+    /// Returns the address of the display refresh buffer, which only holds a screen of usual size.
+    fn display_buffer(&self) -> Option<u16> {
+        if self.display.framebuffer().pixels().len() != DISPLAY_BUFFER_SIZE {
+            return None;
+        }
+        let layout = self.options.memory_layout;
+        layout.display_buffer(self.memory.size())
+    }
+
+    /// Returns how much load and store instructions of registers up to `x` increment I.
+    fn load_store_increment(&self, x: VReg) -> u16 {
+        let quirks = self.options.quirks;
+        if quirks.contains(Quirks::LOAD_STORE_IGNORES_I) {
+            0
+        } else if quirks.contains(Quirks::LOAD_STORE_INCREMENTS_I_BY_X) {
+            x as u16
+        } else {
+            x as u16 + 1
+        }
+    }
+
+    /// Loads V0 to `x` from memory starting at I, then increments I by `i_increment`.
+    pub(crate) fn load_regs(&mut self, x: VReg, i_increment: u16) -> Result<(), SystemError> {
+        let s = self
+            .memory
+            .read_slice(self.cpu.i, x as u8 + 1)
+            .ok_or(SystemError::MemoryReadOverflow)?;
+        self.cpu.v[0..=x as usize].copy_from_slice(s);
+        self.cpu.i += i_increment;
+        Ok(())
+    }
+}
+
+impl OutputPort<SystemMetrics> for System {
+    fn output(&self) -> Receiver<SystemMetrics> {
+        self.metrics.output()
+    }
+}
+
+/// Emits the pacing of the display updates in wall clock time, along with the metrics.
+impl OutputPort<FramePacing> for System {
+    fn output(&self) -> Receiver<FramePacing> {
+        self.display.pacing_output()
+    }
+}
+
+impl OutputPort<Diagnostic> for System {
+    fn output(&self) -> Receiver<Diagnostic> {
+        self.code_tracker.output()
+    }
+}
+
+/// Publishes the state of the CPU, if enabled by `SystemOptionsBuilder::debug_state_interval`.
+impl OutputPort<CpuState> for System {
+    fn output(&self) -> Receiver<CpuState> {
+        self.debug_state.receiver()
+    }
+}
+
+/// Publishes views of the memory, if enabled by `SystemOptionsBuilder::memory_view`.
+impl OutputPort<MemoryView> for System {
+    fn output(&self) -> Receiver<MemoryView> {
+        self.memory_viewer.output()
+    }
+}
+
+/// Emits the XO-CHIP audio pattern each time it changes.
+impl OutputPort<AudioPattern> for System {
+    fn output(&self) -> Receiver<AudioPattern> {
+        self.audio.receiver()
+    }
+}
+
+/// Emits the flag registers each time a program saves them, to persist them across runs.
+impl OutputPort<RplFlags> for System {
+    fn output(&self) -> Receiver<RplFlags> {
+        self.rpl_output.receiver()
+    }
+}
+
+impl OutputPort<DebugEvent> for System {
+    fn output(&self) -> Receiver<DebugEvent> {
+        self.debugger.output()
+    }
+}
+
+/// The display updates, sound timer, metrics and debug events on a single port,
+/// and a last event once the system stops running.
+impl OutputPort<SystemEvent> for System {
+    fn output(&self) -> Receiver<SystemEvent> {
+        let mut forwarders = self.events_forwarders.lock().unwrap();
+        if forwarders.is_none() {
+            *forwarders = Some(self.forward_events());
+        }
+        self.events.1.clone()
+    }
+}
+
+/// Detects programs polling the delay timer in a loop without doing anything else.
+/// If the CPU state is the same each time the timer is read, and no instruction
+/// with side effects has been executed in between, then nothing can happen
+/// until the value of the timer changes.
+#[derive(Default)]
+struct IdleDetector {
+    last_poll: Option<PollState>,
+}
+
+#[derive(PartialEq)]
+struct PollState {
+    pc: u16,
+    v: VRegBank,
+    i: u16,
+    stack_depth: usize,
+}
+
+/// Detects programs spinning in a loop closed by a jump backwards: if the CPU state is the
+/// same at two consecutive jumps, and no instruction with side effects has been executed
+/// in between, then the loop repeats forever.
+#[derive(Default)]
+struct SpinDetector {
+    // the state at the last jump, along with the instruction count
+    last_jump: Option<(PollState, u64)>,
+}
+
+impl SpinDetector {
+    fn reset(&mut self) {
+        self.last_jump = None;
+    }
+
+    /// Records a jump backwards, returns true if the program is spinning in a loop
+    /// of at most `max` instructions.
+    fn jumped(&mut self, cpu: &Cpu, instructions: u64, max: u64) -> bool {
+        let state = PollState {
+            pc: cpu.pc,
+            v: cpu.v,
+            i: cpu.i,
+            stack_depth: cpu.stack.len(),
+        };
+        let spinning = matches!(
+            &self.last_jump,
+            Some((last, n)) if *last == state && instructions - n <= max
+        );
+        self.last_jump = Some((state, instructions));
+        spinning
+    }
+}
+
+impl IdleDetector {
+    fn reset(&mut self) {
+        self.last_poll = None;
+    }
+
+    /// Records a read of the delay timer, returns true if the program is idle.
+    fn poll(&mut self, cpu: &Cpu) -> bool {
+        let state = PollState {
+            pc: cpu.pc,
+            v: cpu.v,
+            i: cpu.i,
+            stack_depth: cpu.stack.len(),
+        };
+        let idle = self.last_poll.as_ref() == Some(&state);
+        self.last_poll = Some(state);
+        idle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::debug::{MemoryChange, SymbolsError};
+    use crate::display::{DisplayContent, DisplayMessage, DISPLAY_WIDTH};
+    use crate::keyboard::KeyboardMessage;
+    use crate::port::InputPort;
+    use crate::random::ScriptedRandom;
+    use crate::timer::TimerMessage;
+    use approx::assert_relative_eq;
+    use std::time::Duration;
 
     #[test]
     fn stop_works() {
-        let mut chip8 = System::new();
+        let clock = ManualClock::new();
+        let opts = SystemOptions::builder()
+            .clock(Arc::new(clock.clone()))
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
         let ctrl = chip8.controller();
 
         let image = [0x00, 0xE0, 0x12, 0x00];
@@ -438,8 +2351,10 @@ mod tests {
 
         let j = chip8.start();
 
-        sleep(Duration::from_millis(200));
+        // the run loop stops once woken up
+        clock.wait_for_sleepers(1);
         ctrl.stop();
+        clock.advance(Duration::from_secs(1));
 
         let r = j.join();
 
@@ -447,20 +2362,1469 @@ mod tests {
     }
 
     #[test]
-    fn stop_when_waiting_for_key_press_works() {
-        let mut chip8 = System::new();
+    fn batched_execution_keeps_frequency() {
+        let clock = ManualClock::new();
+        let opts = SystemOptions::builder()
+            .cpu_frequency_hz(200.0)
+            .clock(Arc::new(clock.clone()))
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
         let ctrl = chip8.controller();
 
+        // add v0, 1; jp 0x200
+        let image = [0x70, 0x01, 0x12, 0x00];
+        chip8.load_image_bytes(&image);
+
+        let j = thread::spawn(move || {
+            chip8.run().unwrap();
+            chip8
+        });
+
+        // one second, in slices of 5 ms
+        for _ in 0..200 {
+            clock.wait_for_sleepers(1);
+            clock.advance(Duration::from_millis(5));
+        }
+        clock.wait_for_sleepers(1);
+        ctrl.stop();
+        clock.advance(Duration::from_millis(5));
+
+        let chip8 = j.join().unwrap();
+
+        // 200 instructions in one second, half of them are additions
+        assert_relative_eq!(chip8.cpu.v[VReg::V0] as f64, 100.0, epsilon = 1.0);
+    }
+
+    #[test]
+    fn stop_when_waiting_for_key_press_works() {
+        let clock = ManualClock::new();
+        let opts = SystemOptions::builder()
+            .clock(Arc::new(clock.clone()))
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        let kb_controller = chip8.keyboard.controller();
+
         let image = [0xF1, 0x0A];
         chip8.load_image_bytes(&image);
 
         let j = chip8.start();
 
-        sleep(Duration::from_millis(200));
-        ctrl.stop();
+        // the interruption is kept until the key press is waited for
+        clock.wait_for_sleepers(1);
+        kb_controller.stop();
+        clock.advance(Duration::from_secs(1));
 
         let r = j.join();
 
         assert!(r.is_ok());
     }
+
+    fn step(chip8: &mut System, n: usize) {
+        for _ in 0..n {
+            chip8.execute_next_inst().unwrap();
+        }
+    }
+
+    /// Presses a key through the input port of the keyboard.
+    fn press_key(chip8: &System, key: Key) {
+        chip8
+            .keyboard
+            .input()
+            .send(KeyboardMessage::down(key))
+            .unwrap();
+    }
+
+    #[test]
+    fn shift_depends_on_variant() {
+        // ld v1, 5; ld v2, 3; shr v1, v2
+        let image = [0x61, 0x05, 0x62, 0x03, 0x81, 0x26];
+
+        let mut chip8 = System::new();
+        chip8.load_image_bytes(&image);
+        step(&mut chip8, 3);
+        assert_eq!(chip8.cpu.v[VReg::V1], 1);
+        assert_eq!(chip8.cpu.v[VReg::VF], 1);
+
+        let opts = SystemOptions::builder()
+            .variant(Variant::SChip)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        chip8.load_image_bytes(&image);
+        step(&mut chip8, 3);
+        assert_eq!(chip8.cpu.v[VReg::V1], 2);
+        assert_eq!(chip8.cpu.v[VReg::VF], 1);
+    }
+
+    #[test]
+    fn chip48_preset_enables_its_quirks() {
+        // ld v2, 3; ld v1, 8; shr v1, v2; ld i, 0x300; ld [i], v1; jp v0, 0x210
+        let image = [
+            0x62, 0x03, 0x61, 0x08, 0x81, 0x26, 0xA3, 0x00, 0xF1, 0x55, 0xB2, 0x10,
+        ];
+
+        let opts = SystemOptions::builder()
+            .quirk(Variant::Chip48.quirks())
+            .build()
+            .unwrap();
+        assert_eq!(opts.variant(), Variant::Chip8);
+        let mut chip8 = System::new_with_options(opts);
+        chip8.load_image_bytes(&image);
+        step(&mut chip8, 6);
+        assert_eq!(chip8.cpu.v[VReg::V1], 4);
+        assert_eq!(chip8.cpu.i, 0x301);
+        assert_eq!(chip8.cpu.pc, 0x213);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn rom_profiles_are_overridden_by_later_options() {
+        let profile = RomProfile {
+            name: "test".to_string(),
+            variant: Variant::MegaChip,
+            quirks: Quirks::empty(),
+            speed: Some(Speed::InstructionsPerFrame(1000)),
+        };
+
+        let opts = SystemOptions::builder()
+            .quirk(Quirks::DRAW_WRAPS_PIXELS)
+            .rom_profile(&profile)
+            .build()
+            .unwrap();
+        assert_eq!(opts.variant, Variant::MegaChip);
+        assert_eq!(opts.memory_size, MAX_MEMORY_SIZE);
+        assert_eq!(opts.max_program_size(), MAX_MEMORY_SIZE - RESERVED_SIZE);
+        assert_eq!(opts.speed, Speed::InstructionsPerFrame(1000));
+        assert_eq!(opts.quirks, Quirks::DRAW_WRAPS_PIXELS);
+
+        let opts = SystemOptions::builder()
+            .rom_profile(&profile)
+            .instructions_per_frame(10)
+            .build()
+            .unwrap();
+        assert_eq!(opts.speed, Speed::InstructionsPerFrame(10));
+    }
+
+    #[test]
+    fn machine_routines_are_called_by_0nnn() {
+        // ld i, 0x400; sys 0x300; sys 0x302
+        let image = [0xA4, 0x00, 0x03, 0x00, 0x03, 0x02];
+
+        let mut chip8 = System::new();
+        chip8.register_routine(0x300, |ctx| {
+            ctx.v[0] = 42;
+            ctx.memory[*ctx.i as usize] = 7;
+        });
+        chip8.load_image_bytes(&image);
+        step(&mut chip8, 2);
+        assert_eq!(chip8.cpu.v[VReg::V0], 42);
+        assert_eq!(chip8.memory.as_bytes()[0x400], 7);
+        assert_eq!(chip8.cpu.pc, 0x204);
+        assert!(matches!(
+            chip8.execute_next_inst(),
+            Err(SystemError::UnknownInstruction(0x0302))
+        ));
+    }
+
+    #[test]
+    fn machine_routines_are_stepped_and_tick_the_timers() {
+        let opts = SystemOptions::builder()
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .timers_tick_every(1)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        let events: Receiver<DebugEvent> = chip8.output();
+        chip8.register_routine(0x300, |ctx| ctx.v[0] = 42);
+
+        // ld v1, 5; ld dt, v1; sys 0x300; jp 0x206
+        chip8.load_image_bytes(&[0x61, 0x05, 0xF1, 0x15, 0x03, 0x00, 0x12, 0x06]);
+        step(&mut chip8, 2);
+        chip8.pause();
+        chip8.handle_debug_command(DebugCommand::Step);
+        chip8.run_frame().unwrap();
+        assert!(chip8.debugger.paused);
+        assert_eq!(chip8.cpu.pc, 0x206);
+        assert_eq!(chip8.cpu.v[VReg::V0], 42);
+        assert_eq!(chip8.delay_timer.value(), 3);
+
+        let stepped = events.try_iter().find_map(|event| match event {
+            DebugEvent::Stepped { instruction, .. } => Some(instruction),
+            _ => None,
+        });
+        assert_eq!(stepped.as_deref(), Some("sys 0x300"));
+    }
+
+    #[test]
+    fn writes_to_reserved_memory_can_be_refused() {
+        // ld i, 0x050; ld [i], v0
+        let image = [0xA0, 0x50, 0xF0, 0x55];
+
+        let opts = SystemOptions::builder()
+            .protect_reserved(true)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        chip8.load_image_bytes(&image);
+        step(&mut chip8, 1);
+        assert!(matches!(
+            chip8.execute_next_inst(),
+            Err(SystemError::MemoryWriteViolation(0x50))
+        ));
+
+        // ld i, 0xfff; ld b, v0
+        let image = [0xAF, 0xFF, 0xF0, 0x33];
+        let mut chip8 = System::new();
+        chip8.load_image_bytes(&image);
+        step(&mut chip8, 1);
+        assert!(matches!(
+            chip8.execute_next_inst(),
+            Err(SystemError::MemoryWriteOverflow)
+        ));
+    }
+
+    #[test]
+    fn random_numbers_can_be_scripted() {
+        // rnd v0, 0x0f; rnd v1, 0xff
+        let image = [0xC0, 0x0F, 0xC1, 0xFF];
+
+        let opts = SystemOptions::builder()
+            .random_source(Box::new(ScriptedRandom::new(vec![0xAB, 0xCD])))
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        chip8.load_image_bytes(&image);
+        step(&mut chip8, 2);
+        assert_eq!(chip8.cpu.v[VReg::V0], 0x0B);
+        assert_eq!(chip8.cpu.v[VReg::V1], 0xCD);
+    }
+
+    #[test]
+    fn jump_with_offset_depends_on_variant() {
+        // ld v2, 3; jp v0, 0x210
+        let image = [0x62, 0x03, 0xB2, 0x10];
+
+        let mut chip8 = System::new();
+        chip8.load_image_bytes(&image);
+        step(&mut chip8, 2);
+        assert_eq!(chip8.cpu.pc, 0x210);
+
+        let opts = SystemOptions::builder()
+            .variant(Variant::Chip48)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        chip8.load_image_bytes(&image);
+        step(&mut chip8, 2);
+        assert_eq!(chip8.cpu.pc, 0x213);
+
+        let opts = SystemOptions::builder()
+            .quirk(Quirks::JUMP_USES_VX)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        chip8.load_image_bytes(&image);
+        step(&mut chip8, 2);
+        assert_eq!(chip8.cpu.pc, 0x213);
+    }
+
+    #[test]
+    fn timers_can_tick_every_n_instructions() {
+        let opts = SystemOptions::builder()
+            .instructions_per_frame(7)
+            .timers_tick_every(10)
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+
+        // ld v0, 60; ld dt, v0; ld v1, dt; jp 0x204
+        let image = [0x60, 0x3C, 0xF0, 0x15, 0xF1, 0x07, 0x12, 0x04];
+        chip8.load_image_bytes(&image);
+        for _ in 0..3 {
+            chip8.run_frame().unwrap();
+        }
+
+        // 21 instructions, ticks after the 10th and the 20th
+        assert_eq!(chip8.delay_timer.value(), 58);
+
+        assert_eq!(
+            SystemOptions::builder().timers_tick_every(10).build().err(),
+            Some(OptionsError::InstructionTimersRequireSingleThreaded)
+        );
+    }
+
+    #[test]
+    fn restored_snapshots_keep_the_timer_phase() {
+        let opts = SystemOptions::builder()
+            .timers_tick_every(3)
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .halt_on_spin(0)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+
+        // ld v0, 60; ld dt, v0; jp 0x204
+        chip8.load_image_bytes(&[0x60, 0x3C, 0xF0, 0x15, 0x12, 0x04]);
+        step(&mut chip8, 2);
+        let snapshot = chip8.snapshot();
+        step(&mut chip8, 4);
+        assert_eq!(chip8.delay_timer.value(), 58);
+
+        chip8.restore_snapshot(&snapshot);
+        step(&mut chip8, 4);
+        assert_eq!(chip8.delay_timer.value(), 58);
+    }
+
+    #[test]
+    fn restored_snapshots_forget_the_loops_spun_in() {
+        let opts = SystemOptions::builder()
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+
+        // jp 0x200
+        chip8.load_image_bytes(&[0x12, 0x00]);
+        let snapshot = chip8.snapshot();
+        step(&mut chip8, 1);
+
+        // the jump replayed isn't taken for a second turn of the loop
+        chip8.restore_snapshot(&snapshot);
+        assert!(chip8.execute_next_inst().is_ok());
+        assert!(matches!(
+            chip8.execute_next_inst(),
+            Err(SystemError::Spinning)
+        ));
+    }
+
+    #[test]
+    fn single_threaded_mode_is_deterministic() {
+        let opts = SystemOptions::builder()
+            .cpu_frequency_hz(600.0)
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+
+        // ld v0, 10; ld dt, v0; ld v1, dt; jp 0x204
+        let image = [0x60, 0x0A, 0xF0, 0x15, 0xF1, 0x07, 0x12, 0x04];
+        chip8.load_image_bytes(&image);
+
+        for _ in 0..5 {
+            chip8.run_frame().unwrap();
+        }
+
+        assert_eq!(chip8.delay_timer.value(), 5);
+        assert_eq!(chip8.cpu.v[VReg::V1], 6);
+    }
+
+    #[test]
+    fn instructions_per_frame_are_executed_each_frame() {
+        let opts = SystemOptions::builder()
+            .instructions_per_frame(7)
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+
+        // add v0, 1; jp 0x200
+        let image = [0x70, 0x01, 0x12, 0x00];
+        chip8.load_image_bytes(&image);
+
+        for _ in 0..3 {
+            chip8.run_frame().unwrap();
+        }
+
+        assert_eq!(chip8.instructions, 21);
+        assert_eq!(chip8.cpu.v[VReg::V0], 11);
+    }
+
+    #[test]
+    fn vip_timing_counts_the_cycles_of_each_instruction() {
+        let opts = SystemOptions::builder()
+            .instructions_per_frame(7)
+            .timing(Timing::VipCycles)
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+
+        // add v0, 1; jp 0x200
+        let image = [0x70, 0x01, 0x12, 0x00];
+        chip8.load_image_bytes(&image);
+        chip8.run_frame().unwrap();
+
+        // 3668 cycles per frame, 50 + 52 per loop
+        assert_eq!(chip8.instructions, 72);
+        assert_eq!(chip8.cpu.v[VReg::V0], 36);
+    }
+
+    #[test]
+    fn display_messages_are_numbered_and_timed() {
+        let opts = SystemOptions::builder()
+            .instructions_per_frame(2)
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        let display: Receiver<DisplayMessage> = chip8.display.output();
+
+        // cls; jp 0x200
+        let image = [0x00, 0xE0, 0x12, 0x00];
+        chip8.load_image_bytes(&image);
+
+        for frame in 1..=3 {
+            chip8.run_frame().unwrap();
+            let msg = display.try_recv().unwrap();
+            assert_eq!(msg.sequence, frame);
+            assert_relative_eq!(msg.time.as_secs_f64(), frame as f64 / 60.0);
+        }
+    }
+
+    #[test]
+    fn single_threaded_mode_polls_key_press() {
+        let opts = SystemOptions::builder()
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+
+        // ld v1, k; add v2, 1; jp 0x202
+        let image = [0xF1, 0x0A, 0x72, 0x01, 0x12, 0x02];
+        chip8.load_image_bytes(&image);
+
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.cpu.pc, 0x200);
+
+        press_key(&chip8, Key::Key5);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.cpu.v[VReg::V1], 5);
+    }
+
+    #[test]
+    fn options_are_validated() {
+        assert!(SystemOptions::builder().build().is_ok());
+        assert_eq!(
+            SystemOptions::builder().cpu_frequency_hz(0.0).build().err(),
+            Some(OptionsError::CpuFrequencyOutOfRange(0.0))
+        );
+        assert_eq!(
+            SystemOptions::builder()
+                .instructions_per_frame(0)
+                .build()
+                .err(),
+            Some(OptionsError::InstructionsPerFrameOutOfRange(0))
+        );
+        assert_eq!(
+            SystemOptions::builder()
+                .quirk(Quirks::SHIFT_READS_VX | Quirks::DRAW_WRAPS_PIXELS)
+                .variant(Variant::SChip)
+                .build()
+                .err(),
+            Some(OptionsError::ConflictingQuirks(
+                Quirks::SHIFT_READS_VX,
+                Variant::SChip
+            ))
+        );
+        assert_eq!(
+            SystemOptions::builder().memory_size(1024).build().err(),
+            Some(OptionsError::MemorySizeOutOfRange(1024))
+        );
+    }
+
+    #[test]
+    fn idle_loop_is_detected() {
+        let mut detector = IdleDetector::default();
+        let mut cpu = Cpu::new(0x200);
+
+        assert!(!detector.poll(&cpu));
+        assert!(detector.poll(&cpu));
+
+        // a register has been modified since the last poll
+        cpu.v[VReg::V2] += 1;
+        assert!(!detector.poll(&cpu));
+        assert!(detector.poll(&cpu));
+
+        // an instruction with side effects has been executed since the last poll
+        detector.reset();
+        assert!(!detector.poll(&cpu));
+    }
+
+    #[test]
+    fn metrics_are_emitted_every_second() {
+        let opts = SystemOptions::builder()
+            .cpu_frequency_hz(600.0)
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        let metrics: Receiver<SystemMetrics> = chip8.output();
+
+        // ld v0, 10; ld dt, v0; cls; jp 0x204
+        let image = [0x60, 0x0A, 0xF0, 0x15, 0x00, 0xE0, 0x12, 0x04];
+        chip8.load_image_bytes(&image);
+
+        for _ in 0..59 {
+            chip8.run_frame().unwrap();
+        }
+        assert!(metrics.try_recv().is_err());
+
+        chip8.run_frame().unwrap();
+        let m = metrics.try_recv().unwrap();
+        assert_relative_eq!(m.instructions_per_second, 600.0);
+        assert_eq!(m.frames, 60);
+        assert_eq!(m.delay_timer_ticks, 10);
+    }
+
+    #[test]
+    fn self_modifying_code_is_reported() {
+        let mut chip8 = System::new();
+        let diagnostics: Receiver<Diagnostic> = chip8.output();
+
+        // ld i, 0x200; ld [i], v0
+        let image = [0xA2, 0x00, 0xF0, 0x55];
+        chip8.load_image_bytes(&image);
+        step(&mut chip8, 2);
+
+        assert_eq!(
+            diagnostics.try_recv(),
+            Ok(Diagnostic::SelfModifyingCode {
+                pc: 0x202,
+                address: 0x200,
+                len: 1
+            })
+        );
+    }
+
+    #[test]
+    fn memory_dump_and_restore_works() {
+        let path = std::env::temp_dir().join("chip8_memory_dump_test.bin");
+
+        let mut chip8 = System::new();
+        chip8.load_image_bytes(&[0x12, 0x34]);
+        chip8.dump_memory(&path).unwrap();
+
+        let mut other = System::new();
+        other.restore_memory(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(other.memory_bytes(), chip8.memory_bytes());
+        assert_eq!(other.memory_bytes()[0x200..0x202], [0x12, 0x34]);
+        assert!(other.restore_memory_bytes(&[0; 16]).is_err());
+    }
+
+    #[test]
+    fn images_are_loaded_from_files() {
+        let path = std::env::temp_dir().join("chip8_load_image_test.c8");
+        let program = include_bytes!("../../examples/octojam2title.c8");
+
+        fs::write(&path, program).unwrap();
+        let mut chip8 = System::new();
+        chip8.load_image(&path).unwrap();
+        assert_eq!(
+            chip8.memory_bytes()[0x200..0x200 + program.len()],
+            program[..]
+        );
+
+        fs::write(&path, [0; MEMORY_SIZE]).unwrap();
+        assert!(System::new().load_image(&path).is_err());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn debug_state_is_published() {
+        let opts = SystemOptions::builder()
+            .debug_state_interval(2)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        let states: Receiver<CpuState> = chip8.output();
+
+        // ld v1, 5; ld i, 0x300; ld v2, 7
+        let image = [0x61, 0x05, 0xA3, 0x00, 0x62, 0x07];
+        chip8.load_image_bytes(&image);
+
+        step(&mut chip8, 1);
+        assert!(states.try_recv().is_err());
+
+        step(&mut chip8, 2);
+        let state = states.try_recv().unwrap();
+        assert_eq!(state.pc, 0x204);
+        assert_eq!(state.i, 0x300);
+        assert_eq!(state.v[1], 5);
+        assert_eq!(state.v[2], 0);
+    }
+
+    #[test]
+    fn registers_are_set_only_while_paused() {
+        let opts = SystemOptions::builder()
+            .debug_state_interval(1000)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        let states: Receiver<CpuState> = chip8.output();
+        let ctrl = chip8.controller();
+
+        // add v3, 1; jp 0x200
+        let image = [0x73, 0x01, 0x12, 0x00];
+        chip8.load_image_bytes(&image);
+
+        ctrl.set_register(Register::V(3), 7);
+        chip8.handle_debug_commands();
+        assert_eq!(chip8.cpu.v[VReg::V3], 0);
+
+        chip8.pause();
+        assert_eq!(states.try_recv().unwrap().v[3], 0);
+        // handling the commands would block while paused
+        chip8.set_register(Register::V(3), 7);
+        chip8.set_register(Register::I, 0x345);
+        let state = states.try_recv().unwrap();
+        assert_eq!(state.v[3], 7);
+        assert_eq!(state.i, 0x345);
+
+        chip8.resume();
+        step(&mut chip8, 1);
+        assert_eq!(chip8.cpu.v[VReg::V3], 8);
+    }
+
+    #[test]
+    fn state_diff_reports_the_changes_since_the_mark() {
+        let mut chip8 = System::new();
+        let events: Receiver<DebugEvent> = chip8.output();
+        let ctrl = chip8.controller();
+
+        // ld i, 0x300; ld v0, 5; ld v1, 6; ld [i], v1
+        let image = [0xA3, 0x00, 0x60, 0x05, 0x61, 0x06, 0xF1, 0x55];
+        chip8.load_image_bytes(&image);
+        step(&mut chip8, 1);
+
+        ctrl.diff_state();
+        chip8.handle_debug_commands();
+        assert!(events.try_recv().is_err());
+
+        ctrl.mark_state();
+        chip8.handle_debug_commands();
+        step(&mut chip8, 3);
+        ctrl.diff_state();
+        chip8.handle_debug_commands();
+
+        let Ok(DebugEvent::StateDiff(diff)) = events.try_recv() else {
+            panic!("no state diff emitted");
+        };
+        let changed = |register| diff.registers.iter().find(|c| c.register == register);
+        assert_eq!(changed(Register::V(0)).unwrap().after, 5);
+        assert_eq!(changed(Register::V(1)).unwrap().after, 6);
+        assert_eq!(changed(Register::Pc).unwrap().after, 0x208);
+        assert!(changed(Register::V(2)).is_none());
+        assert_eq!(
+            diff.memory,
+            [MemoryChange {
+                address: 0x300,
+                before: vec![0, 0],
+                after: vec![5, 6],
+            }]
+        );
+    }
+
+    #[test]
+    fn memory_view_is_published_each_frame() {
+        let opts = SystemOptions::builder()
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .memory_view(true)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        let views: Receiver<MemoryView> = chip8.output();
+
+        // ld i, 0x300; ld v0, 7; ld [i], v0; add v1, 1; jp 0x206
+        let image = [0xA3, 0x00, 0x60, 0x07, 0xF0, 0x55, 0x71, 0x01, 0x12, 0x06];
+        chip8.load_image_bytes(&image);
+
+        chip8.run_frame().unwrap();
+        let view = views.try_recv().unwrap();
+        assert_eq!(view.sequence, 1);
+        assert_eq!(view.bytes[0x300], 7);
+        assert!(view.written[0x300] && !view.written[0x301]);
+        assert_eq!(view.i, 0x301);
+        assert_eq!(view.stack_region, None);
+
+        chip8.run_frame().unwrap();
+        let view = views.try_recv().unwrap();
+        assert_eq!(view.sequence, 2);
+        assert!(view.written.not_any());
+    }
+
+    #[test]
+    fn events_are_multiplexed_on_a_single_port() {
+        let opts = SystemOptions::builder()
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        let events: Receiver<SystemEvent> = chip8.output();
+        let ctrl = chip8.controller();
+
+        // ld f, v0; drw v0, v0, 5; jp 0x200
+        let image = [0xF0, 0x29, 0xD0, 0x05, 0x12, 0x00];
+        chip8.load_image_bytes(&image);
+        chip8.run_frame().unwrap();
+        chip8.pause();
+        ctrl.stop();
+        chip8.run().unwrap();
+
+        // the events of each port are forwarded by their own thread, in any order
+        let (mut display, mut paused, mut halted) = (false, false, false);
+        while !(display && paused && halted) {
+            match events.recv_timeout(Duration::from_secs(1)).unwrap() {
+                SystemEvent::Display(_) => display = true,
+                SystemEvent::Debug(DebugEvent::Paused { .. }) => paused = true,
+                SystemEvent::Halted { error: None } => halted = true,
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn spinning_programs_are_halted_without_error() {
+        // jp 0x200
+        let self_jump: &[u8] = &[0x12, 0x00];
+        // ld v0, 1; ld i, 0x300; jp 0x202
+        let short_loop: &[u8] = &[0x60, 0x01, 0xA3, 0x00, 0x12, 0x02];
+
+        for image in [self_jump, short_loop] {
+            let opts = SystemOptions::builder()
+                .execution_mode(ExecutionMode::SingleThreaded)
+                .build()
+                .unwrap();
+            let mut chip8 = System::new_with_options(opts);
+            let events: Receiver<SystemEvent> = chip8.output();
+            chip8.load_image_bytes(image);
+            chip8.run().unwrap();
+            assert!(chip8.is_spinning());
+
+            while !matches!(
+                events.recv_timeout(Duration::from_secs(1)).unwrap(),
+                SystemEvent::Halted { error: None }
+            ) {}
+        }
+
+        // the loop is left alone when disabled
+        let opts = SystemOptions::builder()
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .halt_on_spin(0)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        chip8.load_image_bytes(self_jump);
+        chip8.run_frame().unwrap();
+        assert!(!chip8.is_spinning());
+    }
+
+    #[test]
+    fn instructions_are_profiled_by_kind() {
+        let opts = SystemOptions::builder()
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .instructions_per_frame(10)
+            .profile_instructions(true)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        let ctrl = chip8.controller();
+
+        // add v0, 1; jp 0x200
+        chip8.load_image_bytes(&[0x70, 0x01, 0x12, 0x00]);
+        chip8.run_frame().unwrap();
+        chip8.pause();
+
+        let report = ctrl.profile_report();
+        assert_eq!(report, chip8.profile_report());
+        let mut counts: Vec<_> = report
+            .entries
+            .iter()
+            .map(|e| (e.instruction.as_str(), e.count))
+            .collect();
+        counts.sort();
+        assert_eq!(counts, [("AddImm", 5), ("Jump", 5)]);
+
+        assert!(System::new().profile_report().is_empty());
+    }
+
+    #[test]
+    fn state_is_read_through_the_controller() {
+        let opts = SystemOptions::builder()
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .instructions_per_frame(3)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        let ctrl = chip8.controller();
+
+        // ld v0, 5; ld i, 0x300; ld dt, v0; jp 0x206
+        chip8.load_image_bytes(&[0x60, 0x05, 0xA3, 0x00, 0xF0, 0x15, 0x12, 0x06]);
+        assert_eq!(ctrl.state_snapshot().pc, 0x200);
+
+        chip8.run_frame().unwrap();
+        let state = thread::spawn(move || ctrl.state_snapshot()).join().unwrap();
+        assert_eq!(state, chip8.cpu_state());
+        assert_eq!((state.pc, state.i, state.v[0]), (0x206, 0x300, 5));
+        assert_eq!(state.delay_timer, 4);
+    }
+
+    #[test]
+    fn single_steps_report_the_instruction() {
+        let opts = SystemOptions::builder()
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        let events: Receiver<DebugEvent> = chip8.output();
+
+        // ld v0, 5; add v0, 1; jp 0x202
+        chip8.load_image_bytes(&[0x60, 0x05, 0x70, 0x01, 0x12, 0x02]);
+        chip8.pause();
+        chip8.handle_debug_command(DebugCommand::Step);
+        chip8.run_frame().unwrap();
+        assert!(chip8.debugger.paused);
+        assert_eq!(chip8.cpu.pc, 0x202);
+        assert_eq!(chip8.cpu.v[VReg::V0], 5);
+
+        let stepped = events.try_iter().find_map(|event| match event {
+            DebugEvent::Stepped {
+                address,
+                opcode,
+                instruction,
+            } => Some((address, opcode, instruction)),
+            _ => None,
+        });
+        assert_eq!(stepped, Some((0x200, 0x6005, "ld v0, 0x05".to_string())));
+
+        // ignored while running
+        chip8.resume();
+        chip8.handle_debug_command(DebugCommand::Step);
+        assert!(!chip8.debugger.single_step);
+    }
+
+    #[test]
+    fn instructions_can_be_run_synchronously() {
+        let mut chip8 = System::new();
+        let ctrl = chip8.controller();
+
+        // add v0, 1; add v1, 1; jp 0x200
+        chip8.load_image_bytes(&[0x70, 0x01, 0x71, 0x01, 0x12, 0x00]);
+        assert_eq!(chip8.run_for(4).unwrap(), RunStop::Done);
+        assert_eq!(chip8.cpu.v[VReg::V0], 2);
+        assert_eq!(chip8.cpu.v[VReg::V1], 1);
+
+        let stop = chip8.run_until(|system| system.cpu_state().v[0] == 10);
+        assert_eq!(stop.unwrap(), RunStop::Done);
+        assert_eq!(chip8.cpu.pc, 0x202);
+
+        ctrl.set_breakpoint(0x204, true);
+        assert_eq!(chip8.run_for(100).unwrap(), RunStop::Paused);
+        assert_eq!(chip8.cpu.pc, 0x204);
+        assert_eq!(chip8.cpu.v[VReg::V1], 10);
+
+        // the SUPER-CHIP exit instruction
+        let mut chip8 = System::new_with_options(
+            SystemOptions::builder()
+                .variant(Variant::SChip)
+                .build()
+                .unwrap(),
+        );
+        chip8.load_image_bytes(&[0x00, 0xFD]);
+        assert_eq!(chip8.run_for(100).unwrap(), RunStop::Exited);
+        assert!(chip8.has_exited());
+    }
+
+    #[test]
+    fn timers_are_frozen_while_running_synchronously() {
+        let clock = ManualClock::new();
+        let opts = SystemOptions::builder()
+            .clock(Arc::new(clock.clone()))
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        let sound: Receiver<TimerMessage> = chip8.sound_timer.output();
+
+        // ld v0, 60; ld st, v0; jp 0x204
+        chip8.load_image_bytes(&[0x60, 0x3C, 0xF0, 0x18, 0x12, 0x04]);
+        let mut started = false;
+        let stop = chip8.run_until(|system| {
+            started |= sound.try_recv().is_ok();
+            system.cpu.pc == 0x204
+        });
+        assert_eq!(stop.unwrap(), RunStop::Done);
+        assert!(!started);
+        assert_eq!(chip8.sound_timer.value(), 60);
+
+        // the timer counts down once the run is over
+        assert!(matches!(sound.try_recv(), Ok(TimerMessage::Started)));
+    }
+
+    #[test]
+    fn exit_stops_the_system_without_error() {
+        let opts = SystemOptions::builder()
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .variant(Variant::SChip)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        let events: Receiver<SystemEvent> = chip8.output();
+        chip8.load_image_bytes(&[0x00, 0xFD]);
+        chip8.run().unwrap();
+        assert!(chip8.has_exited());
+
+        let (mut exited, mut halted) = (false, false);
+        while !(exited && halted) {
+            match events.recv_timeout(Duration::from_secs(1)).unwrap() {
+                SystemEvent::Exited => exited = true,
+                SystemEvent::Halted { error: None } => halted = true,
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn step_frame_pauses_after_display_update() {
+        let mut chip8 = System::new();
+        let ctrl = chip8.controller();
+        let events: Receiver<DebugEvent> = chip8.output();
+
+        // ld v0, 0; add v0, 1; se v0, 100; jp 0x202; cls; jp 0x200
+        let image = [
+            0x60, 0x00, 0x70, 0x01, 0x30, 0x64, 0x12, 0x02, 0x00, 0xE0, 0x12, 0x00,
+        ];
+        chip8.load_image_bytes(&image);
+
+        ctrl.pause();
+        let j = chip8.start();
+        assert!(matches!(
+            events.recv_timeout(Duration::from_secs(1)),
+            Ok(DebugEvent::Paused { .. })
+        ));
+
+        ctrl.step_frame();
+        assert_eq!(
+            events.recv_timeout(Duration::from_secs(1)),
+            Ok(DebugEvent::Resumed)
+        );
+        assert_eq!(
+            events.recv_timeout(Duration::from_secs(2)),
+            Ok(DebugEvent::Paused { pc: 0x20A })
+        );
+
+        ctrl.stop();
+        assert!(j.join().is_ok());
+    }
+
+    #[test]
+    fn vip_layout_keeps_stack_and_display_in_memory() {
+        let options = SystemOptions::builder()
+            .memory_layout(MemoryLayout::CosmacVip)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(options);
+        // call 0x206; ld v0, 1; ld v0, 2;
+        // 0x206: ld i, 0xece; ld v0, 0x02; ld v1, 0x04; ld [i], v1; ret
+        let image = [
+            0x22, 0x06, 0x60, 0x01, 0x60, 0x02, 0xAE, 0xCE, 0x60, 0x02, 0x61, 0x04, 0xF1, 0x55,
+            0x00, 0xEE,
+        ];
+        chip8.load_image_bytes(&image);
+
+        // the return address is overwritten before returning
+        step(&mut chip8, 1);
+        assert_eq!(chip8.memory.read_u16(0xECE), Some(0x202));
+        step(&mut chip8, 5);
+        assert_eq!(chip8.cpu.pc, 0x204);
+
+        // ld v0, 0; ld f, v0; drw v0, v0, 5; ld i, 0xf08; ld v0, 0x80; ld [i], v0
+        let image = [
+            0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0xAF, 0x08, 0x60, 0x80, 0xF0, 0x55,
+        ];
+        chip8.load_image_bytes(&image);
+        chip8.cpu.pc = 0x200;
+        step(&mut chip8, 3);
+        assert_eq!(chip8.memory.as_bytes()[0xF00], 0xF0);
+        step(&mut chip8, 3);
+        let pixels = chip8.display.framebuffer().pixels();
+        assert!(pixels[0] && pixels[64]);
+    }
+
+    #[test]
+    fn backtrace_lists_last_instructions() {
+        // ld v0, 5; call 0x206; ret; ret
+        let image = [0x60, 0x05, 0x22, 0x06, 0x00, 0xEE, 0x00, 0xEE];
+
+        let mut chip8 = System::new();
+        chip8.load_image_bytes(&image);
+        step(&mut chip8, 3);
+        assert!(matches!(
+            chip8.execute_next_inst(),
+            Err(SystemError::StackUnderflow)
+        ));
+
+        let bt = chip8.backtrace();
+        let pcs: Vec<u16> = bt.frames.iter().map(|f| f.pc).collect();
+        assert_eq!(pcs, [0x200, 0x202, 0x206, 0x204]);
+        assert_eq!(bt.frames[3].opcode, 0x00EE);
+        assert_eq!(bt.frames[3].disassembly.as_deref(), Some("ret"));
+        assert!(bt.to_string().starts_with("   0: 0x0204  00EE  ret"));
+    }
+
+    #[test]
+    fn call_stack_is_resolved_with_symbols() {
+        // start: call sub; halt: jp halt; sub: call sub2; ret; sub2: ret
+        let image = [0x22, 0x04, 0x12, 0x02, 0x22, 0x08, 0x00, 0xEE, 0x00, 0xEE];
+        let symbols = "0x0200 start\n0x0202 halt\n0x0204 sub\n0x0208 sub2\n";
+
+        let mut chip8 = System::new();
+        chip8.load_image_bytes(&image);
+        chip8.set_symbols(Symbols::from_text(symbols).unwrap());
+        step(&mut chip8, 2);
+
+        let cs = chip8.call_stack();
+        let frames: Vec<(u16, Option<&str>)> = cs
+            .frames
+            .iter()
+            .map(|f| (f.return_address, f.symbol.as_deref()))
+            .collect();
+        assert_eq!(frames, [(0x206, Some("sub+0x2")), (0x202, Some("halt"))]);
+        assert_eq!(
+            cs.to_string(),
+            "   0: 0x0206  sub+0x2\n   1: 0x0202  halt\n"
+        );
+
+        chip8.set_symbols(Symbols::default());
+        assert_eq!(chip8.call_stack().frames[0].symbol, None);
+        assert!(matches!(
+            Symbols::from_text("0x200\n"),
+            Err(SymbolsError::Parse(1))
+        ));
+    }
+
+    #[test]
+    fn step_over_and_out_run_whole_subroutines() {
+        // call 0x206; add v0, 1; jp 0x200; add v1, 1; ret
+        let image = [0x22, 0x06, 0x70, 0x01, 0x12, 0x00, 0x71, 0x01, 0x00, 0xEE];
+        let mut chip8 = System::new();
+        chip8.load_image_bytes(&image);
+        let ctrl = chip8.controller();
+
+        ctrl.step_over();
+        chip8.handle_debug_commands();
+        step(&mut chip8, 2);
+        assert!(!chip8.debugger.paused);
+        step(&mut chip8, 1);
+        assert!(chip8.debugger.paused);
+        assert_eq!(chip8.cpu.pc, 0x202);
+
+        // a step over an instruction which is not a call runs only that instruction
+        ctrl.step_over();
+        chip8.handle_debug_commands();
+        step(&mut chip8, 1);
+        assert!(chip8.debugger.paused);
+        assert_eq!(chip8.cpu.pc, 0x204);
+
+        chip8.cpu.pc = 0x200;
+        step(&mut chip8, 1);
+        ctrl.step_out();
+        chip8.handle_debug_commands();
+        step(&mut chip8, 1);
+        assert!(!chip8.debugger.paused);
+        step(&mut chip8, 1);
+        assert!(chip8.debugger.paused);
+        assert_eq!(chip8.cpu.pc, 0x202);
+    }
+
+    #[test]
+    fn breakpoints_pause_before_the_instruction() {
+        // add v0, 1; add v1, 1; jp 0x200
+        let image = [0x70, 0x01, 0x71, 0x01, 0x12, 0x00];
+        let mut chip8 = System::new();
+        chip8.load_image_bytes(&image);
+        let ctrl = chip8.controller();
+        let events: Receiver<DebugEvent> = chip8.output();
+
+        ctrl.set_breakpoint(0x202, true);
+        chip8.handle_debug_commands();
+        step(&mut chip8, 1);
+        assert!(chip8.debugger.paused);
+        assert_eq!(chip8.cpu.v[VReg::V1], 0);
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            [
+                DebugEvent::Paused { pc: 0x202 },
+                DebugEvent::BreakpointHit { address: 0x202 }
+            ]
+        );
+
+        // resuming runs the instruction, until the breakpoint is reached again
+        chip8.resume();
+        step(&mut chip8, 2);
+        assert!(!chip8.debugger.paused);
+        step(&mut chip8, 1);
+        assert!(chip8.debugger.paused);
+        assert_eq!(chip8.cpu.v[VReg::V0], 2);
+
+        chip8.resume();
+        ctrl.set_breakpoint(0x202, false);
+        chip8.handle_debug_commands();
+        step(&mut chip8, 3);
+        assert!(!chip8.debugger.paused);
+    }
+
+    #[test]
+    fn hires_programs_run_on_a_64x64_screen() {
+        // jp 0x260, then at 0x2c0: ld v0, 0; ld v1, 40; ld i, 0x2d0; drw v0, v1, 1; cls
+        let mut image = vec![0; 0xD1];
+        image[0..2].copy_from_slice(&[0x12, 0x60]);
+        image[0xC0..0xCA]
+            .copy_from_slice(&[0x60, 0x00, 0x61, 0x28, 0xA2, 0xD0, 0xD0, 0x11, 0x02, 0x30]);
+        image[0xD0] = 0x80;
+        let mut chip8 = System::new();
+        chip8.load_image_bytes(&image);
+        assert_eq!(chip8.cpu.pc, 0x2C0);
+
+        step(&mut chip8, 4);
+        let pixels = chip8.display.framebuffer().pixels();
+        assert_eq!(pixels.len(), DISPLAY_WIDTH * HIRES_DISPLAY_HEIGHT);
+        assert!(pixels[40 * DISPLAY_WIDTH]);
+
+        step(&mut chip8, 1);
+        assert!(chip8.display.framebuffer().pixels().not_any());
+    }
+
+    #[test]
+    fn megachip_frames_are_shown_when_the_screen_is_cleared() {
+        let opts = SystemOptions::builder()
+            .variant(Variant::MegaChip)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        let display: Receiver<DisplayMessage> = chip8.display.output();
+        // megaon; ldhi i, 0x000214; ldpal 1; ld i, 0x218; sprw 2; sprh 1; drw v0, v0, 1; cls;
+        // jp 0x212; palette; sprite
+        let image = [
+            0x00, 0x11, 0x01, 0x00, 0x02, 0x14, 0x02, 0x01, 0xA2, 0x18, 0x03, 0x02, 0x04, 0x01,
+            0xD0, 0x01, 0x00, 0xE0, 0x12, 0x12, 0xFF, 0x12, 0x34, 0x56, 0x01, 0x00,
+        ];
+        chip8.load_image_bytes(&image);
+
+        step(&mut chip8, 7);
+        // drawn on the next frame only
+        match display.try_recv().unwrap().content {
+            DisplayContent::Indexed(frame) => assert!(frame.pixels.iter().all(|&p| p == 0)),
+            _ => panic!("expected a colored frame"),
+        }
+        step(&mut chip8, 1);
+        match display.try_recv().unwrap().content {
+            DisplayContent::Indexed(frame) => {
+                assert_eq!((frame.width, frame.height), (256, 192));
+                assert_eq!(frame.color(0), 0xFF12_3456);
+                assert_eq!(frame.pixels[1], 0);
+            }
+            _ => panic!("expected a colored frame"),
+        }
+        assert_eq!(chip8.memory.size(), MAX_MEMORY_SIZE);
+    }
+
+    #[test]
+    fn megachip_addresses_are_limited_to_64_kib() {
+        let opts = SystemOptions::builder()
+            .variant(Variant::MegaChip)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        // ldhi i, 0x010000
+        chip8.load_image_bytes(&[0x01, 0x01, 0x00, 0x00]);
+        assert!(matches!(
+            chip8.execute_next_inst(),
+            Err(SystemError::AddressOutOfRange(0x10000))
+        ));
+    }
+
+    #[test]
+    fn schip_scrolls_by_half_pixels_in_low_resolution() {
+        let opts = SystemOptions::builder()
+            .variant(Variant::SChip)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        // ld i, 0x20c; drw v0, v0, 1; scroll-down 4; scroll-right; hires; scroll-right; sprite
+        let image = [
+            0xA2, 0x0C, 0xD0, 0x01, 0x00, 0xC4, 0x00, 0xFB, 0x00, 0xFF, 0x00, 0xFB, 0x80,
+        ];
+        chip8.load_image_bytes(&image);
+
+        step(&mut chip8, 4);
+        let pixels = chip8.display.framebuffer().pixels();
+        assert_eq!(
+            pixels.iter_ones().collect::<Vec<_>>(),
+            [2 * DISPLAY_WIDTH + 2]
+        );
+
+        step(&mut chip8, 1);
+        chip8.display.draw_sprite_clipped((0, 0), &[0x80]);
+        step(&mut chip8, 1);
+        assert!(chip8.display.framebuffer().pixels()[4]);
+    }
+
+    #[test]
+    fn schip_programs_draw_large_sprites_in_high_resolution() {
+        let opts = SystemOptions::builder()
+            .variant(Variant::SChip)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        // hires; ld v0, 120; ld v1, 0; ld i, 0x214; drw v0, v1, 0;
+        // ld v2, 7; ld r, v2; ld v2, 0; ld v2, r; exit; 16x16 sprite
+        let mut image = vec![
+            0x00, 0xFF, 0x60, 0x78, 0x61, 0x00, 0xA2, 0x14, 0xD0, 0x10, 0x62, 0x07, 0xF2, 0x75,
+            0x62, 0x00, 0xF2, 0x85, 0x00, 0xFD,
+        ];
+        image.extend([0xFF; 32]);
+        chip8.load_image_bytes(&image);
+        let flags: Receiver<RplFlags> = chip8.output();
+
+        step(&mut chip8, 5);
+        let pixels = chip8.display.framebuffer().pixels();
+        assert_eq!(pixels.len(), EXTENDED_DISPLAY_WIDTH * HIRES_DISPLAY_HEIGHT);
+        assert!(pixels[120] && pixels[127]);
+        // clipped at the right edge
+        assert_eq!(pixels.count_ones(), 8 * 16);
+
+        step(&mut chip8, 4);
+        assert_eq!(chip8.cpu.v[VReg::V2], 7);
+        assert_eq!(flags.try_recv().unwrap().0[..3], [120, 0, 7]);
+        assert!(matches!(
+            chip8.execute_next_inst(),
+            Err(SystemError::Exited)
+        ));
+    }
+
+    #[test]
+    fn rewind_restores_snapshot() {
+        let opts = SystemOptions::builder()
+            .cpu_frequency_hz(60.0)
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+
+        // add v0, 1; jp 0x200
+        let image = [0x70, 0x01, 0x12, 0x00];
+        chip8.load_image_bytes(&image);
+
+        // one instruction per frame, a snapshot is taken after 60 frames
+        for _ in 0..90 {
+            chip8.run_frame().unwrap();
+        }
+        assert_eq!(chip8.cpu.v[VReg::V0], 45);
+
+        chip8.rewind();
+        assert_eq!(chip8.cpu.v[VReg::V0], 30);
+        assert_eq!(chip8.cpu.pc, 0x200);
+
+        // nothing left to rewind to
+        chip8.run_frame().unwrap();
+        chip8.rewind();
+        assert_eq!(chip8.cpu.v[VReg::V0], 31);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn frozen_cheats_are_applied_each_frame() {
+        let opts = SystemOptions::builder()
+            .instructions_per_frame(3)
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        let ctrl = chip8.controller();
+
+        // ld i, 0x300; ld v0, [i]; jp 0x200
+        let image = [0xA3, 0x00, 0xF0, 0x65, 0x12, 0x00];
+        chip8.load_image_bytes(&image);
+        chip8.set_cheats(
+            Cheats::from_toml(
+                "[[cheat]]\nname = \"a\"\naddress = 0x300\nvalue = 3\nfreeze = true\n",
+            )
+            .unwrap(),
+        );
+        assert_eq!(chip8.memory.as_bytes()[0x300], 3);
+
+        chip8.memory.as_bytes_mut()[0x300] = 0;
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.cpu.v[VReg::V0], 3);
+
+        ctrl.set_cheat(0, false);
+        chip8.handle_debug_commands();
+        chip8.memory.as_bytes_mut()[0x300] = 0;
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.cpu.v[VReg::V0], 0);
+    }
+
+    #[test]
+    fn movies_replay_input_and_random_numbers() {
+        let opts = SystemOptions::builder()
+            .instructions_per_frame(2)
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+
+        // rnd v2, 0xff; ld v1, k; add v3, 1; jp 0x204
+        let image = [0xC2, 0xFF, 0xF1, 0x0A, 0x73, 0x01, 0x12, 0x04];
+        chip8.load_image_bytes(&image);
+        chip8.record_movie(&image).unwrap();
+        for frame in 0..10 {
+            if frame == 4 {
+                press_key(&chip8, Key::Key5);
+            }
+            chip8.run_frame().unwrap();
+        }
+        let movie = chip8.take_movie().unwrap();
+        assert_eq!(movie.frames, 10);
+
+        let mut replay = System::new_with_options(movie.options().unwrap());
+        replay.load_image_bytes(&image);
+        assert!(matches!(
+            replay.play_movie(movie.clone(), &[0x12, 0x00]),
+            Err(MovieError::ProgramMismatch)
+        ));
+        replay.play_movie(movie, &image).unwrap();
+        for _ in 0..10 {
+            replay.run_frame().unwrap();
+        }
+        assert_eq!(replay.cpu.v[VReg::V1], 5);
+        assert_eq!(replay.cpu.v, chip8.cpu.v);
+
+        // the system is paused at the end of the movie
+        replay.run_frame().unwrap();
+        assert!(replay.debugger.paused);
+        assert_eq!(replay.cpu.v, chip8.cpu.v);
+    }
+
+    #[test]
+    fn turbo_is_ignored_while_recording_a_movie() {
+        let opts = SystemOptions::builder()
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        let image = [0x12, 0x00];
+        chip8.load_image_bytes(&image);
+        chip8.record_movie(&image).unwrap();
+
+        chip8.controller().turbo(true);
+        chip8.handle_debug_commands();
+        assert!(!chip8.turbo);
+    }
+
+    #[test]
+    fn hotkey_commands_are_applied() {
+        let opts = SystemOptions::builder()
+            .cpu_frequency_hz(60.0)
+            .execution_mode(ExecutionMode::SingleThreaded)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        let ctrl = chip8.controller();
+
+        // add v0, 1; jp 0x200
+        let image = [0x70, 0x01, 0x12, 0x00];
+        chip8.load_image_bytes(&image);
+
+        for _ in 0..20 {
+            chip8.run_frame().unwrap();
+        }
+        ctrl.save_state();
+        chip8.handle_debug_commands();
+        for _ in 0..20 {
+            chip8.run_frame().unwrap();
+        }
+        assert_eq!(chip8.cpu.v[VReg::V0], 20);
+
+        // the saved state can be loaded several times
+        for _ in 0..2 {
+            ctrl.load_state();
+            chip8.handle_debug_commands();
+            assert_eq!(chip8.cpu.v[VReg::V0], 10);
+            assert_eq!(chip8.cpu.pc, 0x200);
+        }
+
+        ctrl.turbo(true);
+        chip8.handle_debug_commands();
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.cpu.v[VReg::V0], 12);
+
+        let events: Receiver<DebugEvent> = chip8.output();
+        ctrl.toggle_pause();
+        ctrl.toggle_pause();
+        chip8.handle_debug_commands();
+        assert_eq!(events.try_recv(), Ok(DebugEvent::Paused { pc: 0x200 }));
+        assert_eq!(events.try_recv(), Ok(DebugEvent::Resumed));
+    }
+
+    #[test]
+    fn run_ahead_presents_next_frame() {
+        assert_eq!(
+            SystemOptions::builder().run_ahead(true).build().err(),
+            Some(OptionsError::RunAheadRequiresSingleThreaded)
+        );
+
+        let mut builder = SystemOptions::builder();
+        builder
+            .cpu_frequency_hz(120.0)
+            .execution_mode(ExecutionMode::SingleThreaded);
+        let mut chip8 = System::new_with_options(builder.build().unwrap());
+        let mut ahead = System::new_with_options(builder.run_ahead(true).build().unwrap());
+
+        // ld v1, k; add v2, 1; jp 0x202
+        let image = [0xF1, 0x0A, 0x72, 0x01, 0x12, 0x02];
+        for sys in [&mut chip8, &mut ahead] {
+            sys.load_image_bytes(&image);
+            for _ in 0..3 {
+                sys.run_frame().unwrap();
+            }
+            press_key(sys, Key::Key3);
+        }
+
+        for _ in 0..3 {
+            chip8.run_frame().unwrap();
+            ahead.run_frame().unwrap();
+            assert_eq!(ahead.cpu.v[VReg::V1], 3);
+            assert_eq!(ahead.cpu.v[VReg::V2], chip8.cpu.v[VReg::V2] + 1);
+            // the frames run again after the key press are only counted once
+            let presented = ahead.run_ahead.as_ref().unwrap();
+            assert_eq!(presented.instructions, chip8.instructions);
+        }
+    }
+
+    #[test]
+    fn audio_pattern_is_emitted() {
+        let opts = SystemOptions::builder()
+            .variant(Variant::XoChip)
+            .build()
+            .unwrap();
+        let mut chip8 = System::new_with_options(opts);
+        let audio: Receiver<AudioPattern> = chip8.output();
+
+        // ld i, 0x208; audio; ld v0, 112; pitch v0; pattern
+        let image = [
+            0xA2, 0x08, 0xF0, 0x02, 0x60, 0x70, 0xF0, 0x3A, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00,
+            0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00,
+        ];
+        chip8.load_image_bytes(&image);
+        step(&mut chip8, 4);
+
+        let p = audio.try_recv().unwrap();
+        assert_eq!(p.pattern, [0xFF, 0x00].repeat(8).as_slice());
+        assert_eq!(p.pitch, 112);
+        assert_eq!(p.sample_rate(), 8000.0);
+    }
 }
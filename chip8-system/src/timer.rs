@@ -1,13 +1,15 @@
+use crate::clock::{default_clock, Clock, Pacer};
 use crate::port::{ControlPin, OutputPort};
 use crossbeam_channel::{Receiver, Sender};
-use spin_sleep::LoopHelper;
-use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::Duration;
 
 const TIMER_RESOLUTION: f64 = 60.0;
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TimerMessage {
     Started,
     Stopped,
@@ -15,10 +17,31 @@ pub enum TimerMessage {
 
 pub struct CountDownTimer {
     value: Arc<AtomicU8>,
+    // set while the system is paused, the value is then kept as is
+    frozen: Arc<AtomicBool>,
     stop: ControlPin,
-    ticker: JoinHandle<()>,
+    // no ticker thread when the timer is ticked manually
+    ticker: Option<JoinHandle<()>>,
     sender: Sender<TimerMessage>,
     receiver: Receiver<TimerMessage>,
+    // notified each time the ticker thread decrements the value
+    tick_receiver: Receiver<()>,
+    counters: Arc<TimerCounters>,
+}
+
+/// Activity counters, for metrics.
+#[derive(Default)]
+pub(crate) struct TimerCounters {
+    pub ticks: AtomicU64,
+    pub dropped_messages: AtomicU64,
+}
+
+impl TimerCounters {
+    fn notify(&self, sender: &Sender<TimerMessage>, msg: TimerMessage) {
+        if sender.try_send(msg).is_err() {
+            self.dropped_messages.fetch_add(1, Ordering::Relaxed);
+        }
+    }
 }
 
 impl Default for CountDownTimer {
@@ -34,8 +57,15 @@ impl Default for CountDownTimer {
 /// It is possible to update the value while the timer is running.
 impl CountDownTimer {
     pub fn new() -> Self {
+        Self::with_clock(default_clock())
+    }
+
+    /// Creates a timer counting down at 60 Hz of the time of `clock`.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
         let value = Arc::new(AtomicU8::new(0));
         let value_clone = Arc::clone(&value);
+        let frozen = Arc::new(AtomicBool::new(false));
+        let frozen_clone = Arc::clone(&frozen);
 
         let stop = ControlPin::default();
         let stop_clone = stop.clone();
@@ -43,26 +73,39 @@ impl CountDownTimer {
         let (s, r) = crossbeam_channel::bounded(1);
         let s_clone = s.clone();
 
+        let (tick_s, tick_r) = crossbeam_channel::bounded(1);
+
+        let counters = Arc::new(TimerCounters::default());
+        let counters_clone = Arc::clone(&counters);
+
         let ticker = thread::spawn(move || {
-            let mut loop_helper = LoopHelper::builder().build_with_target_rate(TIMER_RESOLUTION);
+            let mut pacer = Pacer::new(clock, TIMER_RESOLUTION);
 
             loop {
                 thread::park();
+                if stop_clone.is_raised() {
+                    break;
+                }
+                // the first tick is a full period after the update
+                let _ = pacer.loop_start();
                 loop {
-                    let _ = loop_helper.loop_start();
-                    let r = value_clone.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
-                        if v == 0 {
-                            None
-                        } else {
-                            Some(v - 1)
-                        }
-                    });
+                    pacer.loop_sleep();
+                    let _ = pacer.loop_start();
+                    // the value is kept, wait for the timer to be thawed
+                    if frozen_clone.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let r = value_clone
+                        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| v.checked_sub(1));
+                    if r.is_ok() {
+                        counters_clone.ticks.fetch_add(1, Ordering::Relaxed);
+                        let _ = tick_s.try_send(());
+                    }
                     // we reached 0, exit the timer loop, wait for the next wakeup
-                    if r.is_err() {
-                        let _ = s_clone.try_send(TimerMessage::Stopped);
+                    if matches!(r, Ok(1) | Err(_)) {
+                        counters_clone.notify(&s_clone, TimerMessage::Stopped);
                         break;
                     }
-                    loop_helper.loop_sleep();
                 }
                 if stop_clone.is_raised() {
                     break;
@@ -72,20 +115,86 @@ impl CountDownTimer {
 
         Self {
             value,
+            frozen,
             stop,
-            ticker,
+            ticker: Some(ticker),
             sender: s,
             receiver: r,
+            tick_receiver: tick_r,
+            counters,
         }
     }
 
+    /// Creates a timer that doesn't count down on its own,
+    /// the owner is responsible for calling `tick` at 60 Hz.
+    pub fn new_manual() -> Self {
+        let (s, r) = crossbeam_channel::bounded(1);
+
+        Self {
+            value: Default::default(),
+            frozen: Default::default(),
+            stop: Default::default(),
+            ticker: None,
+            sender: s,
+            receiver: r,
+            tick_receiver: crossbeam_channel::never(),
+            counters: Default::default(),
+        }
+    }
+
+    /// Sets the value of the timer, which starts counting down unless it is frozen.
     pub fn update(&self, val: u8) {
         self.value.store(val, Ordering::Relaxed);
-        if val != 0 {
-            self.ticker.thread().unpark();
-            let _ = self.sender.try_send(TimerMessage::Started);
+        if val != 0 && !self.frozen.load(Ordering::Relaxed) {
+            if let Some(ticker) = &self.ticker {
+                ticker.thread().unpark();
+            }
+            self.counters.notify(&self.sender, TimerMessage::Started);
+        }
+    }
+
+    /// Stops counting down until `thaw` is called, keeping the value.
+    /// A running timer is reported as stopped meanwhile.
+    pub fn freeze(&self) {
+        if !self.frozen.swap(true, Ordering::Relaxed) && self.value() != 0 {
+            self.counters.notify(&self.sender, TimerMessage::Stopped);
+        }
+    }
+
+    /// Resumes counting down from the value kept by `freeze`.
+    pub fn thaw(&self) {
+        if self.frozen.swap(false, Ordering::Relaxed) {
+            self.update(self.value());
         }
     }
+
+    /// Blocks until the value of the timer is different from `value`,
+    /// or the timeout expires.
+    pub(crate) fn wait_for_change(&self, value: u8, timeout: Duration) {
+        // forget about older ticks
+        while self.tick_receiver.try_recv().is_ok() {}
+
+        if self.value() == value {
+            let _ = self.tick_receiver.recv_timeout(timeout);
+        }
+    }
+
+    /// Decrements the value of a manual timer.
+    pub fn tick(&self) {
+        let r = self
+            .value
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| v.checked_sub(1));
+        if r.is_ok() {
+            self.counters.ticks.fetch_add(1, Ordering::Relaxed);
+        }
+        if r == Ok(1) {
+            self.counters.notify(&self.sender, TimerMessage::Stopped);
+        }
+    }
+
+    pub(crate) fn counters(&self) -> &TimerCounters {
+        &self.counters
+    }
 }
 
 pub(crate) trait ObservableTimer {
@@ -110,42 +219,100 @@ impl Drop for CountDownTimer {
     fn drop(&mut self) {
         self.stop.raise();
         self.update(0);
-        self.ticker.thread().unpark();
+        if let Some(ticker) = &self.ticker {
+            ticker.thread().unpark();
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use approx::assert_relative_eq;
-    use std::time::{Duration, Instant};
+    use crate::clock::ManualClock;
+
+    /// Advances the clock tick by tick, until the ticker thread is done with each one.
+    /// The timer must still be running after the last one.
+    fn tick_clock(clock: &ManualClock, ticks: usize) {
+        for _ in 0..ticks {
+            clock.wait_for_sleepers(1);
+            clock.advance(Duration::from_secs_f64(1.0 / TIMER_RESOLUTION));
+        }
+        clock.wait_for_sleepers(1);
+    }
 
     #[test]
     fn timer_works() {
-        let t = CountDownTimer::new();
+        let clock = ManualClock::new();
+        let t = CountDownTimer::with_clock(Arc::new(clock.clone()));
+        let r = t.output();
 
         t.update(10);
-        thread::sleep(Duration::from_secs(1));
+        assert!(matches!(r.try_recv(), Ok(TimerMessage::Started)));
+        tick_clock(&clock, 9);
+        assert_eq!(t.value(), 1);
+        clock.advance(Duration::from_secs_f64(1.0 / TIMER_RESOLUTION));
+        assert!(matches!(
+            r.recv_timeout(Duration::from_secs(1)),
+            Ok(TimerMessage::Stopped)
+        ));
         assert_eq!(t.value(), 0);
 
         t.update(10);
-        thread::sleep(Duration::from_secs(1));
-        assert_eq!(t.value(), 0);
+        tick_clock(&clock, 4);
+        assert_eq!(t.value(), 6);
     }
 
     #[test]
-    fn timer_is_accurate() {
-        let t = CountDownTimer::new();
-        let now = Instant::now();
+    fn timer_counts_down_at_60_hz() {
+        let clock = ManualClock::new();
+        let t = CountDownTimer::with_clock(Arc::new(clock.clone()));
+
         t.update(60);
-        loop {
-            let v = t.value();
-            thread::sleep(Duration::from_millis(2));
-            if v == 0 {
-                break;
-            }
-        }
-        let elapsed = now.elapsed().as_secs_f64();
-        assert_relative_eq!(elapsed, 1.0, epsilon = 0.01);
+        tick_clock(&clock, 30);
+        assert_eq!(t.value(), 30);
+    }
+
+    #[test]
+    fn frozen_timer_keeps_its_value() {
+        let clock = ManualClock::new();
+        let t = CountDownTimer::with_clock(Arc::new(clock.clone()));
+        let r = t.output();
+
+        t.freeze();
+        t.update(10);
+        assert!(r.try_recv().is_err());
+        assert_eq!(t.value(), 10);
+
+        t.thaw();
+        assert!(matches!(r.try_recv(), Ok(TimerMessage::Started)));
+        tick_clock(&clock, 4);
+        assert_eq!(t.value(), 6);
+
+        let t = CountDownTimer::new_manual();
+        let r = t.output();
+        t.update(2);
+        assert!(matches!(r.try_recv(), Ok(TimerMessage::Started)));
+        t.freeze();
+        assert!(matches!(r.try_recv(), Ok(TimerMessage::Stopped)));
+        t.thaw();
+        assert!(matches!(r.try_recv(), Ok(TimerMessage::Started)));
+        assert_eq!(t.value(), 2);
+    }
+
+    #[test]
+    fn manual_timer_works() {
+        let t = CountDownTimer::new_manual();
+        let r = t.output();
+
+        t.update(2);
+        assert!(matches!(r.try_recv(), Ok(TimerMessage::Started)));
+        t.tick();
+        assert_eq!(t.value(), 1);
+        t.tick();
+        assert_eq!(t.value(), 0);
+        assert!(matches!(r.try_recv(), Ok(TimerMessage::Stopped)));
+        t.tick();
+        assert_eq!(t.value(), 0);
+        assert!(r.try_recv().is_err());
     }
 }
@@ -0,0 +1,101 @@
+//! Duration of the instructions on the COSMAC VIP.
+//!
+//! The counts are in machine cycles of the VIP's CDP1802, 8 clock cycles each,
+//! as measured on the original interpreter.
+
+use crate::cpu::Cpu;
+use crate::opcode::Instr;
+
+/// Machine cycles per second of the COSMAC VIP, clocked at 1.76 MHz.
+pub const VIP_CYCLES_PER_SECOND: f64 = 1_760_640.0 / 8.0;
+
+/// Machine cycles spent by the interpreter fetching and decoding each instruction.
+const FETCH_CYCLES: u32 = 40;
+
+/// How the time spent by the CPU is counted.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Timing {
+    /// Every instruction takes the same time, set by the speed.
+    #[default]
+    FlatRate,
+    /// Every instruction takes as many machine cycles as on the COSMAC VIP,
+    /// which runs at a fixed rate: the speed is then ignored.
+    VipCycles,
+}
+
+/// Returns the machine cycles taken by `instr` on the COSMAC VIP,
+/// given the state of the CPU before it is executed.
+///
+/// Skips on a key press are counted as not taken, and instructions which
+/// the VIP doesn't have are counted as the cheapest ones.
+pub(crate) fn vip_cycles(instr: &Instr, cpu: &Cpu) -> u32 {
+    let skip = |taken: bool, cycles: u32| if taken { cycles + 4 } else { cycles };
+    let page_crossed = |base: u16, offset: u8| (base & 0xFF) + offset as u16 > 0xFF;
+
+    let cycles = match *instr {
+        Instr::ClearDisplay => 24,
+        Instr::Return => 10,
+        Instr::Jump(_) | Instr::LoadI(_) => 12,
+        Instr::Call(_) => 26,
+        Instr::SkipEqImm(x, n) => skip(cpu.v[x] == n, 10),
+        Instr::SkipNotEqImm(x, n) => skip(cpu.v[x] != n, 10),
+        Instr::SkipEqReg(x, y) => skip(cpu.v[x] == cpu.v[y], 14),
+        Instr::SkipNotEqReg(x, y) => skip(cpu.v[x] != cpu.v[y], 14),
+        Instr::SkipKeyPressed(_) | Instr::SkipKeyNotPressed(_) => 14,
+        Instr::LoadImm(..) => 6,
+        Instr::AddImm(..) => 10,
+        Instr::LoadReg(..) => 12,
+        Instr::OrReg(..)
+        | Instr::AndReg(..)
+        | Instr::XorReg(..)
+        | Instr::AddReg(..)
+        | Instr::SubReg(..)
+        | Instr::ShiftRight(..)
+        | Instr::SubN(..)
+        | Instr::ShiftLeft(..) => 44,
+        Instr::JumpV0(base) => 22 + 2 * page_crossed(base, cpu.v[0]) as u32,
+        Instr::Random(..) => 36,
+        // the interpreter shifts each row into place before drawing it
+        Instr::Draw(_, _, n) => 26 + 68 * n as u32,
+        Instr::LoadDelayTimer(_) | Instr::SetDelayTimer(_) | Instr::SetSoundTimer(_) => 10,
+        Instr::WaitKeyPress(_) => 18,
+        Instr::AddI(x) => 16 + 4 * page_crossed(cpu.i, cpu.v[x]) as u32,
+        Instr::LoadSprite(_) => 16,
+        // each digit is computed by repeated subtractions
+        Instr::LoadBCD(x) => {
+            let v = cpu.v[x] as u32;
+            80 + 16 * (v / 100 + v / 10 % 10 + v % 10)
+        }
+        Instr::SaveRegs(x) | Instr::LoadRegs(x) => 14 + 14 * (x as u32 + 1),
+        _ => 6,
+    };
+    FETCH_CYCLES + cycles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::VReg;
+
+    #[test]
+    fn cycles_depend_on_the_cpu_state() {
+        let mut cpu = Cpu::new(0x200);
+        cpu.v[VReg::V1] = 255;
+
+        assert_eq!(vip_cycles(&Instr::LoadImm(VReg::V0, 1), &cpu), 46);
+        assert_eq!(vip_cycles(&Instr::SkipEqImm(VReg::V1, 0), &cpu), 50);
+        assert_eq!(vip_cycles(&Instr::SkipEqImm(VReg::V1, 255), &cpu), 54);
+        // 2 + 5 + 5
+        assert_eq!(vip_cycles(&Instr::LoadBCD(VReg::V1), &cpu), 312);
+        assert_eq!(vip_cycles(&Instr::SaveRegs(VReg::V1), &cpu), 82);
+
+        cpu.i = 0x2F0;
+        assert_eq!(vip_cycles(&Instr::AddI(VReg::V0), &cpu), 56);
+        assert_eq!(vip_cycles(&Instr::AddI(VReg::V1), &cpu), 60);
+    }
+}
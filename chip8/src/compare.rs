@@ -0,0 +1,129 @@
+use crate::sidecar::{Overrides, Sidecar};
+use crate::watch::load_program;
+use chip8_system::display::{DISPLAY_HEIGHT, DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT};
+use chip8_system::system::{ExecutionMode, Quirks, System, SystemOptions};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// What the first run is compared against.
+pub enum Against {
+    /// A second run, with other quirks.
+    Quirks(Quirks),
+    /// A screen saved by a previous run, eg. with another build.
+    Reference(PathBuf),
+}
+
+/// Runs the program without a terminal for a number of frames, and compares the screen
+/// with the one of another run. Exits with an error status if they differ.
+/// Both runs follow the sidecar of the program, the quirks given replacing its own.
+pub fn run(
+    path: &Path,
+    frames: u32,
+    quirks: Quirks,
+    against: Against,
+    save: Option<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    let sidecar = Sidecar::load(path)?;
+    let a = run_headless(path, &sidecar, frames, quirks)?;
+    if let Some(save) = save {
+        fs::write(save, to_text(&a))?;
+    }
+
+    let b = match against {
+        Against::Quirks(quirks) => run_headless(path, &sidecar, frames, quirks)?,
+        Against::Reference(reference) => from_text(&fs::read_to_string(reference)?)?,
+    };
+
+    if a.len() != b.len() {
+        return Err("the screens have different heights".into());
+    }
+    let differences = a.iter().zip(&b).filter(|(a, b)| a != b).count();
+    if differences == 0 {
+        println!("Screens are identical after {} frames", frames);
+        return Ok(());
+    }
+
+    println!(
+        "{} pixels differ after {} frames (A: first run only, B: second only)",
+        differences, frames
+    );
+    for y in 0..a.len() / DISPLAY_WIDTH {
+        let row: String = (0..DISPLAY_WIDTH)
+            .map(
+                |x| match (a[y * DISPLAY_WIDTH + x], b[y * DISPLAY_WIDTH + x]) {
+                    (true, true) => '#',
+                    (true, false) => 'A',
+                    (false, true) => 'B',
+                    (false, false) => '.',
+                },
+            )
+            .collect();
+        println!("{}", row);
+    }
+    process::exit(1);
+}
+
+/// Returns the pixels of the screen after running the given number of frames.
+/// A program stopping on an error keeps the screen it had at that point.
+fn run_headless(
+    path: &Path,
+    sidecar: &Sidecar,
+    frames: u32,
+    quirks: Quirks,
+) -> Result<Vec<bool>, Box<dyn Error>> {
+    let mut options = SystemOptions::builder();
+    options.execution_mode(ExecutionMode::SingleThreaded);
+    let cli = Overrides {
+        variant: None,
+        quirks,
+        cpu_frequency: None,
+        instructions_per_frame: None,
+    };
+    sidecar.apply(&mut options, &cli)?;
+    let options = options.build()?;
+    let program = load_program(path, &options)?;
+    let mut system = System::new_with_options(options);
+    system.load_image_bytes(&program);
+
+    for frame in 0..frames {
+        if let Err(e) = system.run_frame() {
+            eprintln!("Stopped at frame {}: {}", frame, e);
+            break;
+        }
+    }
+
+    Ok(system
+        .display
+        .framebuffer()
+        .pixels()
+        .iter()
+        .by_vals()
+        .collect())
+}
+
+fn to_text(pixels: &[bool]) -> String {
+    pixels
+        .chunks(DISPLAY_WIDTH)
+        .map(|row| {
+            let mut line: String = row.iter().map(|&p| if p { '#' } else { '.' }).collect();
+            line.push('\n');
+            line
+        })
+        .collect()
+}
+
+fn from_text(s: &str) -> Result<Vec<bool>, Box<dyn Error>> {
+    let pixels: Vec<bool> = s
+        .lines()
+        .flat_map(|line| line.trim_end().chars())
+        .map(|c| c == '#')
+        .collect();
+    if pixels.len() != DISPLAY_WIDTH * DISPLAY_HEIGHT
+        && pixels.len() != DISPLAY_WIDTH * HIRES_DISPLAY_HEIGHT
+    {
+        return Err("the reference is not a saved screen".into());
+    }
+    Ok(pixels)
+}
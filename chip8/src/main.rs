@@ -1,18 +1,51 @@
-use chip8_system::keyboard_map::load_profiles;
-use chip8_system::port::connect;
-use chip8_system::system::{Quirks, System, SystemOptions};
-use clap::Parser;
-use gui_druid::{Color, ColorParseError, Terminal, TerminalOptions};
-use sound_cpal::Beeper;
+use crate::movie::MovieMode;
+use crate::sidecar::{Overrides, Sidecar};
+use crate::watch::Frontend;
+use c8dump::analysis::analyze;
+use chip8_system::database;
+use chip8_system::dialect::Variant;
+use chip8_system::keyboard::{Key, KeyRepeat};
+use chip8_system::keyboard_map::{load_profiles, KeyboardMap};
+use chip8_system::memory::{MemoryLayout, MEMORY_SIZE, RESERVED_SIZE};
+use chip8_system::port::{InputPort, OutputPort};
+use chip8_system::system::{
+    ExecutionMode, Quirks, System, SystemController, SystemOptions, SystemOptionsBuilder,
+};
+use chip8_system::timing::Timing;
+use clap::{Args, Parser, Subcommand};
+use crossbeam_channel::Receiver;
+use gui_druid::{
+    Color, ColorParseError, Hotkey, PixelShape, Settings, Terminal, TerminalOptions, Theme,
+};
+use sound_cpal::{Beeper, BeeperOptions, Message};
+use std::collections::HashMap;
 use std::error::Error;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::thread;
+use std::time::Duration;
+
+mod compare;
+mod movie;
+mod preferences;
+mod remote;
+mod serve;
+mod sidecar;
+mod signal;
+mod soundtest;
+mod stdio;
+mod sweep;
+mod watch;
 
 #[derive(Parser)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Options {
-    /// Set CPU frequency (> 0 and < 5000 Hz)
-    #[clap(long, short)]
-    cpu_frequency: Option<f64>,
+    #[clap(flatten)]
+    system: SystemArgs,
+
+    /// Set the theme of the windows and the matching colors (supported themes: auto, dark, light)
+    #[clap(long, value_parser = parse_theme)]
+    theme: Option<Theme>,
 
     /// Set background color for the gui (hex HTML-like RGB color value)
     #[clap(long, short, value_parser = parse_color)]
@@ -22,10 +55,125 @@ struct Options {
     #[clap(long, short, value_parser = parse_color)]
     fg_color: Option<Color>,
 
+    /// Set the shape of the pixels (supported shapes: square, rounded, dot)
+    #[clap(long, short, value_parser = parse_pixel_shape)]
+    pixel_shape: Option<PixelShape>,
+
+    /// Set the ratio of the width of the pixels to their height, eg. 2:1 for pixels twice as wide as high
+    #[clap(long, value_name = "W:H", value_parser = parse_pixel_aspect)]
+    pixel_aspect: Option<f64>,
+
+    /// Leave a 1 pixel gap between pixels, making each of them visible
+    #[clap(long)]
+    grid: bool,
+
+    /// Draw white pixels on black with a thick border around the screen, overrides the colors
+    #[clap(long)]
+    high_contrast: bool,
+
+    /// Keep pixels lit for this number of frames after they are turned off, smoothing the flicker of sprites
+    #[clap(long, value_name = "FRAMES")]
+    persistence: Option<u8>,
+
+    /// Show the keys of the keypad held down (can also be toggled with F1)
+    #[clap(long)]
+    input_overlay: bool,
+
     /// Set profile mapping physical to virtual keyboard (supported profiles: default, qwerty, azerty)
     #[clap(long, short)]
     kb_profile: Option<String>,
 
+    /// Reload the program each time its file is modified (assembly sources are assembled first)
+    #[clap(long, short)]
+    watch: bool,
+
+    /// Keep the flag registers saved by SUPER-CHIP programs (FX75), eg. high scores, across runs
+    #[clap(long)]
+    persist_flags: bool,
+
+    /// Present frames one frame ahead to reduce input latency (runs the system on a single thread)
+    #[clap(long, short)]
+    run_ahead: bool,
+
+    /// Set the position of the sound, from -1 (left) to 1 (right)
+    #[clap(long, allow_hyphen_values = true)]
+    pan: Option<f32>,
+
+    /// Keep the buzzer playing along with the XO-CHIP pattern voice
+    #[clap(long)]
+    mix_buzzer: bool,
+
+    /// Run without a terminal, serving the display and keypad to a remote one (eg. 0.0.0.0:7000, or ws://0.0.0.0:7000 over WebSocket)
+    #[clap(long, value_name = "ADDRESS")]
+    listen: Option<String>,
+
+    /// Run without a terminal, speaking JSON lines on stdin and stdout with an external frontend
+    #[clap(long, conflicts_with = "listen")]
+    stdio_frontend: bool,
+
+    /// Run without a terminal, driven by an HTTP/JSON control API (eg. 127.0.0.1:8080)
+    #[clap(long, value_name = "ADDRESS", conflicts_with_all = ["listen", "stdio_frontend", "watch"])]
+    serve: Option<String>,
+
+    /// Record the input in a movie file, to replay the run exactly with --play (runs the system on a single thread)
+    #[clap(long, value_name = "FILE", conflicts_with_all = ["watch", "listen", "stdio_frontend", "serve"])]
+    record: Option<PathBuf>,
+
+    /// Replay a movie recorded with --record, with the options it was recorded with
+    #[clap(long, value_name = "FILE", conflicts_with_all = ["record", "watch", "listen", "stdio_frontend", "serve"])]
+    play: Option<PathBuf>,
+
+    /// Run the Nth program opened most recently, as listed by "chip8 recent"
+    #[clap(long, value_name = "N", conflicts_with = "filename")]
+    recent: Option<usize>,
+
+    /// List the keyboard profiles, with the keys bound to each key of the keypad
+    #[clap(long)]
+    list_profiles: bool,
+
+    /// List the quirk presets, which can be used along with quirk names, with the quirks they enable
+    #[clap(long)]
+    list_presets: bool,
+
+    /// Set input filename of the image to run
+    #[clap(required_unless_present_any = ["recent", "list_profiles", "list_presets"])]
+    filename: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+/// Options of the system, applied to each program run over the settings of its sidecar.
+#[derive(Args, Clone)]
+struct SystemArgs {
+    /// Set CPU frequency (> 0 and < 5000 Hz)
+    #[clap(long, short)]
+    cpu_frequency: Option<f64>,
+
+    /// Set CPU speed as a number of instructions per 60 Hz frame (1 to 1000), overrides the frequency
+    #[clap(long, short)]
+    instructions_per_frame: Option<u32>,
+
+    /// Set the instruction set variant, along with its quirks (see --list-presets, eg. schip)
+    #[clap(long)]
+    variant: Option<Variant>,
+
+    /// Take as long for each instruction as the COSMAC VIP did, instead of following a speed
+    #[clap(long, conflicts_with_all = ["cpu_frequency", "instructions_per_frame"])]
+    vip_timing: bool,
+
+    /// Repeat a key held down while the program waits for a key press, after a delay then at an interval (in ms, eg. 400,100)
+    #[clap(long, value_name = "DELAY,INTERVAL", value_parser = parse_key_repeat)]
+    key_repeat: Option<KeyRepeat>,
+
+    /// Keep a key down for at least this number of frames after it is pressed, so that quick taps aren't missed at low CPU frequencies
+    #[clap(long, value_name = "FRAMES")]
+    min_press_frames: Option<u8>,
+
+    /// Seed the random number generator, so that random numbers (CXNN) are the same from one run to the other
+    #[clap(long)]
+    seed: Option<u64>,
+
     /// Load and store instructions do not increment the I register
     #[clap(long, short, help_heading(Some("QUIRKS")))]
     load_store_ignores_i: bool,
@@ -38,71 +186,539 @@ struct Options {
     #[clap(long, short, help_heading(Some("QUIRKS")))]
     draw_wraps_pixels: bool,
 
-    /// Set input filename of the image to run
-    filename: PathBuf,
+    /// Load and store instructions increment the I register by X instead of X + 1
+    #[clap(long, help_heading(Some("QUIRKS")))]
+    load_store_increments_i_by_x: bool,
+
+    /// Jumps with offset (BNNN) add the VX register given by the address instead of V0
+    #[clap(long, help_heading(Some("QUIRKS")))]
+    jump_uses_vx: bool,
+
+    /// Run known programs with the settings given, instead of the ones of the ROM database
+    #[clap(long, help_heading(Some("QUIRKS")))]
+    no_rom_database: bool,
+
+    /// Analyze the program to enable the quirks it seems to expect, along with the ones given
+    #[clap(long, help_heading(Some("QUIRKS")))]
+    detect_quirks: bool,
+
+    /// Stop programs writing below 0x200, where the fonts are, instead of letting them overwrite them
+    #[clap(long)]
+    protect_reserved: bool,
+
+    /// Print the number of instructions run and the time they took, by kind, when the program stops
+    #[clap(long)]
+    profile_instructions: bool,
+
+    /// Halt programs spinning in a loop of at most N instructions which does nothing (default 4), 0 to let them spin
+    #[clap(long, value_name = "N")]
+    halt_on_spin: Option<u32>,
+
+    /// Keep the stack and the display in upper memory as the COSMAC VIP interpreter did, leaving 3232 bytes to programs
+    #[clap(long)]
+    vip_memory_layout: bool,
+}
+
+impl SystemArgs {
+    /// Returns the quirks given one by one.
+    fn quirks(&self) -> Quirks {
+        let mut quirks = Quirks::empty();
+        quirks.set(Quirks::LOAD_STORE_IGNORES_I, self.load_store_ignores_i);
+        quirks.set(Quirks::SHIFT_READS_VX, self.shift_reads_vx);
+        quirks.set(Quirks::DRAW_WRAPS_PIXELS, self.draw_wraps_pixels);
+        quirks.set(
+            Quirks::LOAD_STORE_INCREMENTS_I_BY_X,
+            self.load_store_increments_i_by_x,
+        );
+        quirks.set(Quirks::JUMP_USES_VX, self.jump_uses_vx);
+        quirks
+    }
+
+    /// Returns the options to run the program at `path` with: the ones of the ROM database
+    /// if the program is known, overridden by its sidecar, then by the command line.
+    fn builder(&self, path: &Path) -> Result<SystemOptionsBuilder, Box<dyn Error>> {
+        let program = watch::read_program(path)?;
+        let mut sys_opts = SystemOptions::builder();
+        let profile = match self.no_rom_database {
+            true => None,
+            false => database::lookup(&program),
+        };
+        if let Some(profile) = profile {
+            eprintln!(
+                "Known program: {}, running it with its own settings",
+                profile.name
+            );
+            sys_opts.rom_profile(profile);
+        }
+
+        let cli = Overrides {
+            variant: self.variant,
+            quirks: self.quirks(),
+            cpu_frequency: self.cpu_frequency,
+            instructions_per_frame: self.instructions_per_frame,
+        };
+        let sidecar = Sidecar::load(path)?;
+        sidecar.apply(&mut sys_opts, &cli)?;
+        let variant = cli
+            .variant
+            .or(sidecar.variant()?)
+            .or(profile.map(|profile| profile.variant))
+            .unwrap_or_default();
+
+        if self.protect_reserved {
+            sys_opts.protect_reserved(true);
+        }
+        if self.profile_instructions {
+            sys_opts.profile_instructions(true);
+        }
+        if let Some(n) = self.halt_on_spin {
+            sys_opts.halt_on_spin(n);
+        }
+        if self.vip_timing {
+            sys_opts.timing(Timing::VipCycles);
+        }
+        if let Some(repeat) = self.key_repeat {
+            sys_opts.key_repeat(repeat);
+        }
+        if let Some(frames) = self.min_press_frames {
+            sys_opts.min_press_frames(frames);
+        }
+        if let Some(seed) = self.seed {
+            sys_opts.seed(seed);
+        }
+
+        if self.detect_quirks {
+            let quirks = analyze(&program, variant).suggested_quirks();
+            if !quirks.is_empty() {
+                eprintln!("Detected quirks: {:?}", quirks);
+            }
+            sys_opts.quirk(quirks);
+        }
+
+        if self.vip_memory_layout {
+            let layout = MemoryLayout::CosmacVip;
+            let space = layout.program_end(MEMORY_SIZE) - RESERVED_SIZE;
+            if program.len() > space {
+                eprintln!(
+                    "Warning: the program ({} bytes) overlaps the interpreter memory ({} bytes available)",
+                    program.len(),
+                    space
+                );
+            }
+            sys_opts.memory_layout(layout);
+        }
+        Ok(sys_opts)
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the audio output devices, and play the beep for a second on one of them
+    Soundtest {
+        /// Name of the device to test, instead of the default one
+        #[clap(long, short)]
+        device: Option<String>,
+    },
+    /// List the programs opened recently, the most recent first
+    Recent,
+    /// Show the display of a program run with --listen on another machine, and drive its keypad
+    Remote {
+        /// Address of the machine running the program (eg. 192.168.1.10:7000, or ws://192.168.1.10:7000 over WebSocket)
+        address: String,
+    },
+    /// Run a program without a terminal with two sets of quirks, and compare the screens
+    Compare {
+        /// Number of 60 Hz frames to run
+        #[clap(long, short, default_value_t = 300)]
+        frames: u32,
+
+        /// Quirks of the first run, comma separated (eg. shift-reads-vx,draw-wraps-pixels)
+        #[clap(long, short = 'a', value_parser = parse_quirks, default_value = "")]
+        quirks_a: Quirks,
+
+        /// Quirks of the second run
+        #[clap(long, short = 'b', value_parser = parse_quirks, default_value = "")]
+        quirks_b: Quirks,
+
+        /// Compare the first run with a screen saved by --save instead of a second run
+        #[clap(long, short, conflicts_with = "quirks_b")]
+        reference: Option<PathBuf>,
+
+        /// Save the screen of the first run, eg. to compare it with another build
+        #[clap(long, short)]
+        save: Option<PathBuf>,
+
+        /// Set input filename of the image to run
+        filename: PathBuf,
+    },
+    /// Run every program of a directory without a terminal, and print a compatibility report
+    Sweep {
+        /// Number of 60 Hz frames to run each program
+        #[clap(long, short, default_value_t = 600)]
+        frames: u32,
+
+        /// Preset the programs are run with (see --list-presets), instead of the settings of their sidecar
+        #[clap(long, short)]
+        preset: Option<Variant>,
+
+        /// Print the report as a Markdown table instead of CSV
+        #[clap(long, short)]
+        markdown: bool,
+
+        /// Directory of the programs to run
+        dir: PathBuf,
+    },
+}
+
+/// Parses a comma separated list of quirks, or of presets enabling the quirks of a variant.
+fn parse_quirks(s: &str) -> Result<Quirks, String> {
+    s.split(',')
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            let name = name.trim();
+            match name.parse::<Variant>() {
+                Ok(variant) => Ok(variant.quirks()),
+                Err(_) => Quirks::from_name(&name.replace('-', "_").to_uppercase())
+                    .ok_or_else(|| format!("unknown quirk: {}", name)),
+            }
+        })
+        .collect()
+}
+
+/// Returns the names of quirks, as given on the command line.
+fn quirk_names(quirks: Quirks) -> Vec<String> {
+    quirks
+        .iter_names()
+        .map(|(name, _)| name.to_lowercase().replace('_', "-"))
+        .collect()
+}
+
+fn list_presets() {
+    for variant in Variant::ALL {
+        let names = quirk_names(variant.quirks());
+        let quirks = match names.is_empty() {
+            true => "(no quirks)".to_string(),
+            false => names.join(","),
+        };
+        println!("{:<10} {}", variant.name(), quirks);
+    }
+}
+
+fn list_profiles(profiles: &HashMap<String, KeyboardMap>) {
+    let mut names: Vec<&String> = profiles.keys().collect();
+    names.sort();
+    for name in names {
+        // keypad keys as laid out on the COSMAC VIP
+        let keys: Vec<String> = [1, 2, 3, 0xC, 4, 5, 6, 0xD, 7, 8, 9, 0xE, 0xA, 0, 0xB, 0xF]
+            .into_iter()
+            .filter_map(Key::from)
+            .map(|key| format!("{:X}={}", key as u8, profiles[name].names(key).join("/")))
+            .collect();
+        println!("{:<10} {}", name, keys.join(" "));
+    }
 }
 
 fn parse_color(s: &str) -> Result<Color, ColorParseError> {
     Color::from_hex_str(s)
 }
 
+fn parse_pixel_shape(s: &str) -> Result<PixelShape, String> {
+    match s {
+        "square" => Ok(PixelShape::Square),
+        "rounded" => Ok(PixelShape::Rounded),
+        "dot" => Ok(PixelShape::Dot),
+        _ => Err(format!("unknown pixel shape: {}", s)),
+    }
+}
+
+fn parse_pixel_aspect(s: &str) -> Result<f64, String> {
+    let invalid = || format!("invalid aspect ratio: {} (expected W:H, eg. 2:1)", s);
+    let (width, height) = s.split_once(':').ok_or_else(invalid)?;
+    let width: f64 = width.trim().parse().map_err(|_| invalid())?;
+    let height: f64 = height.trim().parse().map_err(|_| invalid())?;
+    match width / height {
+        ratio if ratio.is_finite() && ratio > 0.0 => Ok(ratio),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_theme(s: &str) -> Result<Theme, String> {
+    match s {
+        "auto" => Ok(Theme::Auto),
+        "dark" => Ok(Theme::Dark),
+        "light" => Ok(Theme::Light),
+        _ => Err(format!("unknown theme: {}", s)),
+    }
+}
+
+fn parse_key_repeat(s: &str) -> Result<KeyRepeat, String> {
+    let parse_ms = |v: &str| {
+        v.trim()
+            .parse()
+            .map(Duration::from_millis)
+            .map_err(|e| format!("invalid duration {:?}: {}", v, e))
+    };
+    match s.split_once(',') {
+        Some((delay, interval)) => Ok(KeyRepeat::After {
+            delay: parse_ms(delay)?,
+            interval: parse_ms(interval)?,
+        }),
+        None => Err("expected a delay and an interval, eg. 400,100".to_string()),
+    }
+}
+
+/// Applies a hotkey of the terminal to the running system.
+fn apply_hotkey(ctrl: &SystemController, hotkey: Hotkey) {
+    match hotkey {
+        Hotkey::Rewind => ctrl.rewind(),
+        Hotkey::Pause => ctrl.toggle_pause(),
+        Hotkey::SaveState => ctrl.save_state(),
+        Hotkey::LoadState => ctrl.load_state(),
+        Hotkey::ToggleCheats => ctrl.toggle_cheats(),
+        Hotkey::Turbo(enabled) => ctrl.turbo(enabled),
+        Hotkey::StepOver => ctrl.step_over(),
+        Hotkey::StepOut => ctrl.step_out(),
+        Hotkey::Breakpoint { address, enabled } => ctrl.set_breakpoint(address, enabled),
+        Hotkey::SetRegister { register, value } => ctrl.set_register(register, value),
+    }
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     let options: Options = Options::parse();
 
-    // system options
-    let mut sys_opts = SystemOptions::new();
-    if let Some(f) = options.cpu_frequency {
-        sys_opts.cpu_frequency_hz(f);
+    let profiles = match load_profiles() {
+        Ok(profiles) => profiles,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if options.list_profiles || options.list_presets {
+        if options.list_profiles {
+            list_profiles(&profiles);
+        }
+        if options.list_presets {
+            list_presets();
+        }
+        return Ok(());
     }
 
-    // Setup quirks
-    if options.load_store_ignores_i {
-        sys_opts.quirk(Quirks::LOAD_STORE_IGNORES_I);
+    match options.command {
+        Some(Command::Soundtest { device }) => return soundtest::run(device),
+        Some(Command::Recent) => {
+            for (i, program) in preferences::recent().iter().enumerate() {
+                println!("{:>2}  {}", i + 1, program.display());
+            }
+            return Ok(());
+        }
+        Some(Command::Remote { address }) => {
+            let mut term_opts = TerminalOptions::new();
+            term_opts.settings(preferences::load().unwrap_or_default());
+            return remote::view(&address, term_opts);
+        }
+        Some(Command::Compare {
+            frames,
+            quirks_a,
+            quirks_b,
+            reference,
+            save,
+            filename,
+        }) => {
+            let against = match reference {
+                Some(path) => compare::Against::Reference(path),
+                None => compare::Against::Quirks(quirks_b),
+            };
+            return compare::run(&filename, frames, quirks_a, against, save);
+        }
+        Some(Command::Sweep {
+            frames,
+            preset,
+            markdown,
+            dir,
+        }) => return sweep::run(&dir, frames, preset, markdown),
+        None => {}
     }
-    if options.shift_reads_vx {
-        sys_opts.quirk(Quirks::SHIFT_READS_VX);
+    let filename = match options.recent {
+        Some(n) => n
+            .checked_sub(1)
+            .and_then(|i| preferences::recent().into_iter().nth(i))
+            .ok_or_else(|| format!("no recent program #{}", n))?,
+        None => options.filename.expect("filename is required"),
+    };
+    // a missing file is reported when loading it
+    let _ = preferences::add_recent(&filename);
+
+    // the options of the system are resolved again for each program run
+    let system = options.system.clone();
+    let record = options.record.is_some();
+    let run_ahead = options.run_ahead;
+    let headless = options.listen.is_some() || options.stdio_frontend || options.serve.is_some();
+    let resolve = move |path: &Path| -> Result<SystemOptions, Box<dyn Error>> {
+        let mut sys_opts = system.builder(path)?;
+        if record {
+            sys_opts.execution_mode(ExecutionMode::SingleThreaded);
+        } else if run_ahead {
+            sys_opts
+                .execution_mode(ExecutionMode::SingleThreaded)
+                .run_ahead(true);
+        }
+
+        // for the memory viewer and the registers panel of the terminal
+        if !headless {
+            sys_opts.memory_view(true);
+            // about 60 times per second at the default speed
+            sys_opts.debug_state_interval(8);
+        }
+        Ok(sys_opts.build()?)
+    };
+    let sys_opts = resolve(&filename)?;
+
+    if let Some(address) = options.listen {
+        let program = watch::load_program(&filename, &sys_opts)?;
+        let mut system = System::new_with_options(sys_opts);
+        system.load_image_bytes(&program);
+        system.set_cheats(watch::load_cheats(&filename));
+        system.set_symbols(watch::load_symbols(&filename));
+        let ctrl = system.controller();
+        signal::on_interrupt(move || ctrl.stop());
+        return remote::serve(&address, system);
+    }
+
+    if let Some(address) = options.serve {
+        return serve::serve(&address, &filename, sys_opts);
+    }
+
+    if options.stdio_frontend {
+        let program = watch::load_program(&filename, &sys_opts)?;
+        let mut system = System::new_with_options(sys_opts);
+        system.load_image_bytes(&program);
+        system.set_cheats(watch::load_cheats(&filename));
+        system.set_symbols(watch::load_symbols(&filename));
+        let ctrl = system.controller();
+        signal::on_interrupt(move || ctrl.stop());
+        return stdio::run(system);
     }
-    if options.draw_wraps_pixels {
-        sys_opts.quirk(Quirks::DRAW_WRAPS_PIXELS);
+
+    let mut beeper_opts = BeeperOptions::new();
+    if let Some(pan) = options.pan {
+        beeper_opts.pan(pan);
     }
+    beeper_opts.mix_buzzer(options.mix_buzzer);
+    let beeper = Beeper::new_with_options(beeper_opts).unwrap_or_else(|e| {
+        eprintln!("{}, the terminal bell is used instead", e);
+        Beeper::terminal_bell()
+    });
 
-    let mut system = System::new_with_options(sys_opts);
-    let beeper = Beeper::new()?;
-    connect(&system.sound_timer, &beeper);
+    // terminal options, the saved settings are overridden by the command line
+    let settings = preferences::load().unwrap_or_default();
+    let _ = beeper
+        .input()
+        .try_send(Message::Volume(settings.volume as f32));
 
-    // terminal options
     let mut term_opts = TerminalOptions::new();
+    term_opts.settings(settings);
+    // the look of the terminal follows the sidecar of the first program
+    let sidecar = Sidecar::load(&filename)?;
+    let (bg_color, fg_color) = sidecar.colors()?;
+    if let Some(c) = bg_color {
+        term_opts.background_color(c);
+    }
+    if let Some(c) = fg_color {
+        term_opts.foreground_color(c);
+    }
+    if let Some(theme) = options.theme {
+        term_opts.theme(theme);
+    }
     if let Some(c) = options.bg_color {
         term_opts.background_color(c);
     }
     if let Some(c) = options.fg_color {
         term_opts.foreground_color(c);
     }
-    if let Some(profile) = options.kb_profile {
-        let mut profiles = load_profiles();
-        if let Some(km) = profiles.remove(&profile) {
-            term_opts.keyboard_map(km);
+    if let Some(shape) = options.pixel_shape {
+        term_opts.pixel_shape(shape);
+    }
+    if let Some(ratio) = options.pixel_aspect {
+        term_opts.pixel_aspect(ratio);
+    }
+    if options.grid {
+        term_opts.grid(true);
+    }
+    if options.high_contrast {
+        term_opts.high_contrast(true);
+    }
+    if let Some(frames) = options.persistence {
+        term_opts.persistence(frames);
+    }
+    term_opts.input_overlay(options.input_overlay);
+    term_opts.recent(preferences::recent());
+    term_opts.variant(sys_opts.variant());
+    if let Some(profile) = options.kb_profile.or(sidecar.keyboard_profile) {
+        if profiles.contains_key(&profile) {
+            term_opts.keyboard_profile(&profile);
         }
     }
 
     let term = Terminal::new_with_options(term_opts);
 
-    // connect term output to system input
-    connect(&term, &system.keyboard);
+    // apply and save the settings changed in the preferences window
+    let changes: Receiver<Settings> = term.output();
+    let sound = beeper.input();
+    // joined once the terminal is closed, to save the geometry of its window
+    let settings_saver = thread::spawn(move || {
+        for settings in changes {
+            let _ = sound.try_send(Message::Volume(settings.volume as f32));
+            if let Err(e) = preferences::save(&settings) {
+                eprintln!("Unable to save settings: {}", e);
+            }
+        }
+    });
+
+    if let Some(file) = options.record {
+        // the movie is written once the terminal is closed
+        let handle = term.handle();
+        signal::on_interrupt(move || handle.close());
+        let r = movie::run(&filename, sys_opts, MovieMode::Record(file), term, &beeper);
+        let _ = settings_saver.join();
+        return r;
+    }
+    if let Some(file) = options.play {
+        let handle = term.handle();
+        signal::on_interrupt(move || handle.close());
+        let r = movie::run(&filename, sys_opts, MovieMode::Play(file), term, &beeper);
+        let _ = settings_saver.join();
+        return r;
+    }
 
-    // connect system output to term input
-    connect(&system.display, &term);
+    // without --watch, the program must be valid before opening the terminal
+    if !options.watch {
+        watch::load_program(&filename, &sys_opts)?;
+    }
 
-    // load program to run
-    let filename = options.filename;
-    system.load_image(&filename)?;
+    // closing the terminal stops the systems and joins their threads
+    let handle = term.handle();
+    signal::on_interrupt(move || handle.close());
 
-    thread::spawn(move || {
-        if let Err(e) = system.run() {
-            println!("System Error: {}", e);
-        }
-    });
+    // systems are connected to the frontend, and replaced when another program is opened
+    let frontend = Frontend {
+        display: term.input(),
+        keyboard: term.output(),
+        keypad: term.input(),
+        memory: term.input(),
+        debug_events: term.input(),
+        registers: term.input(),
+        pacing: term.input(),
+        sound: beeper.input(),
+        hotkeys: term.output(),
+        open: term.output(),
+    };
+    let (reload, persist_flags) = (options.watch, options.persist_flags);
+    let watcher =
+        thread::spawn(move || watch::run(filename, resolve, frontend, reload, persist_flags));
     term.run();
+    let _ = watcher.join();
+    let _ = settings_saver.join();
 
     Ok(())
 }
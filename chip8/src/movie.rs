@@ -0,0 +1,95 @@
+use crate::apply_hotkey;
+use crate::watch::load_program;
+use chip8_system::audio::AudioPattern;
+use chip8_system::cpu::CpuState;
+use chip8_system::debug::{DebugEvent, MemoryView};
+use chip8_system::movie::Movie;
+use chip8_system::port::{connect, connect_latest, OutputPort};
+use chip8_system::system::{System, SystemOptions};
+use crossbeam_channel::{Receiver, Sender};
+use gui_druid::{Hotkey, Terminal};
+use sound_cpal::Beeper;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+pub enum MovieMode {
+    /// Records the input in a movie file, written when the terminal is closed.
+    Record(PathBuf),
+    /// Replays the input of a movie file, ignoring the keyboard.
+    Play(PathBuf),
+}
+
+/// Runs the program in the terminal, recording or replaying a movie.
+/// When replaying, the options are the ones the movie was recorded with.
+pub fn run(
+    path: &Path,
+    options: SystemOptions,
+    mode: MovieMode,
+    term: Terminal,
+    beeper: &Beeper,
+) -> Result<(), Box<dyn Error>> {
+    let mut system = match &mode {
+        MovieMode::Record(_) => {
+            let program = load_program(path, &options)?;
+            let mut system = System::new_with_options(options);
+            system.load_image_bytes(&program);
+            system.record_movie(&program)?;
+            system
+        }
+        MovieMode::Play(file) => {
+            let movie = Movie::load(file)?;
+            let options = movie.options()?;
+            let program = load_program(path, &options)?;
+            let mut system = System::new_with_options(options);
+            system.load_image_bytes(&program);
+            system.play_movie(movie, &program)?;
+            system
+        }
+    };
+
+    connect(&system.sound_timer, beeper);
+    let audio: Receiver<AudioPattern> = system.output();
+    connect(&audio, beeper);
+    connect(&term, &system.keyboard);
+    connect(&system.display, &term);
+    connect_latest(&system.keyboard, &term);
+    let memory: Receiver<MemoryView> = system.output();
+    let viewer: Sender<MemoryView> = term.input();
+    connect_latest(&memory, &viewer);
+    let events: Receiver<DebugEvent> = system.output();
+    let panel: Sender<DebugEvent> = term.input();
+    connect(&events, &panel);
+    let states: Receiver<CpuState> = system.output();
+    let registers: Sender<CpuState> = term.input();
+    connect_latest(&states, &registers);
+
+    let ctrl = system.controller();
+    let hotkeys: Receiver<Hotkey> = term.output();
+    let hotkey_ctrl = system.controller();
+    thread::spawn(move || {
+        for hotkey in hotkeys {
+            apply_hotkey(&hotkey_ctrl, hotkey);
+        }
+    });
+
+    let handle = thread::spawn(move || {
+        if let Err(e) = system.run() {
+            println!("System Error: {}", e);
+            println!("Last instructions:\n{}", system.backtrace());
+        }
+        system
+    });
+    term.run();
+
+    ctrl.stop();
+    let mut system = handle.join().map_err(|_| "the system has panicked")?;
+    if let MovieMode::Record(file) = mode {
+        if let Some(movie) = system.take_movie() {
+            movie.save(&file)?;
+            println!("Recorded {} frames in {}", movie.frames, file.display());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use chip8_system::movie::program_hash;
+use chip8_system::system::RplFlags;
+use gui_druid::Settings;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Number of programs kept in the recent list.
+const RECENT_SIZE: usize = 10;
+
+/// Location of a file in the user's configuration directory.
+fn path(name: &str) -> Option<PathBuf> {
+    let config_dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+    };
+    Some(config_dir?.join("chip8").join(name))
+}
+
+/// Location of a file in the user's data directory.
+fn data_path(name: &str) -> Option<PathBuf> {
+    let data_dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/share")))
+    };
+    Some(data_dir?.join("chip8").join(name))
+}
+
+/// File of the flag registers of a program, named after its hash so that they follow the program
+/// when it is moved or renamed.
+fn flags_path(program: &[u8]) -> Option<PathBuf> {
+    data_path(&format!("flags/{:016x}.bin", program_hash(program)))
+}
+
+/// Loads the flag registers saved by a previous run of the program, if any.
+pub fn load_flags(program: &[u8]) -> Option<RplFlags> {
+    let bytes = fs::read(flags_path(program)?).ok()?;
+    bytes.try_into().ok().map(RplFlags)
+}
+
+pub fn save_flags(program: &[u8], flags: &RplFlags) -> Result<(), Box<dyn Error>> {
+    let path = flags_path(program).ok_or("no data directory")?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, flags.0)?;
+    Ok(())
+}
+
+/// Loads the settings saved by a previous run, if any.
+pub fn load() -> Option<Settings> {
+    let path = path("settings.toml")?;
+    let s = fs::read_to_string(&path).ok()?;
+    match toml::from_str(&s) {
+        Ok(settings) => Some(settings),
+        Err(e) => {
+            eprintln!("Invalid settings file {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+pub fn save(settings: &Settings) -> Result<(), Box<dyn Error>> {
+    write("settings.toml", &toml::to_string(settings)?)
+}
+
+/// Returns the programs opened recently, the most recent first.
+pub fn recent() -> Vec<PathBuf> {
+    path("recent.txt")
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|s| s.lines().map(PathBuf::from).collect())
+        .unwrap_or_default()
+}
+
+/// Moves a program at the top of the recent list, or adds it there.
+pub fn add_recent(program: &Path) -> Result<(), Box<dyn Error>> {
+    let program = program.canonicalize()?;
+    let mut programs = recent();
+    programs.retain(|p| *p != program);
+    programs.insert(0, program);
+    programs.truncate(RECENT_SIZE);
+
+    let lines: Vec<_> = programs.iter().map(|p| p.to_string_lossy()).collect();
+    write("recent.txt", &lines.join("\n"))
+}
+
+fn write(name: &str, contents: &str) -> Result<(), Box<dyn Error>> {
+    let path = path(name).ok_or("no configuration directory")?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, contents)?;
+    Ok(())
+}
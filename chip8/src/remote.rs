@@ -0,0 +1,85 @@
+use crate::signal;
+use chip8_system::display::DisplayMessage;
+use chip8_system::keyboard::KeyboardMessage;
+use chip8_system::net::{tcp_input, tcp_output, websocket_accept, websocket_connect};
+use chip8_system::port::connect;
+use chip8_system::system::System;
+use gui_druid::{Terminal, TerminalOptions};
+use std::error::Error;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+/// Time between two checks for an interrupt while waiting for a remote terminal.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Prefix of the addresses of connections over WebSocket instead of plain TCP.
+const WEBSOCKET_SCHEME: &str = "ws://";
+
+/// Runs the system without a local terminal, once a remote one is connected:
+/// the display is sent to it, and its keypad drives the system.
+/// Addresses starting with `ws://` wait for a connection over WebSocket.
+pub fn serve(address: &str, mut system: System) -> Result<(), Box<dyn Error>> {
+    let websocket = address.starts_with(WEBSOCKET_SCHEME);
+    let listener = TcpListener::bind(address.trim_start_matches(WEBSOCKET_SCHEME))?;
+    println!(
+        "Waiting for a remote terminal on {}",
+        listener.local_addr()?
+    );
+
+    // polled, so that an interrupt stops waiting
+    listener.set_nonblocking(true)?;
+    let (stream, peer) = loop {
+        match listener.accept() {
+            Ok(connection) => break connection,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                if signal::interrupted() {
+                    return Ok(());
+                }
+                thread::sleep(ACCEPT_INTERVAL);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+    stream.set_nonblocking(false)?;
+    println!("Remote terminal connected from {}", peer);
+
+    if websocket {
+        let (display, keyboard) = websocket_accept::<DisplayMessage, KeyboardMessage>(stream)?;
+        connect(&system.display, &display);
+        connect(&keyboard, &system.keyboard);
+    } else {
+        connect(
+            &system.display,
+            &tcp_input::<DisplayMessage>(stream.try_clone()?),
+        );
+        connect(&tcp_output::<KeyboardMessage>(stream), &system.keyboard);
+    }
+
+    system.run()?;
+    Ok(())
+}
+
+/// Shows the display of a system served on another machine, and sends it the keypad.
+/// Addresses starting with `ws://` connect over WebSocket.
+pub fn view(address: &str, options: TerminalOptions) -> Result<(), Box<dyn Error>> {
+    let host = address.trim_start_matches(WEBSOCKET_SCHEME);
+    let stream = TcpStream::connect(host.split('/').next().unwrap_or(host))?;
+    let term = Terminal::new_with_options(options);
+    let handle = term.handle();
+    signal::on_interrupt(move || handle.close());
+
+    if address.starts_with(WEBSOCKET_SCHEME) {
+        let (keyboard, display) =
+            websocket_connect::<KeyboardMessage, DisplayMessage>(stream, address)?;
+        connect(&display, &term);
+        connect(&term, &keyboard);
+    } else {
+        connect(&tcp_output::<DisplayMessage>(stream.try_clone()?), &term);
+        connect(&term, &tcp_input::<KeyboardMessage>(stream));
+    }
+
+    term.run();
+    Ok(())
+}
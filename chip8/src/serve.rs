@@ -0,0 +1,252 @@
+use crate::{signal, watch};
+use chip8_system::debug::DebugEvent;
+use chip8_system::display::{pixel_buffer, screen_size, PixelBuffer};
+use chip8_system::keyboard::{Key, KeyState, KeyboardMessage};
+use chip8_system::port::{InputPort, OutputPort};
+use chip8_system::system::{System, SystemController, SystemOptions};
+use crossbeam_channel::{Receiver, Sender};
+use image::{GrayImage, ImageOutputFormat, Luma};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+type HttpResponse = Response<Cursor<Vec<u8>>>;
+
+#[derive(Serialize)]
+struct Status {
+    paused: bool,
+}
+
+#[derive(Deserialize)]
+struct KeyRequest {
+    key: u8,
+    down: bool,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// The running system, replaced when another program is loaded.
+struct Emulator {
+    ctrl: SystemController,
+    handle: JoinHandle<()>,
+    keyboard: Sender<KeyboardMessage>,
+    screen: Arc<Mutex<PixelBuffer>>,
+    paused: Arc<AtomicBool>,
+}
+
+impl Emulator {
+    fn start(program: &[u8], options: &SystemOptions) -> Self {
+        let mut system = System::new_with_options(options.clone());
+        system.load_image_bytes(program);
+
+        let screen = Arc::new(Mutex::new(pixel_buffer()));
+        let display = system.display.output();
+        let s = Arc::clone(&screen);
+        thread::spawn(move || {
+            for msg in display {
+                *s.lock().unwrap() = msg.into_pixels();
+            }
+        });
+
+        let paused = Arc::new(AtomicBool::new(false));
+        let events: Receiver<DebugEvent> = system.output();
+        let p = Arc::clone(&paused);
+        thread::spawn(move || {
+            for event in events {
+                match event {
+                    DebugEvent::Paused { .. } => p.store(true, Ordering::Relaxed),
+                    DebugEvent::Resumed => p.store(false, Ordering::Relaxed),
+                    _ => {}
+                }
+            }
+        });
+
+        let keyboard = system.keyboard.input();
+        let ctrl = system.controller();
+        let handle = thread::spawn(move || {
+            if let Err(e) = system.run() {
+                eprintln!("System Error: {}", e);
+            }
+        });
+
+        Self {
+            ctrl,
+            handle,
+            keyboard,
+            screen,
+            paused,
+        }
+    }
+
+    fn stop(self) {
+        self.ctrl.stop();
+        let _ = self.handle.join();
+    }
+}
+
+/// Runs the program without a terminal, driven by an HTTP/JSON API until interrupted.
+pub fn serve(address: &str, program: &Path, options: SystemOptions) -> Result<(), Box<dyn Error>> {
+    let server = Arc::new(Server::http(address).map_err(|e| e.to_string())?);
+    println!("Serving the control API on http://{}", address);
+    let s = Arc::clone(&server);
+    signal::on_interrupt(move || s.unblock());
+
+    let mut emulator = Emulator::start(&watch::load_program(program, &options)?, &options);
+    for mut request in server.incoming_requests() {
+        let response = handle(&mut request, &mut emulator, &options).unwrap_or_else(|e| {
+            json(&ErrorResponse {
+                error: e.to_string(),
+            })
+            .with_status_code(400)
+        });
+        let _ = request.respond(response);
+    }
+
+    emulator.stop();
+    Ok(())
+}
+
+fn handle(
+    request: &mut Request,
+    emulator: &mut Emulator,
+    options: &SystemOptions,
+) -> Result<HttpResponse, Box<dyn Error>> {
+    // requests carry at most a program, larger bodies are refused without being read
+    let body = read_body(request.as_reader(), options.max_program_size())?;
+    let path = request.url().split('?').next().unwrap_or_default();
+
+    let response = match (request.method(), path) {
+        (Method::Get, "/status") => json(&Status {
+            paused: emulator.paused.load(Ordering::Relaxed),
+        }),
+        (Method::Get, "/screenshot") => {
+            let png = screenshot(&emulator.screen.lock().unwrap())?;
+            let header = Header::from_bytes(&b"Content-Type"[..], &b"image/png"[..]).unwrap();
+            Response::from_data(png).with_header(header)
+        }
+        (Method::Post, "/load") => {
+            let previous = std::mem::replace(emulator, Emulator::start(&body, options));
+            previous.stop();
+            no_content()
+        }
+        (Method::Post, "/pause") => {
+            emulator.ctrl.pause();
+            no_content()
+        }
+        (Method::Post, "/resume") => {
+            emulator.ctrl.resume();
+            no_content()
+        }
+        (Method::Post, "/state/save") => {
+            emulator.ctrl.save_state();
+            no_content()
+        }
+        (Method::Post, "/state/load") => {
+            emulator.ctrl.load_state();
+            no_content()
+        }
+        (Method::Post, "/key") => {
+            let r: KeyRequest = serde_json::from_slice(&body)?;
+            let key = Key::from(r.key).ok_or_else(|| format!("invalid key: {}", r.key))?;
+            let state = if r.down { KeyState::Down } else { KeyState::Up };
+            let _ = emulator.keyboard.send(KeyboardMessage::new(state, key));
+            no_content()
+        }
+        _ => json(&ErrorResponse {
+            error: format!("no such endpoint: {} {}", request.method(), path),
+        })
+        .with_status_code(404),
+    };
+    Ok(response)
+}
+
+/// Reads the body of a request, up to `limit` bytes.
+fn read_body(reader: impl Read, limit: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut body = vec![];
+    reader.take(limit as u64 + 1).read_to_end(&mut body)?;
+    if body.len() > limit {
+        return Err(format!("request body is too large (more than {} bytes)", limit).into());
+    }
+    Ok(body)
+}
+
+fn json<T: Serialize>(value: &T) -> HttpResponse {
+    let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+    Response::from_data(serde_json::to_vec(value).unwrap_or_default()).with_header(header)
+}
+
+fn no_content() -> HttpResponse {
+    Response::from_data(vec![]).with_status_code(204)
+}
+
+/// Encodes the screen as a PNG image, one pixel per pixel.
+fn screenshot(pixels: &PixelBuffer) -> Result<Vec<u8>, image::ImageError> {
+    let (width, height) = screen_size(pixels.len());
+    let image = GrayImage::from_fn(width as u32, height as u32, |x, y| {
+        let on = pixels
+            .get(y as usize * width + x as usize)
+            .map_or(false, |p| *p);
+        Luma([if on { 255 } else { 0 }])
+    });
+    let mut png = Cursor::new(vec![]);
+    image.write_to(&mut png, ImageOutputFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiny_http::TestRequest;
+
+    fn request(method: Method, path: &str, body: &'static str) -> Request {
+        TestRequest::new()
+            .with_method(method)
+            .with_path(path)
+            .with_body(body)
+            .into()
+    }
+
+    #[test]
+    fn bodies_are_read_up_to_the_limit() {
+        assert_eq!(read_body(Cursor::new([1, 2, 3]), 3).unwrap(), [1, 2, 3]);
+        assert!(read_body(Cursor::new([1, 2, 3, 4]), 3).is_err());
+        assert!(read_body(std::io::repeat(0), 4096).is_err());
+    }
+
+    #[test]
+    fn endpoints_are_handled() {
+        // the self jump keeps running, for the requests to find the system running
+        let options = SystemOptions::builder().halt_on_spin(0).build().unwrap();
+        let mut emulator = Emulator::start(&[0x12, 0x00], &options);
+        let mut handle_request = |method: Method, path: &str, body: &'static str| {
+            handle(&mut request(method, path, body), &mut emulator, &options)
+        };
+
+        let response = handle_request(Method::Get, "/status", "").unwrap();
+        assert_eq!(response.status_code().0, 200);
+
+        let response = handle_request(Method::Post, "/key", r#"{"key": 5, "down": true}"#).unwrap();
+        assert_eq!(response.status_code().0, 204);
+        assert!(handle_request(Method::Post, "/key", r#"{"key": 16, "down": true}"#).is_err());
+
+        let size = options.max_program_size() + 1;
+        let program: &'static str = Box::leak("\u{12}".repeat(size).into_boxed_str());
+        assert!(handle_request(Method::Post, "/load", program).is_err());
+        let response = handle_request(Method::Post, "/load", "\u{12}\u{00}").unwrap();
+        assert_eq!(response.status_code().0, 204);
+
+        let response = handle_request(Method::Get, "/nothing", "").unwrap();
+        assert_eq!(response.status_code().0, 404);
+
+        emulator.stop();
+    }
+}
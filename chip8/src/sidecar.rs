@@ -0,0 +1,121 @@
+use crate::{parse_color, parse_quirks};
+use chip8_system::dialect::Variant;
+use chip8_system::system::{Quirks, SystemOptionsBuilder};
+use gui_druid::Color;
+use serde::Deserialize;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Settings of a program, read from a TOML file next to it: `game.toml` for `game.ch8`.
+/// They are read again for each program run, and options given on the command line
+/// take precedence over them.
+///
+/// ```toml
+/// variant = "schip"
+/// quirks = ["shift-reads-vx", "load-store-ignores-i"]
+/// instructions_per_frame = 15
+/// background_color = "#1d2021"
+/// foreground_color = "#fabd2f"
+/// keyboard_profile = "qwerty"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sidecar {
+    variant: Option<String>,
+    #[serde(default)]
+    quirks: Vec<String>,
+    cpu_frequency: Option<f64>,
+    instructions_per_frame: Option<u32>,
+    background_color: Option<String>,
+    foreground_color: Option<String>,
+    pub keyboard_profile: Option<String>,
+}
+
+impl Sidecar {
+    pub fn path_for(program: &Path) -> PathBuf {
+        program.with_extension("toml")
+    }
+
+    /// Loads the settings of a program, which are all unset if it has no sidecar file.
+    pub fn load(program: &Path) -> Result<Self, Box<dyn Error>> {
+        let path = Self::path_for(program);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let sidecar: Self = toml::from_str(&fs::read_to_string(&path)?)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        // report invalid values once, when loading the file
+        let check = || -> Result<(), String> {
+            sidecar.variant()?;
+            sidecar.quirks()?;
+            sidecar.colors()?;
+            Ok(())
+        };
+        check().map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        Ok(sidecar)
+    }
+
+    /// Returns the instruction set variant, if set.
+    pub fn variant(&self) -> Result<Option<Variant>, String> {
+        self.variant
+            .as_deref()
+            .map(|v| v.parse::<Variant>().map_err(|e| e.to_string()))
+            .transpose()
+    }
+
+    pub fn quirks(&self) -> Result<Quirks, String> {
+        parse_quirks(&self.quirks.join(","))
+    }
+
+    /// Applies the variant, quirks and speed of the program to `options`, then the ones given
+    /// on the command line. Quirks or a variant given on the command line replace the quirks
+    /// of the sidecar instead of adding to them, as these go with its variant.
+    pub fn apply(&self, options: &mut SystemOptionsBuilder, cli: &Overrides) -> Result<(), String> {
+        if let Some(variant) = cli.variant.or(self.variant()?) {
+            options.variant(variant);
+        }
+        if cli.variant.is_none() && cli.quirks.is_empty() {
+            options.quirk(self.quirks()?);
+        }
+        options.quirk(cli.quirks);
+
+        // a speed given on the command line replaces the one of the program
+        let speed = match (cli.cpu_frequency, cli.instructions_per_frame) {
+            (None, None) => (self.cpu_frequency, self.instructions_per_frame),
+            speed => speed,
+        };
+        if let Some(f) = speed.0 {
+            options.cpu_frequency_hz(f);
+        }
+        if let Some(n) = speed.1 {
+            options.instructions_per_frame(n);
+        }
+        Ok(())
+    }
+
+    /// Returns the background and foreground colors, if set.
+    pub fn colors(&self) -> Result<(Option<Color>, Option<Color>), String> {
+        let parse = |c: &Option<String>| {
+            c.as_deref()
+                .map(parse_color)
+                .transpose()
+                .map_err(|e| format!("invalid color: {}", e))
+        };
+        Ok((
+            parse(&self.background_color)?,
+            parse(&self.foreground_color)?,
+        ))
+    }
+}
+
+/// Settings of the system given on the command line, which take precedence over a sidecar.
+#[derive(Clone, Debug)]
+pub struct Overrides {
+    pub variant: Option<Variant>,
+    pub quirks: Quirks,
+    pub cpu_frequency: Option<f64>,
+    pub instructions_per_frame: Option<u32>,
+}
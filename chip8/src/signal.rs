@@ -0,0 +1,24 @@
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Runs `shutdown` when the process is interrupted by Ctrl+C or terminated,
+/// instead of exiting immediately, so that files being written are complete
+/// and threads are joined. A second interrupt exits without waiting.
+pub fn on_interrupt(shutdown: impl Fn() + Send + 'static) {
+    let r = ctrlc::set_handler(move || {
+        if INTERRUPTED.swap(true, Ordering::Relaxed) {
+            process::exit(130);
+        }
+        shutdown();
+    });
+    if let Err(e) = r {
+        eprintln!("Unable to handle interrupts: {}", e);
+    }
+}
+
+/// Returns true once the process has been interrupted.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
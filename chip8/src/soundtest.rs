@@ -0,0 +1,41 @@
+use crate::preferences;
+use chip8_system::port::InputPort;
+use sound_cpal::{output_devices, Beeper, BeeperOptions, Message};
+use std::error::Error;
+use std::thread;
+use std::time::Duration;
+
+const BEEP_DURATION: Duration = Duration::from_secs(1);
+
+/// Lists the audio output devices, then plays the beep on the selected one
+/// and reports the stream configuration negotiated with it.
+pub fn run(device: Option<String>) -> Result<(), Box<dyn Error>> {
+    println!("Audio output devices:");
+    for name in output_devices()? {
+        println!("  {}", name);
+    }
+
+    let mut options = BeeperOptions::new();
+    if let Some(name) = &device {
+        options.device(name);
+    }
+    let beeper = Beeper::new_with_options(options)?;
+
+    let info = beeper.output_info();
+    println!(
+        "Playing on {}: {} Hz, {} channels, {} samples",
+        info.device, info.sample_rate, info.channels, info.sample_format
+    );
+
+    if let Some(settings) = preferences::load() {
+        let _ = beeper
+            .input()
+            .try_send(Message::Volume(settings.volume as f32));
+    }
+
+    beeper.play();
+    thread::sleep(BEEP_DURATION);
+    beeper.pause();
+
+    Ok(())
+}
@@ -0,0 +1,301 @@
+use chip8_system::audio::{AudioPattern, PATTERN_SIZE};
+use chip8_system::debug::{DebugCommand, DebugEvent, MemoryChange, RegisterChange, StackFrame};
+use chip8_system::display::{pixel_buffer, screen_size, DisplayMessage};
+use chip8_system::keyboard::{Key, KeyState, KeyboardMessage};
+use chip8_system::port::{InputPort, OutputPort};
+use chip8_system::system::System;
+use chip8_system::timer::TimerMessage;
+use crossbeam_channel::{select, Receiver, Sender};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::thread;
+
+/// Messages written on stdout, one JSON object per line.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Event {
+    /// Sent once, before any other message.
+    Ready {
+        width: usize,
+        height: usize,
+    },
+    /// The screen changed size and is blank, eg. a SUPER-CHIP program switching resolution.
+    Resize {
+        width: usize,
+        height: usize,
+    },
+    /// Pixels switched on and off since the previous display message, as [x, y],
+    /// along with the sequence number and emulation time in milliseconds of the update.
+    Display {
+        sequence: u64,
+        time_ms: u64,
+        on: Vec<(usize, usize)>,
+        off: Vec<(usize, usize)>,
+    },
+    Sound {
+        playing: bool,
+    },
+    /// The XO-CHIP pattern played instead of the buzzer, 128 1-bit samples,
+    /// along with the number of samples played per second.
+    AudioPattern {
+        pattern: [u8; PATTERN_SIZE],
+        sample_rate: f32,
+    },
+    /// The program has reached a breakpoint set with `breakpoint`, and is paused before it.
+    Breakpoint {
+        address: u16,
+    },
+    /// The instruction run by `step`, with its address and code.
+    Step {
+        address: u16,
+        opcode: u16,
+        instruction: String,
+    },
+    /// The subroutine calls in progress, innermost first, sent in response to `call_stack`.
+    CallStack {
+        frames: Vec<StackFrame>,
+    },
+    /// The registers and memory changed since `mark`, sent in response to `diff`.
+    StateDiff {
+        registers: Vec<RegisterChange>,
+        memory: Vec<MemoryChange>,
+    },
+    /// The program has exited on its own, the system has stopped.
+    Exited,
+    /// The system has stopped on an error.
+    Error {
+        message: String,
+    },
+}
+
+/// Messages read from stdin, one JSON object per line.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    Key {
+        key: u8,
+        down: bool,
+    },
+    Pause,
+    Resume,
+    StepFrame,
+    Step,
+    StepOver,
+    StepOut,
+    Rewind,
+    Cheat {
+        index: usize,
+        enabled: bool,
+    },
+    CallStack,
+    /// Keeps the registers and memory, to compare them with a later `diff`.
+    Mark,
+    Diff,
+    Breakpoint {
+        address: u16,
+        enabled: bool,
+    },
+    Quit,
+}
+
+/// Runs the system with a frontend speaking JSON lines on stdin and stdout.
+/// Returns when `quit` is received, stdin is closed, or the system stops.
+pub fn run(mut system: System) -> Result<(), Box<dyn Error>> {
+    let (events, out) = crossbeam_channel::unbounded();
+    let writer = thread::spawn(move || write_events(out));
+    let _ = events.send(Event::Ready {
+        width: system.display.framebuffer().width(),
+        height: system.display.framebuffer().height(),
+    });
+
+    let display: Receiver<DisplayMessage> = system.display.output();
+    let display_events = events.clone();
+    thread::spawn(move || send_display_deltas(display, display_events));
+
+    let sound: Receiver<TimerMessage> = system.sound_timer.output();
+    let sound_events = events.clone();
+    thread::spawn(move || {
+        for msg in sound {
+            let playing = matches!(msg, TimerMessage::Started);
+            if sound_events.send(Event::Sound { playing }).is_err() {
+                break;
+            }
+        }
+    });
+
+    let audio: Receiver<AudioPattern> = system.output();
+    let audio_events = events.clone();
+    thread::spawn(move || {
+        for p in audio {
+            let event = Event::AudioPattern {
+                pattern: p.pattern,
+                sample_rate: p.sample_rate(),
+            };
+            if audio_events.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    let debug: Receiver<DebugEvent> = system.output();
+    let debug_events = events.clone();
+    thread::spawn(move || {
+        for event in debug {
+            let event = match event {
+                DebugEvent::BreakpointHit { address } => Event::Breakpoint { address },
+                DebugEvent::Stepped {
+                    address,
+                    opcode,
+                    instruction,
+                } => Event::Step {
+                    address,
+                    opcode,
+                    instruction,
+                },
+                DebugEvent::CallStack(cs) => Event::CallStack { frames: cs.frames },
+                DebugEvent::StateDiff(diff) => Event::StateDiff {
+                    registers: diff.registers,
+                    memory: diff.memory,
+                },
+                _ => continue,
+            };
+            if debug_events.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    let keyboard: Sender<KeyboardMessage> = system.keyboard.input();
+    let ctrl = system.controller();
+    let system_events = events.clone();
+    // disconnected when the system stops, eg. on an error or an interrupt
+    let (stopped_sender, stopped) = crossbeam_channel::bounded::<()>(0);
+    let handle = thread::spawn(move || {
+        match system.run() {
+            Ok(()) if system.has_exited() => {
+                let _ = system_events.send(Event::Exited);
+            }
+            Ok(()) => {}
+            Err(e) => {
+                let message = e.to_string();
+                let _ = system_events.send(Event::Error { message });
+            }
+        }
+        drop(stopped_sender);
+    });
+    drop(events);
+
+    // stdin is read on its own thread, which can stay blocked once the system has stopped
+    let (line_sender, lines) = crossbeam_channel::unbounded();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            if line_sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    loop {
+        let line = select! {
+            recv(lines) -> line => match line {
+                Ok(line) => line?,
+                Err(_) => break,
+            },
+            recv(stopped) -> _ => break,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = match serde_json::from_str(&line) {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("Invalid request {:?}: {}", line, e);
+                continue;
+            }
+        };
+        match request {
+            Request::Key { key, down } => {
+                let Some(key) = Key::from(key) else {
+                    eprintln!("Invalid key: {}", key);
+                    continue;
+                };
+                let state = if down { KeyState::Down } else { KeyState::Up };
+                let _ = keyboard.send(KeyboardMessage::new(state, key));
+            }
+            Request::Pause => ctrl.send(DebugCommand::Pause),
+            Request::Resume => ctrl.send(DebugCommand::Resume),
+            Request::StepFrame => ctrl.send(DebugCommand::StepFrame),
+            Request::Step => ctrl.step(),
+            Request::StepOver => ctrl.step_over(),
+            Request::StepOut => ctrl.step_out(),
+            Request::Rewind => ctrl.send(DebugCommand::Rewind),
+            Request::Cheat { index, enabled } => ctrl.set_cheat(index, enabled),
+            Request::CallStack => ctrl.call_stack(),
+            Request::Mark => ctrl.mark_state(),
+            Request::Diff => ctrl.diff_state(),
+            Request::Breakpoint { address, enabled } => ctrl.set_breakpoint(address, enabled),
+            Request::Quit => break,
+        }
+    }
+
+    ctrl.stop();
+    let _ = handle.join();
+    let _ = writer.join();
+    Ok(())
+}
+
+fn send_display_deltas(display: Receiver<DisplayMessage>, events: Sender<Event>) {
+    let mut previous = pixel_buffer();
+    for msg in display {
+        let (sequence, time_ms) = (msg.sequence, msg.time.as_millis() as u64);
+        let pixels = msg.into_pixels();
+        let (width, height) = screen_size(pixels.len());
+        if pixels.len() != previous.len() {
+            if events.send(Event::Resize { width, height }).is_err() {
+                break;
+            }
+            previous.clear();
+            previous.resize(pixels.len(), false);
+        }
+
+        let (mut on, mut off) = (vec![], vec![]);
+        for (i, (old, new)) in previous.iter().zip(pixels.iter()).enumerate() {
+            let pos = (i % width, i / width);
+            match (*old, *new) {
+                (false, true) => on.push(pos),
+                (true, false) => off.push(pos),
+                _ => {}
+            }
+        }
+        previous = pixels;
+
+        if on.is_empty() && off.is_empty() {
+            continue;
+        }
+        if events
+            .send(Event::Display {
+                sequence,
+                time_ms,
+                on,
+                off,
+            })
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+fn write_events(events: Receiver<Event>) {
+    let mut stdout = io::stdout().lock();
+    for event in events {
+        let written = serde_json::to_writer(&mut stdout, &event)
+            .map_err(io::Error::from)
+            .and_then(|_| stdout.write_all(b"\n"))
+            .and_then(|_| stdout.flush());
+        if written.is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use crate::sidecar::{Overrides, Sidecar};
+use crate::watch::load_program;
+use chip8_system::dialect::Variant;
+use chip8_system::system::{ExecutionMode, Quirks, System, SystemError, SystemOptions};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Extensions of the files run by a sweep.
+const EXTENSIONS: [&str; 5] = ["ch8", "c8", "sc8", "xo8", "c8asm"];
+
+/// Number of frames without any change of the CPU or screen after which a program is hung.
+const HUNG_FRAMES: u32 = 60;
+
+/// How a program behaved during its run.
+enum Outcome {
+    /// Still running at the end, or waiting for a key.
+    Ok,
+    /// Stopped on a jump to itself, the usual way to end a program, or exited on SUPER-CHIP.
+    Halted {
+        frame: u32,
+    },
+    Crashed {
+        frame: u32,
+        error: SystemError,
+    },
+    /// Stuck in a loop which changes nothing, not even the timers.
+    Hung {
+        frame: u32,
+    },
+    /// The file could not be loaded or assembled.
+    Invalid(String),
+}
+
+impl Outcome {
+    fn name(&self) -> &'static str {
+        match self {
+            Outcome::Ok => "ok",
+            Outcome::Halted { .. } => "halted",
+            Outcome::Crashed { .. } => "crashed",
+            Outcome::Hung { .. } => "hung",
+            Outcome::Invalid(_) => "invalid",
+        }
+    }
+
+    fn frame(&self) -> Option<u32> {
+        match self {
+            Outcome::Halted { frame }
+            | Outcome::Crashed { frame, .. }
+            | Outcome::Hung { frame } => Some(*frame),
+            Outcome::Ok | Outcome::Invalid(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Crashed { error, .. } => write!(f, "{}", error),
+            Outcome::Invalid(error) => write!(f, "{}", error),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Runs every program of a directory without a terminal for a number of frames,
+/// and prints a compatibility report, as CSV or as a Markdown table.
+/// Programs run with the settings of their sidecar, unless a `preset` is given.
+pub fn run(
+    dir: &Path,
+    frames: u32,
+    preset: Option<Variant>,
+    markdown: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut programs: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|e| e.to_str())
+                .map_or(false, |e| EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        })
+        .collect();
+    programs.sort();
+
+    if markdown {
+        println!("| Program | Outcome | Frame | Error |");
+        println!("|---------|---------|-------|-------|");
+    } else {
+        println!("program,outcome,frame,error");
+    }
+    for path in programs {
+        let loaded = program_options(&path, preset).and_then(|options| {
+            let program = load_program(&path, &options)?;
+            Ok((program, options))
+        });
+        let outcome = match loaded {
+            Ok((program, options)) => run_program(&program, frames, options),
+            Err(e) => Outcome::Invalid(e.to_string()),
+        };
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let frame = outcome.frame().map(|f| f.to_string()).unwrap_or_default();
+        let error = outcome.to_string();
+        if markdown {
+            println!(
+                "| {} | {} | {} | {} |",
+                name.replace('|', "\\|"),
+                outcome.name(),
+                frame,
+                error.replace('|', "\\|")
+            );
+        } else {
+            println!(
+                "{},{},{},{}",
+                csv_field(&name),
+                outcome.name(),
+                frame,
+                csv_field(&error)
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Returns the options to run a program with, the ones of its sidecar unless `preset` is given.
+fn program_options(path: &Path, preset: Option<Variant>) -> Result<SystemOptions, Box<dyn Error>> {
+    let mut options = SystemOptions::builder();
+    options.execution_mode(ExecutionMode::SingleThreaded);
+    let cli = Overrides {
+        variant: preset,
+        quirks: Quirks::empty(),
+        cpu_frequency: None,
+        instructions_per_frame: None,
+    };
+    Sidecar::load(path)?.apply(&mut options, &cli)?;
+    Ok(options.build()?)
+}
+
+fn run_program(program: &[u8], frames: u32, options: SystemOptions) -> Outcome {
+    let mut system = System::new_with_options(options);
+    system.load_image_bytes(program);
+
+    let mut last = None;
+    let mut unchanged = 0;
+    for frame in 0..frames {
+        match system.run_frame() {
+            Err(SystemError::Spinning | SystemError::Exited) => return Outcome::Halted { frame },
+            Err(error) => return Outcome::Crashed { frame, error },
+            Ok(()) => {}
+        }
+
+        let state = (
+            system.cpu_state(),
+            system.display.framebuffer().pixels().clone(),
+        );
+        if last.as_ref() == Some(&state) && !is_waiting_for_key(&system) {
+            unchanged += 1;
+            if unchanged == HUNG_FRAMES {
+                return Outcome::Hung {
+                    frame: frame - HUNG_FRAMES,
+                };
+            }
+        } else {
+            unchanged = 0;
+        }
+        last = Some(state);
+    }
+    Outcome::Ok
+}
+
+/// Returns true if the next instruction waits for a key press (`ld vx, k`),
+/// a program waiting for input is not hung.
+fn is_waiting_for_key(system: &System) -> bool {
+    let pc = system.cpu_state().pc as usize;
+    matches!(system.memory_bytes().get(pc..pc + 2), Some(&[hi, 0x0A]) if hi & 0xF0 == 0xF0)
+}
+
+/// Quotes a CSV field if it contains a separator or quotes.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
@@ -0,0 +1,284 @@
+use crate::{apply_hotkey, preferences};
+use c8asm::{assemble_file, symbols_file};
+use chip8_system::audio::AudioPattern;
+use chip8_system::cheat::Cheats;
+use chip8_system::cpu::CpuState;
+use chip8_system::debug::{DebugEvent, MemoryView, Symbols};
+use chip8_system::display::DisplayMessage;
+use chip8_system::keyboard::{KeyboardMessage, KeypadState};
+use chip8_system::pacing::FramePacing;
+use chip8_system::port::{connect, connect_latest, InputPort, OutputPort};
+use chip8_system::system::{RplFlags, System, SystemController, SystemOptions};
+use crossbeam_channel::{select, Receiver, Sender};
+use gui_druid::Hotkey;
+use sound_cpal::Message;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The ports of the frontend, successive systems get connected to them.
+pub struct Frontend {
+    pub display: Sender<DisplayMessage>,
+    pub keyboard: Receiver<KeyboardMessage>,
+    pub keypad: Sender<KeypadState>,
+    pub memory: Sender<MemoryView>,
+    pub debug_events: Sender<DebugEvent>,
+    pub registers: Sender<CpuState>,
+    pub pacing: Sender<FramePacing>,
+    pub sound: Sender<Message>,
+    pub hotkeys: Receiver<Hotkey>,
+    pub open: Receiver<PathBuf>,
+}
+
+/// Runs the program, and restarts the system from scratch when another program is opened
+/// from the frontend, or each time the file of the program is modified if `reload` is set.
+/// The options of the system are resolved by `options` for each program run.
+/// The flag registers of each program are kept across runs if `persist_flags` is set.
+/// Returns once the frontend is closed.
+pub fn run(
+    mut path: PathBuf,
+    options: impl Fn(&Path) -> Result<SystemOptions, Box<dyn Error>>,
+    frontend: Frontend,
+    reload: bool,
+    persist_flags: bool,
+) {
+    let mut closed = false;
+    // breakpoints are kept across reloads, as the disassembly panel still shows them
+    let mut breakpoints = BTreeSet::new();
+    let keyboard = relay_keyboard(frontend.keyboard.clone());
+    while !closed {
+        let last_modified = modified(&path);
+        let loaded = options(&path).and_then(|options| {
+            let program = load_program(&path, &options)?;
+            Ok((program, options))
+        });
+        let running = match loaded {
+            Ok((program, options)) => {
+                let cheats = load_cheats(&path);
+                let symbols = load_symbols(&path);
+                Some(start(
+                    &program,
+                    cheats,
+                    symbols,
+                    options,
+                    &breakpoints,
+                    &frontend,
+                    &keyboard,
+                    persist_flags,
+                ))
+            }
+            Err(e) => {
+                eprintln!("Unable to load {}: {}", path.display(), e);
+                None
+            }
+        };
+
+        // wait for another program or for the file to change,
+        // forwarding hotkeys to the running system
+        loop {
+            select! {
+                recv(frontend.hotkeys) -> hotkey => match hotkey {
+                    Ok(hotkey) => {
+                        if let Hotkey::Breakpoint { address, enabled } = hotkey {
+                            match enabled {
+                                true => breakpoints.insert(address),
+                                false => breakpoints.remove(&address),
+                            };
+                        }
+                        if let Some((ctrl, _)) = &running {
+                            apply_hotkey(ctrl, hotkey);
+                        }
+                    }
+                    Err(_) => {
+                        closed = true;
+                        break;
+                    }
+                },
+                recv(frontend.open) -> program => {
+                    if let Ok(program) = program {
+                        if let Err(e) = preferences::add_recent(&program) {
+                            eprintln!("Unable to save the recent programs: {}", e);
+                        }
+                        path = program;
+                        break;
+                    }
+                }
+                default(POLL_INTERVAL) => {}
+            }
+            if reload && modified(&path) != last_modified {
+                println!("{} has changed, reloading", path.display());
+                break;
+            }
+        }
+
+        if let Some((ctrl, handle)) = running {
+            ctrl.stop();
+            let _ = handle.join();
+        }
+        let _ = frontend.sound.try_send(Message::Pause);
+        let _ = frontend.display.try_send(DisplayMessage::clear());
+    }
+}
+
+fn start(
+    program: &[u8],
+    cheats: Cheats,
+    symbols: Symbols,
+    options: SystemOptions,
+    breakpoints: &BTreeSet<u16>,
+    frontend: &Frontend,
+    keyboard: &Sender<Sender<KeyboardMessage>>,
+    persist_flags: bool,
+) -> (SystemController, JoinHandle<()>) {
+    let mut system = System::new_with_options(options);
+    connect(&system.sound_timer, &frontend.sound);
+    let audio: Receiver<AudioPattern> = system.output();
+    connect(&audio, &frontend.sound);
+    let _ = keyboard.send(system.keyboard.input());
+    connect(&system.display, &frontend.display);
+    connect_latest(&system.keyboard, &frontend.keypad);
+    let memory: Receiver<MemoryView> = system.output();
+    connect_latest(&memory, &frontend.memory);
+    let events: Receiver<DebugEvent> = system.output();
+    connect(&events, &frontend.debug_events);
+    let states: Receiver<CpuState> = system.output();
+    connect_latest(&states, &frontend.registers);
+    let pacing: Receiver<FramePacing> = system.output();
+    connect_latest(&pacing, &frontend.pacing);
+    system.load_image_bytes(program);
+    system.set_cheats(cheats);
+    system.set_symbols(symbols);
+    if persist_flags {
+        persist_rpl_flags(&mut system, program);
+    }
+
+    let ctrl = system.controller();
+    for &address in breakpoints {
+        ctrl.set_breakpoint(address, true);
+    }
+    let handle = thread::spawn(move || {
+        match system.run() {
+            Ok(()) if system.has_exited() => println!("Program exited"),
+            Ok(()) if system.is_spinning() => println!("Program halted in an endless loop"),
+            Ok(()) => {}
+            Err(e) => {
+                println!("System Error: {}", e);
+                println!("Last instructions:\n{}", system.backtrace());
+                println!("Call stack:\n{}", system.call_stack());
+            }
+        }
+        let profile = system.profile_report();
+        if !profile.is_empty() {
+            println!("Instructions run:\n{}", profile);
+        }
+    });
+
+    (ctrl, handle)
+}
+
+/// Forwards the keys of the frontend to the running system, for as long as the frontend runs,
+/// rather than connecting each successive system to them. Returns the sender of the keyboard
+/// input of each new system, which replaces the previous one.
+fn relay_keyboard(keys: Receiver<KeyboardMessage>) -> Sender<Sender<KeyboardMessage>> {
+    let (retarget, targets) = crossbeam_channel::unbounded::<Sender<KeyboardMessage>>();
+    thread::spawn(move || {
+        let mut target = None;
+        loop {
+            select! {
+                recv(targets) -> t => match t {
+                    Ok(t) => target = Some(t),
+                    Err(_) => break,
+                },
+                recv(keys) -> msg => match msg {
+                    Ok(msg) => {
+                        if let Some(t) = &target {
+                            let _ = t.send(msg);
+                        }
+                    }
+                    Err(_) => break,
+                },
+            }
+        }
+    });
+    retarget
+}
+
+/// Restores the flag registers saved by the previous run of the program,
+/// and saves them each time the program changes them.
+fn persist_rpl_flags(system: &mut System, program: &[u8]) {
+    if let Some(flags) = preferences::load_flags(program) {
+        system.set_rpl_flags(flags);
+    }
+    let saved: Receiver<RplFlags> = system.output();
+    let program = program.to_vec();
+    // stops once the system is dropped
+    thread::spawn(move || {
+        for flags in saved {
+            if let Err(e) = preferences::save_flags(&program, &flags) {
+                eprintln!("Unable to save the flag registers: {}", e);
+            }
+        }
+    });
+}
+
+/// Reads a program image, assembling it first if it is an assembly source file.
+pub fn read_program(path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    let program = match path.extension().and_then(|e| e.to_str()) {
+        Some("asm" | "c8asm" | "s") => assemble_file(path)?,
+        _ => fs::read(path)?,
+    };
+    Ok(program)
+}
+
+/// Reads a program image, which must fit in the memory of a system with `options`.
+pub fn load_program(path: &Path, options: &SystemOptions) -> Result<Vec<u8>, Box<dyn Error>> {
+    let program = read_program(path)?;
+    if program.len() > options.max_program_size() {
+        return Err(format!("program is too large ({} bytes)", program.len()).into());
+    }
+    Ok(program)
+}
+
+/// Loads the cheat file of a program, if it has one.
+pub fn load_cheats(program: &Path) -> Cheats {
+    let path = Cheats::path_for(program);
+    if !path.exists() {
+        return Cheats::default();
+    }
+    match Cheats::load(&path) {
+        Ok(cheats) => cheats,
+        Err(e) => {
+            eprintln!("Invalid cheat file {}: {}", path.display(), e);
+            Cheats::default()
+        }
+    }
+}
+
+/// Loads the symbols of a program: the labels of an assembly source file,
+/// or the symbol map next to a ROM, if it has one.
+pub fn load_symbols(program: &Path) -> Symbols {
+    let symbols = match program.extension().and_then(|e| e.to_str()) {
+        Some("asm" | "c8asm" | "s") => symbols_file(program).map(Symbols::new),
+        _ => {
+            let path = Symbols::path_for(program);
+            if !path.exists() {
+                return Symbols::default();
+            }
+            Symbols::load(&path).map_err(|e| format!("{}: {}", path.display(), e).into())
+        }
+    };
+    symbols.unwrap_or_else(|e| {
+        eprintln!("Invalid symbols: {}", e);
+        Symbols::default()
+    })
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
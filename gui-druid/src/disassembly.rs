@@ -0,0 +1,162 @@
+use crate::memory::MEMORY;
+use crate::text::{self, FONT_SIZE};
+use crate::{AppState, Hotkey};
+use chip8_system::debug::{DebugEvent, MemoryView};
+use chip8_system::dialect::Variant;
+use crossbeam_channel::Sender;
+use druid::*;
+use std::collections::BTreeSet;
+
+pub const DEBUG_EVENT: Selector<DebugEvent> = Selector::new("terminal.debug-event");
+
+/// Number of instructions shown, the one at PC being in the middle.
+const LINES: usize = 25;
+/// Characters of a line, eg. `● 0204  A2F0  ld i, 0x2f0`.
+const LINE_WIDTH: usize = 36;
+
+const PC_COLOR: Color = Color::rgba8(0xff, 0x50, 0x50, 0x60);
+const BREAKPOINT_COLOR: Color = Color::rgb8(0xe0, 0x30, 0x30);
+
+/// The instructions around PC, disassembled from the memory views of the running system.
+/// Clicking a line sets or clears a breakpoint on its instruction.
+pub(crate) struct DisassemblyPanel {
+    variant: Variant,
+    hotkey_sender: Sender<Hotkey>,
+    view: Option<MemoryView>,
+    paused: bool,
+    breakpoints: BTreeSet<u16>,
+    // size of a character of the monospace font
+    cell: Size,
+}
+
+impl DisassemblyPanel {
+    pub fn new(variant: Variant, hotkey_sender: Sender<Hotkey>) -> Self {
+        Self {
+            variant,
+            hotkey_sender,
+            view: None,
+            paused: false,
+            breakpoints: BTreeSet::new(),
+            cell: Size::new(FONT_SIZE * 0.6, FONT_SIZE * 1.25),
+        }
+    }
+
+    /// Returns the address of the first line, so that PC is in the middle.
+    fn first_address(&self) -> Option<u16> {
+        let pc = self.view.as_ref()?.pc;
+        Some(pc.saturating_sub(2 * (LINES / 2) as u16))
+    }
+
+    /// Returns the text of the line showing the instruction at `address`.
+    fn line(&self, view: &MemoryView, address: u16) -> Option<String> {
+        let a = address as usize;
+        let bytes = view.bytes.get(a..a + 2)?;
+        let opcode = u16::from_be_bytes([bytes[0], bytes[1]]);
+        let instr = match self.variant.decode(opcode) {
+            Some(instr) => instr.to_string(),
+            None => "??".to_string(),
+        };
+        let marker = if self.breakpoints.contains(&address) {
+            '●'
+        } else {
+            ' '
+        };
+        Some(format!(
+            "{} {:04X}  {:04X}  {}",
+            marker, address, opcode, instr
+        ))
+    }
+}
+
+impl Widget<AppState> for DisassemblyPanel {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, _data: &mut AppState, _env: &Env) {
+        match event {
+            Event::Command(c) => {
+                if let Some(view) = c.get(MEMORY) {
+                    self.view = Some(view.clone());
+                    ctx.request_paint();
+                }
+                match c.get(DEBUG_EVENT) {
+                    Some(DebugEvent::Paused { .. }) => self.paused = true,
+                    Some(DebugEvent::Resumed) => self.paused = false,
+                    _ => {}
+                }
+            }
+            Event::MouseDown(m) => {
+                // the first line is the status of the system
+                let line = (m.pos.y / self.cell.height).floor() as usize;
+                if let (Some(first), 1..=LINES) = (self.first_address(), line) {
+                    let address = first.wrapping_add(2 * (line - 1) as u16);
+                    let enabled = self.breakpoints.insert(address);
+                    if !enabled {
+                        self.breakpoints.remove(&address);
+                    }
+                    let _ = self
+                        .hotkey_sender
+                        .try_send(Hotkey::Breakpoint { address, enabled });
+                    ctx.request_paint();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        _ctx: &mut LifeCycleCtx,
+        _event: &LifeCycle,
+        _data: &AppState,
+        _env: &Env,
+    ) {
+    }
+
+    fn update(&mut self, _ctx: &mut UpdateCtx, _old: &AppState, _data: &AppState, _env: &Env) {}
+
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        _bc: &BoxConstraints,
+        _data: &AppState,
+        _env: &Env,
+    ) -> Size {
+        self.cell = text::cell_size(ctx.text());
+        Size::new(
+            LINE_WIDTH as f64 * self.cell.width,
+            (LINES + 1) as f64 * self.cell.height,
+        )
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, _data: &AppState, env: &Env) {
+        let bounds = ctx.size().to_rect();
+        ctx.fill(bounds, &env.get(theme::BACKGROUND_LIGHT));
+        let text_color = env.get(theme::TEXT_COLOR);
+        let (Some(view), Some(first)) = (self.view.clone(), self.first_address()) else {
+            return;
+        };
+
+        let status = match self.paused {
+            true => format!("Paused at {:04X}, F10/F11 to step", view.pc),
+            false => "Running, F3 to pause".to_string(),
+        };
+        let layout = text::monospace(ctx.text(), status, &text_color);
+        ctx.draw_text(&layout, Point::ORIGIN);
+
+        for row in 0..LINES {
+            let address = first.wrapping_add(2 * row as u16);
+            let Some(line) = self.line(&view, address) else {
+                break;
+            };
+            let y = (row + 1) as f64 * self.cell.height;
+            if address == view.pc {
+                let r = Rect::new(0.0, y, bounds.width(), y + self.cell.height);
+                ctx.fill(r, &PC_COLOR);
+            }
+            let color = match self.breakpoints.contains(&address) {
+                true => BREAKPOINT_COLOR,
+                false => text_color.clone(),
+            };
+            let layout = text::monospace(ctx.text(), line, &color);
+            ctx.draw_text(&layout, Point::new(0.0, y));
+        }
+    }
+}
@@ -1,38 +1,124 @@
+use chip8_system::cpu::CpuState;
+use chip8_system::debug::{DebugEvent, MemoryView, Register};
+use chip8_system::dialect::Variant;
 use chip8_system::display::{
-    pixel_buffer, DisplayMessage, PixelBuffer, DISPLAY_HEIGHT, DISPLAY_WIDTH,
+    pixel_buffer, DisplayContent, DisplayMessage, IndexedFrame, PixelBuffer, DISPLAY_HEIGHT,
+    DISPLAY_WIDTH,
 };
-use chip8_system::keyboard::{Key, KeyboardMessage};
-use chip8_system::keyboard_map::KeyboardMap;
+use chip8_system::keyboard::{Key, KeyboardMessage, KeypadState};
+use chip8_system::keyboard_map::{load_profiles, HeldKeys, KeyboardMap};
+use chip8_system::pacing::FramePacing;
 use chip8_system::port::{InputPort, OutputPort};
 use crossbeam_channel::{Receiver, Sender};
-use druid::widget::Align;
+use druid::kurbo::Circle;
+use druid::piet::{Text, TextLayout as _, TextLayoutBuilder};
+use druid::widget::{Align, Either, Flex, Scroll, SizedBox};
 use druid::*;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::thread;
+use std::time::{Duration, Instant};
+
+mod disassembly;
+mod memory;
+mod registers;
+mod settings;
+mod status;
+mod text;
+
+use disassembly::{DisassemblyPanel, DEBUG_EVENT};
+use memory::{MemoryPanel, MEMORY};
+use registers::{RegistersPanel, REGISTERS};
+use settings::preferences_window;
+pub use settings::{PixelShape, Settings, Theme, WindowGeometry};
+use status::{PaintTimes, StatusBar, PACING};
 
 // expose Color to the outside
 pub use druid::piet::{Color, ColorParseError};
 
-const SCALING_FACTOR: f64 = 8.0;
+/// Duration of a frame, for the persistence of the pixels.
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// Size of a key of the input overlay.
+const OVERLAY_KEY_SIZE: f64 = 10.0;
+
+/// Keys of the input overlay, as laid out on the COSMAC VIP keypad.
+const KEYPAD_LAYOUT: [[Key; 4]; 4] = [
+    [Key::Key1, Key::Key2, Key::Key3, Key::KeyC],
+    [Key::Key4, Key::Key5, Key::Key6, Key::KeyD],
+    [Key::Key7, Key::Key8, Key::Key9, Key::KeyE],
+    [Key::KeyA, Key::Key0, Key::KeyB, Key::KeyF],
+];
 
 pub const UPDATE: Selector<DisplayMessage> = Selector::new("terminal.update");
+pub const KEYPAD: Selector<KeypadState> = Selector::new("terminal.keypad");
+/// Gives the keyboard back to the screen, eg. once a panel is done with it.
+const TAKE_FOCUS: Selector = Selector::new("terminal.take-focus");
 
 #[derive(Clone, Data, Lens)]
-struct AppState {}
+struct AppState {
+    settings: Settings,
+    show_disassembly: bool,
+    show_registers: bool,
+    show_memory: bool,
+    show_status_bar: bool,
+}
 
-pub struct TerminalOptions {
-    background_color: Color,
-    foreground_color: Color,
-    keyboard_map: KeyboardMap,
+/// Controls of the emulator rather than the program, mostly keys of the host keyboard.
+/// They are sent on their own port, and keys never reach the keypad of the program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hotkey {
+    /// Backspace: go back in time.
+    Rewind,
+    /// F3: pause or resume.
+    Pause,
+    /// F5: keep the current state.
+    SaveState,
+    /// F8: go back to the state kept by the last save.
+    LoadState,
+    /// F6: enable or disable the cheats of the program.
+    ToggleCheats,
+    /// Tab: run faster while held down.
+    Turbo(bool),
+    /// F10: run the next instruction, or the whole subroutine it calls, then pause.
+    StepOver,
+    /// F11: run until the current subroutine returns, then pause.
+    StepOut,
+    /// A line of the disassembly panel clicked: set or clear a breakpoint.
+    Breakpoint { address: u16, enabled: bool },
+    /// A register edited in the registers panel while paused.
+    SetRegister { register: Register, value: u16 },
 }
 
-impl Default for TerminalOptions {
-    fn default() -> Self {
-        Self {
-            background_color: Color::BLACK,
-            foreground_color: Color::GRAY,
-            keyboard_map: Default::default(),
+impl Hotkey {
+    /// Returns the hotkey sent when `key` is pressed, or released if `down` is false.
+    fn from_key(key: &KbKey, down: bool) -> Option<Self> {
+        match key {
+            KbKey::Backspace if down => Some(Hotkey::Rewind),
+            KbKey::F3 if down => Some(Hotkey::Pause),
+            KbKey::F5 if down => Some(Hotkey::SaveState),
+            KbKey::F6 if down => Some(Hotkey::ToggleCheats),
+            KbKey::F8 if down => Some(Hotkey::LoadState),
+            KbKey::F10 if down => Some(Hotkey::StepOver),
+            KbKey::F11 if down => Some(Hotkey::StepOut),
+            KbKey::Tab => Some(Hotkey::Turbo(down)),
+            _ => None,
         }
     }
+
+    /// Keys reserved by the terminal, which are never sent to the program.
+    fn is_reserved(key: &KbKey) -> bool {
+        matches!(key, KbKey::F1 | KbKey::F2 | KbKey::F4) || Hotkey::from_key(key, true).is_some()
+    }
+}
+
+#[derive(Default)]
+pub struct TerminalOptions {
+    settings: Settings,
+    input_overlay: bool,
+    recent: Vec<PathBuf>,
+    variant: Variant,
 }
 
 impl TerminalOptions {
@@ -40,18 +126,84 @@ impl TerminalOptions {
         Default::default()
     }
 
+    /// Replaces all the settings at once, eg. with the ones saved by a previous run.
+    pub fn settings(&mut self, settings: Settings) -> &mut Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Selects the theme of the windows, along with the matching colors of the screen.
+    pub fn theme(&mut self, theme: Theme) -> &mut Self {
+        self.settings.theme = theme;
+        (
+            self.settings.background_color,
+            self.settings.foreground_color,
+        ) = theme.colors();
+        self
+    }
+
     pub fn background_color(&mut self, color: Color) -> &mut Self {
-        self.background_color = color;
+        self.settings.background_color = color;
         self
     }
 
     pub fn foreground_color(&mut self, color: Color) -> &mut Self {
-        self.foreground_color = color;
+        self.settings.foreground_color = color;
+        self
+    }
+
+    /// Selects one of the profiles returned by `load_profiles`.
+    pub fn keyboard_profile(&mut self, profile: &str) -> &mut Self {
+        self.settings.keyboard_profile = profile.to_string();
+        self
+    }
+
+    pub fn pixel_shape(&mut self, shape: PixelShape) -> &mut Self {
+        self.settings.pixel_shape = shape;
+        self
+    }
+
+    /// Stretches the pixels to this ratio of their width to their height, eg. 2.0 for pixels
+    /// twice as wide as high, so that programs show with their intended proportions.
+    pub fn pixel_aspect(&mut self, ratio: f64) -> &mut Self {
+        self.settings.pixel_aspect = ratio;
+        self
+    }
+
+    /// Leaves a 1 pixel gap between pixels, eg. to count them at large scales.
+    pub fn grid(&mut self, enabled: bool) -> &mut Self {
+        self.settings.grid = enabled;
+        self
+    }
+
+    pub fn high_contrast(&mut self, enabled: bool) -> &mut Self {
+        self.settings.high_contrast = enabled;
         self
     }
 
-    pub fn keyboard_map(&mut self, map: KeyboardMap) -> &mut Self {
-        self.keyboard_map = map;
+    /// Keeps pixels lit for a number of frames after they are turned off, which smooths
+    /// the flicker of sprites erased then redrawn. This only changes how the screen is shown.
+    pub fn persistence(&mut self, frames: u8) -> &mut Self {
+        self.settings.persistence = frames;
+        self
+    }
+
+    /// Shows the keys of the keypad held down, in the bottom right corner.
+    /// The overlay can also be toggled with F1.
+    pub fn input_overlay(&mut self, enabled: bool) -> &mut Self {
+        self.input_overlay = enabled;
+        self
+    }
+
+    /// Lists programs in the File > Open Recent menu, the ones selected are sent on the output port.
+    pub fn recent(&mut self, programs: Vec<PathBuf>) -> &mut Self {
+        self.recent = programs;
+        self
+    }
+
+    /// Selects the instruction set used by the disassembly panel, the one of the system.
+    pub fn variant(&mut self, variant: Variant) -> &mut Self {
+        self.variant = variant;
         self
     }
 }
@@ -60,6 +212,15 @@ pub struct Terminal {
     app_launcher: AppLauncher<AppState>,
     keyboard_receiver: Receiver<KeyboardMessage>,
     display_sender: Sender<DisplayMessage>,
+    hotkey_receiver: Receiver<Hotkey>,
+    keypad_sender: Sender<KeypadState>,
+    settings_receiver: Receiver<Settings>,
+    open_receiver: Receiver<PathBuf>,
+    memory_sender: Sender<MemoryView>,
+    debug_event_sender: Sender<DebugEvent>,
+    registers_sender: Sender<CpuState>,
+    pacing_sender: Sender<FramePacing>,
+    state: AppState,
 }
 
 impl Default for Terminal {
@@ -75,17 +236,80 @@ impl Terminal {
 
     pub fn new_with_options(options: TerminalOptions) -> Self {
         let (ks, kr) = crossbeam_channel::bounded(128);
-        let (ds, dr) = crossbeam_channel::bounded(128);
+        // a single slot, so that the display port always delivers the latest frame
+        let (ds, dr) = crossbeam_channel::bounded(1);
+        let (hs, hr) = crossbeam_channel::bounded(16);
+        let (ps, pr) = crossbeam_channel::bounded(16);
+        let (ss, sr) = crossbeam_channel::bounded(16);
+        let (os, or) = crossbeam_channel::bounded(16);
+        let (ms, mr) = crossbeam_channel::bounded(1);
+        let (es, er) = crossbeam_channel::bounded(16);
+        let (rs, rr) = crossbeam_channel::bounded(1);
+        let (fs, fr) = crossbeam_channel::bounded(1);
+
+        let state = AppState {
+            settings: options.settings,
+            show_disassembly: false,
+            show_registers: false,
+            show_memory: false,
+            show_status_bar: false,
+        };
+        let (width, height) = screen_size(&state.settings);
 
-        let main_window = WindowDesc::new(Align::centered(TerminalWidget::new(ks, options)))
+        let disassembly_panel = Either::new(
+            |data: &AppState, _| data.show_disassembly,
+            DisassemblyPanel::new(options.variant, hs.clone()),
+            SizedBox::empty(),
+        );
+        let registers_panel = Either::new(
+            |data: &AppState, _| data.show_registers,
+            RegistersPanel::new(hs.clone()),
+            SizedBox::empty(),
+        );
+        let paint_times = PaintTimes::default();
+        let widget = TerminalWidget::new(ks, hs, ss, options.input_overlay, paint_times.clone());
+        let recent = options.recent;
+        let memory_panel = Either::new(
+            |data: &AppState, _| data.show_memory,
+            Scroll::new(MemoryPanel::new()).vertical(),
+            SizedBox::empty(),
+        );
+        let status_bar = Either::new(
+            |data: &AppState, _| data.show_status_bar,
+            StatusBar::new(paint_times),
+            SizedBox::empty(),
+        );
+        let root = Flex::row()
+            .with_flex_child(
+                Flex::column()
+                    .with_flex_child(Align::centered(widget), 1.0)
+                    .with_child(status_bar),
+                1.0,
+            )
+            .with_child(disassembly_panel)
+            .with_child(registers_panel)
+            .with_child(memory_panel);
+        let mut main_window = WindowDesc::new(root)
             .title("Chip-8")
-            .window_size((
-                DISPLAY_WIDTH as f64 * SCALING_FACTOR + 25.0,
-                DISPLAY_HEIGHT as f64 * SCALING_FACTOR + 50.0,
-            ))
-            .resizable(true);
+            .window_size((width + 25.0, height + 50.0))
+            .resizable(true)
+            .menu(move |_, _, _| {
+                Menu::empty()
+                    .entry(file_menu(&recent, &os))
+                    .entry(debug_menu())
+            });
+        if let Some(g) = state.settings.window {
+            main_window = main_window
+                .window_size((g.width, g.height))
+                .set_position((g.x, g.y));
+            if g.maximized {
+                main_window = main_window.set_window_state(WindowState::Maximized);
+            }
+        }
 
-        let app_launcher = AppLauncher::with_window(main_window);
+        let theme = state.settings.theme;
+        let app_launcher =
+            AppLauncher::with_window(main_window).configure_env(move |env, _| theme.apply(env));
 
         // event sink where to push display messages received from the chip8 system
         let event_sink = app_launcher.get_external_handle();
@@ -98,85 +322,507 @@ impl Terminal {
             }
         });
 
+        let event_sink = app_launcher.get_external_handle();
+
+        thread::spawn(move || {
+            while let Ok(state) = pr.recv() {
+                event_sink
+                    .submit_command(KEYPAD, state, Target::Global)
+                    .expect("Failed to submit keypad command");
+            }
+        });
+
+        let event_sink = app_launcher.get_external_handle();
+
+        thread::spawn(move || {
+            while let Ok(view) = mr.recv() {
+                event_sink
+                    .submit_command(MEMORY, view, Target::Global)
+                    .expect("Failed to submit memory command");
+            }
+        });
+
+        let event_sink = app_launcher.get_external_handle();
+
+        thread::spawn(move || {
+            while let Ok(event) = er.recv() {
+                event_sink
+                    .submit_command(DEBUG_EVENT, event, Target::Global)
+                    .expect("Failed to submit debug event command");
+            }
+        });
+
+        let event_sink = app_launcher.get_external_handle();
+
+        thread::spawn(move || {
+            while let Ok(state) = rr.recv() {
+                event_sink
+                    .submit_command(REGISTERS, state, Target::Global)
+                    .expect("Failed to submit registers command");
+            }
+        });
+
+        let event_sink = app_launcher.get_external_handle();
+
+        thread::spawn(move || {
+            while let Ok(pacing) = fr.recv() {
+                event_sink
+                    .submit_command(PACING, pacing, Target::Global)
+                    .expect("Failed to submit pacing command");
+            }
+        });
+
         Self {
             app_launcher,
             keyboard_receiver: kr,
             display_sender: ds,
+            hotkey_receiver: hr,
+            keypad_sender: ps,
+            settings_receiver: sr,
+            open_receiver: or,
+            memory_sender: ms,
+            debug_event_sender: es,
+            registers_sender: rs,
+            pacing_sender: fs,
+            state,
         }
     }
 
+    /// Returns a handle to close the terminal from another thread.
+    pub fn handle(&self) -> TerminalHandle {
+        TerminalHandle(self.app_launcher.get_external_handle())
+    }
+
     pub fn run(self) {
         self.app_launcher
-            .launch(AppState {})
+            .launch(self.state)
             .expect("Failed to launch application");
     }
 }
 
+/// Closes a running terminal from any thread, `Terminal::run` then returns.
+#[derive(Clone)]
+pub struct TerminalHandle(ExtEventSink);
+
+impl TerminalHandle {
+    pub fn close(&self) {
+        let _ = self
+            .0
+            .submit_command(commands::QUIT_APP, (), Target::Global);
+    }
+}
+
+fn file_menu(recent: &[PathBuf], open_sender: &Sender<PathBuf>) -> Menu<AppState> {
+    let mut open_recent = Menu::new("Open Recent");
+    for program in recent {
+        let name = program.file_name().unwrap_or(program.as_os_str());
+        let (program, open_sender) = (program.clone(), open_sender.clone());
+        open_recent = open_recent.entry(
+            MenuItem::new(name.to_string_lossy().to_string()).on_activate(move |_, _, _| {
+                let _ = open_sender.try_send(program.clone());
+            }),
+        );
+    }
+    Menu::new("File").entry(open_recent)
+}
+
+fn debug_menu() -> Menu<AppState> {
+    Menu::new("Debug")
+        .entry(
+            MenuItem::new("Disassembly")
+                .selected_if(|data: &AppState, _| data.show_disassembly)
+                .on_activate(|_, data: &mut AppState, _| {
+                    data.show_disassembly = !data.show_disassembly
+                }),
+        )
+        .entry(
+            MenuItem::new("Registers")
+                .selected_if(|data: &AppState, _| data.show_registers)
+                .on_activate(|_, data: &mut AppState, _| {
+                    data.show_registers = !data.show_registers
+                }),
+        )
+        .entry(
+            MenuItem::new("Memory Viewer")
+                .selected_if(|data: &AppState, _| data.show_memory)
+                .on_activate(|_, data: &mut AppState, _| data.show_memory = !data.show_memory),
+        )
+        .entry(
+            MenuItem::new("Frame Pacing")
+                .selected_if(|data: &AppState, _| data.show_status_bar)
+                .on_activate(|_, data: &mut AppState, _| {
+                    data.show_status_bar = !data.show_status_bar
+                }),
+        )
+}
+
+/// Size of the screen and its border, in logical pixels.
+fn screen_size(settings: &Settings) -> (f64, f64) {
+    let border = settings.border_width();
+    let pixel = settings.pixel_size();
+    (
+        DISPLAY_WIDTH as f64 * pixel.width + 2.0 * border,
+        DISPLAY_HEIGHT as f64 * pixel.height + 2.0 * border,
+    )
+}
+
 impl OutputPort<KeyboardMessage> for Terminal {
     fn output(&self) -> Receiver<KeyboardMessage> {
         self.keyboard_receiver.clone()
     }
 }
 
+/// Emits the settings each time they are changed in the preferences window.
+impl OutputPort<Settings> for Terminal {
+    fn output(&self) -> Receiver<Settings> {
+        self.settings_receiver.clone()
+    }
+}
+
+/// Emits the programs selected in the Open Recent menu.
+impl OutputPort<PathBuf> for Terminal {
+    fn output(&self) -> Receiver<PathBuf> {
+        self.open_receiver.clone()
+    }
+}
+
+impl OutputPort<Hotkey> for Terminal {
+    fn output(&self) -> Receiver<Hotkey> {
+        self.hotkey_receiver.clone()
+    }
+}
+
 impl InputPort<DisplayMessage> for Terminal {
     fn input(&self) -> Sender<DisplayMessage> {
         self.display_sender.clone()
     }
 }
 
+/// Views of the memory shown by the memory viewer, opened from the Debug menu.
+impl InputPort<MemoryView> for Terminal {
+    fn input(&self) -> Sender<MemoryView> {
+        self.memory_sender.clone()
+    }
+}
+
+/// Registers, timers and call stack of the system, shown by the registers panel.
+impl InputPort<CpuState> for Terminal {
+    fn input(&self) -> Sender<CpuState> {
+        self.registers_sender.clone()
+    }
+}
+
+/// Pauses and resumes of the system, shown by the debug panels.
+impl InputPort<DebugEvent> for Terminal {
+    fn input(&self) -> Sender<DebugEvent> {
+        self.debug_event_sender.clone()
+    }
+}
+
+/// Pacing of the display updates emitted by the system, shown in the status bar
+/// next to the pacing of the frames painted by the terminal.
+impl InputPort<FramePacing> for Terminal {
+    fn input(&self) -> Sender<FramePacing> {
+        self.pacing_sender.clone()
+    }
+}
+
+impl InputPort<KeypadState> for Terminal {
+    fn input(&self) -> Sender<KeypadState> {
+        self.keypad_sender.clone()
+    }
+}
+
 struct TerminalWidget {
     key_sender: Sender<KeyboardMessage>,
+    hotkey_sender: Sender<Hotkey>,
+    settings_sender: Sender<Settings>,
+    profiles: HashMap<String, KeyboardMap>,
+    held_keys: HeldKeys,
     pixels: PixelBuffer,
-    options: TerminalOptions,
+    // the colors of the pixels drawn by a MegaChip program
+    colors: Option<IndexedFrame>,
+    // when each pixel was last turned off, while it may still be shown lit
+    turned_off: Vec<Option<Instant>>,
+    keypad: KeypadState,
+    show_overlay: bool,
+    show_keymap: bool,
+    // set when new pixels are received, until they are painted
+    new_frame: bool,
+    paint_times: PaintTimes,
 }
 
 impl TerminalWidget {
-    fn new(key_sender: Sender<KeyboardMessage>, options: TerminalOptions) -> Self {
+    fn new(
+        key_sender: Sender<KeyboardMessage>,
+        hotkey_sender: Sender<Hotkey>,
+        settings_sender: Sender<Settings>,
+        show_overlay: bool,
+        paint_times: PaintTimes,
+    ) -> Self {
         Self {
             key_sender,
+            hotkey_sender,
+            settings_sender,
+            // invalid profiles are reported by the application before the terminal starts
+            profiles: load_profiles().unwrap_or_default(),
+            held_keys: HeldKeys::new(),
             pixels: pixel_buffer(),
-            options,
+            colors: None,
+            turned_off: vec![],
+            keypad: Default::default(),
+            show_overlay,
+            show_keymap: false,
+            new_frame: false,
+            paint_times,
+        }
+    }
+
+    fn press_key(&mut self, k: &KeyEvent, profile: &str) {
+        if let Some(map) = self.profiles.get(profile) {
+            if let Some(msg) = self.held_keys.press(map, &key_name(k)) {
+                let _ = self.key_sender.try_send(msg);
+            }
+        }
+    }
+
+    fn release_key(&mut self, k: &KeyEvent) {
+        if let Some(msg) = self.held_keys.release(&key_name(k)) {
+            let _ = self.key_sender.try_send(msg);
+        }
+    }
+
+    /// Replaces the pixels of the screen, keeping track of the ones turned off.
+    fn update_pixels(&mut self, pixels: PixelBuffer, persistence: u8) {
+        if persistence == 0 || self.turned_off.len() != pixels.len() {
+            self.turned_off = vec![None; pixels.len()];
+        }
+        if persistence > 0 {
+            let now = Instant::now();
+            for (i, t) in self.turned_off.iter_mut().enumerate() {
+                let (was_on, on) = (self.pixels.get(i).map_or(false, |p| *p), pixels[i]);
+                if on {
+                    *t = None;
+                } else if was_on {
+                    *t = Some(now);
+                }
+            }
+        }
+        self.pixels = pixels;
+    }
+
+    /// Returns the color of a lit pixel, its palette color in the frames of MegaChip programs.
+    fn pixel_color<'a>(&self, i: usize, foreground: &'a Color) -> Cow<'a, Color> {
+        match &self.colors {
+            Some(frame) if frame.pixels[i] != 0 => {
+                // ARGB to RGBA
+                Cow::Owned(Color::from_rgba32_u32(frame.color(i).rotate_left(8)))
+            }
+            _ => Cow::Borrowed(foreground),
+        }
+    }
+
+    /// Returns true if the pixel is lit, or has been turned off for less than `persistence` frames.
+    fn is_lit(&self, i: usize, persistence: u8, now: Instant) -> bool {
+        self.pixels.get(i).map_or(false, |p| *p)
+            || matches!(self.turned_off.get(i), Some(Some(t))
+                if now.duration_since(*t) < FRAME_DURATION * persistence as u32)
+    }
+
+    /// Returns true if some pixels turned off are still shown lit.
+    fn is_persisting(&self, persistence: u8, now: Instant) -> bool {
+        (0..self.turned_off.len()).any(|i| !self.pixels[i] && self.is_lit(i, persistence, now))
+    }
+
+    fn paint_overlay(&self, ctx: &mut PaintCtx, screen: Rect, color: &Color) {
+        let origin = Point::new(
+            screen.x1 - 4.5 * OVERLAY_KEY_SIZE,
+            screen.y1 - 4.5 * OVERLAY_KEY_SIZE,
+        );
+
+        for (row, keys) in KEYPAD_LAYOUT.iter().enumerate() {
+            for (col, &key) in keys.iter().enumerate() {
+                let r = Rect::from((
+                    origin + (col as f64 * OVERLAY_KEY_SIZE, row as f64 * OVERLAY_KEY_SIZE),
+                    Size::new(OVERLAY_KEY_SIZE, OVERLAY_KEY_SIZE),
+                ))
+                .inset(-1.0);
+                if self.keypad.is_down(key) {
+                    ctx.fill(r, color);
+                } else {
+                    ctx.stroke(r, color, 1.0);
+                }
+            }
         }
     }
 
-    fn translate_key(&self, k: &KbKey) -> Option<Key> {
-        if let KbKey::Character(s) = k {
-            self.options.keyboard_map.key(s)
-        } else {
-            None
+    /// Paints the keypad over the screen, with the physical keys bound to each key by the profile.
+    fn paint_keymap(
+        &self,
+        ctx: &mut PaintCtx,
+        screen: Rect,
+        profile: &str,
+        colors: (&Color, &Color),
+    ) {
+        let (background, foreground) = colors;
+        let Some(map) = self.profiles.get(profile) else {
+            return;
+        };
+
+        let panel = screen.inset(-screen.height() / 16.0);
+        ctx.fill(panel, background);
+        ctx.stroke(panel, foreground, 1.0);
+
+        let cell = Size::new(panel.width() / 4.0, panel.height() / 4.0);
+        for (row, keys) in KEYPAD_LAYOUT.iter().enumerate() {
+            for (col, &key) in keys.iter().enumerate() {
+                let r = Rect::from((
+                    panel.origin() + (col as f64 * cell.width, row as f64 * cell.height),
+                    cell,
+                ))
+                .inset(-2.0);
+                let width = if self.keypad.is_down(key) { 3.0 } else { 1.0 };
+                ctx.stroke(r, foreground, width);
+
+                let digit = format!("{:X}", key as u8);
+                let layout = ctx
+                    .text()
+                    .new_text_layout(digit)
+                    .font(FontFamily::MONOSPACE, cell.height / 3.0)
+                    .text_color(foreground.clone())
+                    .build()
+                    .unwrap();
+                ctx.draw_text(&layout, r.origin() + (4.0, 2.0));
+
+                let names: Vec<String> = map.names(key).into_iter().map(key_label).collect();
+                let layout = ctx
+                    .text()
+                    .new_text_layout(names.join(" "))
+                    .font(FontFamily::SANS_SERIF, cell.height / 5.0)
+                    .text_color(foreground.clone())
+                    .max_width(r.width() - 8.0)
+                    .build()
+                    .unwrap();
+                let size = layout.size();
+                ctx.draw_text(
+                    &layout,
+                    Point::new(r.center().x - size.width / 2.0, r.y1 - size.height - 4.0),
+                );
+            }
         }
     }
 }
 
+/// How a physical key is shown on the keymap overlay.
+fn key_label(name: &str) -> String {
+    match name {
+        "ArrowUp" => "↑".to_string(),
+        "ArrowDown" => "↓".to_string(),
+        "ArrowLeft" => "←".to_string(),
+        "ArrowRight" => "→".to_string(),
+        _ => name.to_uppercase(),
+    }
+}
+
+/// The name of a key in keyboard maps: the character it types, "Space",
+/// the name of other keys such as "ArrowUp", or "Numpad4" for numpad keys.
+fn key_name(k: &KeyEvent) -> String {
+    match &k.key {
+        _ if k.location == Location::Numpad => k.code.to_string(),
+        KbKey::Character(s) if s == " " => "Space".to_string(),
+        key => key.to_string(),
+    }
+}
+
 impl Widget<AppState> for TerminalWidget {
-    fn event(&mut self, ctx: &mut EventCtx, event: &Event, _data: &mut AppState, _env: &Env) {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut AppState, _env: &Env) {
+        let profile = &data.settings.keyboard_profile;
         match event {
             Event::WindowConnected => {
                 ctx.request_focus();
                 ctx.request_paint();
             }
+            Event::MouseDown(_) => ctx.request_focus(),
+            Event::AnimFrame(_) => {
+                // repaint until the pixels turned off are shown as such
+                if self.is_persisting(data.settings.persistence, Instant::now()) {
+                    ctx.request_anim_frame();
+                }
+                ctx.request_paint();
+            }
+            Event::WindowCloseRequested => {
+                // saved with the other settings, to reopen the window as it was left
+                let window = ctx.window();
+                let (position, size) = (window.get_position(), window.get_size());
+                data.settings.window = Some(WindowGeometry {
+                    x: position.x,
+                    y: position.y,
+                    width: size.width,
+                    height: size.height,
+                    maximized: window.get_window_state() == WindowState::Maximized,
+                });
+            }
             Event::KeyDown(k) => {
                 //println!("Key Down: {:?}", k);
-                if !k.repeat {
-                    if let Some(k) = self.translate_key(&k.key) {
-                        let _ = self.key_sender.try_send(KeyboardMessage::down(k));
+                if k.key == KbKey::F1 {
+                    if !k.repeat {
+                        self.show_overlay = !self.show_overlay;
+                        ctx.request_paint();
+                    }
+                } else if k.key == KbKey::F4 {
+                    if !k.repeat {
+                        self.show_keymap = !self.show_keymap;
+                        ctx.request_paint();
+                    }
+                } else if k.key == KbKey::F2 {
+                    if !k.repeat {
+                        ctx.new_window(preferences_window());
                     }
+                } else if let Some(hotkey) = Hotkey::from_key(&k.key, true) {
+                    // rewind keeps going back in time while held down
+                    if !k.repeat || hotkey == Hotkey::Rewind {
+                        let _ = self.hotkey_sender.try_send(hotkey);
+                    }
+                } else if !k.repeat {
+                    self.press_key(k, profile);
                 }
             }
             Event::KeyUp(k) => {
                 //println!("Key Up: {:?}", k);
-                if let Some(k) = self.translate_key(&k.key) {
-                    let _ = self.key_sender.try_send(KeyboardMessage::up(k));
+                if let Some(hotkey) = Hotkey::from_key(&k.key, false) {
+                    let _ = self.hotkey_sender.try_send(hotkey);
+                } else if !Hotkey::is_reserved(&k.key) {
+                    self.release_key(k);
                 }
             }
             Event::Command(c) => {
                 if let Some(dm) = c.get(UPDATE) {
-                    self.pixels = match dm {
-                        DisplayMessage::Clear => pixel_buffer(),
-                        DisplayMessage::Update(b) => b.clone(),
+                    self.colors = None;
+                    let pixels = match &dm.content {
+                        DisplayContent::Clear => pixel_buffer(),
+                        DisplayContent::Update(b) => b.clone(),
+                        DisplayContent::Indexed(frame) => {
+                            self.colors = Some(frame.clone());
+                            frame.lit()
+                        }
                     };
+                    let persistence = data.settings.persistence;
+                    self.update_pixels(pixels, persistence);
+                    self.new_frame = true;
+                    if self.is_persisting(persistence, Instant::now()) {
+                        ctx.request_anim_frame();
+                    }
                     ctx.request_paint();
                 }
+                if c.is(TAKE_FOCUS) {
+                    ctx.request_focus();
+                }
+                if let Some(state) = c.get(KEYPAD) {
+                    self.keypad = *state;
+                    if self.show_overlay || self.show_keymap {
+                        ctx.request_paint();
+                    }
+                }
             }
             _ => {}
         }
@@ -191,7 +837,11 @@ impl Widget<AppState> for TerminalWidget {
     ) {
     }
 
-    fn update(&mut self, ctx: &mut UpdateCtx, _old_data: &AppState, _data: &AppState, _env: &Env) {
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &AppState, data: &AppState, _env: &Env) {
+        if !old_data.settings.same(&data.settings) {
+            let _ = self.settings_sender.try_send(data.settings.clone());
+            ctx.request_layout();
+        }
         ctx.request_paint();
     }
 
@@ -199,30 +849,66 @@ impl Widget<AppState> for TerminalWidget {
         &mut self,
         _ctx: &mut LayoutCtx,
         _bc: &BoxConstraints,
-        _data: &AppState,
+        data: &AppState,
         _env: &Env,
     ) -> Size {
-        Size::from((
-            DISPLAY_WIDTH as f64 * SCALING_FACTOR,
-            DISPLAY_HEIGHT as f64 * SCALING_FACTOR,
-        ))
+        Size::from(screen_size(&data.settings))
     }
 
-    fn paint(&mut self, ctx: &mut PaintCtx, _data: &AppState, _env: &Env) {
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &AppState, _env: &Env) {
+        let settings = &data.settings;
+        let (background, foreground) = settings.colors();
+        let border = settings.border_width();
+
         let bounds = ctx.size().to_rect();
-        ctx.fill(bounds, &self.options.background_color);
+        if border > 0.0 {
+            ctx.fill(bounds, foreground);
+        }
+        ctx.fill(bounds.inset(-border), background);
 
-        for y in 0..DISPLAY_HEIGHT {
-            for x in 0..DISPLAY_WIDTH {
-                let i = DISPLAY_WIDTH * y + x;
-                let r = Rect::from((
-                    Point::new(x as f64 * SCALING_FACTOR, y as f64 * SCALING_FACTOR),
-                    Size::new(SCALING_FACTOR, SCALING_FACTOR),
+        // hires programs have more rows and columns, drawn with smaller pixels in the same area
+        let (columns, rows) = chip8_system::display::screen_size(self.pixels.len());
+        let rows = rows.max(1);
+        let width = settings.pixel_size().width * DISPLAY_WIDTH as f64 / columns as f64;
+        let height = settings.pixel_size().height * DISPLAY_HEIGHT as f64 / rows as f64;
+        let size = width.min(height);
+        let (persistence, now) = (settings.persistence, Instant::now());
+        if self.new_frame {
+            self.new_frame = false;
+            self.paint_times.record(now);
+        }
+        for y in 0..rows {
+            for x in 0..columns {
+                let i = columns * y + x;
+                let mut r = Rect::from((
+                    Point::new(border + x as f64 * width, border + y as f64 * height),
+                    Size::new(width, height),
                 ));
-                if let Some(true) = self.pixels.get(i).as_deref() {
-                    ctx.fill(r, &self.options.foreground_color);
+                if settings.grid {
+                    r = r.inset(-0.5);
+                }
+                if self.is_lit(i, persistence, now) {
+                    let color = &*self.pixel_color(i, foreground);
+                    match settings.pixel_shape {
+                        PixelShape::Square => ctx.fill(r, color),
+                        PixelShape::Rounded => ctx.fill(r.to_rounded_rect(size / 4.0), color),
+                        PixelShape::Dot => ctx.fill(Circle::new(r.center(), size * 0.4), color),
+                    }
                 }
             }
         }
+
+        if self.show_overlay {
+            self.paint_overlay(ctx, bounds.inset(-border), foreground);
+        }
+        if self.show_keymap {
+            let profile = &settings.keyboard_profile;
+            self.paint_keymap(
+                ctx,
+                bounds.inset(-border),
+                profile,
+                (background, foreground),
+            );
+        }
     }
 }
@@ -0,0 +1,146 @@
+use crate::text::{self, FONT_SIZE};
+use crate::AppState;
+use chip8_system::debug::MemoryView;
+use druid::*;
+
+pub const MEMORY: Selector<MemoryView> = Selector::new("terminal.memory");
+
+const BYTES_PER_ROW: usize = 16;
+/// Characters before the first byte of a row, eg. `0200  `.
+const ADDRESS_WIDTH: usize = 6;
+/// Number of views during which a written byte stays highlighted, about a second.
+const WRITE_HIGHLIGHT_VIEWS: u64 = 60;
+
+const PC_COLOR: Color = Color::rgba8(0xff, 0x50, 0x50, 0xa0);
+const I_COLOR: Color = Color::rgba8(0x50, 0xa0, 0xff, 0xa0);
+const STACK_COLOR: Color = Color::rgba8(0xa0, 0xa0, 0xa0, 0x60);
+const WRITE_COLOR: Color = Color::rgba8(0xff, 0xc8, 0x00, 0xa0);
+
+/// A hex view of the memory of the running system, updated with the views it publishes.
+/// The bytes at PC and I, the stack when it is kept in memory (eg. with the COSMAC VIP layout)
+/// and the bytes written recently are highlighted.
+pub(crate) struct MemoryPanel {
+    view: Option<MemoryView>,
+    // sequence number of the view where each byte has last been written
+    written_at: Vec<u64>,
+    // size of a character of the monospace font
+    cell: Size,
+}
+
+impl MemoryPanel {
+    pub fn new() -> Self {
+        Self {
+            view: None,
+            written_at: vec![],
+            cell: Size::new(FONT_SIZE * 0.6, FONT_SIZE * 1.25),
+        }
+    }
+
+    fn rows(&self) -> usize {
+        let size = self.view.as_ref().map_or(4096, |v| v.bytes.len());
+        size.div_ceil(BYTES_PER_ROW)
+    }
+
+    /// Returns the rectangle of the byte at `address`.
+    fn byte_rect(&self, address: usize) -> Rect {
+        let (row, col) = (address / BYTES_PER_ROW, address % BYTES_PER_ROW);
+        Rect::from((
+            Point::new(
+                (ADDRESS_WIDTH + 3 * col) as f64 * self.cell.width,
+                row as f64 * self.cell.height,
+            ),
+            Size::new(2.0 * self.cell.width, self.cell.height),
+        ))
+        .inset((1.0, 0.0))
+    }
+
+    fn highlight(&self, ctx: &mut PaintCtx, address: u16, len: usize, color: &Color) {
+        for a in address as usize..address as usize + len {
+            ctx.fill(self.byte_rect(a), color);
+        }
+    }
+}
+
+impl Widget<AppState> for MemoryPanel {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, _data: &mut AppState, _env: &Env) {
+        if let Event::Command(c) = event {
+            if let Some(view) = c.get(MEMORY) {
+                if self.written_at.len() != view.bytes.len() {
+                    self.written_at = vec![0; view.bytes.len()];
+                    ctx.request_layout();
+                }
+                for a in view.written.iter_ones() {
+                    self.written_at[a] = view.sequence;
+                }
+                self.view = Some(view.clone());
+                ctx.request_paint();
+            }
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        _ctx: &mut LifeCycleCtx,
+        _event: &LifeCycle,
+        _data: &AppState,
+        _env: &Env,
+    ) {
+    }
+
+    fn update(&mut self, _ctx: &mut UpdateCtx, _old: &AppState, _data: &AppState, _env: &Env) {}
+
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        _bc: &BoxConstraints,
+        _data: &AppState,
+        _env: &Env,
+    ) -> Size {
+        self.cell = text::cell_size(ctx.text());
+        Size::new(
+            (ADDRESS_WIDTH + 3 * BYTES_PER_ROW) as f64 * self.cell.width,
+            self.rows() as f64 * self.cell.height,
+        )
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, _data: &AppState, env: &Env) {
+        let bounds = ctx.size().to_rect();
+        ctx.fill(bounds, &env.get(theme::BACKGROUND_LIGHT));
+        let Some(view) = self.view.clone() else {
+            return;
+        };
+
+        // only the visible rows are painted
+        let visible = ctx.region().bounding_box();
+        let first = (visible.y0 / self.cell.height).floor().max(0.0) as usize;
+        let last = ((visible.y1 / self.cell.height).ceil() as usize).min(self.rows());
+
+        for address in first * BYTES_PER_ROW..(last * BYTES_PER_ROW).min(view.bytes.len()) {
+            let age = view.sequence.saturating_sub(self.written_at[address]);
+            if self.written_at[address] != 0 && age < WRITE_HIGHLIGHT_VIEWS {
+                let fade = 1.0 - age as f64 / WRITE_HIGHLIGHT_VIEWS as f64;
+                ctx.fill(self.byte_rect(address), &WRITE_COLOR.with_alpha(0.6 * fade));
+            }
+        }
+        if let Some(region) = &view.stack_region {
+            self.highlight(ctx, region.start, region.len(), &STACK_COLOR);
+        }
+        self.highlight(ctx, view.i, 1, &I_COLOR);
+        self.highlight(ctx, view.pc, 2, &PC_COLOR);
+
+        let text_color = env.get(theme::TEXT_COLOR);
+        for row in first..last {
+            let start = row * BYTES_PER_ROW;
+            let Some(bytes) = view
+                .bytes
+                .get(start..(start + BYTES_PER_ROW).min(view.bytes.len()))
+            else {
+                break;
+            };
+            let hex: Vec<_> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let line = format!("{:04X}  {}", start, hex.join(" "));
+            let layout = text::monospace(ctx.text(), line, &text_color);
+            ctx.draw_text(&layout, Point::new(0.0, row as f64 * self.cell.height));
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use crate::disassembly::DEBUG_EVENT;
+use crate::text::{self, FONT_SIZE};
+use crate::{AppState, Hotkey, TAKE_FOCUS};
+use chip8_system::cpu::CpuState;
+use chip8_system::debug::{DebugEvent, Register};
+use crossbeam_channel::Sender;
+use druid::*;
+
+pub const REGISTERS: Selector<CpuState> = Selector::new("terminal.registers");
+
+/// Characters of a line, eg. `PC 0200 I  0300`.
+const LINE_WIDTH: usize = 16;
+/// Lines before the call stack.
+const STACK_LINE: usize = 13;
+/// Maximum number of lines of the call stack.
+const STACK_LINES: usize = 16;
+
+const EDIT_COLOR: Color = Color::rgba8(0x50, 0xa0, 0xff, 0x60);
+
+/// Where a register is shown: its line, the column of its value and its number of digits.
+struct Field {
+    register: Register,
+    line: usize,
+    column: usize,
+    digits: usize,
+}
+
+/// The fields of the registers, which can be edited while paused.
+fn fields() -> Vec<Field> {
+    let mut fields: Vec<_> = (0..16)
+        .map(|x| Field {
+            register: Register::V(x),
+            line: x as usize % 8,
+            column: 3 + 8 * (x as usize / 8),
+            digits: 2,
+        })
+        .collect();
+    let others = [
+        (Register::Pc, 9, 3, 4),
+        (Register::I, 9, 11, 4),
+        (Register::DelayTimer, 11, 3, 2),
+        (Register::SoundTimer, 11, 11, 2),
+    ];
+    for (register, line, column, digits) in others {
+        fields.push(Field {
+            register,
+            line,
+            column,
+            digits,
+        });
+    }
+    fields
+}
+
+/// The registers, timers and call stack of the running system, from its debug state.
+/// While paused, clicking a value edits it: type hex digits, then Enter to change it.
+pub(crate) struct RegistersPanel {
+    hotkey_sender: Sender<Hotkey>,
+    state: Option<CpuState>,
+    paused: bool,
+    // register being edited, with the digits typed so far
+    editing: Option<(Register, String)>,
+    // size of a character of the monospace font
+    cell: Size,
+}
+
+impl RegistersPanel {
+    pub fn new(hotkey_sender: Sender<Hotkey>) -> Self {
+        Self {
+            hotkey_sender,
+            state: None,
+            paused: false,
+            editing: None,
+            cell: Size::new(FONT_SIZE * 0.6, FONT_SIZE * 1.25),
+        }
+    }
+
+    /// Returns the text of the value of a register, or the digits typed when it is edited.
+    fn text_of(&self, state: &CpuState, field: &Field) -> String {
+        match &self.editing {
+            Some((register, typed)) if *register == field.register => {
+                format!("{:_<1$}", typed, field.digits)
+            }
+            _ => format!("{:01$X}", field.register.read(state), field.digits),
+        }
+    }
+
+    fn lines(&self, state: &CpuState) -> Vec<String> {
+        let mut lines = vec![String::new(); STACK_LINE];
+        for (x, line) in lines.iter_mut().enumerate().take(8) {
+            *line = format!("V{:X}{:6}V{:X}", x, "", x + 8);
+        }
+        lines[9] = "PC      I".to_string();
+        lines[10] = format!("SP {}", state.stack.len());
+        lines[11] = "DT      ST".to_string();
+        lines[12] = "Stack".to_string();
+        for field in fields() {
+            let line = &mut lines[field.line];
+            let end = field.column + field.digits;
+            if line.len() < end {
+                line.push_str(&" ".repeat(end - line.len()));
+            }
+            line.replace_range(field.column..end, &self.text_of(state, &field));
+        }
+        // innermost call first
+        for (depth, address) in state.stack.iter().rev().enumerate().take(STACK_LINES) {
+            lines.push(format!("{:2} {:04X}", depth, address));
+        }
+        lines
+    }
+
+    /// Changes the register being edited, and gives the focus back to the screen.
+    fn commit(&mut self, ctx: &mut EventCtx) {
+        if let Some((register, typed)) = self.editing.take() {
+            if let Ok(value) = u16::from_str_radix(&typed, 16) {
+                let _ = self
+                    .hotkey_sender
+                    .try_send(Hotkey::SetRegister { register, value });
+            }
+        }
+        ctx.submit_command(TAKE_FOCUS);
+        ctx.request_paint();
+    }
+
+    fn cancel(&mut self, ctx: &mut EventCtx) {
+        if self.editing.take().is_some() {
+            ctx.submit_command(TAKE_FOCUS);
+            ctx.request_paint();
+        }
+    }
+}
+
+impl Widget<AppState> for RegistersPanel {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, _data: &mut AppState, _env: &Env) {
+        match event {
+            Event::Command(c) => {
+                if let Some(state) = c.get(REGISTERS) {
+                    self.state = Some(state.clone());
+                    ctx.request_paint();
+                }
+                match c.get(DEBUG_EVENT) {
+                    Some(DebugEvent::Paused { .. }) => self.paused = true,
+                    Some(DebugEvent::Resumed) => {
+                        self.paused = false;
+                        self.cancel(ctx);
+                    }
+                    _ => {}
+                }
+            }
+            Event::MouseDown(m) if self.paused => {
+                let line = (m.pos.y / self.cell.height).floor() as usize;
+                let column = (m.pos.x / self.cell.width).floor() as usize;
+                let field = fields()
+                    .into_iter()
+                    .find(|f| f.line == line && (f.column..f.column + f.digits).contains(&column));
+                match field {
+                    Some(f) => {
+                        self.editing = Some((f.register, String::new()));
+                        ctx.request_focus();
+                        ctx.request_paint();
+                    }
+                    None => self.cancel(ctx),
+                }
+            }
+            Event::KeyDown(k) if self.editing.is_some() => {
+                let digits = self
+                    .editing
+                    .as_ref()
+                    .and_then(|(r, _)| fields().into_iter().find(|f| f.register == *r))
+                    .map_or(0, |f| f.digits);
+                match &k.key {
+                    KbKey::Enter => self.commit(ctx),
+                    KbKey::Escape => self.cancel(ctx),
+                    KbKey::Backspace => {
+                        if let Some((_, typed)) = &mut self.editing {
+                            typed.pop();
+                        }
+                    }
+                    KbKey::Character(s) => {
+                        if let Some((_, typed)) = &mut self.editing {
+                            for c in s.chars().filter(char::is_ascii_hexdigit) {
+                                if typed.len() < digits {
+                                    typed.push(c.to_ascii_uppercase());
+                                }
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+                ctx.set_handled();
+                ctx.request_paint();
+            }
+            _ => {}
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        _ctx: &mut LifeCycleCtx,
+        _event: &LifeCycle,
+        _data: &AppState,
+        _env: &Env,
+    ) {
+    }
+
+    fn update(&mut self, _ctx: &mut UpdateCtx, _old: &AppState, _data: &AppState, _env: &Env) {}
+
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        _bc: &BoxConstraints,
+        _data: &AppState,
+        _env: &Env,
+    ) -> Size {
+        self.cell = text::cell_size(ctx.text());
+        Size::new(
+            LINE_WIDTH as f64 * self.cell.width,
+            (STACK_LINE + STACK_LINES) as f64 * self.cell.height,
+        )
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, _data: &AppState, env: &Env) {
+        let bounds = ctx.size().to_rect();
+        ctx.fill(bounds, &env.get(theme::BACKGROUND_LIGHT));
+        let text_color = env.get(theme::TEXT_COLOR);
+        let Some(state) = self.state.clone() else {
+            return;
+        };
+
+        if let Some((register, _)) = &self.editing {
+            if let Some(f) = fields().into_iter().find(|f| f.register == *register) {
+                let r = Rect::from((
+                    Point::new(
+                        f.column as f64 * self.cell.width,
+                        f.line as f64 * self.cell.height,
+                    ),
+                    Size::new(f.digits as f64 * self.cell.width, self.cell.height),
+                ));
+                ctx.fill(r, &EDIT_COLOR);
+            }
+        }
+
+        for (i, line) in self.lines(&state).into_iter().enumerate() {
+            let layout = text::monospace(ctx.text(), line, &text_color);
+            ctx.draw_text(&layout, Point::new(0.0, i as f64 * self.cell.height));
+        }
+    }
+}
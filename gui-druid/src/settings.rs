@@ -0,0 +1,285 @@
+use crate::AppState;
+use chip8_system::keyboard_map::load_profiles;
+use druid::widget::{
+    Button, Checkbox, CrossAxisAlignment, Flex, Label, RadioGroup, Slider, Stepper,
+};
+use druid::{theme, Color, Data, Env, Lens, LensExt, Size, Widget, WidgetExt, WindowDesc};
+use serde::{Deserialize, Serialize};
+
+/// Width of the border around the screen in high contrast mode.
+const HIGH_CONTRAST_BORDER: f64 = 4.0;
+
+/// Color presets offered by the preferences window, as (name, background, foreground).
+const PALETTES: [(&str, Color, Color); 4] = [
+    ("Classic", Color::BLACK, Color::GRAY),
+    (
+        "Green",
+        Color::rgb8(0x0a, 0x14, 0x0a),
+        Color::rgb8(0x33, 0xff, 0x66),
+    ),
+    (
+        "Amber",
+        Color::rgb8(0x14, 0x0c, 0x00),
+        Color::rgb8(0xff, 0xb0, 0x00),
+    ),
+    (
+        "Paper",
+        Color::rgb8(0xf4, 0xf1, 0xe8),
+        Color::rgb8(0x20, 0x20, 0x20),
+    ),
+];
+
+/// Colors of the windows, along with default colors for the screen.
+#[derive(Copy, Clone, Data, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    /// Follows the theme of the operating system.
+    #[default]
+    Auto,
+    Dark,
+    Light,
+}
+
+impl Theme {
+    /// Returns `Dark` or `Light`, detecting the theme of the operating system for `Auto`.
+    pub fn resolve(self) -> Self {
+        match self {
+            Theme::Auto => match dark_light::detect() {
+                dark_light::Mode::Light => Theme::Light,
+                _ => Theme::Dark,
+            },
+            theme => theme,
+        }
+    }
+
+    /// Background and foreground colors of the screen matching the theme.
+    pub fn colors(self) -> (Color, Color) {
+        let (_, background, foreground) = match self.resolve() {
+            Theme::Light => PALETTES[3],
+            _ => PALETTES[0],
+        };
+        (background, foreground)
+    }
+
+    /// Sets the colors of the widgets, druid's default ones are dark.
+    pub(crate) fn apply(self, env: &mut Env) {
+        if self.resolve() != Theme::Light {
+            return;
+        }
+        env.set(
+            theme::WINDOW_BACKGROUND_COLOR,
+            Color::rgb8(0xf0, 0xf0, 0xf0),
+        );
+        env.set(theme::TEXT_COLOR, Color::rgb8(0x20, 0x20, 0x20));
+        env.set(theme::PLACEHOLDER_COLOR, Color::rgb8(0x80, 0x80, 0x80));
+        env.set(theme::BACKGROUND_LIGHT, Color::WHITE);
+        env.set(theme::BACKGROUND_DARK, Color::rgb8(0xe4, 0xe4, 0xe4));
+        env.set(theme::FOREGROUND_LIGHT, Color::rgb8(0x20, 0x20, 0x20));
+        env.set(theme::FOREGROUND_DARK, Color::rgb8(0x40, 0x40, 0x40));
+        env.set(theme::BUTTON_LIGHT, Color::rgb8(0xfa, 0xfa, 0xfa));
+        env.set(theme::BUTTON_DARK, Color::rgb8(0xdc, 0xdc, 0xdc));
+        env.set(theme::BORDER_LIGHT, Color::rgb8(0xc0, 0xc0, 0xc0));
+        env.set(theme::BORDER_DARK, Color::rgb8(0xa0, 0xa0, 0xa0));
+    }
+}
+
+/// How each pixel of the screen is drawn.
+#[derive(Copy, Clone, Data, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PixelShape {
+    #[default]
+    Square,
+    Rounded,
+    Dot,
+}
+
+/// Position, size and state of the main window, restored on the next launch.
+#[derive(Clone, Copy, Data, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+    pub maximized: bool,
+}
+
+/// The preferences which can be changed while the terminal is running.
+#[derive(Clone, Data, Lens, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub theme: Theme,
+    #[serde(with = "hex_color")]
+    pub background_color: Color,
+    #[serde(with = "hex_color")]
+    pub foreground_color: Color,
+    /// Size of a pixel of the screen, in logical pixels.
+    pub scale: f64,
+    /// Width of a pixel divided by its height, for programs designed around non-square pixels.
+    pub pixel_aspect: f64,
+    pub pixel_shape: PixelShape,
+    /// Leaves a 1 pixel gap between the pixels of the screen, making each of them visible.
+    pub grid: bool,
+    /// Draws pure white pixels on black, surrounded by a thick border
+    /// which makes the edges of the screen visible. Overrides the colors.
+    pub high_contrast: bool,
+    /// Number of frames a pixel stays lit after it is turned off, which hides the flicker
+    /// of sprites erased then drawn again. 0 shows the screen as it is.
+    pub persistence: u8,
+    pub keyboard_profile: String,
+    /// Volume of the sound, between 0 and 1.
+    pub volume: f64,
+    /// Geometry of the main window when it was last closed.
+    pub window: Option<WindowGeometry>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let (background_color, foreground_color) = Theme::Auto.colors();
+        Self {
+            theme: Theme::Auto,
+            background_color,
+            foreground_color,
+            scale: 8.0,
+            pixel_aspect: 1.0,
+            pixel_shape: Default::default(),
+            grid: false,
+            high_contrast: false,
+            persistence: 0,
+            keyboard_profile: "default".to_string(),
+            volume: 1.0,
+            window: None,
+        }
+    }
+}
+
+impl Settings {
+    pub(crate) fn colors(&self) -> (&Color, &Color) {
+        if self.high_contrast {
+            (&Color::BLACK, &Color::WHITE)
+        } else {
+            (&self.background_color, &self.foreground_color)
+        }
+    }
+
+    /// Size of a pixel of the screen, stretched horizontally by the aspect ratio.
+    pub(crate) fn pixel_size(&self) -> Size {
+        Size::new(self.scale * self.pixel_aspect, self.scale)
+    }
+
+    pub(crate) fn border_width(&self) -> f64 {
+        if self.high_contrast {
+            HIGH_CONTRAST_BORDER
+        } else {
+            0.0
+        }
+    }
+}
+
+pub(crate) fn preferences_window() -> WindowDesc<AppState> {
+    WindowDesc::new(preferences().lens(AppState::settings))
+        .title("Preferences")
+        .window_size((360.0, 530.0))
+}
+
+fn preferences() -> impl Widget<Settings> {
+    let mut palettes = Flex::row();
+    for (name, background, foreground) in PALETTES {
+        let (bg, fg) = (background.as_rgba_u32(), foreground.as_rgba_u32());
+        palettes.add_child(Button::new(name).on_click(move |_, s: &mut Settings, _| {
+            s.background_color = Color::from_rgba32_u32(bg);
+            s.foreground_color = Color::from_rgba32_u32(fg);
+        }));
+    }
+
+    let mut profiles: Vec<String> = load_profiles()
+        .map(|p| p.into_keys().collect())
+        .unwrap_or_default();
+    profiles.sort();
+    let profiles = profiles.into_iter().map(|p| (p.clone(), p));
+
+    // selecting a theme also resets the colors of the screen
+    let mut themes = Flex::row();
+    for (name, theme) in [
+        ("Auto", Theme::Auto),
+        ("Dark", Theme::Dark),
+        ("Light", Theme::Light),
+    ] {
+        themes.add_child(Button::new(name).on_click(move |_, s: &mut Settings, _| {
+            s.theme = theme;
+            (s.background_color, s.foreground_color) = theme.colors();
+        }));
+    }
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Label::new(|s: &Settings, _: &_| {
+            format!("Theme: {:?} (windows follow it after a restart)", s.theme)
+        }))
+        .with_child(themes)
+        .with_spacer(8.0)
+        .with_child(Label::new("Palette"))
+        .with_child(palettes)
+        .with_spacer(8.0)
+        .with_child(Label::new(|s: &Settings, _: &_| {
+            format!("Scale: {}", s.scale)
+        }))
+        .with_child(
+            Stepper::new()
+                .with_range(2.0, 16.0)
+                .with_step(1.0)
+                .lens(Settings::scale),
+        )
+        .with_spacer(8.0)
+        .with_child(Label::new("Pixel aspect ratio"))
+        .with_child(
+            RadioGroup::row([("1:1", 1.0), ("4:3", 4.0 / 3.0), ("2:1", 2.0)])
+                .lens(Settings::pixel_aspect),
+        )
+        .with_spacer(8.0)
+        .with_child(Label::new("Pixel shape"))
+        .with_child(
+            RadioGroup::row([
+                ("Square", PixelShape::Square),
+                ("Rounded", PixelShape::Rounded),
+                ("Dot", PixelShape::Dot),
+            ])
+            .lens(Settings::pixel_shape),
+        )
+        .with_child(Checkbox::new("Grid").lens(Settings::grid))
+        .with_child(Checkbox::new("High contrast").lens(Settings::high_contrast))
+        .with_spacer(8.0)
+        .with_child(Label::new(|s: &Settings, _: &_| match s.persistence {
+            0 => "Anti-flicker: off".to_string(),
+            n => format!("Anti-flicker: pixels stay lit for {} frames", n),
+        }))
+        .with_child(
+            Stepper::new()
+                .with_range(0.0, 8.0)
+                .with_step(1.0)
+                .lens(Settings::persistence.map(|p| *p as f64, |p, v| *p = v as u8)),
+        )
+        .with_spacer(8.0)
+        .with_child(Label::new("Keyboard profile"))
+        .with_child(RadioGroup::row(profiles).lens(Settings::keyboard_profile))
+        .with_spacer(8.0)
+        .with_child(Label::new("Volume"))
+        .with_child(Slider::new().lens(Settings::volume))
+        .padding(10.0)
+}
+
+/// Colors are stored as HTML-like hex strings.
+mod hex_color {
+    use druid::Color;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(color: &Color, s: S) -> Result<S::Ok, S::Error> {
+        let (r, g, b, _) = color.as_rgba8();
+        s.serialize_str(&format!("#{:02x}{:02x}{:02x}", r, g, b))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Color, D::Error> {
+        let s = String::deserialize(d)?;
+        Color::from_hex_str(&s).map_err(D::Error::custom)
+    }
+}
@@ -0,0 +1,120 @@
+use crate::text::{self, FONT_SIZE};
+use crate::AppState;
+use chip8_system::pacing::FramePacing;
+use druid::piet::TextLayout as _;
+use druid::*;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+pub const PACING: Selector<FramePacing> = Selector::new("terminal.pacing");
+
+/// Intervals kept until the status bar takes them, the oldest ones are dropped
+/// while it is hidden.
+const MAX_PAINT_TIMES: usize = 600;
+
+/// Time between the frames painted by the screen, collected for the status bar.
+#[derive(Clone, Default)]
+pub(crate) struct PaintTimes(Rc<RefCell<(Option<Instant>, VecDeque<Duration>)>>);
+
+impl PaintTimes {
+    pub fn record(&self, now: Instant) {
+        let (last, intervals) = &mut *self.0.borrow_mut();
+        if let Some(last) = last.replace(now) {
+            if intervals.len() == MAX_PAINT_TIMES {
+                intervals.pop_front();
+            }
+            intervals.push_back(now - last);
+        }
+    }
+
+    /// Returns the intervals recorded since the last call.
+    pub fn take(&self) -> Vec<Duration> {
+        std::mem::take(&mut self.0.borrow_mut().1).into()
+    }
+}
+
+/// A line under the screen with the pacing of the display updates, as emitted by the system
+/// and as painted by the terminal: stutter on one side only tells where it comes from.
+pub(crate) struct StatusBar {
+    paint_times: PaintTimes,
+    emulation: Option<FramePacing>,
+    rendering: Option<FramePacing>,
+}
+
+impl StatusBar {
+    pub fn new(paint_times: PaintTimes) -> Self {
+        Self {
+            paint_times,
+            emulation: None,
+            rendering: None,
+        }
+    }
+
+    fn text(&self) -> String {
+        match (&self.emulation, &self.rendering) {
+            (Some(emulation), Some(rendering)) => format!(
+                "Emulation {}  |  Rendering {}",
+                describe(emulation),
+                describe(rendering)
+            ),
+            _ => "Waiting for frame pacing...".to_string(),
+        }
+    }
+}
+
+fn describe(pacing: &FramePacing) -> String {
+    if pacing.intervals == 0 {
+        return "idle".to_string();
+    }
+    format!(
+        "{:.1} ms ±{:.1} (max {:.1}), {} stutters",
+        pacing.mean_interval.as_secs_f64() * 1000.0,
+        pacing.jitter.as_secs_f64() * 1000.0,
+        pacing.max_interval.as_secs_f64() * 1000.0,
+        pacing.stutters()
+    )
+}
+
+impl Widget<AppState> for StatusBar {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, _data: &mut AppState, _env: &Env) {
+        if let Event::Command(c) = event {
+            if let Some(pacing) = c.get(PACING) {
+                self.emulation = Some(pacing.clone());
+                self.rendering = Some(FramePacing::from_intervals(&self.paint_times.take()));
+                ctx.request_paint();
+            }
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        _ctx: &mut LifeCycleCtx,
+        _event: &LifeCycle,
+        _data: &AppState,
+        _env: &Env,
+    ) {
+    }
+
+    fn update(&mut self, _ctx: &mut UpdateCtx, _old: &AppState, _data: &AppState, _env: &Env) {}
+
+    fn layout(
+        &mut self,
+        _ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        _data: &AppState,
+        _env: &Env,
+    ) -> Size {
+        bc.constrain((bc.max().width, FONT_SIZE * 1.5))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, _data: &AppState, env: &Env) {
+        let bounds = ctx.size().to_rect();
+        ctx.fill(bounds, &env.get(theme::BACKGROUND_LIGHT));
+        let line = self.text();
+        let layout = text::monospace(ctx.text(), line, &env.get(theme::TEXT_COLOR));
+        let y = (bounds.height() - layout.size().height) / 2.0;
+        ctx.draw_text(&layout, Point::new(4.0, y));
+    }
+}
@@ -0,0 +1,20 @@
+use druid::piet::{PietText, PietTextLayout, Text, TextLayout as _, TextLayoutBuilder};
+use druid::{Color, FontFamily, Size};
+
+/// Font size of the debugger panels and the status bar.
+pub const FONT_SIZE: f64 = 12.0;
+
+/// Lays out `text` in the monospace font shared by the panels.
+pub fn monospace(factory: &mut PietText, text: String, color: &Color) -> PietTextLayout {
+    factory
+        .new_text_layout(text)
+        .font(FontFamily::MONOSPACE, FONT_SIZE)
+        .text_color(color.clone())
+        .build()
+        .unwrap()
+}
+
+/// Size of one character of the monospace font, used as the panels' grid cell.
+pub fn cell_size(factory: &mut PietText) -> Size {
+    monospace(factory, "0".to_string(), &Color::WHITE).size()
+}
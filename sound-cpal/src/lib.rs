@@ -1,15 +1,35 @@
+use chip8_system::audio::AudioPattern;
 use chip8_system::port::InputPort;
 use chip8_system::timer::TimerMessage;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{BackendSpecificError, BuildStreamError, FromSample, Sample, SizedSample, Stream};
-use crossbeam_channel::Sender;
+use crossbeam_channel::{RecvTimeoutError, Sender};
 use std::error::Error;
+use std::f32::consts::PI;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
+
+/// Frequency of the legacy buzzer.
+const BUZZER_FREQUENCY: f32 = 440.0;
+
+/// Minimum time between two rings of the terminal bell, which can't be as short as a beep.
+const BELL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Interval at which the beeper checks its output device, to follow changes of the default one
+/// or to find one again after it has been lost.
+const DEVICE_CHECK_INTERVAL: Duration = Duration::from_millis(500);
 
 pub enum Message {
     Play,
     Pause,
     Stop,
+    /// Sets the volume, between 0 and 1.
+    Volume(f32),
+    /// Replaces the buzzer by the XO-CHIP pattern voice.
+    Pattern(AudioPattern),
 }
 
 impl From<TimerMessage> for Message {
@@ -21,60 +41,329 @@ impl From<TimerMessage> for Message {
     }
 }
 
+impl From<AudioPattern> for Message {
+    fn from(p: AudioPattern) -> Self {
+        Message::Pattern(p)
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct BeeperOptions {
+    device: Option<String>,
+    pan: f32,
+    mix_buzzer: bool,
+}
+
+impl BeeperOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Selects an output device by name, instead of the default one.
+    pub fn device(&mut self, name: &str) -> &mut Self {
+        self.device = Some(name.to_string());
+        self
+    }
+
+    /// Position of the sound in the stereo field, from -1 (left) to 1 (right).
+    pub fn pan(&mut self, pan: f32) -> &mut Self {
+        self.pan = pan.clamp(-1.0, 1.0);
+        self
+    }
+
+    /// Keeps the buzzer playing along with the XO-CHIP pattern voice,
+    /// instead of replacing it.
+    pub fn mix_buzzer(&mut self, enabled: bool) -> &mut Self {
+        self.mix_buzzer = enabled;
+        self
+    }
+}
+
+/// Parameters of the sound, shared with the audio callback.
+#[derive(Clone)]
+struct Mix {
+    options: BeeperOptions,
+    volume: f32,
+    pattern: Option<AudioPattern>,
+}
+
+/// Generates the sound, one frame of left and right samples at a time.
+struct Synth {
+    sample_rate: f32,
+    buzzer_clock: f32,
+    // position in the pattern, in pattern samples
+    pattern_position: f32,
+}
+
+impl Synth {
+    fn next_frame(&mut self, mix: &Mix) -> (f32, f32) {
+        self.buzzer_clock = (self.buzzer_clock + 1.0) % self.sample_rate;
+        let buzzer = (self.buzzer_clock * BUZZER_FREQUENCY * 2.0 * PI / self.sample_rate).sin();
+
+        let value = match &mix.pattern {
+            Some(p) => {
+                self.pattern_position =
+                    (self.pattern_position + p.sample_rate() / self.sample_rate) % 128.0;
+                let voice = if p.sample(self.pattern_position as usize) {
+                    1.0
+                } else {
+                    -1.0
+                };
+                if mix.options.mix_buzzer {
+                    (voice + buzzer) / 2.0
+                } else {
+                    voice
+                }
+            }
+            None => buzzer,
+        } * mix.volume;
+
+        // constant power panning
+        let angle = (mix.options.pan + 1.0) * PI / 4.0;
+        (value * angle.cos(), value * angle.sin())
+    }
+}
+
+/// The output device, and the stream configuration negotiated with it.
+#[derive(Clone, Debug)]
+pub struct OutputInfo {
+    pub device: String,
+    pub sample_format: String,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+/// Returns the names of the audio output devices of the default host.
+pub fn output_devices() -> Result<Vec<String>, Box<dyn Error>> {
+    let host = cpal::default_host();
+    Ok(host
+        .output_devices()?
+        .filter_map(|d| d.name().ok())
+        .collect())
+}
+
+/// Returns the output device selected by the options, or the default one.
+fn find_device(host: &cpal::Host, options: &BeeperOptions) -> Result<cpal::Device, Box<dyn Error>> {
+    let device = match &options.device {
+        Some(name) => host
+            .output_devices()?
+            .find(|d| d.name().ok().as_ref() == Some(name))
+            .ok_or_else(|| format!("No audio output device named {}", name))?,
+        None => host
+            .default_output_device()
+            .ok_or("No audio output device")?,
+    };
+    Ok(device)
+}
+
+/// Opens a new stream on the output device selected by the options, eg. after the previous one failed.
+fn reopen(
+    host: &cpal::Host,
+    options: &BeeperOptions,
+    mix: &Arc<Mutex<Mix>>,
+) -> Result<Output, Box<dyn Error>> {
+    let device = find_device(host, options)?;
+    let config = device.default_output_config()?;
+    Output::open(&device, config, mix)
+}
+
+/// A stream playing the sound on an output device.
+struct Output {
+    stream: Stream,
+    device: String,
+    // set when the stream fails, eg. when its device is unplugged
+    failed: Arc<AtomicBool>,
+}
+
+impl Output {
+    /// Opens a paused stream on the device.
+    fn open(
+        device: &cpal::Device,
+        config: cpal::SupportedStreamConfig,
+        mix: &Arc<Mutex<Mix>>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let failed = Arc::new(AtomicBool::new(false));
+        let (m, f) = (Arc::clone(mix), Arc::clone(&failed));
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::F32 => Beeper::create_stream::<f32>(device, &config.into(), m, f),
+            cpal::SampleFormat::I16 => Beeper::create_stream::<i16>(device, &config.into(), m, f),
+            cpal::SampleFormat::U16 => Beeper::create_stream::<u16>(device, &config.into(), m, f),
+            sample_format => Err(BuildStreamError::BackendSpecific {
+                err: BackendSpecificError {
+                    description: format!("Unsupported sample format '{sample_format}'"),
+                },
+            }),
+        }?;
+        let _ = stream.pause();
+
+        Ok(Self {
+            stream,
+            device: device.name()?,
+            failed,
+        })
+    }
+
+    fn failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    fn set_playing(&self, playing: bool) {
+        let _ = if playing {
+            self.stream.play()
+        } else {
+            self.stream.pause()
+        };
+    }
+}
+
+/// Rings the terminal bell instead of playing sounds, at most once per `BELL_INTERVAL`.
+struct Bell {
+    volume: f32,
+    last_ring: Option<Instant>,
+}
+
+impl Bell {
+    /// Returns true if the bell should ring for a sound starting at `now`.
+    fn ring(&mut self, now: Instant) -> bool {
+        if self.volume == 0.0 {
+            return false;
+        }
+        if let Some(last) = self.last_ring {
+            if now.duration_since(last) < BELL_INTERVAL {
+                return false;
+            }
+        }
+        self.last_ring = Some(now);
+        true
+    }
+}
+
 pub struct Beeper {
     sender: Sender<Message>,
+    info: OutputInfo,
 }
 
 impl Beeper {
     pub fn new() -> Result<Self, Box<dyn Error>> {
-        let host = cpal::default_host();
-        let device = host
-            .default_output_device()
-            .ok_or("No audio output device")?;
+        Self::new_with_options(Default::default())
+    }
+
+    pub fn new_with_options(options: BeeperOptions) -> Result<Self, Box<dyn Error>> {
+        let device = find_device(&cpal::default_host(), &options)?;
         let config = device.default_output_config()?;
+        let info = OutputInfo {
+            device: device.name()?,
+            sample_format: config.sample_format().to_string(),
+            sample_rate: config.sample_rate().0,
+            channels: config.channels(),
+        };
 
         let (s, r) = crossbeam_channel::unbounded();
         thread::spawn(move || {
-            let stream = match config.sample_format() {
-                cpal::SampleFormat::F32 => Self::create_stream::<f32>(&device, &config.into()),
-                cpal::SampleFormat::I16 => Self::create_stream::<i16>(&device, &config.into()),
-                cpal::SampleFormat::U16 => Self::create_stream::<u16>(&device, &config.into()),
-                sample_format => Err(BuildStreamError::BackendSpecific {
-                    err: BackendSpecificError {
-                        description: format!("Unsupported sample format '{sample_format}'"),
-                    },
-                }),
-            };
+            let host = cpal::default_host();
+            let follow_default = options.device.is_none();
+            let mix = Arc::new(Mutex::new(Mix {
+                options: options.clone(),
+                volume: 1.0,
+                pattern: None,
+            }));
+            let mut output = Output::open(&device, config, &mix)
+                .map_err(|e| eprintln!("BuildStreamError {:?}", e))
+                .ok();
+            let mut playing = false;
+            let mut last_check = Instant::now();
 
-            match stream {
-                Ok(stream) => {
-                    let _ = stream.pause();
+            loop {
+                match r.recv_timeout(DEVICE_CHECK_INTERVAL) {
+                    Ok(Message::Play) => playing = true,
+                    Ok(Message::Pause) => playing = false,
+                    Ok(Message::Stop) | Err(RecvTimeoutError::Disconnected) => return,
+                    Ok(Message::Volume(v)) => {
+                        mix.lock().unwrap().volume = v.clamp(0.0, 1.0);
+                    }
+                    Ok(Message::Pattern(p)) => {
+                        mix.lock().unwrap().pattern = Some(p);
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                }
 
-                    loop {
-                        match r.recv() {
-                            Ok(Message::Play) => {
-                                let _ = stream.play();
-                            }
-                            Ok(Message::Pause) => {
-                                let _ = stream.pause();
-                            }
-                            Ok(Message::Stop) => {
-                                let _ = stream.pause();
-                                return;
-                            }
-                            Err(e) => {
-                                eprintln!("Receive error: {}", e);
+                // devices are only looked up from time to time, as it may be slow
+                let check = last_check.elapsed() >= DEVICE_CHECK_INTERVAL;
+                if check {
+                    last_check = Instant::now();
+                }
+                let rebuild = match &output {
+                    Some(o) if o.failed() => true,
+                    Some(o) if check && follow_default => host
+                        .default_output_device()
+                        .and_then(|d| d.name().ok())
+                        .map_or(false, |name| name != o.device),
+                    Some(_) => false,
+                    None => check,
+                };
+                if rebuild {
+                    let lost = output.take().is_some();
+                    output = match reopen(&host, &options, &mix) {
+                        Ok(o) => {
+                            eprintln!("Audio output switched to {}", o.device);
+                            Some(o)
+                        }
+                        Err(e) => {
+                            if lost {
+                                eprintln!("Audio output lost: {}", e);
                             }
+                            None
                         }
-                    }
+                    };
+                }
+
+                if let Some(o) = &output {
+                    o.set_playing(playing);
                 }
-                Err(e) => {
-                    eprintln!("BuildStreamError {:?}", e);
+            }
+        });
+
+        Ok(Self { sender: s, info })
+    }
+
+    /// Creates a beeper for machines without audio output, which rings the terminal bell
+    /// when a sound starts, eg. so that games can still be played on headless machines.
+    pub fn terminal_bell() -> Self {
+        let info = OutputInfo {
+            device: "terminal bell".to_string(),
+            sample_format: "none".to_string(),
+            sample_rate: 0,
+            channels: 0,
+        };
+
+        let (s, r) = crossbeam_channel::unbounded();
+        thread::spawn(move || {
+            let mut bell = Bell {
+                volume: 1.0,
+                last_ring: None,
+            };
+            for msg in r {
+                match msg {
+                    Message::Play => {
+                        if bell.ring(Instant::now()) {
+                            // stderr, as stdout may carry the messages of a frontend
+                            let mut stderr = io::stderr();
+                            let _ = stderr.write_all(b"\x07");
+                            let _ = stderr.flush();
+                        }
+                    }
+                    Message::Volume(v) => bell.volume = v.clamp(0.0, 1.0),
+                    Message::Stop => return,
+                    Message::Pause | Message::Pattern(_) => {}
                 }
             }
         });
 
-        Ok(Self { sender: s })
+        Self { sender: s, info }
+    }
+
+    pub fn output_info(&self) -> &OutputInfo {
+        &self.info
     }
 
     pub fn play(&self) {
@@ -92,40 +381,54 @@ impl Beeper {
     fn create_stream<T>(
         device: &cpal::Device,
         config: &cpal::StreamConfig,
+        mix: Arc<Mutex<Mix>>,
+        failed: Arc<AtomicBool>,
     ) -> Result<Stream, BuildStreamError>
     where
         T: SizedSample + FromSample<f32>,
     {
-        let sample_rate = config.sample_rate.0 as f32;
         let channels = config.channels as usize;
-
-        // Produce a sinusoid of maximum amplitude.
-        let mut sample_clock = 0f32;
-        let mut next_value = move || {
-            sample_clock = (sample_clock + 1.0) % sample_rate;
-            (sample_clock * 440.0 * 2.0 * std::f32::consts::PI / sample_rate).sin()
+        let mut synth = Synth {
+            sample_rate: config.sample_rate.0 as f32,
+            buzzer_clock: 0.0,
+            pattern_position: 0.0,
         };
 
-        let err_fn = |err| eprintln!("Stream error: {}", err);
+        let err_fn = move |err| {
+            eprintln!("Stream error: {}", err);
+            failed.store(true, Ordering::Relaxed);
+        };
 
         device.build_output_stream(
             config,
             move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-                Self::write_data(data, channels, &mut next_value)
+                let mix = mix.lock().unwrap().clone();
+                Self::write_data(data, channels, &mut || synth.next_frame(&mix))
             },
             err_fn,
             None,
         )
     }
 
-    fn write_data<T>(output: &mut [T], channels: usize, next_sample: &mut dyn FnMut() -> f32)
+    /// Writes stereo frames according to the channel layout of the device:
+    /// mono devices get the average of both sides, and channels beyond
+    /// the first two (eg. surround) are left silent.
+    fn write_data<T>(output: &mut [T], channels: usize, next_frame: &mut dyn FnMut() -> (f32, f32))
     where
         T: Copy + FromSample<f32>,
     {
         for frame in output.chunks_mut(channels) {
-            let value = next_sample().to_sample::<T>();
-            for sample in frame.iter_mut() {
-                *sample = value;
+            let (left, right) = next_frame();
+            match frame {
+                [mono] => *mono = ((left + right) / 2.0).to_sample::<T>(),
+                [l, r, rest @ ..] => {
+                    *l = left.to_sample::<T>();
+                    *r = right.to_sample::<T>();
+                    for sample in rest {
+                        *sample = 0.0f32.to_sample::<T>();
+                    }
+                }
+                [] => {}
             }
         }
     }
@@ -177,4 +480,31 @@ mod tests {
             thread::sleep(Duration::from_secs(2));
         }
     }
+
+    #[test]
+    fn bell_rings_at_most_once_per_interval() {
+        let mut bell = Bell {
+            volume: 1.0,
+            last_ring: None,
+        };
+        let start = Instant::now();
+
+        assert!(bell.ring(start));
+        assert!(!bell.ring(start + BELL_INTERVAL / 2));
+        assert!(bell.ring(start + BELL_INTERVAL));
+
+        bell.volume = 0.0;
+        assert!(!bell.ring(start + BELL_INTERVAL * 4));
+    }
+
+    #[test]
+    fn frames_follow_channel_layout() {
+        let mut surround = [0.5f32; 6];
+        Beeper::write_data(&mut surround, 3, &mut || (1.0, -1.0));
+        assert_eq!(surround, [1.0, -1.0, 0.0, 1.0, -1.0, 0.0]);
+
+        let mut mono = [0.0f32; 2];
+        Beeper::write_data(&mut mono, 1, &mut || (1.0, 0.0));
+        assert_eq!(mono, [0.5, 0.5]);
+    }
 }